@@ -0,0 +1,15 @@
+use std::fs;
+
+/// Keeps the version macros of the installed header in step with the crate
+fn main() {
+    println!("cargo:rerun-if-changed=include/android_xml_converter.h");
+    let header = fs::read_to_string("include/android_xml_converter.h")
+        .expect("include/android_xml_converter.h is readable");
+    let version = env!("CARGO_PKG_VERSION");
+    let expected = format!("#define ANDROID_XML_CONVERTER_VERSION \"{}\"", version);
+    assert!(
+        header.lines().any(|line| line == expected),
+        "include/android_xml_converter.h does not declare version {}",
+        version
+    );
+}
@@ -0,0 +1,79 @@
+/*
+ * C interface of android-xml-converter, converting between Android Binary XML
+ * (ABX) and text XML.
+ *
+ * Functions return 0 on success and -1 on failure; abx_last_error() then
+ * describes the failure of the last call made on the same thread.
+ */
+
+#ifndef ANDROID_XML_CONVERTER_H
+#define ANDROID_XML_CONVERTER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define ANDROID_XML_CONVERTER_VERSION_MAJOR 0
+#define ANDROID_XML_CONVERTER_VERSION_MINOR 2
+#define ANDROID_XML_CONVERTER_VERSION_PATCH 2
+#define ANDROID_XML_CONVERTER_VERSION "0.2.2"
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Version of the linked library, to compare with ANDROID_XML_CONVERTER_VERSION */
+const char *abx_version(void);
+
+/* Message of the last failed call on this thread, or NULL; valid until the next call */
+const char *abx_last_error(void);
+
+/* Converts the ABX file at input_path to XML at output_path */
+int abx_to_xml_file(const char *input_path, const char *output_path);
+
+/* Converts the XML file at input_path to ABX at output_path */
+int xml_to_abx_file(const char *input_path, const char *output_path);
+
+/*
+ * Converts len bytes of ABX to XML. On success *xml points to *xml_len bytes
+ * of UTF-8, not NUL-terminated, to be released with abx_free().
+ */
+int abx_to_xml_buffer(const uint8_t *data, size_t len, char **xml, size_t *xml_len);
+
+/*
+ * Converts len bytes of UTF-8 XML to ABX. On success *abx points to *abx_len
+ * bytes to be released with abx_free().
+ */
+int xml_to_abx_buffer(const char *xml, size_t len, uint8_t **abx, size_t *abx_len);
+
+/*
+ * Stream callbacks. A read callback fills up to len bytes of buf and returns
+ * how many, 0 at the end of the input or -1 on failure; a write callback
+ * consumes up to len bytes of buf and returns how many or -1 on failure.
+ */
+typedef ptrdiff_t (*abx_read_fn)(void *ctx, uint8_t *buf, size_t len);
+typedef ptrdiff_t (*abx_write_fn)(void *ctx, const uint8_t *buf, size_t len);
+
+/* Converts ABX pulled from read to XML pushed to write, without holding either document in memory */
+int abx_to_xml_stream(abx_read_fn read, void *read_ctx, abx_write_fn write, void *write_ctx);
+
+/* Converts XML pulled from read to ABX pushed to write */
+int xml_to_abx_stream(abx_read_fn read, void *read_ctx, abx_write_fn write, void *write_ctx);
+
+#ifndef _WIN32
+/*
+ * Converts between open file descriptors, e.g. STDIN_FILENO and
+ * STDOUT_FILENO, streaming like the command line tools. The descriptors stay
+ * open.
+ */
+int abx_to_xml_fd(int input_fd, int output_fd);
+int xml_to_abx_fd(int input_fd, int output_fd);
+#endif
+
+/* Releases a buffer returned by this library; ptr may be NULL */
+void abx_free(void *ptr, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* ANDROID_XML_CONVERTER_H */
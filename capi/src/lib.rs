@@ -0,0 +1,481 @@
+use converter::{AbxToXmlConverter, ConversionError, Result, XmlToAbxConverter};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ptr;
+
+// ============================================================================
+// Errors
+// ============================================================================
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Records the outcome of a call and turns it into the C status code
+fn status(result: Result<()>) -> c_int {
+    match result {
+        Ok(()) => {
+            LAST_ERROR.with(|last| *last.borrow_mut() = None);
+            0
+        }
+        Err(e) => {
+            // messages never contain NUL, but a lost message beats a panic
+            let message = CString::new(e.to_string()).unwrap_or_default();
+            LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+            -1
+        }
+    }
+}
+
+/// # Safety
+///
+/// `s` must be null or a NUL-terminated string.
+unsafe fn path<'a>(s: *const c_char, what: &str) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(ConversionError::ParseError(format!("{} is NULL", what)));
+    }
+    unsafe { CStr::from_ptr(s) }
+        .to_str()
+        .map_err(|_| ConversionError::ParseError(format!("{} is not valid UTF-8", what)))
+}
+
+/// # Safety
+///
+/// `data` must be null only if `len` is 0, and point to `len` readable bytes.
+unsafe fn input<'a>(data: *const u8, len: usize) -> Result<&'a [u8]> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if data.is_null() {
+        return Err(ConversionError::ParseError(
+            "Input buffer is NULL".to_string(),
+        ));
+    }
+    Ok(unsafe { std::slice::from_raw_parts(data, len) })
+}
+
+/// Hands a buffer to C; [`abx_free`] takes it back
+///
+/// # Safety
+///
+/// `out` and `out_len` must be valid for writes.
+unsafe fn output(bytes: Vec<u8>, out: *mut *mut u8, out_len: *mut usize) -> Result<()> {
+    if out.is_null() || out_len.is_null() {
+        return Err(ConversionError::ParseError(
+            "Output pointer is NULL".to_string(),
+        ));
+    }
+    let bytes = bytes.into_boxed_slice();
+    unsafe {
+        *out_len = bytes.len();
+        *out = Box::into_raw(bytes).cast::<u8>();
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Streams
+// ============================================================================
+
+/// Reads up to `len` bytes into `buf`; returns how many, 0 at the end of the
+/// input or -1 on failure
+pub type AbxReadFn = unsafe extern "C" fn(ctx: *mut c_void, buf: *mut u8, len: usize) -> isize;
+
+/// Writes up to `len` bytes from `buf`; returns how many or -1 on failure
+pub type AbxWriteFn = unsafe extern "C" fn(ctx: *mut c_void, buf: *const u8, len: usize) -> isize;
+
+/// Input pulled through a C callback
+struct CallbackReader {
+    read: AbxReadFn,
+    ctx: *mut c_void,
+}
+
+impl Read for CallbackReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = unsafe { (self.read)(self.ctx, buf.as_mut_ptr(), buf.len()) };
+        usize::try_from(read)
+            .ok()
+            .filter(|&read| read <= buf.len())
+            .ok_or_else(|| io::Error::other("read callback failed"))
+    }
+}
+
+/// Output pushed through a C callback
+struct CallbackWriter {
+    write: AbxWriteFn,
+    ctx: *mut c_void,
+}
+
+impl Write for CallbackWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = unsafe { (self.write)(self.ctx, buf.as_ptr(), buf.len()) };
+        usize::try_from(written)
+            .ok()
+            .filter(|&written| written <= buf.len())
+            .ok_or_else(|| io::Error::other("write callback failed"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn callbacks(
+    read: Option<AbxReadFn>,
+    read_ctx: *mut c_void,
+    write: Option<AbxWriteFn>,
+    write_ctx: *mut c_void,
+) -> Result<(CallbackReader, BufWriter<CallbackWriter>)> {
+    let (Some(read), Some(write)) = (read, write) else {
+        return Err(ConversionError::ParseError(
+            "Stream callback is NULL".to_string(),
+        ));
+    };
+    Ok((
+        CallbackReader {
+            read,
+            ctx: read_ctx,
+        },
+        BufWriter::new(CallbackWriter {
+            write,
+            ctx: write_ctx,
+        }),
+    ))
+}
+
+/// A descriptor of the caller as a file, left open when dropped
+#[cfg(unix)]
+fn borrowed_fd(fd: c_int, what: &str) -> Result<std::mem::ManuallyDrop<File>> {
+    use std::os::fd::FromRawFd;
+
+    if fd < 0 {
+        return Err(ConversionError::ParseError(format!(
+            "{} descriptor {} is invalid",
+            what, fd
+        )));
+    }
+    Ok(std::mem::ManuallyDrop::new(unsafe {
+        File::from_raw_fd(fd)
+    }))
+}
+
+// ============================================================================
+// C ABI
+// ============================================================================
+
+#[unsafe(no_mangle)]
+pub extern "C" fn abx_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn abx_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// # Safety
+///
+/// Both paths must be null or NUL-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn abx_to_xml_file(
+    input_path: *const c_char,
+    output_path: *const c_char,
+) -> c_int {
+    status((|| unsafe {
+        AbxToXmlConverter::convert_file(
+            path(input_path, "Input path")?,
+            path(output_path, "Output path")?,
+        )
+    })())
+}
+
+/// # Safety
+///
+/// Both paths must be null or NUL-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xml_to_abx_file(
+    input_path: *const c_char,
+    output_path: *const c_char,
+) -> c_int {
+    status((|| unsafe {
+        let input = path(input_path, "Input path")?;
+        let output = File::create(path(output_path, "Output path")?)?;
+        XmlToAbxConverter::convert_from_file(input, BufWriter::new(output))
+    })())
+}
+
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, `xml` and `xml_len` must be
+/// valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn abx_to_xml_buffer(
+    data: *const u8,
+    len: usize,
+    xml: *mut *mut c_char,
+    xml_len: *mut usize,
+) -> c_int {
+    status((|| unsafe {
+        let converted = AbxToXmlConverter::convert_bytes(input(data, len)?)?;
+        output(converted.into_bytes(), xml.cast(), xml_len)
+    })())
+}
+
+/// # Safety
+///
+/// `xml` must point to `len` readable bytes, `abx` and `abx_len` must be valid
+/// for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xml_to_abx_buffer(
+    xml: *const c_char,
+    len: usize,
+    abx: *mut *mut u8,
+    abx_len: *mut usize,
+) -> c_int {
+    status((|| unsafe {
+        let text = std::str::from_utf8(input(xml.cast(), len)?)?;
+        let mut converted = Vec::new();
+        XmlToAbxConverter::convert_from_string(text, &mut converted)?;
+        output(converted, abx, abx_len)
+    })())
+}
+
+/// # Safety
+///
+/// The callbacks must behave as [`AbxReadFn`] and [`AbxWriteFn`] describe for
+/// the contexts they are given.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn abx_to_xml_stream(
+    read: Option<AbxReadFn>,
+    read_ctx: *mut c_void,
+    write: Option<AbxWriteFn>,
+    write_ctx: *mut c_void,
+) -> c_int {
+    status((|| {
+        let (input, mut output) = callbacks(read, read_ctx, write, write_ctx)?;
+        AbxToXmlConverter::convert(BufReader::new(input), &mut output)?;
+        output.flush()?;
+        Ok(())
+    })())
+}
+
+/// # Safety
+///
+/// The callbacks must behave as [`AbxReadFn`] and [`AbxWriteFn`] describe for
+/// the contexts they are given.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xml_to_abx_stream(
+    read: Option<AbxReadFn>,
+    read_ctx: *mut c_void,
+    write: Option<AbxWriteFn>,
+    write_ctx: *mut c_void,
+) -> c_int {
+    status((|| {
+        let (input, mut output) = callbacks(read, read_ctx, write, write_ctx)?;
+        XmlToAbxConverter::convert_from_reader(BufReader::new(input), &mut output)?;
+        output.flush()?;
+        Ok(())
+    })())
+}
+
+/// # Safety
+///
+/// Both descriptors must be open for as long as the call runs; they are not
+/// closed.
+#[cfg(unix)]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn abx_to_xml_fd(input_fd: c_int, output_fd: c_int) -> c_int {
+    status((|| {
+        let input = borrowed_fd(input_fd, "Input")?;
+        let output = borrowed_fd(output_fd, "Output")?;
+        let mut output = BufWriter::new(&*output);
+        AbxToXmlConverter::convert(BufReader::new(&*input), &mut output)?;
+        output.flush()?;
+        Ok(())
+    })())
+}
+
+/// # Safety
+///
+/// Both descriptors must be open for as long as the call runs; they are not
+/// closed.
+#[cfg(unix)]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn xml_to_abx_fd(input_fd: c_int, output_fd: c_int) -> c_int {
+    status((|| {
+        let input = borrowed_fd(input_fd, "Input")?;
+        let output = borrowed_fd(output_fd, "Output")?;
+        let mut output = BufWriter::new(&*output);
+        XmlToAbxConverter::convert_from_reader(BufReader::new(&*input), &mut output)?;
+        output.flush()?;
+        Ok(())
+    })())
+}
+
+/// # Safety
+///
+/// `ptr` must be null or a buffer of `len` bytes returned by this library and
+/// not yet freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn abx_free(ptr: *mut c_void, len: usize) {
+    if !ptr.is_null() {
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(ptr.cast::<u8>(), len)) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Seek};
+
+    const XML: &str = r#"<map><int name="a" value="1" /><string name="b">hi</string></map>"#;
+
+    unsafe extern "C" fn read_cursor(ctx: *mut c_void, buf: *mut u8, len: usize) -> isize {
+        let cursor = unsafe { &mut *ctx.cast::<Cursor<Vec<u8>>>() };
+        let buf = unsafe { std::slice::from_raw_parts_mut(buf, len) };
+        cursor.read(buf).map_or(-1, |read| read as isize)
+    }
+
+    unsafe extern "C" fn write_vec(ctx: *mut c_void, buf: *const u8, len: usize) -> isize {
+        let output = unsafe { &mut *ctx.cast::<Vec<u8>>() };
+        output.extend_from_slice(unsafe { std::slice::from_raw_parts(buf, len) });
+        len as isize
+    }
+
+    unsafe extern "C" fn read_failure(_: *mut c_void, _: *mut u8, _: usize) -> isize {
+        -1
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(abx_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn abx() -> Vec<u8> {
+        let mut abx = Vec::new();
+        XmlToAbxConverter::convert_from_string(XML, &mut abx).unwrap();
+        abx
+    }
+
+    /// Runs a stream entry point from `input` to a buffer
+    fn stream(
+        convert: unsafe extern "C" fn(
+            Option<AbxReadFn>,
+            *mut c_void,
+            Option<AbxWriteFn>,
+            *mut c_void,
+        ) -> c_int,
+        input: Vec<u8>,
+    ) -> (c_int, Vec<u8>) {
+        let mut input = Cursor::new(input);
+        let mut output = Vec::new();
+        let status = unsafe {
+            convert(
+                Some(read_cursor),
+                (&raw mut input).cast(),
+                Some(write_vec),
+                (&raw mut output).cast(),
+            )
+        };
+        (status, output)
+    }
+
+    #[test]
+    fn streams_convert_both_ways() {
+        let (status, converted) = stream(xml_to_abx_stream, XML.as_bytes().to_vec());
+        assert_eq!(status, 0);
+        assert_eq!(converted, abx());
+        assert!(abx_last_error().is_null());
+
+        let (status, xml) = stream(abx_to_xml_stream, converted);
+        assert_eq!(status, 0);
+        assert!(
+            String::from_utf8(xml).unwrap().ends_with(
+                r#"<map><int name="a" value="1"></int><string name="b">hi</string></map>"#
+            )
+        );
+    }
+
+    #[test]
+    fn streams_report_failures() {
+        let (status, _) = stream(abx_to_xml_stream, b"<map />".to_vec());
+        assert_eq!(status, -1);
+        assert!(
+            last_error().starts_with("Invalid ABX file format"),
+            "{}",
+            last_error()
+        );
+
+        let mut output: Vec<u8> = Vec::new();
+        let status = unsafe {
+            xml_to_abx_stream(
+                Some(read_failure),
+                ptr::null_mut(),
+                Some(write_vec),
+                (&raw mut output).cast(),
+            )
+        };
+        assert_eq!(status, -1);
+        assert_eq!(
+            last_error(),
+            "XML parsing failed: I/O error: read callback failed"
+        );
+
+        let status = unsafe { xml_to_abx_stream(None, ptr::null_mut(), None, ptr::null_mut()) };
+        assert_eq!(status, -1);
+        assert_eq!(last_error(), "Parse error: Stream callback is NULL");
+    }
+
+    /// A new file opened for reading and writing, already unlinked
+    #[cfg(unix)]
+    fn scratch_file(name: &str) -> File {
+        let path = std::env::temp_dir().join(format!("abx-capi-{}-{}", std::process::id(), name));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+        file
+    }
+
+    /// Everything written to `file` so far
+    #[cfg(unix)]
+    fn contents(file: &mut File) -> Vec<u8> {
+        let mut contents = Vec::new();
+        file.rewind().unwrap();
+        file.read_to_end(&mut contents).unwrap();
+        contents
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn descriptors_convert_and_stay_open() {
+        use std::os::fd::AsRawFd;
+
+        let mut input = scratch_file("in.abx");
+        input.write_all(&abx()).unwrap();
+        input.rewind().unwrap();
+        let mut xml = scratch_file("out.xml");
+        let status = unsafe { abx_to_xml_fd(input.as_raw_fd(), xml.as_raw_fd()) };
+        assert_eq!(status, 0, "{}", last_error());
+        // the descriptors are still the caller's to use
+        let text = String::from_utf8(contents(&mut xml)).unwrap();
+        assert!(text.ends_with(r#"<string name="b">hi</string></map>"#));
+
+        xml.rewind().unwrap();
+        let mut output = scratch_file("out.abx");
+        let status = unsafe { xml_to_abx_fd(xml.as_raw_fd(), output.as_raw_fd()) };
+        assert_eq!(status, 0, "{}", last_error());
+        assert_eq!(contents(&mut output), abx());
+
+        let status = unsafe { abx_to_xml_fd(-1, output.as_raw_fd()) };
+        assert_eq!(status, -1);
+        assert_eq!(last_error(), "Parse error: Input descriptor -1 is invalid");
+    }
+}
@@ -0,0 +1,250 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, Ident, LitStr, parse_macro_input};
+
+/// Names accepted by `type = "..."`, the same as `AttributeValue::TYPE_NAMES`
+const TYPE_NAMES: &[&str] = &[
+    "string",
+    "interned",
+    "int",
+    "int_hex",
+    "long",
+    "long_hex",
+    "float",
+    "double",
+    "boolean",
+    "bytes_hex",
+    "bytes_base64",
+];
+
+/// Reads a struct from an `android_xml_converter::dom::Element`, see the
+/// `mapping` module of `android_xml_converter`
+#[proc_macro_derive(FromAbx, attributes(abx))]
+pub fn derive_from_abx(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input, from_abx)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Writes a struct as an element of an `android_xml_converter::dom::AbxDocument`,
+/// see the `mapping` module of `android_xml_converter`
+#[proc_macro_derive(ToAbx, attributes(abx))]
+pub fn derive_to_abx(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input, to_abx)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+// ============================================================================
+// Attributes
+// ============================================================================
+
+enum Kind {
+    /// `#[abx(attr = "name", type = "int_hex")]`, the default
+    Attribute {
+        name: String,
+        type_name: Option<String>,
+    },
+    /// `#[abx(child)]`
+    Child,
+    /// `#[abx(text)]`
+    Text,
+    /// `#[abx(skip)]`
+    Skip,
+}
+
+struct Field {
+    ident: Ident,
+    kind: Kind,
+}
+
+fn element_name(input: &DeriveInput) -> syn::Result<String> {
+    let mut name = input.ident.to_string();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("abx"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("element") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else {
+                Err(meta.error("expected `element = \"...\"`"))
+            }
+        })?;
+    }
+    Ok(name)
+}
+
+fn fields(input: &DeriveInput) -> syn::Result<Vec<Field>> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "only structs can be mapped to ABX elements",
+        ));
+    };
+    let Fields::Named(named) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "only structs with named fields can be mapped to ABX elements",
+        ));
+    };
+
+    let mut fields = Vec::new();
+    for field in &named.named {
+        let ident = field.ident.clone().expect("named field");
+        let mut attribute = None;
+        let mut type_name = None;
+        let mut kind = None;
+
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("abx"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("attr") {
+                    attribute = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("type") {
+                    let value = meta.value()?.parse::<LitStr>()?;
+                    if !TYPE_NAMES.contains(&value.value().as_str()) {
+                        return Err(syn::Error::new_spanned(
+                            &value,
+                            format!(
+                                "unknown ABX type, expected one of: {}",
+                                TYPE_NAMES.join(", ")
+                            ),
+                        ));
+                    }
+                    type_name = Some(value.value());
+                } else if meta.path.is_ident("child") {
+                    kind = Some(Kind::Child);
+                } else if meta.path.is_ident("text") {
+                    kind = Some(Kind::Text);
+                } else if meta.path.is_ident("skip") {
+                    kind = Some(Kind::Skip);
+                } else {
+                    return Err(meta.error(
+                        "expected `attr = \"...\"`, `type = \"...\"`, `child`, `text` or `skip`",
+                    ));
+                }
+                Ok(())
+            })?;
+        }
+
+        let kind = match kind {
+            Some(_) if attribute.is_some() || type_name.is_some() => {
+                return Err(syn::Error::new_spanned(
+                    &ident,
+                    "`attr` and `type` only apply to attribute fields",
+                ));
+            }
+            Some(kind) => kind,
+            None => Kind::Attribute {
+                name: attribute.unwrap_or_else(|| ident.to_string()),
+                type_name,
+            },
+        };
+        fields.push(Field { ident, kind });
+    }
+    Ok(fields)
+}
+
+// ============================================================================
+// Expansion
+// ============================================================================
+
+fn expand(
+    input: &DeriveInput,
+    generate: fn(&DeriveInput, &str, &[Field]) -> proc_macro2::TokenStream,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let element = element_name(input)?;
+    let fields = fields(input)?;
+    Ok(generate(input, &element, &fields))
+}
+
+fn type_name_tokens(type_name: &Option<String>) -> proc_macro2::TokenStream {
+    match type_name {
+        Some(type_name) => quote!(::core::option::Option::Some(#type_name)),
+        None => quote!(::core::option::Option::None),
+    }
+}
+
+fn from_abx(input: &DeriveInput, element: &str, fields: &[Field]) -> proc_macro2::TokenStream {
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let mapping = quote!(::android_xml_converter::mapping);
+    let source = Ident::new("element", Span::mixed_site());
+
+    let values = fields.iter().map(|field| {
+        let member = &field.ident;
+        let value = match &field.kind {
+            Kind::Attribute { name, type_name } => {
+                let type_name = type_name_tokens(type_name);
+                quote!(#mapping::read_attribute(#source, #name, #type_name)?)
+            }
+            Kind::Child => quote!(#mapping::FromAbxChildren::from_children(#source)?),
+            Kind::Text => quote!(::core::convert::From::from(#source.text())),
+            Kind::Skip => quote!(::core::default::Default::default()),
+        };
+        quote!(#member: #value)
+    });
+
+    quote! {
+        impl #impl_generics #mapping::FromAbx for #ident #type_generics #where_clause {
+            const ELEMENT: &'static str = #element;
+
+            fn from_element(
+                #source: ::android_xml_converter::dom::Element<'_>,
+            ) -> ::android_xml_converter::Result<Self> {
+                ::core::result::Result::Ok(Self { #(#values,)* })
+            }
+        }
+    }
+}
+
+fn to_abx(input: &DeriveInput, element: &str, fields: &[Field]) -> proc_macro2::TokenStream {
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let mapping = quote!(::android_xml_converter::mapping);
+    let document = Ident::new("document", Span::mixed_site());
+    let target = Ident::new("element", Span::mixed_site());
+
+    let writes = fields.iter().filter_map(|field| {
+        let member = &field.ident;
+        match &field.kind {
+            Kind::Attribute { name, type_name } => {
+                let type_name = type_name_tokens(type_name);
+                Some(quote! {
+                    #mapping::write_attribute(#document, #target, #name, &self.#member, #type_name)?;
+                })
+            }
+            Kind::Child => Some(quote! {
+                #mapping::ToAbxChildren::write_children(&self.#member, #document, #target)?;
+            }),
+            Kind::Text => Some(quote! {
+                #mapping::write_text(#document, #target, &self.#member);
+            }),
+            Kind::Skip => None,
+        }
+    });
+
+    quote! {
+        impl #impl_generics #mapping::ToAbx for #ident #type_generics #where_clause {
+            const ELEMENT: &'static str = #element;
+
+            fn write_element(
+                &self,
+                #document: &mut ::android_xml_converter::dom::AbxDocument,
+                #target: ::android_xml_converter::dom::ElementId,
+            ) -> ::android_xml_converter::Result<()> {
+                #(#writes)*
+                ::core::result::Result::Ok(())
+            }
+        }
+    }
+}
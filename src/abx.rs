@@ -0,0 +1,1677 @@
+use android_xml_converter::archive::{
+    EntryConversion, EntryFormat, convert_android_backup, convert_tar, is_android_backup,
+    list_android_backup, list_tar,
+};
+use android_xml_converter::atomic::AtomicFile;
+use android_xml_converter::audit::AuditReport;
+use android_xml_converter::batch::{self, BatchSummary, Manifest};
+use android_xml_converter::codec::{self, AbxCodec, DocumentCodec};
+use android_xml_converter::compliance::{ComplianceReport, DeviceProfile};
+use android_xml_converter::diagnostics::Diagnostics;
+use android_xml_converter::dictionary::Dictionary;
+use android_xml_converter::flat::{self, FlatLayout};
+use android_xml_converter::forensics::ForensicReport;
+use android_xml_converter::journal::{Journal, JournalEntry};
+use android_xml_converter::pipeline::{
+    AbxSink, DropWhitespace, Pipeline, ReinternValues, Rename, Step, TypeHints,
+};
+use android_xml_converter::query::root_element;
+use android_xml_converter::roundtrip::{RawDocument, Token, TokenReader};
+use android_xml_converter::schema::TypeSchema;
+use android_xml_converter::xpath::{self, Selection, XPath};
+use android_xml_converter::*;
+use smol_str::SmolStr;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+// ============================================================================
+// Daemon
+// ============================================================================
+
+#[cfg(unix)]
+mod daemon {
+    use android_xml_converter::*;
+    use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+    use std::fs;
+    use std::io::{self, BufReader, BufWriter, Cursor, Read, Write};
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    /// Request mode byte: convert the ABX payload to XML
+    pub const MODE_ABX_TO_XML: u8 = b'a';
+    /// Request mode byte: convert the XML payload to ABX
+    pub const MODE_XML_TO_ABX: u8 = b'x';
+
+    pub const STATUS_OK: u8 = 0;
+    pub const STATUS_ERROR: u8 = 1;
+
+    /// Upper bound for a single request payload, so a bad length cannot exhaust memory
+    const MAX_PAYLOAD_SIZE: u32 = 256 * 1024 * 1024;
+
+    /// Connections served at the same time; more are answered with an error
+    /// and closed
+    const MAX_CONNECTIONS: usize = 32;
+
+    /// Payload and output bytes buffered by all connections together; a
+    /// request that would go over it is answered with an error and its
+    /// connection closed
+    const MAX_BUFFERED_BYTES: usize = 512 * 1024 * 1024;
+
+    /// Limits of every conversion, so a small payload cannot expand without
+    /// bound: a three-byte string reference re-emits up to 64 KiB of XML
+    fn limits() -> Limits {
+        Limits::new()
+            .max_depth(1024)
+            .max_string_length(u16::MAX as usize)
+            .max_output_bytes(MAX_PAYLOAD_SIZE as u64)
+    }
+
+    /// Accepts connections forever, serving each one on its own thread.
+    ///
+    /// A request is a mode byte, a big-endian u32 payload length and the payload.
+    /// Every request is answered with a status byte, a big-endian u32 length and
+    /// either the converted document or a UTF-8 error message. A connection may
+    /// carry any number of requests and ends when the client closes it.
+    ///
+    /// A socket file left behind by a daemon that was killed is replaced; one
+    /// that still accepts connections is not. The socket file is removed again
+    /// on SIGINT and SIGTERM.
+    pub fn serve(socket_path: &str) -> Result<()> {
+        remove_stale_socket(socket_path)?;
+        let listener = UnixListener::bind(socket_path)?;
+        let _socket = SocketFile(socket_path.to_string());
+        let path = socket_path.to_string();
+        ctrlc::set_handler(move || {
+            let _ = fs::remove_file(&path);
+            std::process::exit(0);
+        })
+        .map_err(io::Error::other)?;
+        eprintln!("Listening on {}", socket_path);
+
+        let active = Arc::new(AtomicUsize::new(0));
+        let buffered = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            match stream {
+                Ok(mut stream) => {
+                    if active.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                        active.fetch_sub(1, Ordering::SeqCst);
+                        let message = format!("More than {} connections", MAX_CONNECTIONS);
+                        let _ = respond(&mut stream, STATUS_ERROR, message.as_bytes());
+                        continue;
+                    }
+                    let slot = Slot(Arc::clone(&active));
+                    let buffered = Arc::clone(&buffered);
+                    thread::spawn(move || {
+                        let _slot = slot;
+                        if let Err(e) = handle_connection(stream, &buffered) {
+                            eprintln!("Warning: Connection error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => eprintln!("Warning: Failed to accept connection: {}", e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the socket file at `socket_path` if nothing accepts connections
+    /// on it any more. Fails if a daemon is listening there or the path is not
+    /// a socket.
+    fn remove_stale_socket(socket_path: &str) -> Result<()> {
+        match fs::symlink_metadata(socket_path) {
+            Ok(metadata) if metadata.file_type().is_socket() => {}
+            // bind reports anything else in the way
+            _ => return Ok(()),
+        }
+        if UnixStream::connect(socket_path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("A daemon is already listening on {}", socket_path),
+            )
+            .into());
+        }
+        fs::remove_file(socket_path)?;
+        Ok(())
+    }
+
+    /// The socket file, removed when [`serve`] returns
+    struct SocketFile(String);
+
+    impl Drop for SocketFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// One of the [`MAX_CONNECTIONS`], given back when the connection ends
+    struct Slot(Arc<AtomicUsize>);
+
+    impl Drop for Slot {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// A share of [`MAX_BUFFERED_BYTES`], given back when the request is answered
+    struct Reservation<'a> {
+        buffered: &'a AtomicUsize,
+        bytes: usize,
+    }
+
+    impl<'a> Reservation<'a> {
+        fn new(buffered: &'a AtomicUsize, bytes: usize) -> Option<Self> {
+            reserve(buffered, bytes).then_some(Self { buffered, bytes })
+        }
+
+        /// Takes `bytes` more, if the budget has room for them
+        fn grow(&mut self, bytes: usize) -> bool {
+            let grown = reserve(self.buffered, bytes);
+            if grown {
+                self.bytes += bytes;
+            }
+            grown
+        }
+    }
+
+    fn reserve(buffered: &AtomicUsize, bytes: usize) -> bool {
+        buffered
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(bytes)
+                    .filter(|&total| total <= MAX_BUFFERED_BYTES)
+            })
+            .is_ok()
+    }
+
+    impl Drop for Reservation<'_> {
+        fn drop(&mut self) {
+            self.buffered.fetch_sub(self.bytes, Ordering::SeqCst);
+        }
+    }
+
+    fn handle_connection(stream: UnixStream, buffered: &AtomicUsize) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+
+        loop {
+            let mode = match reader.read_u8() {
+                Ok(mode) => mode,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            let length = reader.read_u32::<BigEndian>()?;
+            if length > MAX_PAYLOAD_SIZE {
+                respond(
+                    &mut writer,
+                    STATUS_ERROR,
+                    format!("Payload of {} bytes exceeds limit", length).as_bytes(),
+                )?;
+                return Ok(());
+            }
+            let Some(mut reservation) = Reservation::new(buffered, length as usize) else {
+                respond(
+                    &mut writer,
+                    STATUS_ERROR,
+                    b"Too many payload bytes buffered, try again later",
+                )?;
+                return Ok(());
+            };
+
+            // grows with the data that arrives, not with the length announced
+            let mut payload = Vec::new();
+            (&mut reader)
+                .take(length as u64)
+                .read_to_end(&mut payload)?;
+            if payload.len() < length as usize {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+
+            let mut output = Output {
+                bytes: Vec::new(),
+                reservation: &mut reservation,
+            };
+            match convert(mode, &payload, &mut output) {
+                Ok(()) => respond(&mut writer, STATUS_OK, &output.bytes)?,
+                Err(e) => respond(&mut writer, STATUS_ERROR, e.to_string().as_bytes())?,
+            }
+        }
+    }
+
+    /// The converted document, counted against [`MAX_BUFFERED_BYTES`] as it grows
+    struct Output<'a, 'b> {
+        bytes: Vec<u8>,
+        reservation: &'b mut Reservation<'a>,
+    }
+
+    impl Write for Output<'_, '_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !self.reservation.grow(buf.len()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::OutOfMemory,
+                    "Too many output bytes buffered, try again later",
+                ));
+            }
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn convert(mode: u8, payload: &[u8], output: &mut Output) -> Result<()> {
+        let options = Options::new().limits(limits());
+        match mode {
+            MODE_ABX_TO_XML => {
+                AbxToXmlConverter::convert_with_options(Cursor::new(payload), output, &options)?
+            }
+            MODE_XML_TO_ABX => {
+                let xml = std::str::from_utf8(payload)?;
+                XmlToAbxConverter::convert_from_string_with(xml, output, &options)?;
+            }
+            _ => {
+                return Err(ConversionError::ParseError(format!(
+                    "Unknown request mode: {:#04x}",
+                    mode
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn respond<W: Write>(writer: &mut W, status: u8, body: &[u8]) -> Result<()> {
+        let length = u32::try_from(body.len()).map_err(|_| {
+            ConversionError::ParseError(format!("Response of {} bytes is too long", body.len()))
+        })?;
+        writer.write_u8(status)?;
+        writer.write_u32::<BigEndian>(length)?;
+        writer.write_all(body)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn run_daemon(socket_path: &str) -> Result<()> {
+    daemon::serve(socket_path)
+}
+
+#[cfg(not(unix))]
+fn run_daemon(_socket_path: &str) -> Result<()> {
+    Err(ConversionError::ParseError(
+        "daemon mode requires unix domain sockets".to_string(),
+    ))
+}
+
+// ============================================================================
+// Find
+// ============================================================================
+
+/// Calls `on_file` for every regular file under `dir` that is an ABX document,
+/// returning how many there were.
+///
+/// Symlinks are not followed. Unreadable entries are reported and skipped.
+fn walk_abx_files(dir: &Path, on_file: &mut dyn FnMut(&Path) -> Result<()>) -> Result<usize> {
+    let mut matches = 0;
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Warning: {}: {}", dir.display(), e);
+            return Ok(0);
+        }
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            matches += walk_abx_files(&path, on_file)?;
+        } else if file_type.is_file() {
+            match is_abx_file(&path) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    eprintln!("Warning: {}: {}", path.display(), e);
+                    continue;
+                }
+            }
+
+            matches += 1;
+            on_file(&path)?;
+        }
+    }
+
+    Ok(matches)
+}
+
+/// Recursively prints every regular file under `dir` that is an ABX document
+fn find_abx_files<W: Write>(dir: &Path, with_root: bool, output: &mut W) -> Result<usize> {
+    walk_abx_files(dir, &mut |path| {
+        if with_root {
+            let root = root_element(BufReader::new(File::open(path)?))
+                .ok()
+                .flatten()
+                .unwrap_or_default();
+            writeln!(output, "{}\t{}", path.display(), root)?;
+        } else {
+            writeln!(output, "{}", path.display())?;
+        }
+        Ok(())
+    })
+}
+
+// ============================================================================
+// Tree
+// ============================================================================
+
+/// Prints the elements of an ABX document as an indented outline with their
+/// attribute counts, like `tree` prints directories, down to `max_depth` levels
+/// below the root
+fn write_tree<W: Write>(abx: &[u8], max_depth: Option<usize>, output: &mut W) -> Result<()> {
+    // (depth, name, attribute count) in document order
+    let mut elements: Vec<(usize, SmolStr, usize)> = Vec::new();
+    let mut open = Vec::new();
+    let mut reader = TokenReader::new(abx)?;
+    while let Some(token) = reader.next_token()? {
+        match token {
+            Token::StartTag(name) => {
+                open.push(elements.len());
+                elements.push((open.len() - 1, name, 0));
+            }
+            Token::EndTag(_) => {
+                open.pop();
+            }
+            Token::Attribute { .. } => {
+                if let Some(&element) = open.last() {
+                    elements[element].2 += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    let count = elements.len();
+    if let Some(max_depth) = max_depth {
+        elements.retain(|(depth, _, _)| *depth <= max_depth);
+    }
+
+    // whether each element is the last of its siblings, found back to front
+    let mut last = vec![false; elements.len()];
+    let mut later_sibling: Vec<bool> = Vec::new();
+    for (index, (depth, _, _)) in elements.iter().enumerate().rev() {
+        later_sibling.resize(depth + 1, false);
+        last[index] = !later_sibling[*depth];
+        later_sibling[*depth] = true;
+    }
+
+    // per ancestor level below the root, whether its subtree continues
+    let mut guides: Vec<bool> = Vec::new();
+    for ((depth, name, attributes), last) in elements.iter().zip(last) {
+        let mut line = String::new();
+        if *depth > 0 {
+            guides.truncate(depth - 1);
+            for &more in &guides {
+                line.push_str(if more { "│   " } else { "    " });
+            }
+            line.push_str(if last { "└── " } else { "├── " });
+            guides.push(!last);
+        }
+        line.push_str(name);
+        match attributes {
+            0 => {}
+            1 => line.push_str(" (1 attribute)"),
+            n => line.push_str(&format!(" ({} attributes)", n)),
+        }
+        writeln!(output, "{}", line)?;
+    }
+    writeln!(
+        output,
+        "\n{} element{}",
+        count,
+        if count == 1 { "" } else { "s" }
+    )?;
+    output.flush()?;
+    Ok(())
+}
+
+// ============================================================================
+// CLI
+// ============================================================================
+
+fn print_help(program_name: &str) {
+    eprintln!("Usage: {} <command> [OPTIONS]", program_name);
+    eprintln!();
+    eprintln!("Multi-purpose Android Binary XML (ABX) tool.");
+    eprintln!();
+    eprintln!("Commands:");
+    eprintln!("  daemon --socket PATH  Serve conversion requests on a unix socket");
+    eprintln!("  find [--root] DIR     List ABX files under DIR by magic, not extension");
+    eprintln!("                        --root also prints each file's root element");
+    eprintln!("  query XPATH [INPUT]   Print the elements or attribute values an XPath subset");
+    eprintln!("                        (a/b, a//b, *, [@attr='v'], /@attr) selects");
+    eprintln!("  grep XPATH PATH...    Search ABX files and directories with an XPath subset,");
+    eprintln!("                        printing file, element location and match");
+    eprintln!("  count XPATH [INPUT]   Print the number of elements an XPath such as package or");
+    eprintln!("                        package[@name='com.foo'] selects (exit status 1 if none)");
+    eprintln!("  summary [INPUT]       Print per-tag counts and the attribute names in use");
+    eprintln!("  verify-pool [INPUT]   Report duplicate, unused and invalid intern pool entries");
+    eprintln!("                        and a missing END_DOCUMENT (exit status 1 on errors)");
+    eprintln!("  archive [--list] [--to-abx] INPUT [OUTPUT]");
+    eprintln!("                        Convert the ABX entries of a tar stream or `adb backup`");
+    eprintln!("                        (.ab) archive to XML, or XML entries to ABX with");
+    eprintln!("                        --to-abx; --list only prints the documents found");
+    eprintln!("  batch -o DIR [--manifest FILE [--resume]] [-k] [-j N] [--dictionary FILE]");
+    eprintln!("        INPUT...        Convert files or directories in either direction");
+    eprintln!("                        and record their checksums in a manifest; stops at");
+    eprintln!("                        the first failure unless -k/--keep-going is given.");
+    eprintln!("                        -j/--jobs converts N files at a time, all sharing");
+    eprintln!("                        one read-only intern pool seeded from --dictionary");
+    eprintln!("  flat [--unflatten [--root NAME] [--element NAME]] INPUT [OUTPUT]");
+    eprintln!("                        Dump settings-style files as name=value lines, or");
+    eprintln!("                        rebuild ABX from such lines");
+    eprintln!("  convert [--from FORMAT] [--to FORMAT] INPUT [OUTPUT]");
+    eprintln!("                        Convert between any two formats of `capabilities`");
+    eprintln!("                        through XML, including ones an embedding application");
+    eprintln!("                        registered; by default a detected format becomes XML");
+    eprintln!("                        and XML becomes ABX");
+    eprintln!("  fmt [--strip-whitespace] [--reintern] [--type NAME=TYPE]... INPUT [OUTPUT]");
+    eprintln!("                        Rewrite an ABX file directly as ABX with a fresh intern");
+    eprintln!("                        pool, optionally dropping whitespace-only text,");
+    eprintln!("                        re-deciding value interning and re-typing string");
+    eprintln!("                        attributes named NAME (e.g. userId=int)");
+    eprintln!("  rename [--attr OLD=NEW]... [--element OLD=NEW]... [--journal] PATH...");
+    eprintln!("                        Rename attributes and elements in ABX files or all ABX");
+    eprintln!("                        files under directories, in place, keeping value types");
+    eprintln!("                        and interning; --journal appends each rename to the");
+    eprintln!("                        file's .journal as JSON lines, with a timestamp");
+    eprintln!("  tree [-L DEPTH] INPUT  Print the elements of an ABX or XML file as an");
+    eprintln!("                        indented outline with attribute counts, down to");
+    eprintln!("                        DEPTH levels below the root");
+    eprintln!("  extract XPATH INPUT [-o OUTPUT]");
+    eprintln!("                        Copy the one element XPATH selects, with everything");
+    eprintln!("                        inside it, into a standalone ABX document with a");
+    eprintln!("                        minimal intern pool");
+    eprintln!("  replace XPATH --with FRAGMENT [--journal] INPUT [-o OUTPUT]");
+    eprintln!("                        Replace every element XPATH selects by the root of an");
+    eprintln!("                        XML fragment, in place unless OUTPUT is given; strings");
+    eprintln!("                        already in the intern pool keep their positions;");
+    eprintln!("                        --journal appends each old and new element to");
+    eprintln!("                        OUTPUT.journal as JSON lines");
+    eprintln!("  forensics INPUT       Report traces of hand edits: pool strings never");
+    eprintln!("                        referenced, trailing data, nonstandard interning");
+    eprintln!("                        order and impossible timestamps (exit status 1)");
+    eprintln!("  learn-types INPUT [-o OUTPUT]");
+    eprintln!("                        Record the ABX type of every element/attribute pair");
+    eprintln!("                        as a schema for xml2abx --schema");
+    eprintln!("  audit DIR             Check that every ABX and XML file under DIR decodes");
+    eprintln!("                        and that packages, settings and shared preferences");
+    eprintln!("                        hold their invariants (exit status 1 on errors)");
+    eprintln!("  compare-profile DUMP_DIR PROFILE");
+    eprintln!("                        Check files of a device dump against the expected");
+    eprintln!("                        values of a TOML profile, pass/fail per check (exit");
+    eprintln!("                        status 1 on failures)");
+    eprintln!("  capabilities          Print the protocol versions, formats, token and");
+    eprintln!("                        attribute types and features of this build");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  -h, --help            Show this help message");
+}
+
+fn daemon_command(args: &[String]) -> Result<()> {
+    let mut socket_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--socket" {
+            let path = args
+                .next()
+                .ok_or_else(|| ConversionError::ParseError(format!("Missing value for {}", arg)))?;
+            socket_path = Some(path.as_str());
+        } else {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                arg
+            )));
+        }
+    }
+
+    let socket_path = socket_path.ok_or_else(|| {
+        ConversionError::ParseError("Missing required option: --socket PATH".to_string())
+    })?;
+    run_daemon(socket_path)
+}
+
+fn find_command(args: &[String]) -> Result<()> {
+    let mut with_root = false;
+    let mut dirs = Vec::new();
+
+    for arg in args {
+        if arg == "--root" {
+            with_root = true;
+        } else {
+            dirs.push(Path::new(arg));
+        }
+    }
+
+    if dirs.is_empty() {
+        return Err(ConversionError::ParseError(
+            "Missing required argument: DIR".to_string(),
+        ));
+    }
+
+    let mut output = BufWriter::new(io::stdout().lock());
+    let mut matches = 0;
+    for dir in dirs {
+        matches += find_abx_files(dir, with_root, &mut output)?;
+    }
+    output.flush()?;
+
+    if matches == 0 {
+        // like grep, signal "nothing found" to scripts through the exit status
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn query_command(args: &[String]) -> Result<()> {
+    let (path, input_path) = match args {
+        [path] => (path, "-"),
+        [path, input] => (path, input.as_str()),
+        [] => {
+            return Err(ConversionError::ParseError(
+                "Missing required argument: XPATH".to_string(),
+            ));
+        }
+        [_, _, extra, ..] => {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                extra
+            )));
+        }
+    };
+
+    let path = XPath::parse(path)?;
+    let matches =
+        path.write_matches(open_input(input_path)?, BufWriter::new(io::stdout().lock()))?;
+    if matches == 0 {
+        // like grep, signal "nothing found" to scripts through the exit status
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Opens a file or `-` for stdin, either of them possibly gzipped
+fn open_input(input_path: &str) -> Result<Box<dyn Read>> {
+    Ok(if input_path == "-" {
+        decompress_if_gzip(io::stdin().lock())?
+    } else {
+        decompress_if_gzip(BufReader::new(File::open(input_path)?))?
+    })
+}
+
+fn count_command(args: &[String]) -> Result<()> {
+    let (path, input_path) = match args {
+        [path] => (path, "-"),
+        [path, input] => (path, input.as_str()),
+        [] => {
+            return Err(ConversionError::ParseError(
+                "Missing required argument: ELEMENT".to_string(),
+            ));
+        }
+        [_, _, extra, ..] => {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                extra
+            )));
+        }
+    };
+
+    let count = AbxToXmlConverter::count_elements(open_input(input_path)?, path)?;
+    println!("{}", count);
+    if count == 0 {
+        // like grep, signal "nothing found" to scripts through the exit status
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn summary_command(args: &[String]) -> Result<()> {
+    let input_path = match args {
+        [] => "-",
+        [input] => input.as_str(),
+        [_, extra, ..] => {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                extra
+            )));
+        }
+    };
+
+    AbxToXmlConverter::summarize(open_input(input_path)?)?.write_to(io::stdout().lock())
+}
+
+fn verify_pool_command(args: &[String]) -> Result<()> {
+    let input_path = match args {
+        [] => "-",
+        [input] => input.as_str(),
+        [_, extra, ..] => {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                extra
+            )));
+        }
+    };
+
+    let report = AbxToXmlConverter::verify_pool(open_input(input_path)?)?;
+    report.write_to(io::stdout().lock())?;
+    if report.has_errors() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn grep_command(args: &[String]) -> Result<()> {
+    let Some((path, inputs)) = args.split_first() else {
+        return Err(ConversionError::ParseError(
+            "Missing required argument: XPATH".to_string(),
+        ));
+    };
+    if inputs.is_empty() {
+        return Err(ConversionError::ParseError(
+            "Missing required argument: PATH".to_string(),
+        ));
+    }
+
+    let path = XPath::parse(path)?;
+    let mut output = BufWriter::new(io::stdout().lock());
+    let mut matches = 0;
+
+    let mut search = |file: &Path| -> Result<()> {
+        let reader = BufReader::new(File::open(file)?);
+        let result = path.scan(reader, |stack, selection| {
+            matches += 1;
+            let location = xpath::element_location(stack);
+            match selection {
+                Selection::Attribute(name, value) => writeln!(
+                    output,
+                    "{}:{}/@{}: {}",
+                    file.display(),
+                    location,
+                    name,
+                    value
+                )?,
+                Selection::Element(_) => {
+                    writeln!(output, "{}:{}: {}", file.display(), location, selection)?
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            eprintln!("Warning: {}: {}", file.display(), e);
+        }
+        Ok(())
+    };
+
+    for input in inputs {
+        let input = Path::new(input);
+        if input.is_dir() {
+            walk_abx_files(input, &mut search)?;
+        } else {
+            search(input)?;
+        }
+    }
+    output.flush()?;
+
+    if matches == 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn archive_command(args: &[String]) -> Result<()> {
+    let mut list = false;
+    let mut conversion = EntryConversion::AbxToXml;
+    let mut paths = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "--list" => list = true,
+            "--to-abx" => conversion = EntryConversion::XmlToAbx,
+            _ => paths.push(arg.as_str()),
+        }
+    }
+
+    let (input_path, output_path) = match paths.as_slice() {
+        [input] => (*input, "-"),
+        [input, output] => (*input, *output),
+        [] => {
+            return Err(ConversionError::ParseError(
+                "Missing required argument: INPUT".to_string(),
+            ));
+        }
+        [_, _, extra, ..] => {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                extra
+            )));
+        }
+    };
+
+    let mut reader: Box<dyn BufRead> = if input_path == "-" {
+        Box::new(BufReader::new(io::stdin().lock()))
+    } else {
+        Box::new(BufReader::new(File::open(input_path)?))
+    };
+    let backup = is_android_backup(reader.fill_buf()?);
+
+    if list {
+        let entries = if backup {
+            list_android_backup(reader)?
+        } else {
+            list_tar(reader)?
+        };
+        let mut output = BufWriter::new(io::stdout().lock());
+        for entry in entries {
+            let format = match entry.format {
+                EntryFormat::Abx => "abx",
+                EntryFormat::Xml => "xml",
+            };
+            writeln!(output, "{}\t{}\t{}", format, entry.size, entry.path)?;
+        }
+        output.flush()?;
+        return Ok(());
+    }
+
+    let writer: Box<dyn Write> = if output_path == "-" {
+        Box::new(BufWriter::new(io::stdout().lock()))
+    } else {
+        Box::new(BufWriter::new(File::create(output_path)?))
+    };
+    let options = Options::default();
+    let converted = if backup {
+        convert_android_backup(reader, writer, conversion, &options)?
+    } else {
+        convert_tar(reader, writer, conversion, &options)?
+    };
+    eprintln!("Converted {} entries", converted);
+    Ok(())
+}
+
+fn batch_command(args: &[String]) -> Result<()> {
+    let mut out_dir = None;
+    let mut manifest_path = None;
+    let mut resume = false;
+    let mut keep_going = false;
+    let mut jobs = 1;
+    let mut dictionary = None;
+    let mut inputs = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--out-dir" | "--manifest" | "--dictionary" => {
+                let value = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                match arg.as_str() {
+                    "--manifest" => manifest_path = Some(PathBuf::from(value)),
+                    "--dictionary" => dictionary = Some(Dictionary::load(value)?),
+                    _ => out_dir = Some(PathBuf::from(value)),
+                }
+            }
+            "-j" | "--jobs" => {
+                let value = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                jobs = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&jobs| jobs > 0)
+                    .ok_or_else(|| {
+                        ConversionError::ParseError(format!("Invalid value for {}: {}", arg, value))
+                    })?;
+            }
+            "--resume" => resume = true,
+            "-k" | "--keep-going" => keep_going = true,
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    let out_dir = out_dir.ok_or_else(|| {
+        ConversionError::ParseError("Missing required option: -o DIR".to_string())
+    })?;
+    if inputs.is_empty() {
+        return Err(ConversionError::ParseError(
+            "Missing required argument: INPUT".to_string(),
+        ));
+    }
+    if resume && manifest_path.is_none() {
+        return Err(ConversionError::ParseError(
+            "--resume requires --manifest".to_string(),
+        ));
+    }
+
+    let previous = match &manifest_path {
+        Some(path) if resume && path.exists() => Manifest::load(path)?,
+        _ => Manifest::new(),
+    };
+
+    // a dictionary is shared by all workers, each file only pools what it adds
+    let options = match dictionary {
+        Some(dictionary) => Options::default().dictionary(dictionary),
+        None => Options::default(),
+    };
+    let mut manifest = Manifest::new();
+    let mut summary = BatchSummary::default();
+    let items = batch::plan(&inputs, &out_dir)?;
+    let pending: Vec<bool> = items
+        .iter()
+        .map(|item| {
+            !previous
+                .find(&item.input)
+                .is_some_and(|entry| entry.is_current(item))
+        })
+        .collect();
+
+    // workers take the next pending item until none is left or, without
+    // --keep-going, one failed; results are reported in input order
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let results: Vec<Mutex<Option<_>>> = items.iter().map(|_| Mutex::new(None)).collect();
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(items.len()) {
+            scope.spawn(|| {
+                loop {
+                    if failed.load(Ordering::Relaxed) && !keep_going {
+                        break;
+                    }
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(index) else {
+                        break;
+                    };
+                    if !pending[index] {
+                        continue;
+                    }
+                    let mut diagnostics = Diagnostics::new();
+                    let result =
+                        batch::convert_item_with_diagnostics(item, &options, &mut diagnostics);
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    *results[index].lock().unwrap() = Some((result, diagnostics));
+                }
+            });
+        }
+    });
+
+    for (index, item) in items.iter().enumerate() {
+        if !pending[index] {
+            if let Some(entry) = previous.find(&item.input) {
+                manifest.entries.push(entry.clone());
+            }
+            summary.skipped += 1;
+            continue;
+        }
+
+        let Some((result, diagnostics)) = results[index].lock().unwrap().take() else {
+            summary.not_attempted += 1;
+            continue;
+        };
+        summary.warnings += diagnostics.warning_count();
+        for entry in diagnostics.entries() {
+            eprintln!("{}: {}", item.input.display(), entry);
+        }
+
+        match result {
+            Ok(entry) => {
+                manifest.entries.push(entry);
+                summary.converted += 1;
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {}", item.input.display(), e);
+                summary.failed += 1;
+            }
+        }
+    }
+
+    if let Some(path) = &manifest_path {
+        manifest.save(path)?;
+    }
+
+    summary.write_to(io::stderr().lock())?;
+    if !summary.is_success() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn flat_command(args: &[String]) -> Result<()> {
+    let mut unflatten = false;
+    let mut layout = FlatLayout::default();
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--unflatten" => unflatten = true,
+            "--root" | "--element" => {
+                let value = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                if arg == "--root" {
+                    layout.root = value.clone();
+                } else {
+                    layout.element = value.clone();
+                }
+            }
+            _ => paths.push(arg.as_str()),
+        }
+    }
+
+    let (input_path, output_path) = match paths.as_slice() {
+        [input] => (*input, "-"),
+        [input, output] => (*input, *output),
+        [] => {
+            return Err(ConversionError::ParseError(
+                "Missing required argument: INPUT".to_string(),
+            ));
+        }
+        [_, _, extra, ..] => {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                extra
+            )));
+        }
+    };
+
+    let mut input = Vec::new();
+    if input_path == "-" {
+        decompress_if_gzip(io::stdin().lock())?.read_to_end(&mut input)?;
+    } else {
+        File::open(input_path)?.read_to_end(&mut input)?;
+    }
+    let writer: Box<dyn Write> = if output_path == "-" {
+        Box::new(BufWriter::new(io::stdout().lock()))
+    } else {
+        Box::new(BufWriter::new(File::create(output_path)?))
+    };
+
+    if unflatten {
+        flat::unflatten(input.as_slice(), writer, &layout)?;
+    } else if detect_format(&input) == Format::Abx {
+        flat::flatten(input.as_slice(), writer)?;
+    } else {
+        let mut abx = Vec::new();
+        XmlToAbxConverter::convert_from_string(std::str::from_utf8(&input)?, &mut abx)?;
+        flat::flatten(abx.as_slice(), writer)?;
+    }
+    Ok(())
+}
+
+/// The codec of a format name, `None` for XML
+fn parse_format(name: &str) -> Result<Option<&'static dyn DocumentCodec>> {
+    if name == "xml" {
+        return Ok(None);
+    }
+    codec::codec(name).map(Some).ok_or_else(|| {
+        let mut formats: Vec<_> = codec::codecs().iter().map(|codec| codec.name()).collect();
+        formats.push("xml");
+        ConversionError::ParseError(format!(
+            "Unknown format '{}' (expected one of {})",
+            name,
+            formats.join(", ")
+        ))
+    })
+}
+
+fn convert_command(args: &[String]) -> Result<()> {
+    let mut from = None;
+    let mut to = None;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" | "--to" => {
+                let value = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                if arg == "--from" {
+                    from = Some(parse_format(value)?);
+                } else {
+                    to = Some(parse_format(value)?);
+                }
+            }
+            _ => paths.push(arg.as_str()),
+        }
+    }
+
+    let (input_path, output_path) = match paths.as_slice() {
+        [input] => (*input, "-"),
+        [input, output] => (*input, *output),
+        [] => {
+            return Err(ConversionError::ParseError(
+                "Missing required argument: INPUT".to_string(),
+            ));
+        }
+        [_, _, extra, ..] => {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                extra
+            )));
+        }
+    };
+
+    let mut input = Vec::new();
+    if input_path == "-" {
+        decompress_if_gzip(io::stdin().lock())?.read_to_end(&mut input)?;
+    } else {
+        decompress_if_gzip(File::open(input_path)?)?.read_to_end(&mut input)?;
+    }
+
+    // by default a recognized format becomes XML and XML becomes ABX
+    let from = from.unwrap_or_else(|| codec::detect(&input));
+    let to = match to {
+        Some(to) => to,
+        None if from.is_some() => None,
+        None => Some(&AbxCodec as &dyn DocumentCodec),
+    };
+
+    let options = Options::default();
+    let xml = match from {
+        Some(from) => {
+            let mut xml = Vec::new();
+            from.decode(&mut input.as_slice(), &mut xml, &options)?;
+            xml
+        }
+        None => input,
+    };
+    let mut output = Vec::new();
+    match to {
+        Some(to) => to.encode(std::str::from_utf8(&xml)?, &mut output, &options)?,
+        None => output = xml,
+    }
+
+    if output_path == "-" {
+        io::stdout().lock().write_all(&output)?;
+    } else {
+        fs::write(output_path, &output)?;
+    }
+    Ok(())
+}
+
+fn fmt_command(args: &[String]) -> Result<()> {
+    let mut pipeline = Pipeline::new();
+    let mut hints = TypeHints::new();
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--strip-whitespace" => pipeline = pipeline.step(DropWhitespace),
+            "--reintern" => pipeline = pipeline.step(ReinternValues::default()),
+            "--type" => {
+                let hint = args.next().ok_or_else(|| {
+                    ConversionError::ParseError("Missing value for --type".to_string())
+                })?;
+                hints = hints.parse_hint(hint)?;
+            }
+            _ => paths.push(arg.as_str()),
+        }
+    }
+    if !hints.is_empty() {
+        pipeline = pipeline.step(hints);
+    }
+
+    let (input_path, output_path) = match paths.as_slice() {
+        [input] => (*input, "-"),
+        [input, output] => (*input, *output),
+        [] => {
+            return Err(ConversionError::ParseError(
+                "Missing required argument: INPUT".to_string(),
+            ));
+        }
+        [_, _, extra, ..] => {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                extra
+            )));
+        }
+    };
+
+    // read fully first so OUTPUT may name the input file
+    let mut input = Vec::new();
+    if input_path == "-" {
+        decompress_if_gzip(io::stdin().lock())?.read_to_end(&mut input)?;
+    } else {
+        decompress_if_gzip(File::open(input_path)?)?.read_to_end(&mut input)?;
+    }
+
+    let mut output = Vec::with_capacity(input.len());
+    pipeline.run(input.as_slice(), &mut output)?;
+
+    if output_path == "-" {
+        io::stdout().lock().write_all(&output)?;
+    } else {
+        fs::write(output_path, &output)?;
+    }
+    Ok(())
+}
+
+fn rename_command(args: &[String]) -> Result<()> {
+    let mut rename = Rename::new();
+    let mut inputs = Vec::new();
+    let mut journal = false;
+    // (attribute, old, new) of every rename, for the journal
+    let mut renames = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--attr" | "--element" => {
+                let value = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                rename = if arg == "--attr" {
+                    rename.parse_attribute(value)?
+                } else {
+                    rename.parse_element(value)?
+                };
+                let (old, new) = value.split_once('=').unwrap_or_default();
+                renames.push((arg == "--attr", old, new));
+            }
+            "--journal" => journal = true,
+            _ => inputs.push(Path::new(arg)),
+        }
+    }
+    if rename.is_empty() {
+        return Err(ConversionError::ParseError(
+            "Nothing to rename, use --attr or --element".to_string(),
+        ));
+    }
+    if inputs.is_empty() {
+        return Err(ConversionError::ParseError(
+            "Missing required argument: PATH".to_string(),
+        ));
+    }
+
+    let mut rename_file = |file: &Path| -> Result<()> {
+        let rewrite = || -> Result<usize> {
+            let input = fs::read(file)?;
+            let mut step = rename.clone();
+            let mut tokens = Vec::new();
+            for token in TokenReader::new(input.as_slice())? {
+                tokens.extend(step.apply(token?)?);
+            }
+            // files without matches are left byte for byte as they are
+            if step.renamed() > 0 {
+                let mut output = Vec::with_capacity(input.len());
+                Pipeline::new().run_tokens(tokens, &mut AbxSink::new(&mut output)?)?;
+                AtomicFile::new(file).write_with(|writer| Ok(writer.write_all(&output)?))?;
+                if journal {
+                    // only the renames that changed this file
+                    let entries: Vec<_> = renames
+                        .iter()
+                        .filter(|(attribute, old, _)| {
+                            if *attribute {
+                                step.renamed_attribute(old)
+                            } else {
+                                step.renamed_element(old)
+                            }
+                        })
+                        .map(|(attribute, old, new)| {
+                            let path = format!("//{}{}", if *attribute { "@" } else { "" }, old);
+                            JournalEntry::new("rename", &path).change(*old, *new)
+                        })
+                        .collect();
+                    Journal::for_file(file).append(&entries)?;
+                }
+            }
+            Ok(step.renamed())
+        };
+        match rewrite() {
+            Ok(0) => Ok(()),
+            Ok(renamed) => {
+                println!("{}: {} renamed", file.display(), renamed);
+                Ok(())
+            }
+            Err(e) => Err(ConversionError::ParseError(format!(
+                "{}: {}",
+                file.display(),
+                e
+            ))),
+        }
+    };
+
+    for input in inputs {
+        if input.is_dir() {
+            walk_abx_files(input, &mut rename_file)?;
+        } else {
+            rename_file(input)?;
+        }
+    }
+    Ok(())
+}
+
+fn tree_command(args: &[String]) -> Result<()> {
+    let mut max_depth = None;
+    let mut input_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-L" || arg == "--max-depth" {
+            let value = args
+                .next()
+                .ok_or_else(|| ConversionError::ParseError(format!("Missing value for {}", arg)))?;
+            max_depth = Some(value.parse::<usize>().map_err(|_| {
+                ConversionError::ParseError(format!("Invalid value for {}: {}", arg, value))
+            })?);
+        } else if input_path.is_none() {
+            input_path = Some(arg.as_str());
+        } else {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                arg
+            )));
+        }
+    }
+
+    let input_path = input_path.ok_or_else(|| {
+        ConversionError::ParseError("Missing required argument: INPUT".to_string())
+    })?;
+    let mut input = Vec::new();
+    if input_path == "-" {
+        decompress_if_gzip(io::stdin().lock())?.read_to_end(&mut input)?;
+    } else {
+        decompress_if_gzip(File::open(input_path)?)?.read_to_end(&mut input)?;
+    }
+
+    // anything but ABX is brought to ABX through XML
+    let abx = match codec::detect(&input) {
+        Some(codec) if codec.name() == AbxCodec.name() => input,
+        detected => {
+            let xml = match detected {
+                Some(codec) => {
+                    let mut xml = Vec::new();
+                    codec.decode(&mut input.as_slice(), &mut xml, &Options::default())?;
+                    xml
+                }
+                None => input,
+            };
+            let mut abx = Vec::new();
+            XmlToAbxConverter::convert_from_string(std::str::from_utf8(&xml)?, &mut abx)?;
+            abx
+        }
+    };
+
+    write_tree(&abx, max_depth, &mut io::stdout().lock())
+}
+
+fn extract_command(args: &[String]) -> Result<()> {
+    let mut output_path = "-";
+    let mut positional = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                output_path = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+            }
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    let (path, input_path) = match positional.as_slice() {
+        [path, input] => (*path, *input),
+        [] | [_] => {
+            return Err(ConversionError::ParseError(
+                "Missing required arguments: XPATH INPUT".to_string(),
+            ));
+        }
+        [_, _, extra, ..] => {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                extra
+            )));
+        }
+    };
+
+    let path = XPath::parse(path)?;
+    let mut input = Vec::new();
+    if input_path == "-" {
+        decompress_if_gzip(io::stdin().lock())?.read_to_end(&mut input)?;
+    } else {
+        decompress_if_gzip(File::open(input_path)?)?.read_to_end(&mut input)?;
+    }
+
+    let document = RawDocument::from_bytes(&input)?;
+    let element = match document.select_elements(&path).as_slice() {
+        [element] => *element,
+        [] => {
+            // like grep, signal "nothing found" to scripts through the exit status
+            eprintln!("No element matches the path");
+            std::process::exit(1);
+        }
+        elements => {
+            return Err(ConversionError::ParseError(format!(
+                "The path matches {} elements, narrow it down with a predicate",
+                elements.len()
+            )));
+        }
+    };
+
+    let output = document.extract_element(element)?.to_bytes()?;
+    if output_path == "-" {
+        io::stdout().lock().write_all(&output)?;
+    } else {
+        fs::write(output_path, &output)?;
+    }
+    Ok(())
+}
+
+fn replace_command(args: &[String]) -> Result<()> {
+    let mut fragment_path = None;
+    let mut output_path = None;
+    let mut journal = false;
+    let mut positional = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--journal" => journal = true,
+            "--with" | "-o" | "--output" => {
+                let value = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                if arg == "--with" {
+                    fragment_path = Some(value.as_str());
+                } else {
+                    output_path = Some(value.as_str());
+                }
+            }
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    let fragment_path = fragment_path.ok_or_else(|| {
+        ConversionError::ParseError("Missing required option: --with FRAGMENT".to_string())
+    })?;
+    let (path, input_path) = match positional.as_slice() {
+        [path, input] => (*path, *input),
+        [] | [_] => {
+            return Err(ConversionError::ParseError(
+                "Missing required arguments: XPATH INPUT".to_string(),
+            ));
+        }
+        [_, _, extra, ..] => {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                extra
+            )));
+        }
+    };
+
+    let written_path = output_path.unwrap_or(input_path);
+    if journal && written_path == "-" {
+        return Err(ConversionError::ParseError(
+            "--journal needs an output file".to_string(),
+        ));
+    }
+    let xpath = XPath::parse(path)?;
+    // indentation of the fragment file is not content
+    let mut fragment = Vec::new();
+    XmlToAbxConverter::convert_from_string_with(
+        &fs::read_to_string(fragment_path)?,
+        &mut fragment,
+        &Options::new().preserve_whitespace(false),
+    )?;
+    let fragment = RawDocument::from_bytes(&fragment)?;
+
+    let mut document = RawDocument::from_bytes(&fs::read(input_path)?)?;
+    let elements = document.select_elements(&xpath);
+    if elements.is_empty() {
+        // like grep, signal "nothing found" to scripts through the exit status
+        eprintln!("No element matches the path");
+        std::process::exit(1);
+    }
+    let mut entries = Vec::new();
+    if journal {
+        let new = element_xml(&fragment)?;
+        for element in &elements {
+            let old = element_xml(&document.extract_element(*element)?)?;
+            entries.push(JournalEntry::new("replace", path).change(old, new.as_str()));
+        }
+    }
+    // from the back, so the indices of earlier matches stay valid
+    for element in elements.iter().rev() {
+        document.replace_element(*element, &fragment)?;
+    }
+
+    let output = document.to_bytes()?;
+    match output_path {
+        Some("-") => io::stdout().lock().write_all(&output)?,
+        Some(output_path) => fs::write(output_path, &output)?,
+        None => AtomicFile::new(input_path).write_with(|writer| Ok(writer.write_all(&output)?))?,
+    }
+    if journal {
+        Journal::for_file(written_path).append(&entries)?;
+    }
+    eprintln!("{}: {} replaced", input_path, elements.len());
+    Ok(())
+}
+
+/// A document as XML without the declaration, for the journal
+fn element_xml(document: &RawDocument) -> Result<String> {
+    let xml = AbxToXmlConverter::convert_bytes(&document.to_bytes()?)?;
+    Ok(match xml.split_once("?>") {
+        Some((_, element)) if xml.starts_with("<?xml") => element.to_string(),
+        _ => xml,
+    })
+}
+
+fn forensics_command(args: &[String]) -> Result<()> {
+    let input_path = match args {
+        [input] => input.as_str(),
+        [] => {
+            return Err(ConversionError::ParseError(
+                "Missing required argument: INPUT".to_string(),
+            ));
+        }
+        [_, extra, ..] => {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                extra
+            )));
+        }
+    };
+
+    let mut input = Vec::new();
+    if input_path == "-" {
+        decompress_if_gzip(io::stdin().lock())?.read_to_end(&mut input)?;
+    } else {
+        decompress_if_gzip(File::open(input_path)?)?.read_to_end(&mut input)?;
+    }
+
+    let report = ForensicReport::collect(&input)?;
+    report.write_to(io::stdout().lock())?;
+    if report.has_anomalies() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn learn_types_command(args: &[String]) -> Result<()> {
+    let mut input_path = None;
+    let mut output_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-o" || arg == "--output" {
+            let path = args
+                .next()
+                .ok_or_else(|| ConversionError::ParseError(format!("Missing value for {}", arg)))?;
+            output_path = Some(path.as_str());
+        } else if input_path.is_none() {
+            input_path = Some(arg.as_str());
+        } else {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                arg
+            )));
+        }
+    }
+
+    let input_path = input_path.ok_or_else(|| {
+        ConversionError::ParseError("Missing required argument: INPUT".to_string())
+    })?;
+    let schema = if input_path == "-" {
+        TypeSchema::learn(decompress_if_gzip(io::stdin().lock())?)?
+    } else {
+        TypeSchema::learn(BufReader::new(File::open(input_path)?))?
+    };
+
+    match output_path {
+        None | Some("-") => schema.write_to(io::stdout().lock()),
+        Some(path) => schema.write_to(BufWriter::new(File::create(path)?)),
+    }
+}
+
+fn audit_command(args: &[String]) -> Result<()> {
+    let dir = match args {
+        [dir] => dir,
+        [] => {
+            return Err(ConversionError::ParseError(
+                "Missing required argument: DIR".to_string(),
+            ));
+        }
+        [_, extra, ..] => {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                extra
+            )));
+        }
+    };
+
+    let report = AuditReport::audit_dir(dir)?;
+    report.write_to(io::stdout().lock())?;
+    if report.has_errors() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn compare_profile_command(args: &[String]) -> Result<()> {
+    let (dump, profile) = match args {
+        [dump, profile] => (dump, profile),
+        [] | [_] => {
+            return Err(ConversionError::ParseError(
+                "Missing required arguments: DUMP_DIR PROFILE".to_string(),
+            ));
+        }
+        [_, _, extra, ..] => {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                extra
+            )));
+        }
+    };
+
+    let report = ComplianceReport::compare(dump, &DeviceProfile::load(profile)?);
+    report.write_to(io::stdout().lock())?;
+    if !report.is_compliant() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn capabilities_command() -> Result<()> {
+    let capabilities = capabilities();
+    let versions: Vec<String> = capabilities
+        .protocol_versions
+        .iter()
+        .map(u8::to_string)
+        .collect();
+
+    let mut output = io::stdout().lock();
+    writeln!(output, "version: {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(output, "protocol versions: {}", versions.join(" "))?;
+    writeln!(output, "formats: {}", capabilities.formats.join(" "))?;
+    writeln!(output, "tokens: {}", capabilities.tokens.join(" "))?;
+    writeln!(
+        output,
+        "attribute types: {}",
+        capabilities.attribute_types.join(" ")
+    )?;
+    writeln!(output, "features: {}", capabilities.features.join(" "))?;
+    Ok(())
+}
+
+fn main() {
+    let mut args = env::args();
+    let bin_name = args
+        .next()
+        .as_ref()
+        .and_then(|p| std::path::Path::new(p).file_name())
+        .and_then(|n| n.to_str())
+        .unwrap_or("abx")
+        .to_string();
+
+    let args: Vec<String> = args.collect();
+
+    let Some(command) = args.first() else {
+        print_help(&bin_name);
+        std::process::exit(1);
+    };
+
+    let result = match command.as_str() {
+        "-h" | "--help" => {
+            print_help(&bin_name);
+            return;
+        }
+        "daemon" => daemon_command(&args[1..]),
+        "find" => find_command(&args[1..]),
+        "query" => query_command(&args[1..]),
+        "grep" => grep_command(&args[1..]),
+        "count" => count_command(&args[1..]),
+        "summary" => summary_command(&args[1..]),
+        "verify-pool" => verify_pool_command(&args[1..]),
+        "archive" => archive_command(&args[1..]),
+        "batch" => batch_command(&args[1..]),
+        "flat" => flat_command(&args[1..]),
+        "convert" => convert_command(&args[1..]),
+        "fmt" => fmt_command(&args[1..]),
+        "rename" => rename_command(&args[1..]),
+        "tree" => tree_command(&args[1..]),
+        "extract" => extract_command(&args[1..]),
+        "replace" => replace_command(&args[1..]),
+        "forensics" => forensics_command(&args[1..]),
+        "learn-types" => learn_types_command(&args[1..]),
+        "audit" => audit_command(&args[1..]),
+        "compare-profile" => compare_profile_command(&args[1..]),
+        "capabilities" => capabilities_command(),
+        _ => Err(ConversionError::ParseError(format!(
+            "Unknown command: {}",
+            command
+        ))),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        if let Some(hint) = error_hint(&e) {
+            eprintln!("Note: {}", hint);
+        }
+        std::process::exit(1);
+    }
+}
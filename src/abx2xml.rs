@@ -1,460 +1,16 @@
+use android_xml_converter::atomic::AtomicFile;
+use android_xml_converter::diagnostics::Diagnostics;
+use android_xml_converter::dictionary::Dictionary;
+use android_xml_converter::privileged::ReadCommand;
+use android_xml_converter::profile::Profiler;
+use android_xml_converter::roundtrip::RawDocument;
+use android_xml_converter::timeout::IdleTimeoutReader;
+use android_xml_converter::verify::AndroidVerification;
 use android_xml_converter::*;
-use base64::Engine;
-use faster_hex::hex_string;
-use smol_str::SmolStr;
 use std::env;
 use std::fs::File;
-use std::io::{self, BufReader, BufWriter, Cursor, Read, Write};
-
-// ============================================================================
-// Data Input Reader
-// ============================================================================
-
-pub struct DataInput<R: Read> {
-    reader: R,
-    interned_strings: Vec<SmolStr>,
-    peeked_byte: Option<u8>,
-}
-
-impl<R: Read> DataInput<R> {
-    pub fn new(reader: R) -> Self {
-        Self {
-            reader,
-            interned_strings: Vec::with_capacity(INITIAL_STRING_POOL_CAPACITY),
-            peeked_byte: None,
-        }
-    }
-
-    pub fn read_byte(&mut self) -> Result<u8> {
-        if let Some(byte) = self.peeked_byte.take() {
-            return Ok(byte);
-        }
-        let mut buf = [0u8; 1];
-        self.reader
-            .read_exact(&mut buf)
-            .map_err(|_| ConversionError::ReadError("byte".to_string()))?;
-        Ok(buf[0])
-    }
-
-    pub fn peek_byte(&mut self) -> Result<u8> {
-        if let Some(byte) = self.peeked_byte {
-            return Ok(byte);
-        }
-        let byte = self.read_byte()?;
-        self.peeked_byte = Some(byte);
-        Ok(byte)
-    }
-
-    pub fn read_short(&mut self) -> Result<u16> {
-        let mut buf = [0u8; 2];
-        if let Some(byte) = self.peeked_byte.take() {
-            buf[0] = byte;
-            self.reader
-                .read_exact(&mut buf[1..])
-                .map_err(|_| ConversionError::ReadError("short".to_string()))?;
-        } else {
-            self.reader
-                .read_exact(&mut buf)
-                .map_err(|_| ConversionError::ReadError("short".to_string()))?;
-        }
-        Ok(u16::from_be_bytes(buf))
-    }
-
-    pub fn read_int(&mut self) -> Result<i32> {
-        let mut buf = [0u8; 4];
-        let start_idx = if let Some(byte) = self.peeked_byte.take() {
-            buf[0] = byte;
-            1
-        } else {
-            0
-        };
-        self.reader
-            .read_exact(&mut buf[start_idx..])
-            .map_err(|_| ConversionError::ReadError("int".to_string()))?;
-        Ok(i32::from_be_bytes(buf))
-    }
-
-    pub fn read_long(&mut self) -> Result<i64> {
-        let mut buf = [0u8; 8];
-        let start_idx = if let Some(byte) = self.peeked_byte.take() {
-            buf[0] = byte;
-            1
-        } else {
-            0
-        };
-        self.reader
-            .read_exact(&mut buf[start_idx..])
-            .map_err(|_| ConversionError::ReadError("long".to_string()))?;
-        Ok(i64::from_be_bytes(buf))
-    }
-
-    pub fn read_float(&mut self) -> Result<f32> {
-        let int_value = self.read_int()? as u32;
-        Ok(f32::from_bits(int_value))
-    }
-
-    pub fn read_double(&mut self) -> Result<f64> {
-        let int_value = self.read_long()? as u64;
-        Ok(f64::from_bits(int_value))
-    }
-
-    pub fn read_utf(&mut self) -> Result<String> {
-        let length = self.read_short()?;
-        let mut buffer = vec![0u8; length as usize];
-        self.reader
-            .read_exact(&mut buffer)
-            .map_err(|_| ConversionError::ReadError("UTF string".to_string()))?;
-        String::from_utf8(buffer)
-            .map_err(|_| ConversionError::ReadError("UTF string (invalid UTF-8)".to_string()))
-    }
-
-    pub fn read_interned_utf(&mut self) -> Result<SmolStr> {
-        // Changed from Result<String>
-        let index = self.read_short()?;
-        if index == INTERNED_STRING_NEW_MARKER {
-            let string = self.read_utf()?;
-            let smol = SmolStr::new(string);
-            self.interned_strings.push(smol.clone());
-            Ok(smol)
-        } else {
-            self.interned_strings
-                .get(index as usize)
-                .cloned()
-                .ok_or(ConversionError::InvalidInternedStringIndex(index))
-        }
-    }
-
-    pub fn read_bytes(&mut self, length: u16) -> Result<Vec<u8>> {
-        let mut data = vec![0u8; length as usize];
-        self.reader
-            .read_exact(&mut data)
-            .map_err(|_| ConversionError::ReadError("bytes".to_string()))?;
-        Ok(data)
-    }
-}
-
-// ============================================================================
-// Binary XML Deserializer
-// ============================================================================
-
-pub struct BinaryXmlDeserializer<R: Read, W: Write> {
-    input: DataInput<R>,
-    output: W,
-}
-
-impl<R: Read, W: Write> BinaryXmlDeserializer<R, W> {
-    pub fn new(mut reader: R, output: W) -> Result<Self> {
-        let mut magic = [0u8; 4];
-        reader
-            .read_exact(&mut magic)
-            .map_err(|_| ConversionError::ReadError("magic header".to_string()))?;
-
-        if magic != PROTOCOL_MAGIC_VERSION_0 {
-            return Err(ConversionError::InvalidMagicHeader {
-                expected: PROTOCOL_MAGIC_VERSION_0,
-                actual: magic,
-            });
-        }
-
-        Ok(Self {
-            input: DataInput::new(reader),
-            output,
-        })
-    }
-
-    pub fn deserialize(&mut self) -> Result<()> {
-        self.output
-            .write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
-
-        loop {
-            match self.process_token() {
-                Ok(should_continue) => {
-                    if !should_continue {
-                        break;
-                    }
-                }
-                Err(ConversionError::ReadError(_)) => {
-                    break;
-                }
-                Err(e) => {
-                    eprintln!("Warning: Error parsing token: {}", e);
-                    break;
-                }
-            }
-        }
-
-        Ok(())
-    }
-    fn process_token(&mut self) -> Result<bool> {
-        let token = self.input.read_byte()?;
-        let command = token & 0x0F;
-        let type_info = token & 0xF0;
-
-        match command {
-            START_DOCUMENT => Ok(true),
-            END_DOCUMENT => Ok(false),
-            START_TAG => {
-                let tag_name = self.input.read_interned_utf()?;
-                self.output.write_all(b"<")?;
-                self.output.write_all(tag_name.as_bytes())?;
-
-                while let Ok(next_token) = self.input.peek_byte() {
-                    if (next_token & 0x0F) != ATTRIBUTE {
-                        break;
-                    }
-
-                    let _ = self.input.read_byte()?;
-                    self.process_attribute(next_token)?;
-                }
-
-                self.output.write_all(b">")?;
-                Ok(true)
-            }
-            END_TAG => {
-                let tag_name = self.input.read_interned_utf()?;
-                self.output.write_all(b"</")?;
-                self.output.write_all(tag_name.as_bytes())?;
-                self.output.write_all(b">")?;
-                Ok(true)
-            }
-            TEXT => {
-                if type_info == TYPE_STRING {
-                    let text = self.input.read_utf()?;
-                    if !text.is_empty() {
-                        let encoded = encode_xml_entities(&text);
-                        self.output.write_all(encoded.as_bytes())?;
-                    }
-                }
-                Ok(true)
-            }
-            CDSECT => {
-                if type_info == TYPE_STRING {
-                    let text = self.input.read_utf()?;
-                    self.output.write_all(b"<![CDATA[")?;
-                    self.output.write_all(text.as_bytes())?;
-                    self.output.write_all(b"]]>")?;
-                }
-                Ok(true)
-            }
-            COMMENT => {
-                if type_info == TYPE_STRING {
-                    let text = self.input.read_utf()?;
-                    self.output.write_all(b"<!--")?;
-                    self.output.write_all(text.as_bytes())?;
-                    self.output.write_all(b"-->")?;
-                }
-                Ok(true)
-            }
-            PROCESSING_INSTRUCTION => {
-                if type_info == TYPE_STRING {
-                    let text = self.input.read_utf()?;
-                    self.output.write_all(b"<?")?;
-                    self.output.write_all(text.as_bytes())?;
-                    self.output.write_all(b"?>")?;
-                }
-                Ok(true)
-            }
-            DOCDECL => {
-                if type_info == TYPE_STRING {
-                    let text = self.input.read_utf()?;
-                    self.output.write_all(b"<!DOCTYPE ")?;
-                    self.output.write_all(text.as_bytes())?;
-                    self.output.write_all(b">")?;
-                }
-                Ok(true)
-            }
-            ENTITY_REF => {
-                if type_info == TYPE_STRING {
-                    let text = self.input.read_utf()?;
-                    self.output.write_all(b"&")?;
-                    self.output.write_all(text.as_bytes())?;
-                    self.output.write_all(b";")?;
-                }
-                Ok(true)
-            }
-            IGNORABLE_WHITESPACE => {
-                if type_info == TYPE_STRING {
-                    let text = self.input.read_utf()?;
-                    self.output.write_all(text.as_bytes())?;
-                }
-                Ok(true)
-            }
-            _ => {
-                eprintln!("Warning: Unknown token: {}", command);
-                Ok(true)
-            }
-        }
-    }
-
-    fn process_attribute(&mut self, token: u8) -> Result<()> {
-        let type_info = token & 0xF0;
-        let name = self.input.read_interned_utf()?;
-
-        self.output.write_all(b" ")?;
-        self.output.write_all(name.as_bytes())?;
-        self.output.write_all(b"=\"")?;
-
-        match type_info {
-            TYPE_STRING => {
-                let value = self.input.read_utf()?;
-                let encoded = encode_xml_entities(&value);
-                self.output.write_all(encoded.as_bytes())?;
-            }
-            TYPE_STRING_INTERNED => {
-                let value = self.input.read_interned_utf()?;
-                let encoded = encode_xml_entities(&value);
-                self.output.write_all(encoded.as_bytes())?;
-            }
-            TYPE_INT => {
-                let value = self.input.read_int()?;
-                write!(self.output, "{}", value)?;
-            }
-            TYPE_INT_HEX => {
-                let value = self.input.read_int()?;
-                if value == -1 {
-                    write!(self.output, "{}", value)?;
-                } else {
-                    write!(self.output, "{:x}", value as u32)?;
-                }
-            }
-            TYPE_LONG => {
-                let value = self.input.read_long()?;
-                write!(self.output, "{}", value)?;
-            }
-            TYPE_LONG_HEX => {
-                let value = self.input.read_long()?;
-                if value == -1 {
-                    write!(self.output, "{}", value)?;
-                } else {
-                    write!(self.output, "{:x}", value as u64)?;
-                }
-            }
-            TYPE_FLOAT => {
-                let value = self.input.read_float()?;
-                if value.fract() == 0.0 && value.is_finite() {
-                    write!(self.output, "{:.1}", value)?;
-                } else {
-                    write!(self.output, "{}", value)?;
-                }
-            }
-            TYPE_DOUBLE => {
-                let value = self.input.read_double()?;
-                if value.fract() == 0.0 && value.is_finite() {
-                    write!(self.output, "{:.1}", value)?;
-                } else {
-                    write!(self.output, "{}", value)?;
-                }
-            }
-            TYPE_BOOLEAN_TRUE => {
-                self.output.write_all(b"true")?;
-            }
-            TYPE_BOOLEAN_FALSE => {
-                self.output.write_all(b"false")?;
-            }
-            TYPE_BYTES_HEX => {
-                let length = self.input.read_short()?;
-                let bytes = self.input.read_bytes(length)?;
-                let hex = hex_string(&bytes);
-                self.output.write_all(hex.as_bytes())?;
-            }
-            TYPE_BYTES_BASE64 => {
-                let length = self.input.read_short()?;
-                let bytes = self.input.read_bytes(length)?;
-                let encoded = base64::engine::general_purpose::STANDARD.encode(&bytes);
-                self.output.write_all(encoded.as_bytes())?;
-            }
-            _ => {
-                return Err(ConversionError::UnknownAttributeType(type_info));
-            }
-        }
-
-        self.output.write_all(b"\"")?;
-        Ok(())
-    }
-}
-
-// ============================================================================
-// Converter API
-// ============================================================================
-
-pub struct AbxToXmlConverter;
-
-impl AbxToXmlConverter {
-    pub fn convert<R: Read, W: Write>(reader: R, writer: W) -> Result<()> {
-        let mut deserializer = BinaryXmlDeserializer::new(reader, writer)?;
-        deserializer.deserialize()
-    }
-
-    pub fn convert_file(input_path: &str, output_path: &str) -> Result<()> {
-        if input_path == output_path {
-            return Self::convert_file_in_place(input_path);
-        }
-
-        let input_file = File::open(input_path)?;
-        let reader = BufReader::new(input_file);
-        let output_file = File::create(output_path)?;
-        let writer = BufWriter::new(output_file);
-        Self::convert(reader, writer)
-    }
-
-    pub fn convert_stdin_stdout() -> Result<()> {
-        let stdin = io::stdin();
-        let reader = stdin.lock();
-        let stdout = io::stdout();
-        let writer = BufWriter::new(stdout.lock());
-        Self::convert(reader, writer)
-    }
-
-    pub fn convert_stdin_to_file(output_path: &str) -> Result<()> {
-        let stdin = io::stdin();
-        let reader = stdin.lock();
-        let output_file = File::create(output_path)?;
-        let writer = BufWriter::new(output_file);
-        Self::convert(reader, writer)
-    }
-
-    pub fn convert_file_to_stdout(input_path: &str) -> Result<()> {
-        let input_file = File::open(input_path)?;
-        let reader = BufReader::new(input_file);
-        let writer = io::stdout();
-        Self::convert(reader, writer)
-    }
-
-    fn convert_file_in_place(file_path: &str) -> Result<()> {
-        let input_file = File::open(file_path)?;
-        let mut reader = BufReader::new(input_file);
-        let mut file_data = Vec::new();
-        reader.read_to_end(&mut file_data)?;
-
-        let cursor = Cursor::new(file_data);
-        let mut output_data = Vec::new();
-        {
-            let writer = Cursor::new(&mut output_data);
-            Self::convert(cursor, writer)?;
-        }
-
-        let output_file = File::create(file_path)?;
-        let mut writer = BufWriter::new(output_file);
-        writer.write_all(&output_data)?;
-        writer.flush()?;
-        Ok(())
-    }
-
-    pub fn convert_bytes(abx_data: &[u8]) -> Result<String> {
-        let cursor = Cursor::new(abx_data);
-        let mut output_data = Vec::new();
-        {
-            let writer = Cursor::new(&mut output_data);
-            Self::convert(cursor, writer)?;
-        }
-        String::from_utf8(output_data)
-            .map_err(|_| ConversionError::ParseError("Invalid UTF-8 in output".to_string()))
-    }
-
-    pub fn convert_vec(abx_data: Vec<u8>) -> Result<String> {
-        Self::convert_bytes(&abx_data)
-    }
-}
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::time::Duration;
 
 // ============================================================================
 // CLI
@@ -462,6 +18,18 @@ impl AbxToXmlConverter {
 
 struct Cli;
 
+/// Read-only queries that replace XML output
+enum Query<'a> {
+    Extract(&'a str),
+    Count(&'a str),
+    Summary,
+    VerifyPool,
+    VerifyAndroid,
+    ExportPool(&'a str),
+    CheckPool(&'a str),
+    Dump,
+}
+
 impl Cli {
     fn print_help(program_name: &str) {
         eprintln!("Usage: {} [OPTIONS] <input> [output]", program_name);
@@ -469,12 +37,101 @@ impl Cli {
         eprintln!("Converts Android Binary XML (ABX) to human-readable XML.");
         eprintln!();
         eprintln!("Arguments:");
-        eprintln!("  input              Input file path (use '-' for stdin)");
+        eprintln!("  input              Input file path (use '-' for stdin, may be gzipped)");
         eprintln!("  output             Output file path (use '-' for stdout)");
         eprintln!("                     If not specified, defaults to stdout or in-place");
         eprintln!();
         eprintln!("Options:");
         eprintln!("  -i, --in-place     Overwrite input file with converted output");
+        eprintln!("  -e, --extract PATH Print matching attribute values instead of XML");
+        eprintln!("                     e.g. \"package[@name='com.foo']/@codePath\"");
+        eprintln!("                     (the XPath subset of abx query)");
+        eprintln!("      --count PATH   Print the number of elements matching PATH");
+        eprintln!("      --summary      Print per-tag counts and attribute names");
+        eprintln!("      --verify-pool  Report duplicate, unused and invalid intern pool entries");
+        eprintln!("      --verify-android");
+        eprintln!(
+            "                     Report tokens Android's parser would reject, and attribute"
+        );
+        eprintln!(
+            "                     values of well-known system files not readable as the type"
+        );
+        eprintln!("                     the platform reads them with");
+        eprintln!("      --export-pool FILE");
+        eprintln!("                     Save the intern pool of the input as a snapshot");
+        eprintln!("      --check-pool FILE");
+        eprintln!("                     Compare the intern pool index by index with a snapshot");
+        eprintln!("                     from --export-pool or xml2abx --export-dictionary");
+        eprintln!("      --dump         Print the typed token stream, one token per line");
+        eprintln!("      --max-errors N Record up to N recoverable errors (unknown tokens,");
+        eprintln!("                     invalid pool indices) and report them instead of");
+        eprintln!("                     stopping at the first one");
+        eprintln!("      --fidelity     Record attribute types and token boundaries so that");
+        eprintln!("                     xml2abx --fidelity reproduces the input byte for byte,");
+        eprintln!("                     and fail if the output would not");
+        eprintln!("      --canonical    Write output that depends only on the content: sorted");
+        eprintln!("                     attributes, shortest floats, trimmed text");
+        eprintln!("      --modified-utf8");
+        eprintln!("                     Read strings as Java's modified UTF-8, as Android");
+        eprintln!("                     writes them (NUL and emoji)");
+        eprintln!("      --allow-truncated");
+        eprintln!("                     Write what was read of input that ends before");
+        eprintln!("                     END_DOCUMENT instead of failing");
+        eprintln!("      --multi-doc    Convert every ABX document of a concatenated stream,");
+        eprintln!("                     each into an XML document of its own, or all under");
+        eprintln!("                     one root with --wrap-roots");
+        eprintln!("      --salvage      Convert as much of a corrupted file as possible:");
+        eprintln!("                     skip tokens that cannot be decoded and close the");
+        eprintln!("                     elements left open, with a warning for each");
+        eprintln!("      --strict-names Fail on element and attribute names that are not XML");
+        eprintln!("                     names or longer than 256 bytes, a sign of a corrupted");
+        eprintln!("                     string pool");
+        eprintln!("      --max-name-length BYTES");
+        eprintln!("                     Fail on longer element and attribute names");
+        eprintln!("      --allow-unknown-version");
+        eprintln!("                     Decode ABX versions other than 0 as version 0");
+        eprintln!("                     instead of failing");
+        eprintln!("      --invalid-utf8 MODE");
+        eprintln!("                     Strings that are not UTF-8: error (default), lossy");
+        eprintln!("                     (U+FFFD) or escape (bytes as &#xNN; references)");
+        eprintln!("      --emit-type-hints");
+        eprintln!("                     Record the ABX type of each attribute in an abx:type");
+        eprintln!("                     annotation, for xml2abx --apply-type-hints");
+        eprintln!("      --float-format shortest|java|DIGITS");
+        eprintln!("                     Write float and double attributes as the shortest text");
+        eprintln!("                     that reads back (default), like Java's Float.toString");
+        eprintln!("                     (1.0E-4) or with DIGITS digits after the decimal point");
+        eprintln!("      --float-scientific LOW:HIGH");
+        eprintln!("                     Use scientific notation for magnitudes outside");
+        eprintln!("                     [LOW, HIGH), e.g. 1e-3:1e7");
+        eprintln!("      --wrap-roots NAME");
+        eprintln!("                     Wrap multiple root elements or stray top-level text in");
+        eprintln!("                     a synthetic <NAME> root instead of passing them on");
+        eprintln!("      --buffer-size BYTES");
+        eprintln!("                     Read and write buffer size (default 8192, 0 for none)");
+        eprintln!("      --idle-timeout SECONDS");
+        eprintln!("                     Fail when the input delivers nothing for this long,");
+        eprintln!("                     e.g. a stalled pipe or su, instead of waiting forever");
+        eprintln!("      --profile      Print per-phase timing and peak memory to stderr");
+        eprintln!("      --android-atomic");
+        eprintln!("                     Read and write files like Android's AtomicFile: restore");
+        eprintln!("                     a leftover .bak, write via .new, fsync and rename");
+        eprintln!("      --su           Read the input through 'su -c cat', for protected files");
+        eprintln!("                     on a rooted device");
+        eprintln!("      --su-command TEMPLATE");
+        eprintln!(
+            "                     Read the input through TEMPLATE instead, with {{}} replaced"
+        );
+        eprintln!("                     by the input path or the path appended, e.g. 'su 0 cat'");
+        eprintln!("      --dictionary FILE");
+        eprintln!("                     Input was written with this exported dictionary");
+        if cfg!(feature = "android-dictionary") {
+            eprintln!("      --android-dictionary");
+            eprintln!("                     Input was written with the built-in name dictionary");
+        }
+        eprintln!("      --android-cli-compat");
+        eprintln!("                     As the first argument: accept exactly the arguments of");
+        eprintln!("                     Android's abx2xml ('-i input' or 'input output')");
         eprintln!("  -h, --help         Show this help message");
     }
 
@@ -490,21 +147,175 @@ impl Cli {
 
         let args: Vec<String> = args.collect();
 
+        if let Some(args) = android_cli_compat(&args) {
+            return Self::run_android_compat(args);
+        }
+
         if args.is_empty() || args.iter().any(|a| a == "-h" || a == "--help") {
             Self::print_help(&bin_name);
             std::process::exit(if args.is_empty() { 1 } else { 0 });
         }
 
         let mut in_place = false;
+        let mut query = None;
+        let mut android_dictionary = false;
+        let mut dictionary_path = None;
+        let mut max_errors = None;
+        let mut wrap_roots = None;
+        let mut emit_type_hints = false;
+        let mut fidelity = false;
+        let mut canonical = false;
+        let mut modified_utf8 = false;
+        let mut invalid_utf8 = None;
+        let mut allow_truncated = false;
+        let mut allow_unknown_version = false;
+        let mut salvage = false;
+        let mut multi_doc = false;
+        let mut strict_names = false;
+        let mut max_name_length = None;
+        let mut float_format = None;
+        let mut float_scientific = None;
+        let mut buffer_size = None;
+        let mut idle_timeout = None;
+        let mut profile = false;
+        let mut atomic = false;
+        let mut read_command = None;
         let mut input_path = None;
         let mut output_path = None;
         let mut after_double_dash = false;
 
-        for arg in &args {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
             if !after_double_dash && arg == "--" {
                 after_double_dash = true;
             } else if !after_double_dash && (arg == "-i" || arg == "--in-place") {
                 in_place = true;
+            } else if !after_double_dash && (arg == "-e" || arg == "--extract") {
+                let path = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                query = Some(Query::Extract(path.as_str()));
+            } else if !after_double_dash && arg == "--count" {
+                let path = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                query = Some(Query::Count(path.as_str()));
+            } else if !after_double_dash && arg == "--summary" {
+                query = Some(Query::Summary);
+            } else if !after_double_dash && arg == "--verify-pool" {
+                query = Some(Query::VerifyPool);
+            } else if !after_double_dash && arg == "--verify-android" {
+                query = Some(Query::VerifyAndroid);
+            } else if !after_double_dash && (arg == "--export-pool" || arg == "--check-pool") {
+                let path = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                query = Some(if arg == "--export-pool" {
+                    Query::ExportPool(path.as_str())
+                } else {
+                    Query::CheckPool(path.as_str())
+                });
+            } else if !after_double_dash && arg == "--dump" {
+                query = Some(Query::Dump);
+            } else if !after_double_dash && arg == "--profile" {
+                profile = true;
+            } else if !after_double_dash && arg == "--android-atomic" {
+                atomic = true;
+            } else if !after_double_dash && arg == "--su" {
+                read_command = Some(ReadCommand::default());
+            } else if !after_double_dash && arg == "--su-command" {
+                let template = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                read_command = Some(ReadCommand::from_template(template)?);
+            } else if !after_double_dash && arg == "--android-dictionary" {
+                android_dictionary = true;
+            } else if !after_double_dash && arg == "--dictionary" {
+                let path = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                dictionary_path = Some(path.as_str());
+            } else if !after_double_dash && arg == "--wrap-roots" {
+                let root = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                wrap_roots = Some(root.as_str());
+            } else if !after_double_dash && arg == "--fidelity" {
+                fidelity = true;
+            } else if !after_double_dash && arg == "--canonical" {
+                canonical = true;
+            } else if !after_double_dash && arg == "--modified-utf8" {
+                modified_utf8 = true;
+            } else if !after_double_dash && arg == "--allow-truncated" {
+                allow_truncated = true;
+            } else if !after_double_dash && arg == "--allow-unknown-version" {
+                allow_unknown_version = true;
+            } else if !after_double_dash && arg == "--multi-doc" {
+                multi_doc = true;
+            } else if !after_double_dash && arg == "--salvage" {
+                salvage = true;
+            } else if !after_double_dash && arg == "--strict-names" {
+                strict_names = true;
+            } else if !after_double_dash && arg == "--max-name-length" {
+                let value = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                max_name_length = Some(value.parse::<usize>().map_err(|_| {
+                    ConversionError::ParseError(format!("Invalid value for {}: {}", arg, value))
+                })?);
+            } else if !after_double_dash && arg == "--invalid-utf8" {
+                let value = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                invalid_utf8 = Some(value.parse::<InvalidUtf8>()?);
+            } else if !after_double_dash && arg == "--emit-type-hints" {
+                emit_type_hints = true;
+            } else if !after_double_dash && arg == "--float-format" {
+                let value = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                float_format = Some(value.parse::<FloatFormat>()?);
+            } else if !after_double_dash && arg == "--float-scientific" {
+                let value = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                let invalid =
+                    || ConversionError::ParseError(format!("Invalid value for {}: {}", arg, value));
+                let (low, high) = value.split_once(':').ok_or_else(invalid)?;
+                float_scientific = Some((
+                    low.parse::<f64>().map_err(|_| invalid())?,
+                    high.parse::<f64>().map_err(|_| invalid())?,
+                ));
+            } else if !after_double_dash && arg == "--idle-timeout" {
+                let value = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                idle_timeout = Some(
+                    value
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                        .ok_or_else(|| {
+                            ConversionError::ParseError(format!(
+                                "Invalid value for {}: {}",
+                                arg, value
+                            ))
+                        })?,
+                );
+            } else if !after_double_dash && arg == "--buffer-size" {
+                let value = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                buffer_size = Some(value.parse::<usize>().map_err(|_| {
+                    ConversionError::ParseError(format!("Invalid value for {}: {}", arg, value))
+                })?);
+            } else if !after_double_dash && arg == "--max-errors" {
+                let value = args.next().ok_or_else(|| {
+                    ConversionError::ParseError(format!("Missing value for {}", arg))
+                })?;
+                max_errors = Some(value.parse::<usize>().map_err(|_| {
+                    ConversionError::ParseError(format!("Invalid value for {}: {}", arg, value))
+                })?);
             } else if input_path.is_none() {
                 input_path = Some(arg.as_str());
             } else if output_path.is_none() {
@@ -521,12 +332,109 @@ impl Cli {
             ConversionError::ParseError("Missing required argument: INPUT".to_string())
         })?;
 
+        let mut options = Self::options(android_dictionary)?;
+        if let Some(path) = dictionary_path {
+            options = options.dictionary(Dictionary::load(path)?);
+        }
+        if let Some(max_errors) = max_errors {
+            options = options.max_errors(max_errors);
+        }
+        if let Some(root) = wrap_roots {
+            options = options.wrap_roots(root);
+        }
+        if emit_type_hints {
+            options = options.emit_type_hints(true);
+        }
+        if fidelity && canonical {
+            return Err(ConversionError::ParseError(
+                "--canonical cannot be combined with --fidelity".to_string(),
+            ));
+        }
+        if fidelity {
+            options = options.fidelity(true);
+        }
+        if canonical {
+            options = options.canonical(true);
+        }
+        if modified_utf8 {
+            options = options.modified_utf8(true);
+        }
+        if allow_truncated {
+            options = options.allow_truncated(true);
+        }
+        if allow_unknown_version {
+            options = options.allow_unknown_version(true);
+        }
+        if multi_doc {
+            options = options.multi_doc(true);
+        }
+        if salvage {
+            options = options.salvage(true);
+        }
+        if strict_names || max_name_length.is_some() {
+            let mut policy = if strict_names {
+                NamePolicy::strict()
+            } else {
+                NamePolicy::new()
+            };
+            if let Some(max_length) = max_name_length {
+                policy = policy.max_length(max_length);
+            }
+            options = options.name_policy(policy);
+        }
+        if let Some(invalid_utf8) = invalid_utf8 {
+            options = options.invalid_utf8(invalid_utf8);
+        }
+        if float_format.is_some() || float_scientific.is_some() {
+            let mut format = float_format.unwrap_or_default();
+            if let Some((low, high)) = float_scientific {
+                format = format.scientific(low, high);
+            }
+            options = options.float_format(format);
+        }
+        if let Some(size) = buffer_size {
+            options = options.read_buffer_size(size).write_buffer_size(size);
+        }
+        if let Some(timeout) = idle_timeout {
+            options = options.idle_timeout(timeout);
+        }
+
         if in_place && input_path == "-" {
             return Err(ConversionError::ParseError(
                 "Cannot use -i option with stdin input".to_string(),
             ));
         }
 
+        let read_command = match read_command {
+            Some(_) if input_path == "-" => {
+                return Err(ConversionError::ParseError(
+                    "Cannot use --su with stdin input".to_string(),
+                ));
+            }
+            Some(_) if in_place || atomic => {
+                return Err(ConversionError::ParseError(
+                    "Cannot use --su with -i or --android-atomic, the input is read-only"
+                        .to_string(),
+                ));
+            }
+            read_command => read_command,
+        };
+
+        if let Some(query) = query {
+            if in_place {
+                return Err(ConversionError::ParseError(
+                    "Cannot use -i option with queries".to_string(),
+                ));
+            }
+            return Self::run_query(
+                input_path,
+                output_path.unwrap_or("-"),
+                query,
+                &options,
+                read_command.as_ref(),
+            );
+        }
+
         let output_path = match output_path {
             Some(path) => path,
             None => {
@@ -538,18 +446,249 @@ impl Cli {
             }
         };
 
+        if options.max_errors.is_some() || options.fidelity || profile || atomic {
+            let mut profiler = profile.then(Profiler::new);
+            let result = Self::convert_buffered(
+                input_path,
+                output_path,
+                &options,
+                atomic,
+                read_command.as_ref(),
+                &mut profiler,
+            );
+            if let Some(profiler) = profiler {
+                profiler.write_to(io::stderr().lock())?;
+            }
+            return result;
+        }
+
         match (input_path, output_path) {
-            ("-", "-") => AbxToXmlConverter::convert_stdin_stdout(),
-            ("-", output) => AbxToXmlConverter::convert_stdin_to_file(output),
-            (input, "-") => AbxToXmlConverter::convert_file_to_stdout(input),
-            (input, output) => AbxToXmlConverter::convert_file(input, output),
+            ("-", _) | (_, "-") => {
+                let reader = Self::open_input(input_path, &options, read_command.as_ref())?;
+                let writer = Self::open_output(output_path, &options)?;
+                AbxToXmlConverter::convert_with_options(reader, writer, &options)
+            }
+            (input, output) if read_command.is_some() => {
+                let reader = Self::open_input(input, &options, read_command.as_ref())?;
+                let writer = Self::open_output(output, &options)?;
+                AbxToXmlConverter::convert_with_options(reader, writer, &options)
+            }
+            (input, output) => {
+                AbxToXmlConverter::convert_file_with_options(input, output, &options)
+            }
+        }
+    }
+
+    /// `--android-cli-compat`: Android's argument handling and defaults, with the
+    /// input read completely so `-i` can overwrite it
+    fn run_android_compat(args: &[String]) -> Result<()> {
+        let Some((input_path, output_path)) = parse_android_cli_args(args) else {
+            eprint!("{}", ANDROID_CLI_USAGE);
+            std::process::exit(1);
+        };
+
+        let mut input = Vec::new();
+        if input_path == "-" {
+            io::stdin().lock().read_to_end(&mut input)?;
+        } else {
+            File::open(input_path)?.read_to_end(&mut input)?;
+        }
+        let mut output = Vec::new();
+        AbxToXmlConverter::convert(input.as_slice(), &mut output)?;
+
+        let options = Options::default();
+        let mut writer = Self::open_output(output_path, &options)?;
+        writer.write_all(&output)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Converts with the whole input and output in memory, printing the diagnostics
+    /// report of lenient mode to stderr and timing each phase when profiling.
+    ///
+    /// Reading the input first also keeps in-place conversion working, including
+    /// with `atomic` files.
+    fn convert_buffered(
+        input_path: &str,
+        output_path: &str,
+        options: &Options,
+        atomic: bool,
+        read_command: Option<&ReadCommand>,
+        profiler: &mut Option<Profiler>,
+    ) -> Result<()> {
+        let mut input = Vec::new();
+        if atomic && input_path != "-" {
+            AtomicFile::new(input_path)
+                .open_read()?
+                .read_to_end(&mut input)?;
+        } else {
+            Self::open_input(input_path, options, read_command)?.read_to_end(&mut input)?;
+        }
+        if let Some(profiler) = profiler {
+            profiler.phase("read");
+        }
+
+        let mut output = Vec::new();
+        let mut diagnostics = Diagnostics::new();
+        let result = AbxToXmlConverter::convert_with_diagnostics(
+            input.as_slice(),
+            &mut output,
+            options,
+            &mut diagnostics,
+        );
+        if let Some(profiler) = profiler {
+            profiler.phase("convert");
+        }
+        if !diagnostics.is_empty() {
+            diagnostics.write_to(io::stderr().lock())?;
+        }
+        result?;
+        if options.fidelity {
+            AbxToXmlConverter::check_fidelity(&input, &output, options)?;
+        }
+
+        if atomic && output_path != "-" {
+            AtomicFile::new(output_path).write_with(|writer| Ok(writer.write_all(&output)?))?;
+        } else {
+            let mut writer = Self::open_output(output_path, options)?;
+            writer.write_all(&output)?;
+            writer.flush()?;
+        }
+        if let Some(profiler) = profiler {
+            profiler.phase("write");
+            profiler.sizes(input.len() as u64, output.len() as u64);
+        }
+        Ok(())
+    }
+
+    fn options(android_dictionary: bool) -> Result<Options> {
+        #[cfg(feature = "android-dictionary")]
+        return Ok(Options::default().android_dictionary(android_dictionary));
+
+        #[cfg(not(feature = "android-dictionary"))]
+        if android_dictionary {
+            Err(ConversionError::ParseError(
+                "--android-dictionary requires the android-dictionary feature".to_string(),
+            ))
+        } else {
+            Ok(Options::default())
+        }
+    }
+
+    fn open_input(
+        input_path: &str,
+        options: &Options,
+        read_command: Option<&ReadCommand>,
+    ) -> Result<Box<dyn Read>> {
+        let idle = options.idle_timeout;
+        if input_path == "-" {
+            Ok(decompress_if_gzip(IdleTimeoutReader::wrap(
+                io::stdin(),
+                idle,
+            ))?)
+        } else if let Some(command) = read_command {
+            Ok(decompress_if_gzip(IdleTimeoutReader::wrap(
+                command.open(input_path)?,
+                idle,
+            ))?)
+        } else {
+            let file = IdleTimeoutReader::wrap(File::open(input_path)?, idle);
+            Ok(Box::new(BufReader::with_capacity(
+                options.read_buffer_size,
+                file,
+            )))
         }
     }
+
+    fn open_output(output_path: &str, options: &Options) -> Result<Box<dyn Write>> {
+        let size = options.write_buffer_size;
+        if output_path == "-" {
+            Ok(Box::new(BufWriter::with_capacity(
+                size,
+                io::stdout().lock(),
+            )))
+        } else {
+            Ok(Box::new(BufWriter::with_capacity(
+                size,
+                File::create(output_path)?,
+            )))
+        }
+    }
+
+    fn run_query(
+        input_path: &str,
+        output_path: &str,
+        query: Query,
+        options: &Options,
+        read_command: Option<&ReadCommand>,
+    ) -> Result<()> {
+        let reader = Self::open_input(input_path, options, read_command)?;
+        let writer = Self::open_output(output_path, options)?;
+
+        let matches = match query {
+            Query::Extract(path) => AbxToXmlConverter::extract_attribute(reader, writer, path)?,
+            Query::Count(path) => {
+                let count = AbxToXmlConverter::count_elements(reader, path)?;
+                let mut writer = writer;
+                writeln!(writer, "{}", count)?;
+                writer.flush()?;
+                count
+            }
+            Query::Summary => {
+                AbxToXmlConverter::summarize(reader)?.write_to(writer)?;
+                return Ok(());
+            }
+            Query::Dump => {
+                let mut writer = writer;
+                RawDocument::decode(reader)?.dump_to(&mut writer)?;
+                writer.flush()?;
+                return Ok(());
+            }
+            Query::VerifyPool => {
+                let report = AbxToXmlConverter::verify_pool(reader)?;
+                report.write_to(writer)?;
+                if report.has_errors() {
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
+            Query::VerifyAndroid => {
+                let verification = AndroidVerification::check(reader)?;
+                verification.write_to(writer)?;
+                if !verification.is_valid() {
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
+            Query::ExportPool(path) => {
+                AbxToXmlConverter::export_pool(reader)?.save(path)?;
+                return Ok(());
+            }
+            Query::CheckPool(path) => {
+                let snapshot = Dictionary::load(path)?;
+                let comparison = AbxToXmlConverter::compare_pool(reader, &snapshot)?;
+                comparison.write_to(writer)?;
+                if !comparison.is_compatible() {
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
+        };
+
+        if matches == 0 {
+            // like grep, signal "nothing found" to scripts through the exit status
+            std::process::exit(1);
+        }
+        Ok(())
+    }
 }
 
 fn main() {
     if let Err(e) = Cli::run() {
         eprintln!("Error: {}", e);
+        if let Some(hint) = error_hint(&e) {
+            eprintln!("Note: {}", hint);
+        }
         std::process::exit(1);
     }
 }
@@ -0,0 +1,187 @@
+use android_xml_converter::atomic::AtomicFile;
+use android_xml_converter::*;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+// ============================================================================
+// CLI
+// ============================================================================
+
+fn print_help(program_name: &str) {
+    eprintln!("Usage: {} [OPTIONS] <input> [output]", program_name);
+    eprintln!("       {} -i [OPTIONS] <input>...", program_name);
+    eprintln!();
+    eprintln!("Converts ABX to XML and XML to ABX, telling them apart by content.");
+    eprintln!();
+    eprintln!("Arguments:");
+    eprintln!("  input              Input file path (use '-' for stdin, may be gzipped)");
+    eprintln!("  output             Output file path (use '-' for stdout, the default)");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  -i, --in-place     Replace each input file with its conversion");
+    eprintln!("      --to FORMAT    abx or xml: only convert into FORMAT, passing input that");
+    eprintln!("                     already is FORMAT through unchanged");
+    eprintln!("  -h, --help         Show this help message");
+}
+
+/// Target format of `--to`
+#[derive(Clone, Copy, PartialEq)]
+enum Target {
+    Abx,
+    Xml,
+}
+
+fn parse_target(name: &str) -> Result<Target> {
+    match name {
+        "abx" => Ok(Target::Abx),
+        "xml" => Ok(Target::Xml),
+        _ => Err(ConversionError::ParseError(format!(
+            "Unknown format '{}' (expected abx or xml)",
+            name
+        ))),
+    }
+}
+
+fn read_input(input_path: &str) -> Result<Vec<u8>> {
+    let mut input = Vec::new();
+    if input_path == "-" {
+        decompress_if_gzip(io::stdin().lock())?.read_to_end(&mut input)?;
+    } else {
+        decompress_if_gzip(File::open(input_path)?)?.read_to_end(&mut input)?;
+    }
+    Ok(input)
+}
+
+/// The input in the other format, or `None` if it already is `target`
+fn convert(input: &[u8], target: Option<Target>, options: &Options) -> Result<Option<Vec<u8>>> {
+    let from = match detect_format(input) {
+        Format::Abx => Target::Abx,
+        Format::Xml => Target::Xml,
+        Format::Unknown => {
+            return Err(ConversionError::ParseError(
+                "Input is neither ABX nor XML".to_string(),
+            ));
+        }
+    };
+    if target == Some(from) {
+        return Ok(None);
+    }
+
+    let mut output = Vec::new();
+    match from {
+        Target::Abx => AbxToXmlConverter::convert_with_options(input, &mut output, options)?,
+        Target::Xml => XmlToAbxConverter::convert_from_reader_with(input, &mut output, options)?,
+    }
+    Ok(Some(output))
+}
+
+fn convert_in_place(path: &str, target: Option<Target>, options: &Options) -> Result<()> {
+    if path == "-" {
+        return Err(ConversionError::ParseError(
+            "Cannot use -i option with stdin input".to_string(),
+        ));
+    }
+    let input = read_input(path)?;
+    if let Some(output) = convert(&input, target, options)? {
+        AtomicFile::new(path).write_with(|writer| Ok(writer.write_all(&output)?))?;
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<bool> {
+    let mut in_place = false;
+    let mut target = None;
+    let mut paths = Vec::new();
+    let mut after_double_dash = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !after_double_dash && arg == "--" {
+            after_double_dash = true;
+        } else if !after_double_dash && (arg == "-i" || arg == "--in-place") {
+            in_place = true;
+        } else if !after_double_dash && arg == "--to" {
+            let value = args
+                .next()
+                .ok_or_else(|| ConversionError::ParseError(format!("Missing value for {}", arg)))?;
+            target = Some(parse_target(value)?);
+        } else if !after_double_dash && arg.starts_with('-') && arg != "-" {
+            return Err(ConversionError::ParseError(format!(
+                "Unknown option: {}",
+                arg
+            )));
+        } else {
+            paths.push(arg.as_str());
+        }
+    }
+
+    if paths.is_empty() {
+        return Err(ConversionError::ParseError(
+            "Missing required argument: input".to_string(),
+        ));
+    }
+    let options = Options::default();
+
+    if in_place {
+        // keep going through a dump, reporting each file that failed
+        let mut succeeded = true;
+        for path in paths {
+            if let Err(e) = convert_in_place(path, target, &options) {
+                eprintln!("Error: {}: {}", path, e);
+                succeeded = false;
+            }
+        }
+        return Ok(succeeded);
+    }
+
+    let (input_path, output_path) = match paths.as_slice() {
+        [input] => (*input, "-"),
+        [input, output] => (*input, *output),
+        [_, _, extra, ..] => {
+            return Err(ConversionError::ParseError(format!(
+                "Unexpected argument: {}",
+                extra
+            )));
+        }
+        [] => unreachable!(),
+    };
+
+    let input = read_input(input_path)?;
+    let output = convert(&input, target, &options)?.unwrap_or(input);
+    if output_path == "-" {
+        io::stdout().lock().write_all(&output)?;
+    } else {
+        File::create(output_path)?.write_all(&output)?;
+    }
+    Ok(true)
+}
+
+fn main() {
+    let mut args = env::args();
+    let bin_name = args
+        .next()
+        .as_ref()
+        .and_then(|p| std::path::Path::new(p).file_name())
+        .and_then(|n| n.to_str())
+        .unwrap_or("abxconv")
+        .to_string();
+
+    let args: Vec<String> = args.collect();
+    if args.is_empty() || args.iter().any(|a| a == "-h" || a == "--help") {
+        print_help(&bin_name);
+        std::process::exit(if args.is_empty() { 1 } else { 0 });
+    }
+
+    match run(&args) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            if let Some(hint) = error_hint(&e) {
+                eprintln!("Note: {}", hint);
+            }
+            std::process::exit(1);
+        }
+    }
+}
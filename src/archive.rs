@@ -0,0 +1,277 @@
+use crate::*;
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::io::{self, BufRead, Cursor, Read, Write};
+
+// ============================================================================
+// Archive Entries
+// ============================================================================
+
+/// Header line that starts every `adb backup` archive
+pub const ANDROID_BACKUP_MAGIC: &[u8] = b"ANDROID BACKUP\n";
+
+/// Bytes of each entry inspected to decide whether it is a document
+const SNIFF_SIZE: usize = 512;
+
+/// Kind of document found inside an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryFormat {
+    Abx,
+    Xml,
+}
+
+/// Which embedded documents are rewritten by [`convert_tar`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryConversion {
+    AbxToXml,
+    XmlToAbx,
+}
+
+/// A document located inside an archive
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    pub path: String,
+    pub size: u64,
+    pub format: EntryFormat,
+}
+
+/// Detects ABX by magic and XML by a `.xml` name whose content starts with markup
+fn sniff_format(path: &str, prefix: &[u8]) -> Option<EntryFormat> {
+    match detect_format(prefix) {
+        Format::Abx => Some(EntryFormat::Abx),
+        Format::Xml if path.ends_with(".xml") => Some(EntryFormat::Xml),
+        _ => None,
+    }
+}
+
+/// Reads up to [`SNIFF_SIZE`] bytes without failing on short entries
+fn read_prefix<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut prefix = Vec::with_capacity(SNIFF_SIZE);
+    reader.take(SNIFF_SIZE as u64).read_to_end(&mut prefix)?;
+    Ok(prefix)
+}
+
+// ============================================================================
+// Tar Streams
+// ============================================================================
+
+/// Lists the ABX and XML documents in a tar stream
+pub fn list_tar<R: Read>(reader: R) -> Result<Vec<ArchiveEntry>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut documents = Vec::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        let prefix = read_prefix(&mut entry)?;
+        if let Some(format) = sniff_format(&path, &prefix) {
+            documents.push(ArchiveEntry {
+                path,
+                size: entry.size(),
+                format,
+            });
+        }
+    }
+
+    Ok(documents)
+}
+
+/// Copies a tar stream, converting the embedded documents selected by `conversion`.
+///
+/// All other entries are copied unchanged. A document that fails to convert is
+/// reported on stderr and copied unchanged as well. Headers are copied as
+/// stored, so member names keep their exact spelling, e.g. a leading `./`,
+/// and GNU long name and PAX records stay in place; converted entries only get
+/// a new size. Returns the number of converted entries.
+pub fn convert_tar<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    conversion: EntryConversion,
+    options: &Options,
+) -> Result<usize> {
+    let mut archive = tar::Archive::new(reader);
+    let mut builder = tar::Builder::new(writer);
+    let mut converted = 0;
+    // name of the next entry from a GNU long name or PAX record
+    let mut long_path: Option<Vec<u8>> = None;
+
+    for entry in archive.entries()?.raw(true) {
+        let mut entry = entry?;
+        let mut header = entry.header().clone();
+        let entry_type = header.entry_type();
+        if entry_type.is_gnu_longname() || entry_type.is_pax_local_extensions() {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            long_path = if entry_type.is_gnu_longname() {
+                Some(data.split(|&byte| byte == 0).next().unwrap_or(&[]).to_vec())
+            } else {
+                tar::PaxExtensions::new(&data)
+                    .filter_map(|extension| extension.ok())
+                    .find(|extension| extension.key_bytes() == b"path")
+                    .map(|extension| extension.value_bytes().to_vec())
+            };
+            builder.append(&header, data.as_slice())?;
+            continue;
+        }
+        let path = long_path
+            .take()
+            .unwrap_or_else(|| entry.path_bytes().into_owned());
+        let path = String::from_utf8_lossy(&path);
+        let prefix = read_prefix(&mut entry)?;
+
+        let format = if entry_type.is_file() {
+            sniff_format(&path, &prefix)
+        } else {
+            None
+        };
+        let selected = matches!(
+            (format, conversion),
+            (Some(EntryFormat::Abx), EntryConversion::AbxToXml)
+                | (Some(EntryFormat::Xml), EntryConversion::XmlToAbx)
+        );
+
+        if !selected {
+            builder.append(&header, Cursor::new(prefix).chain(entry))?;
+            continue;
+        }
+
+        let mut data = prefix;
+        entry.read_to_end(&mut data)?;
+        match convert_entry(&data, conversion, options) {
+            Ok(output) => {
+                header.set_size(output.len() as u64);
+                header.set_cksum();
+                builder.append(&header, output.as_slice())?;
+                converted += 1;
+            }
+            Err(e) => {
+                eprintln!("Warning: {}: {}, copied unchanged", path, e);
+                builder.append(&header, data.as_slice())?;
+            }
+        }
+    }
+
+    builder.into_inner()?.flush()?;
+    Ok(converted)
+}
+
+fn convert_entry(data: &[u8], conversion: EntryConversion, options: &Options) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    match conversion {
+        EntryConversion::AbxToXml => {
+            AbxToXmlConverter::convert_with_options(data, &mut output, options)?
+        }
+        EntryConversion::XmlToAbx => {
+            let xml = std::str::from_utf8(data)?;
+            XmlToAbxConverter::convert_from_string_with(xml, &mut output, options)?
+        }
+    }
+    Ok(output)
+}
+
+// ============================================================================
+// Android Backups
+// ============================================================================
+
+/// The text header of an `adb backup` archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupHeader {
+    pub version: String,
+    pub compressed: bool,
+    pub encryption: String,
+}
+
+impl BackupHeader {
+    /// Reads the header lines following [`ANDROID_BACKUP_MAGIC`]
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Self> {
+        let mut magic = Vec::new();
+        reader.read_until(b'\n', &mut magic)?;
+        if magic != ANDROID_BACKUP_MAGIC {
+            return Err(ConversionError::ParseError(
+                "Not an Android backup archive".to_string(),
+            ));
+        }
+
+        let mut read_line = |field: &str| -> Result<String> {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            if !line.ends_with('\n') {
+                return Err(ConversionError::ParseError(format!(
+                    "Truncated Android backup header: missing {}",
+                    field
+                )));
+            }
+            Ok(line.trim_end().to_string())
+        };
+
+        let version = read_line("version")?;
+        let compressed = read_line("compression flag")? == "1";
+        let encryption = read_line("encryption")?;
+
+        if encryption != "none" {
+            return Err(ConversionError::ParseError(format!(
+                "Encrypted Android backups ({}) are not supported",
+                encryption
+            )));
+        }
+
+        Ok(Self {
+            version,
+            compressed,
+            encryption,
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(ANDROID_BACKUP_MAGIC)?;
+        writeln!(writer, "{}", self.version)?;
+        writeln!(writer, "{}", if self.compressed { 1 } else { 0 })?;
+        writeln!(writer, "{}", self.encryption)?;
+        Ok(())
+    }
+
+    /// Wraps the stream following the header in a decompressor if needed
+    pub fn tar_reader<'a, R: Read + 'a>(&self, reader: R) -> Box<dyn Read + 'a> {
+        if self.compressed {
+            Box::new(ZlibDecoder::new(reader))
+        } else {
+            Box::new(reader)
+        }
+    }
+}
+
+/// Whether the stream starts like an `adb backup` archive
+pub fn is_android_backup(prefix: &[u8]) -> bool {
+    prefix.starts_with(ANDROID_BACKUP_MAGIC)
+}
+
+pub fn list_android_backup<R: BufRead>(mut reader: R) -> Result<Vec<ArchiveEntry>> {
+    let header = BackupHeader::read_from(&mut reader)?;
+    list_tar(header.tar_reader(reader))
+}
+
+/// Rewrites an `adb backup` archive like [`convert_tar`], keeping its header and
+/// compression setting
+pub fn convert_android_backup<R: BufRead, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    conversion: EntryConversion,
+    options: &Options,
+) -> Result<usize> {
+    let header = BackupHeader::read_from(&mut reader)?;
+    header.write_to(&mut writer)?;
+    let tar = header.tar_reader(reader);
+
+    if header.compressed {
+        let mut encoder = ZlibEncoder::new(writer, Compression::default());
+        let converted = convert_tar(tar, &mut encoder, conversion, options)?;
+        encoder.finish()?.flush()?;
+        Ok(converted)
+    } else {
+        convert_tar(tar, writer, conversion, options)
+    }
+}
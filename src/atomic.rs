@@ -0,0 +1,128 @@
+use crate::*;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Android AtomicFile
+// ============================================================================
+
+/// Writes a file the way Android's `android.util.AtomicFile` does, so system
+/// files under /data/system survive a reboot in the middle of a write.
+///
+/// New content goes to `<file>.new`, which is synced and then renamed over the
+/// file. A `<file>.bak` left behind by older Android versions holds the last
+/// complete content and is restored before reading or writing; a stale
+/// `<file>.new` is an interrupted write and is discarded on read.
+///
+/// The replacement keeps the permissions of the file it replaces and, when
+/// running as root, its owner, since system_server must still be able to read
+/// it afterwards.
+#[derive(Debug, Clone)]
+pub struct AtomicFile {
+    base: PathBuf,
+    new: PathBuf,
+    legacy_backup: PathBuf,
+}
+
+impl AtomicFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let base = path.as_ref().to_path_buf();
+        let with_suffix = |suffix: &str| {
+            let mut name = base.clone().into_os_string();
+            name.push(suffix);
+            PathBuf::from(name)
+        };
+        Self {
+            new: with_suffix(".new"),
+            legacy_backup: with_suffix(".bak"),
+            base,
+        }
+    }
+
+    pub fn base_path(&self) -> &Path {
+        &self.base
+    }
+
+    /// Opens the last complete version of the file for reading
+    pub fn open_read(&self) -> Result<File> {
+        self.restore_legacy_backup()?;
+        if self.new.exists() && self.base.exists() {
+            fs::remove_file(&self.new)?;
+        }
+        Ok(File::open(&self.base)?)
+    }
+
+    /// Creates `<file>.new` to write the new content to; pass it to
+    /// [`Self::finish_write`] or [`Self::fail_write`] when done
+    pub fn start_write(&self) -> Result<File> {
+        self.restore_legacy_backup()?;
+        Ok(File::create(&self.new)?)
+    }
+
+    /// Syncs the new content and moves it over the file
+    pub fn finish_write(&self, file: File) -> Result<()> {
+        file.sync_all()?;
+        drop(file);
+        if let Ok(metadata) = fs::metadata(&self.base) {
+            fs::set_permissions(&self.new, metadata.permissions())?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                // only root may hand files to another user; elsewhere the owner
+                // is already the caller
+                let _ =
+                    std::os::unix::fs::chown(&self.new, Some(metadata.uid()), Some(metadata.gid()));
+            }
+        }
+        fs::rename(&self.new, &self.base)?;
+        self.sync_directory();
+        Ok(())
+    }
+
+    /// Discards the new content, leaving the file untouched
+    pub fn fail_write(&self, file: File) {
+        drop(file);
+        let _ = fs::remove_file(&self.new);
+    }
+
+    /// Writes the file through a buffered writer, replacing it only if `write`
+    /// and flushing succeed
+    pub fn write_with<F>(&self, write: F) -> Result<()>
+    where
+        F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+    {
+        let mut writer = BufWriter::new(self.start_write()?);
+        let file =
+            write(&mut writer).and_then(|_| writer.into_inner().map_err(|e| e.into_error().into()));
+        match file {
+            Ok(file) => self.finish_write(file),
+            Err(e) => {
+                let _ = fs::remove_file(&self.new);
+                Err(e)
+            }
+        }
+    }
+
+    fn restore_legacy_backup(&self) -> Result<()> {
+        if self.legacy_backup.exists() {
+            fs::rename(&self.legacy_backup, &self.base)?;
+        }
+        Ok(())
+    }
+
+    /// Makes the rename itself durable; failures only lose durability, not data
+    fn sync_directory(&self) {
+        #[cfg(unix)]
+        if let Some(parent) = self.base.parent() {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            if let Ok(dir) = File::open(parent) {
+                let _ = dir.sync_all();
+            }
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use crate::dom::{AbxDocument, Element};
+use crate::query::PoolReport;
+use crate::*;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Directory Audit
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// One problem found in one file
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub path: PathBuf,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Health check over a directory tree such as an extracted /data/system: every
+/// ABX file and every `.xml` file must decode, and the documents Android keeps
+/// there must hold their invariants.
+///
+/// Checked are:
+/// - ABX intern pools (invalid back-references, strings defined twice)
+/// - `packages.xml`: unique package names and `userId`s, also against
+///   `shared-user`s, and `sharedUserId`s that name an existing shared user
+/// - `settings_*.xml`: unique `id`s and `name`s
+/// - SharedPreferences files: unique names, and `int`, `long`, `float` and
+///   `boolean` entries whose value parses as that type
+///
+/// ```
+/// use android_xml_converter::XmlToAbxConverter;
+/// use android_xml_converter::audit::AuditReport;
+///
+/// let dir = std::env::temp_dir().join(format!("abx-audit-doctest-{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let packages = r#"<packages><package name="a" userId="10001"/><package name="b" userId="10001"/></packages>"#;
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string(packages, &mut abx).unwrap();
+/// std::fs::write(dir.join("packages.xml"), abx).unwrap();
+/// std::fs::write(dir.join("prefs.xml"), r#"<map><int name="n" value="ten"/></map>"#).unwrap();
+///
+/// let report = AuditReport::audit_dir(&dir).unwrap();
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// assert_eq!((report.abx_files, report.xml_files), (1, 1));
+/// assert_eq!(report.findings.len(), 2);
+/// assert!(report.has_errors());
+/// ```
+#[derive(Debug, Default)]
+pub struct AuditReport {
+    pub abx_files: usize,
+    pub xml_files: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl AuditReport {
+    /// Audits every file under `dir`, in path order. Files that are neither ABX
+    /// nor named `.xml` are skipped.
+    pub fn audit_dir<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let mut files = Vec::new();
+        collect_files(dir.as_ref(), &mut files)?;
+        files.sort();
+
+        let mut report = Self::default();
+        for path in files {
+            report.audit_file(&path)?;
+        }
+        Ok(report)
+    }
+
+    /// Audits one file, failing only if it cannot be read
+    pub fn audit_file(&mut self, path: &Path) -> Result<()> {
+        let data = fs::read(path)?;
+        let mut findings = Vec::new();
+
+        let document = if detect_format(&data) == Format::Abx {
+            self.abx_files += 1;
+            check_pool(&data, &mut findings);
+            AbxDocument::from_bytes(&data).map_err(|e| format!("not decodable: {}", e))
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("xml"))
+        {
+            self.xml_files += 1;
+            parse_xml(&data).map_err(|e| format!("not well-formed: {}", e))
+        } else {
+            return Ok(());
+        };
+
+        match document {
+            Ok(document) => check_invariants(&document, &mut findings),
+            Err(message) => findings.push((Severity::Error, message)),
+        }
+        self.findings
+            .extend(findings.into_iter().map(|(severity, message)| Finding {
+                path: path.to_path_buf(),
+                severity,
+                message,
+            }));
+        Ok(())
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == Severity::Error)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    pub fn write_to<W: Write>(&self, mut output: W) -> Result<()> {
+        for finding in &self.findings {
+            let severity = match finding.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            writeln!(
+                output,
+                "{}: {}: {}",
+                finding.path.display(),
+                severity,
+                finding.message
+            )?;
+        }
+        writeln!(
+            output,
+            "audited {} ABX and {} XML files: {} error(s), {} warning(s)",
+            self.abx_files,
+            self.xml_files,
+            self.error_count(),
+            self.findings.len() - self.error_count()
+        )?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+fn parse_xml(data: &[u8]) -> Result<AbxDocument> {
+    let xml = std::str::from_utf8(data)?;
+    let mut abx = Vec::new();
+    XmlToAbxConverter::convert_from_string(xml, &mut abx)?;
+    AbxDocument::from_bytes(&abx)
+}
+
+// ============================================================================
+// Checks
+// ============================================================================
+
+type Findings = Vec<(Severity, String)>;
+
+fn check_pool(data: &[u8], findings: &mut Findings) {
+    let pool = match PoolReport::collect(data) {
+        Ok(pool) => pool,
+        // the decoder reports the same problem
+        Err(_) => return,
+    };
+    for (index, token) in &pool.invalid_indices {
+        findings.push((
+            Severity::Error,
+            format!(
+                "intern pool index {} out of range in token #{}",
+                index, token
+            ),
+        ));
+    }
+    for (string, indices) in &pool.duplicates {
+        findings.push((
+            Severity::Warning,
+            format!(
+                "intern pool string {:?} defined {} times",
+                string,
+                indices.len()
+            ),
+        ));
+    }
+}
+
+fn check_invariants(document: &AbxDocument, findings: &mut Findings) {
+    let Some(root) = document.root() else {
+        return;
+    };
+    match root.name() {
+        "packages" => check_packages(root, findings),
+        "settings" => {
+            check_unique(root, "setting", "id", findings);
+            check_unique(root, "setting", "name", findings);
+        }
+        "map" => check_preferences(root, findings),
+        _ => {}
+    }
+}
+
+/// Reports children named `element` sharing a value of `attribute`
+fn check_unique(root: Element, element: &str, attribute: &str, findings: &mut Findings) {
+    let mut seen = FastHashMap::default();
+    for child in root.children().filter(|child| child.name() == element) {
+        let Some(value) = child.attribute(attribute) else {
+            continue;
+        };
+        let value = value.to_string();
+        let count = seen.entry(value.clone()).or_insert(0);
+        *count += 1;
+        if *count == 2 {
+            findings.push((
+                Severity::Error,
+                format!("<{}> {}={:?} is not unique", element, attribute, value),
+            ));
+        }
+    }
+}
+
+fn check_packages(root: Element, findings: &mut Findings) {
+    check_unique(root, "package", "name", findings);
+
+    let user_id = |element: Element, attribute: &str, findings: &mut Findings| {
+        let value = element.attribute(attribute)?;
+        match value.to_i32() {
+            Ok(id) => Some(id),
+            Err(e) => {
+                findings.push((
+                    Severity::Error,
+                    format!("<{}> {}: {}", element.name(), attribute, e),
+                ));
+                None
+            }
+        }
+    };
+    let label = |element: Element| {
+        element
+            .attribute("name")
+            .map_or_else(|| element.name().to_string(), |name| name.to_string())
+    };
+
+    // owner of every userId, packages and shared users alike
+    let mut owners: FastHashMap<i32, String> = FastHashMap::default();
+    for element in root.children() {
+        if !matches!(element.name(), "package" | "shared-user") {
+            continue;
+        }
+        let Some(id) = user_id(element, "userId", findings) else {
+            continue;
+        };
+        let owner = label(element);
+        if let Some(other) = owners.insert(id, owner.clone()) {
+            findings.push((
+                Severity::Error,
+                format!("userId {} used by both {} and {}", id, other, owner),
+            ));
+        }
+    }
+
+    let shared: Vec<i32> = root
+        .children()
+        .filter(|element| element.name() == "shared-user")
+        .filter_map(|element| element.attribute("userId")?.to_i32().ok())
+        .collect();
+    for package in root
+        .children()
+        .filter(|element| element.name() == "package")
+    {
+        if let Some(id) = user_id(package, "sharedUserId", findings)
+            && !shared.contains(&id)
+        {
+            findings.push((
+                Severity::Error,
+                format!(
+                    "package {} refers to missing shared user {}",
+                    label(package),
+                    id
+                ),
+            ));
+        }
+    }
+}
+
+fn check_preferences(root: Element, findings: &mut Findings) {
+    let mut seen = FastHashSet::default();
+    for entry in root.children() {
+        let Some(name) = entry.attribute("name").map(|name| name.to_string()) else {
+            continue;
+        };
+        if !seen.insert(name.clone()) {
+            findings.push((
+                Severity::Error,
+                format!("preference {:?} is not unique", name),
+            ));
+        }
+
+        let Some(value) = entry.attribute("value") else {
+            continue;
+        };
+        let parsed = match entry.name() {
+            "int" => value.to_i32().map(drop),
+            "long" => value.to_i64().map(drop),
+            "float" => value.to_f32().map(drop),
+            "boolean" => value.to_bool().map(drop),
+            _ => Ok(()),
+        };
+        if let Err(e) = parsed {
+            findings.push((
+                Severity::Error,
+                format!(
+                    "preference {:?} is not a valid {}: {}",
+                    name,
+                    entry.name(),
+                    e
+                ),
+            ));
+        }
+    }
+}
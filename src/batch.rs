@@ -0,0 +1,279 @@
+use crate::diagnostics::Diagnostics;
+use crate::hash::CanonicalHasher;
+use crate::*;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Batch Planning
+// ============================================================================
+
+/// One file of a batch conversion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchItem {
+    pub input: PathBuf,
+    pub output: PathBuf,
+}
+
+/// Expands the inputs into items whose outputs live under `out_dir`.
+///
+/// A file input keeps its file name; a directory input is walked recursively and
+/// its layout mirrored. Items are sorted by path so runs are reproducible.
+pub fn plan(inputs: &[PathBuf], out_dir: &Path) -> Result<Vec<BatchItem>> {
+    let mut items = Vec::new();
+
+    for input in inputs {
+        if input.is_dir() {
+            let mut files = Vec::new();
+            collect_files(input, &mut files)?;
+            files.sort();
+            for file in files {
+                let relative = file.strip_prefix(input).unwrap_or(&file).to_path_buf();
+                items.push(BatchItem {
+                    output: out_dir.join(relative),
+                    input: file,
+                });
+            }
+        } else {
+            let name = input.file_name().ok_or_else(|| {
+                ConversionError::ParseError(format!("Not a file: {}", input.display()))
+            })?;
+            items.push(BatchItem {
+                input: input.clone(),
+                output: out_dir.join(name),
+            });
+        }
+    }
+
+    Ok(items)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Conversion
+// ============================================================================
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex_string(&Sha256::digest(data))
+}
+
+/// Digest of the canonical token dump, which only changes when the typed content
+/// changes, whatever the intern pool layout or conversion direction
+fn canonical_sha256(abx_data: &[u8], options: &Options) -> Result<String> {
+    Ok(hex_string(&CanonicalHasher::<Sha256>::hash_abx(
+        abx_data, options,
+    )?))
+}
+
+/// Converts one file in the direction its content calls for: ABX input becomes XML,
+/// anything else is parsed as XML and becomes ABX.
+pub fn convert_item(item: &BatchItem, options: &Options) -> Result<ManifestEntry> {
+    let mut diagnostics = Diagnostics::new();
+    let result = convert_item_with_diagnostics(item, options, &mut diagnostics);
+    if !diagnostics.is_empty() {
+        diagnostics.write_to(std::io::stderr().lock())?;
+    }
+    result
+}
+
+/// Converts like [`convert_item`], handing back the warnings instead of printing them
+pub fn convert_item_with_diagnostics(
+    item: &BatchItem,
+    options: &Options,
+    diagnostics: &mut Diagnostics,
+) -> Result<ManifestEntry> {
+    let input = fs::read(&item.input)?;
+    let mut output = Vec::new();
+
+    let canonical = if detect_format(&input) == Format::Abx {
+        AbxToXmlConverter::convert_with_diagnostics(
+            input.as_slice(),
+            &mut output,
+            options,
+            diagnostics,
+        )?;
+        canonical_sha256(&input, options)?
+    } else {
+        let xml = std::str::from_utf8(&input)?;
+        XmlToAbxConverter::convert_from_string_with_diagnostics(
+            xml,
+            &mut output,
+            options,
+            diagnostics,
+        )?;
+        canonical_sha256(&output, options)?
+    };
+
+    if let Some(parent) = item.output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&item.output, &output)?;
+
+    Ok(ManifestEntry {
+        input: item.input.clone(),
+        input_sha256: sha256_hex(&input),
+        output: item.output.clone(),
+        output_sha256: sha256_hex(&output),
+        canonical_sha256: canonical,
+    })
+}
+
+// ============================================================================
+// Summary
+// ============================================================================
+
+/// Outcome counts of a batch run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchSummary {
+    pub converted: usize,
+    /// Files left alone because the manifest showed them up to date
+    pub skipped: usize,
+    pub failed: usize,
+    /// Files not attempted because the run stopped at a failure
+    pub not_attempted: usize,
+    /// Warnings over all converted files
+    pub warnings: usize,
+}
+
+impl BatchSummary {
+    pub fn is_success(&self) -> bool {
+        self.failed == 0
+    }
+
+    pub fn write_to<W: Write>(&self, mut output: W) -> Result<()> {
+        writeln!(output, "batch summary:")?;
+        writeln!(output, "  converted     {:>8}", self.converted)?;
+        writeln!(output, "  skipped       {:>8}", self.skipped)?;
+        writeln!(output, "  failed        {:>8}", self.failed)?;
+        if self.not_attempted > 0 {
+            writeln!(output, "  not attempted {:>8}", self.not_attempted)?;
+        }
+        writeln!(output, "  warnings      {:>8}", self.warnings)?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Checksum Manifest
+// ============================================================================
+
+/// Digests recorded for one converted file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub input: PathBuf,
+    pub input_sha256: String,
+    pub output: PathBuf,
+    pub output_sha256: String,
+    pub canonical_sha256: String,
+}
+
+impl ManifestEntry {
+    /// Whether the input is unchanged and the recorded output is still in place,
+    /// so the conversion can be skipped when resuming
+    pub fn is_current(&self, item: &BatchItem) -> bool {
+        let matches = |path: &Path, digest: &str| {
+            fs::read(path).is_ok_and(|data| sha256_hex(&data) == digest)
+        };
+        self.input == item.input
+            && self.output == item.output
+            && matches(&item.input, &self.input_sha256)
+            && matches(&item.output, &self.output_sha256)
+    }
+}
+
+/// A `SHA256SUMS`-style record of a batch conversion.
+///
+/// Each entry is written as two checksum lines, input then output, followed by a
+/// `# canonical` comment with the content digest. The checksum lines can be
+/// verified with `sha256sum -c`, which ignores the comments.
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn find(&self, input: &Path) -> Option<&ManifestEntry> {
+        self.entries.iter().find(|entry| entry.input == input)
+    }
+
+    pub fn read_from<R: BufRead>(reader: R) -> Result<Self> {
+        let invalid =
+            |line: &str| ConversionError::ParseError(format!("Invalid manifest line: {}", line));
+        let checksum_line = |line: &str| -> Result<(String, PathBuf)> {
+            let (digest, path) = line.split_once("  ").ok_or_else(|| invalid(line))?;
+            Ok((digest.to_string(), PathBuf::from(path)))
+        };
+
+        let mut lines = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        }
+
+        let mut entries = Vec::new();
+        for group in lines.chunks(3) {
+            let [input, output, canonical] = group else {
+                return Err(invalid(&group.join(" / ")));
+            };
+            let (input_sha256, input) = checksum_line(input)?;
+            let (output_sha256, output) = checksum_line(output)?;
+            let canonical_sha256 = canonical
+                .strip_prefix("# canonical ")
+                .ok_or_else(|| invalid(canonical))?
+                .to_string();
+            entries.push(ManifestEntry {
+                input,
+                input_sha256,
+                output,
+                output_sha256,
+                canonical_sha256,
+            });
+        }
+
+        Ok(Self { entries })
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        for entry in &self.entries {
+            writeln!(writer, "{}  {}", entry.input_sha256, entry.input.display())?;
+            writeln!(
+                writer,
+                "{}  {}",
+                entry.output_sha256,
+                entry.output.display()
+            )?;
+            writeln!(writer, "# canonical {}", entry.canonical_sha256)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+}
@@ -0,0 +1,179 @@
+use crate::*;
+use std::io::{Read, Write};
+use std::sync::{PoisonError, RwLock};
+
+// ============================================================================
+// Document Codecs
+// ============================================================================
+
+/// A binary document format convertible to and from XML.
+///
+/// XML is the common representation: tools written against this trait convert
+/// between any two registered formats by going through it, and pick up new
+/// formats as they are added to [`codecs`].
+///
+/// ```
+/// use android_xml_converter::codec;
+/// use android_xml_converter::{Options, XmlToAbxConverter};
+///
+/// /// Re-encodes any recognized document in its own format
+/// fn normalize(data: &[u8]) -> android_xml_converter::Result<Vec<u8>> {
+///     let codec = codec::detect(data).expect("unknown format");
+///     let mut output = Vec::new();
+///     codec::convert_between(codec, codec, &mut &data[..], &mut output, &Options::new())?;
+///     Ok(output)
+/// }
+///
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string("<a b=\"1\"/>", &mut abx).unwrap();
+/// assert_eq!(normalize(&abx).unwrap(), abx);
+/// ```
+pub trait DocumentCodec: Send + Sync {
+    /// Short lowercase name, as listed in [`Capabilities::formats`]
+    fn name(&self) -> &'static str;
+
+    /// Whether `header`, the first bytes of a file, identify this format
+    fn detect(&self, header: &[u8]) -> bool;
+
+    /// Reads a document in this format and writes it as XML
+    fn decode(&self, input: &mut dyn Read, output: &mut dyn Write, options: &Options)
+    -> Result<()>;
+
+    /// Writes an XML document in this format
+    fn encode(&self, xml: &str, output: &mut dyn Write, options: &Options) -> Result<()>;
+}
+
+/// Android Binary XML
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AbxCodec;
+
+impl DocumentCodec for AbxCodec {
+    fn name(&self) -> &'static str {
+        "abx"
+    }
+
+    fn detect(&self, header: &[u8]) -> bool {
+        // any version, so that a newer one fails with a clear error
+        FormatVersion::detect(header).is_some()
+    }
+
+    fn decode(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        options: &Options,
+    ) -> Result<()> {
+        AbxToXmlConverter::convert_with_options(input, output, options)
+    }
+
+    fn encode(&self, xml: &str, output: &mut dyn Write, options: &Options) -> Result<()> {
+        XmlToAbxConverter::convert_from_string_with(xml, output, options)
+    }
+}
+
+/// Codecs added with [`register`], in registration order
+static REGISTERED: RwLock<Vec<&'static dyn DocumentCodec>> = RwLock::new(Vec::new());
+
+/// Adds a format at runtime, so an embedding application can plug in e.g. a
+/// plist or JSON variant and have [`codec`], [`detect`], [`capabilities`] and
+/// everything built on them pick it up. Fails if the name is already taken,
+/// `xml` included. Registered codecs live for the rest of the process.
+///
+/// ```
+/// use android_xml_converter::codec::{self, DocumentCodec};
+/// use android_xml_converter::{Options, Result};
+/// use std::io::{Read, Write};
+///
+/// /// XML as a line of hex digits
+/// struct HexCodec;
+///
+/// impl DocumentCodec for HexCodec {
+///     fn name(&self) -> &'static str {
+///         "xml-hex"
+///     }
+///
+///     fn detect(&self, header: &[u8]) -> bool {
+///         header.starts_with(b"3c")
+///     }
+///
+///     fn decode(&self, input: &mut dyn Read, output: &mut dyn Write, _: &Options) -> Result<()> {
+///         let mut hex = String::new();
+///         input.read_to_string(&mut hex)?;
+///         for pair in hex.trim().as_bytes().chunks(2) {
+///             let pair = std::str::from_utf8(pair)?;
+///             let byte = u8::from_str_radix(pair, 16)
+///                 .map_err(|_| android_xml_converter::ConversionError::InvalidHex)?;
+///             output.write_all(&[byte])?;
+///         }
+///         Ok(())
+///     }
+///
+///     fn encode(&self, xml: &str, output: &mut dyn Write, _: &Options) -> Result<()> {
+///         for byte in xml.bytes() {
+///             write!(output, "{:02x}", byte)?;
+///         }
+///         Ok(())
+///     }
+/// }
+///
+/// codec::register(HexCodec).unwrap();
+/// assert!(codec::register(HexCodec).is_err());
+/// assert!(android_xml_converter::capabilities().formats.contains(&"xml-hex"));
+///
+/// let hex = "3c612f3e"; // <a/>
+/// let from = codec::detect(hex.as_bytes()).unwrap();
+/// let mut abx = Vec::new();
+/// codec::convert_between(from, codec::codec("abx").unwrap(), &mut hex.as_bytes(), &mut abx, &Options::new())
+///     .unwrap();
+/// assert!(abx.starts_with(b"ABX\0"));
+/// ```
+pub fn register<C: DocumentCodec + 'static>(codec: C) -> Result<()> {
+    let mut registered = REGISTERED.write().unwrap_or_else(PoisonError::into_inner);
+    let name = codec.name();
+    let taken = name == "xml"
+        || AbxCodec.name() == name
+        || registered.iter().any(|codec| codec.name() == name);
+    if taken {
+        return Err(ConversionError::ParseError(format!(
+            "A format named '{}' is already registered",
+            name
+        )));
+    }
+    registered.push(Box::leak(Box::new(codec)));
+    Ok(())
+}
+
+/// Every codec built into this crate, followed by the registered ones
+pub fn codecs() -> Vec<&'static dyn DocumentCodec> {
+    let mut codecs: Vec<&'static dyn DocumentCodec> = vec![&AbxCodec];
+    codecs.extend(
+        REGISTERED
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter(),
+    );
+    codecs
+}
+
+/// Looks a codec up by [`DocumentCodec::name`]
+pub fn codec(name: &str) -> Option<&'static dyn DocumentCodec> {
+    codecs().into_iter().find(|codec| codec.name() == name)
+}
+
+/// Finds the codec whose format `header` belongs to; `None` usually means XML
+pub fn detect(header: &[u8]) -> Option<&'static dyn DocumentCodec> {
+    codecs().into_iter().find(|codec| codec.detect(header))
+}
+
+/// Converts a document from one format to another through XML
+pub fn convert_between(
+    from: &dyn DocumentCodec,
+    to: &dyn DocumentCodec,
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+    options: &Options,
+) -> Result<()> {
+    let mut xml = Vec::new();
+    from.decode(input, &mut xml, options)?;
+    to.encode(std::str::from_utf8(&xml)?, output, options)
+}
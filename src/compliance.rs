@@ -0,0 +1,340 @@
+use crate::xpath::XPath;
+use crate::*;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Device Profiles
+// ============================================================================
+
+/// What the values a check selects must look like
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    /// At least one value, all equal to this
+    Equals(String),
+    /// No value equal to this
+    NotEquals(String),
+    /// At least one value, all containing this
+    Contains(String),
+    /// Something is selected, or nothing is
+    Exists(bool),
+}
+
+/// One rule of a [`DeviceProfile`]
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    /// File relative to the dump directory, ABX or XML
+    pub file: PathBuf,
+    /// Selection inside the file, see [`XPath`]
+    pub path: String,
+    pub expectation: Expectation,
+    xpath: XPath,
+}
+
+/// Expected configuration of a device, as a list of checks against the files
+/// of a dump such as an extracted /data/system.
+///
+/// Profiles are written in a subset of TOML, one `[[check]]` table per rule:
+///
+/// ```toml
+/// [[check]]
+/// name = "verify apps over USB"
+/// file = "users/0/settings_global.xml"
+/// path = "/settings/setting[@name='verifier_verify_adb_installs']/@value"
+/// equals = "1"
+/// ```
+///
+/// Each check has a `name`, a `file`, a `path` and one of `equals`,
+/// `not_equals`, `contains` (strings) or `exists` (`true` or `false`).
+#[derive(Debug, Clone, Default)]
+pub struct DeviceProfile {
+    pub checks: Vec<Check>,
+}
+
+impl DeviceProfile {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut profile = Self::default();
+        let mut table: Option<Vec<(String, String)>> = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let invalid = |reason: &str| {
+                ConversionError::ParseError(format!(
+                    "Invalid profile line {}, {}: {}",
+                    index + 1,
+                    reason,
+                    line.trim()
+                ))
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line == "[[check]]" {
+                if let Some(fields) = table.replace(Vec::new()) {
+                    profile.checks.push(Check::from_fields(fields)?);
+                }
+                continue;
+            }
+            if line.starts_with('[') {
+                return Err(invalid("expected [[check]]"));
+            }
+
+            let fields = table
+                .as_mut()
+                .ok_or_else(|| invalid("key outside [[check]]"))?;
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid("expected KEY = VALUE"))?;
+            let value = toml_value(value).ok_or_else(|| invalid("bad value"))?;
+            fields.push((key.trim().to_string(), value));
+        }
+
+        if let Some(fields) = table {
+            profile.checks.push(Check::from_fields(fields)?);
+        }
+        Ok(profile)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+impl Check {
+    fn from_fields(fields: Vec<(String, String)>) -> Result<Self> {
+        let mut name = None;
+        let mut file = None;
+        let mut path = None;
+        let mut expectation = None;
+
+        for (key, value) in fields {
+            let invalid = |reason: &str| {
+                ConversionError::ParseError(format!("Invalid profile key '{}': {}", key, reason))
+            };
+            let slot = match key.as_str() {
+                "name" => &mut name,
+                "file" => &mut file,
+                "path" => &mut path,
+                "equals" | "not_equals" | "contains" | "exists" => {
+                    let value = match key.as_str() {
+                        "equals" => Expectation::Equals(value),
+                        "not_equals" => Expectation::NotEquals(value),
+                        "contains" => Expectation::Contains(value),
+                        _ => Expectation::Exists(match value.as_str() {
+                            "true" => true,
+                            "false" => false,
+                            _ => return Err(invalid("expected true or false")),
+                        }),
+                    };
+                    if expectation.replace(value).is_some() {
+                        return Err(invalid("a check has a single expectation"));
+                    }
+                    continue;
+                }
+                _ => return Err(invalid("unknown key")),
+            };
+            if slot.replace(value).is_some() {
+                return Err(invalid("repeated"));
+            }
+        }
+
+        let missing =
+            |key: &str| ConversionError::ParseError(format!("Profile check without {}", key));
+        let name = name.ok_or_else(|| missing("name"))?;
+        let path = path.ok_or_else(|| missing("path"))?;
+        Ok(Self {
+            file: file.ok_or_else(|| missing("file"))?.into(),
+            xpath: XPath::parse(&path)?,
+            expectation: expectation
+                .ok_or_else(|| missing("equals, not_equals, contains or exists"))?,
+            name,
+            path,
+        })
+    }
+
+    /// Whether `values` meet the expectation, or why not
+    fn evaluate(&self, values: &[String]) -> std::result::Result<(), String> {
+        let found = || match values {
+            [] => "nothing selected".to_string(),
+            [value] => format!("found '{}'", value),
+            values => format!("found '{}'", values.join("', '")),
+        };
+        let passed = match &self.expectation {
+            Expectation::Equals(expected) => {
+                !values.is_empty() && values.iter().all(|value| value == expected)
+            }
+            Expectation::NotEquals(unexpected) => !values.contains(unexpected),
+            Expectation::Contains(part) => {
+                !values.is_empty() && values.iter().all(|value| value.contains(part.as_str()))
+            }
+            Expectation::Exists(exists) => values.is_empty() != *exists,
+        };
+        if passed { Ok(()) } else { Err(found()) }
+    }
+}
+
+/// A string, boolean or integer value, without a trailing comment
+fn toml_value(text: &str) -> Option<String> {
+    let text = text.trim();
+    let (value, rest) = if let Some(literal) = text.strip_prefix('\'') {
+        let end = literal.find('\'')?;
+        (literal[..end].to_string(), &literal[end + 1..])
+    } else if let Some(basic) = text.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = basic.char_indices();
+        loop {
+            match chars.next()? {
+                (end, '"') => break (value, &basic[end + 1..]),
+                (_, '\\') => value.push(match chars.next()?.1 {
+                    'n' => '\n',
+                    't' => '\t',
+                    c @ ('"' | '\\') => c,
+                    _ => return None,
+                }),
+                (_, c) => value.push(c),
+            }
+        }
+    } else {
+        let bare = text.split('#').next().unwrap_or_default().trim();
+        let valid = matches!(bare, "true" | "false") || bare.parse::<i64>().is_ok();
+        (valid.then(|| bare.to_string())?, "")
+    };
+
+    let rest = rest.trim();
+    (rest.is_empty() || rest.starts_with('#')).then_some(value)
+}
+
+// ============================================================================
+// Comparison
+// ============================================================================
+
+/// Outcome of one check
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: String,
+    /// Why the check failed, `None` if it passed
+    pub failure: Option<String>,
+}
+
+/// Result of comparing a device dump against a [`DeviceProfile`], one result
+/// per check in profile order. A file that is missing or does not decode fails
+/// every check on it.
+///
+/// ```
+/// use android_xml_converter::XmlToAbxConverter;
+/// use android_xml_converter::compliance::{ComplianceReport, DeviceProfile};
+///
+/// let dir = std::env::temp_dir().join(format!("abx-compliance-doctest-{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let settings = r#"<settings version="-1"><setting id="1" name="adb_enabled" value="1"/></settings>"#;
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string(settings, &mut abx).unwrap();
+/// std::fs::write(dir.join("settings_global.xml"), abx).unwrap();
+///
+/// let profile = DeviceProfile::parse(
+///     r#"
+/// [[check]]
+/// name = "adb disabled"
+/// file = "settings_global.xml"
+/// path = "/settings/setting[@name='adb_enabled']/@value"
+/// equals = "0"
+///
+/// [[check]]
+/// name = "settings present"
+/// file = "settings_global.xml"
+/// path = "/settings"
+/// exists = true
+/// "#,
+/// )
+/// .unwrap();
+/// let report = ComplianceReport::compare(&dir, &profile);
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// assert_eq!(report.failed_count(), 1);
+/// assert_eq!(report.results[0].failure.as_deref(), Some("found '1'"));
+/// assert!(report.results[1].failure.is_none());
+/// ```
+#[derive(Debug, Default)]
+pub struct ComplianceReport {
+    pub results: Vec<CheckResult>,
+}
+
+impl ComplianceReport {
+    pub fn compare<P: AsRef<Path>>(dump: P, profile: &DeviceProfile) -> Self {
+        let dump = dump.as_ref();
+        let mut documents: FastHashMap<&Path, std::result::Result<Vec<u8>, String>> =
+            FastHashMap::default();
+
+        let mut report = Self::default();
+        for check in &profile.checks {
+            let document = documents
+                .entry(&check.file)
+                .or_insert_with(|| load_abx(&dump.join(&check.file)));
+            let failure = match document {
+                Ok(abx) => select(&check.xpath, abx)
+                    .map_err(|e| format!("cannot search {}: {}", check.file.display(), e))
+                    .and_then(|values| check.evaluate(&values))
+                    .err(),
+                Err(message) => Some(message.clone()),
+            };
+            report.results.push(CheckResult {
+                name: check.name.clone(),
+                failure,
+            });
+        }
+        report
+    }
+
+    pub fn failed_count(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|result| result.failure.is_some())
+            .count()
+    }
+
+    pub fn is_compliant(&self) -> bool {
+        self.failed_count() == 0
+    }
+
+    pub fn write_to<W: Write>(&self, mut output: W) -> Result<()> {
+        for result in &self.results {
+            match &result.failure {
+                None => writeln!(output, "pass: {}", result.name)?,
+                Some(failure) => writeln!(output, "FAIL: {}: {}", result.name, failure)?,
+            }
+        }
+        writeln!(
+            output,
+            "{} passed, {} failed",
+            self.results.len() - self.failed_count(),
+            self.failed_count()
+        )?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
+/// The file as ABX, converting XML
+fn load_abx(path: &Path) -> std::result::Result<Vec<u8>, String> {
+    let data = fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    if detect_format(&data) == Format::Abx {
+        return Ok(data);
+    }
+    let mut abx = Vec::new();
+    std::str::from_utf8(&data)
+        .map_err(ConversionError::from)
+        .and_then(|xml| XmlToAbxConverter::convert_from_string(xml, &mut abx))
+        .map_err(|e| format!("{} is neither ABX nor XML: {}", path.display(), e))?;
+    Ok(abx)
+}
+
+fn select(xpath: &XPath, abx: &[u8]) -> Result<Vec<String>> {
+    let mut values = Vec::new();
+    xpath.scan(abx, |_, selection| {
+        values.push(selection.to_string());
+        Ok(())
+    })?;
+    Ok(values)
+}
@@ -0,0 +1,1352 @@
+use crate::diagnostics::{Diagnostics, Location};
+use crate::dictionary::Dictionary;
+use crate::query::{DocumentSummary, PoolComparison, PoolReport};
+use crate::reader::{AttributeValue, BinaryXmlDeserializer};
+use crate::schema::{TYPE_HINT_ATTRIBUTE, TYPE_HINT_NAMESPACE, parse_type_hints};
+use crate::timeout::IdleTimeoutReader;
+use crate::writer::{BinaryXmlSerializer, char_boundary, utf_len};
+use crate::xpath::XPath;
+use crate::*;
+use quick_xml::Reader;
+use quick_xml::errors::IllFormedError;
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesStart, Event};
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Write};
+
+// ============================================================================
+// ABX to XML
+// ============================================================================
+
+pub struct AbxToXmlConverter;
+
+impl AbxToXmlConverter {
+    pub fn convert<R: Read, W: Write>(reader: R, writer: W) -> Result<()> {
+        Self::convert_with_options(reader, writer, &Options::default())
+    }
+
+    pub fn convert_with_options<R: Read, W: Write>(
+        reader: R,
+        writer: W,
+        options: &Options,
+    ) -> Result<()> {
+        let mut diagnostics = Diagnostics::new();
+        let result = Self::convert_with_diagnostics(reader, writer, options, &mut diagnostics);
+        if !diagnostics.is_empty() {
+            diagnostics.write_to(io::stderr().lock())?;
+        }
+        result
+    }
+
+    /// Converts like [`Self::convert_with_options`], handing back the warnings and the
+    /// errors recorded in lenient mode (see [`Options::max_errors`]) instead of
+    /// printing them, even when the conversion was aborted
+    pub fn convert_with_diagnostics<R: Read, W: Write>(
+        reader: R,
+        writer: W,
+        options: &Options,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<()> {
+        let Some(root) = &options.wrap_roots else {
+            let mut deserializer = BinaryXmlDeserializer::with_options(reader, writer, options)?;
+            let result = deserializer.deserialize();
+            *diagnostics = deserializer.take_diagnostics();
+            return result;
+        };
+
+        // the extra roots only show up after the first one was written
+        let mut xml = Vec::new();
+        let mut deserializer = BinaryXmlDeserializer::with_options(reader, &mut xml, options)?;
+        let result = deserializer.deserialize();
+        *diagnostics = deserializer.take_diagnostics();
+        result?;
+
+        let xml = std::str::from_utf8(&xml)?;
+        let mut writer = writer;
+        writer.write_all(wrap_roots(xml, root, diagnostics)?.as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn convert_file(input_path: &str, output_path: &str) -> Result<()> {
+        Self::convert_file_with_options(input_path, output_path, &Options::default())
+    }
+
+    pub fn convert_file_with_options(
+        input_path: &str,
+        output_path: &str,
+        options: &Options,
+    ) -> Result<()> {
+        if input_path == output_path {
+            return Self::convert_file_in_place(input_path, options);
+        }
+
+        let input_file = IdleTimeoutReader::wrap(File::open(input_path)?, options.idle_timeout);
+        let reader = BufReader::with_capacity(options.read_buffer_size, input_file);
+        let output_file = File::create(output_path)?;
+        let writer = BufWriter::with_capacity(options.write_buffer_size, output_file);
+        Self::convert_with_options(reader, writer, options)
+    }
+
+    pub fn convert_stdin_stdout() -> Result<()> {
+        let reader = decompress_if_gzip(io::stdin().lock())?;
+        let stdout = io::stdout();
+        let writer = BufWriter::new(stdout.lock());
+        Self::convert(reader, writer)
+    }
+
+    pub fn convert_stdin_to_file(output_path: &str) -> Result<()> {
+        let reader = decompress_if_gzip(io::stdin().lock())?;
+        let output_file = File::create(output_path)?;
+        let writer = BufWriter::new(output_file);
+        Self::convert(reader, writer)
+    }
+
+    pub fn convert_file_to_stdout(input_path: &str) -> Result<()> {
+        let input_file = File::open(input_path)?;
+        let reader = BufReader::new(input_file);
+        let writer = io::stdout();
+        Self::convert(reader, writer)
+    }
+
+    fn convert_file_in_place(file_path: &str, options: &Options) -> Result<()> {
+        let input_file = File::open(file_path)?;
+        let mut reader = BufReader::new(input_file);
+        let mut file_data = Vec::new();
+        reader.read_to_end(&mut file_data)?;
+
+        let cursor = Cursor::new(file_data);
+        let mut output_data = Vec::new();
+        {
+            let writer = Cursor::new(&mut output_data);
+            Self::convert_with_options(cursor, writer, options)?;
+        }
+
+        let output_file = File::create(file_path)?;
+        let mut writer = BufWriter::new(output_file);
+        writer.write_all(&output_data)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn convert_bytes(abx_data: &[u8]) -> Result<String> {
+        let cursor = Cursor::new(abx_data);
+        let mut output_data = Vec::new();
+        {
+            let writer = Cursor::new(&mut output_data);
+            Self::convert(cursor, writer)?;
+        }
+        String::from_utf8(output_data)
+            .map_err(|_| ConversionError::ParseError("Invalid UTF-8 in output".to_string()))
+    }
+
+    pub fn convert_vec(abx_data: Vec<u8>) -> Result<String> {
+        Self::convert_bytes(&abx_data)
+    }
+
+    /// Checks that `xml`, converted from `abx_data` with [`Options::fidelity`],
+    /// converts back to exactly `abx_data`, failing with the offset of the first
+    /// difference otherwise (e.g. for CDATA containing `]]>`, which XML cannot hold)
+    ///
+    /// ```
+    /// use android_xml_converter::{AbxToXmlConverter, Options, XmlToAbxConverter};
+    ///
+    /// let mut abx = Vec::new();
+    /// XmlToAbxConverter::convert_from_string("<a x=\"1 &amp; 2\">\n  <b>x &lt; y</b>\n</a>", &mut abx)
+    ///     .unwrap();
+    ///
+    /// let options = Options::new().fidelity(true);
+    /// let mut xml = Vec::new();
+    /// AbxToXmlConverter::convert_with_options(abx.as_slice(), &mut xml, &options).unwrap();
+    /// AbxToXmlConverter::check_fidelity(&abx, &xml, &options).unwrap();
+    /// ```
+    pub fn check_fidelity(abx_data: &[u8], xml: &[u8], options: &Options) -> Result<()> {
+        let xml = std::str::from_utf8(xml)?;
+        let mut encoded = Vec::with_capacity(abx_data.len());
+        XmlToAbxConverter::convert_from_string_with(xml, &mut encoded, options)?;
+        match encoded.iter().zip(abx_data).position(|(a, b)| a != b) {
+            None if encoded.len() == abx_data.len() => Ok(()),
+            offset => Err(ConversionError::ParseError(format!(
+                "XML does not reproduce the ABX input byte for byte, first difference at offset {}",
+                offset.unwrap_or(encoded.len().min(abx_data.len()))
+            ))),
+        }
+    }
+
+    /// Prints the values of all attributes an [`XPath`] such as
+    /// `package[@name='com.foo']/@codePath` selects, returning how many were found
+    pub fn extract_attribute<R: Read, W: Write>(reader: R, writer: W, path: &str) -> Result<usize> {
+        XPath::parse(path)?.write_matches(reader, writer)
+    }
+
+    /// Counts the elements an [`XPath`] (a tag name or element path) selects
+    pub fn count_elements<R: Read>(reader: R, path: &str) -> Result<usize> {
+        XPath::parse(path)?.scan(reader, |_, _| Ok(()))
+    }
+
+    pub fn summarize<R: Read>(reader: R) -> Result<DocumentSummary> {
+        DocumentSummary::collect(reader)
+    }
+
+    pub fn verify_pool<R: Read>(reader: R) -> Result<PoolReport> {
+        PoolReport::collect(reader)
+    }
+
+    /// The intern pool of a document in definition order, as a snapshot for
+    /// [`Self::compare_pool`] or for pre-seeding conversions
+    pub fn export_pool<R: Read>(reader: R) -> Result<Dictionary> {
+        Ok(Dictionary::new(PoolReport::collect(reader)?.entries))
+    }
+
+    /// Compares the intern pool of a document index by index with a snapshot
+    /// taken by [`Self::export_pool`] or `xml2abx --export-dictionary`
+    pub fn compare_pool<R: Read>(reader: R, snapshot: &Dictionary) -> Result<PoolComparison> {
+        let pool = PoolReport::collect(reader)?;
+        Ok(PoolComparison::new(snapshot.strings(), &pool.entries))
+    }
+}
+
+// ============================================================================
+// XML to ABX
+// ============================================================================
+
+pub struct XmlToAbxConverter;
+
+impl XmlToAbxConverter {
+    pub fn convert_from_string<W: Write>(xml: &str, writer: W) -> Result<()> {
+        Self::convert_from_string_with_options(xml, writer, true)
+    }
+
+    pub fn convert_from_string_with_options<W: Write>(
+        xml: &str,
+        writer: W,
+        preserve_whitespace: bool,
+    ) -> Result<()> {
+        let options = Options::new().preserve_whitespace(preserve_whitespace);
+        Self::convert_from_string_with(xml, writer, &options)
+    }
+
+    pub fn convert_from_string_with<W: Write>(
+        xml: &str,
+        writer: W,
+        options: &Options,
+    ) -> Result<()> {
+        Self::convert_and_export_dictionary(xml, writer, options).map(|_| ())
+    }
+
+    /// Converts like [`Self::convert_from_string_with`] and returns the resulting
+    /// intern pool, which can seed later conversions through [`Options::dictionary`]
+    pub fn convert_and_export_dictionary<W: Write>(
+        xml: &str,
+        writer: W,
+        options: &Options,
+    ) -> Result<Dictionary> {
+        let mut diagnostics = Diagnostics::new();
+        let result = Self::convert_str(xml, writer, options, &mut diagnostics);
+        if !diagnostics.is_empty() {
+            diagnostics.write_to(io::stderr().lock())?;
+        }
+        result
+    }
+
+    pub fn convert_from_file<W: Write>(input_path: &str, writer: W) -> Result<()> {
+        Self::convert_from_file_with_options(input_path, writer, true)
+    }
+
+    pub fn convert_from_file_with_options<W: Write>(
+        input_path: &str,
+        writer: W,
+        preserve_whitespace: bool,
+    ) -> Result<()> {
+        let options = Options::new().preserve_whitespace(preserve_whitespace);
+        Self::convert_from_file_with(input_path, writer, &options)
+    }
+
+    pub fn convert_from_file_with<W: Write>(
+        input_path: &str,
+        writer: W,
+        options: &Options,
+    ) -> Result<()> {
+        let input = IdleTimeoutReader::wrap(File::open(input_path)?, options.idle_timeout);
+        if Self::needs_whole_input(options) {
+            let mut xml = String::new();
+            BufReader::new(input).read_to_string(&mut xml)?;
+            return Self::convert_from_string_with(&xml, writer, options);
+        }
+
+        let input = BufReader::with_capacity(options.read_buffer_size, input);
+        let mut reader = Reader::from_reader(input);
+        reader.config_mut().trim_text(options.trims_text());
+        Self::convert_reader_with_options(reader, writer, options).map(|_| ())
+    }
+
+    pub fn convert_from_reader<R: BufRead, W: Write>(input: R, writer: W) -> Result<()> {
+        Self::convert_from_reader_with_options(input, writer, true)
+    }
+
+    pub fn convert_from_reader_with_options<R: BufRead, W: Write>(
+        input: R,
+        writer: W,
+        preserve_whitespace: bool,
+    ) -> Result<()> {
+        let options = Options::new().preserve_whitespace(preserve_whitespace);
+        Self::convert_from_reader_with(input, writer, &options)
+    }
+
+    pub fn convert_from_reader_with<R: BufRead, W: Write>(
+        mut input: R,
+        writer: W,
+        options: &Options,
+    ) -> Result<()> {
+        if Self::needs_whole_input(options) {
+            let mut xml = String::new();
+            input.read_to_string(&mut xml)?;
+            return Self::convert_from_string_with(&xml, writer, options);
+        }
+
+        let mut reader = Reader::from_reader(input);
+        reader.config_mut().trim_text(options.trims_text());
+        Self::convert_reader_with_options(reader, writer, options).map(|_| ())
+    }
+
+    /// Converts like [`Self::convert_and_export_dictionary`], collecting warnings in
+    /// `diagnostics` instead of printing them
+    pub fn convert_from_string_with_diagnostics<W: Write>(
+        xml: &str,
+        writer: W,
+        options: &Options,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Dictionary> {
+        Self::convert_str(xml, writer, options, diagnostics)
+    }
+
+    /// Whether the repairs enabled in `options` work on the complete text
+    fn needs_whole_input(options: &Options) -> bool {
+        options.wrap_roots.is_some() || options.max_errors.is_some()
+    }
+
+    fn convert_str<W: Write>(
+        xml: &str,
+        writer: W,
+        options: &Options,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Dictionary> {
+        let xml = match options.max_errors {
+            Some(max_errors) => {
+                *diagnostics = Diagnostics::with_max_errors(max_errors);
+                escape_stray_markup(xml, diagnostics)?
+            }
+            None => Cow::Borrowed(xml),
+        };
+        let xml = match &options.wrap_roots {
+            Some(root) => Cow::Owned(wrap_roots(&xml, root, diagnostics)?.into_owned()),
+            None => xml,
+        };
+        let mut reader = Reader::from_str(&xml);
+        reader.config_mut().trim_text(options.trims_text());
+        Self::convert_events(reader, writer, options, diagnostics)
+    }
+
+    /// Converts and prints the aggregated warnings to stderr
+    fn convert_reader_with_options<R: BufRead, W: Write>(
+        reader: Reader<R>,
+        writer: W,
+        options: &Options,
+    ) -> Result<Dictionary> {
+        let mut diagnostics = Diagnostics::new();
+        let result = Self::convert_events(reader, writer, options, &mut diagnostics);
+        if !diagnostics.is_empty() {
+            diagnostics.write_to(io::stderr().lock())?;
+        }
+        result
+    }
+
+    fn convert_events<R: BufRead, W: Write>(
+        mut reader: Reader<R>,
+        writer: W,
+        options: &Options,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Dictionary> {
+        let mut sink = AbxWriter::with_options(writer, options)?;
+        // report into the caller's diagnostics, which lenient mode set up already
+        std::mem::swap(&mut sink.diagnostics, diagnostics);
+        let result = Self::write_events(&mut reader, &mut sink, options);
+        std::mem::swap(&mut sink.diagnostics, diagnostics);
+        result?;
+        Ok(sink.dictionary())
+    }
+
+    fn write_events<R: BufRead, W: Write>(
+        reader: &mut Reader<R>,
+        sink: &mut AbxWriter<W>,
+        options: &Options,
+    ) -> Result<()> {
+        let mut buf = Vec::with_capacity(options.event_buffer_capacity);
+        // in lenient mode end tags are matched by the sink instead of by the parser
+        reader.config_mut().check_end_names = !sink.lenient();
+
+        loop {
+            let location = Location::Offset(reader.buffer_position());
+            let event = match reader.read_event_into(&mut buf) {
+                // the parser lost track of the element after an earlier stray end tag
+                Err(quick_xml::Error::IllFormed(IllFormedError::UnmatchedEndTag(name)))
+                    if sink.lenient() =>
+                {
+                    sink.close_element(&name, location)?;
+                    buf.clear();
+                    continue;
+                }
+                event => event?,
+            };
+            let eof = matches!(event, Event::Eof);
+            sink.write_event_at(&event, location)?;
+            if eof {
+                return Ok(());
+            }
+            buf.clear();
+        }
+    }
+
+    /// Writes the attributes of a start tag, applying [`Options::duplicate_attributes`]
+    fn write_attributes<W: Write>(
+        serializer: &mut BinaryXmlSerializer<W>,
+        start: &BytesStart,
+        options: &Options,
+        path: &[String],
+        location: Location,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<()> {
+        let policy = options.duplicate_attributes;
+        let mut attributes: Vec<Attribute> = Vec::new();
+        for attr in start
+            .attributes()
+            .with_checks(policy == DuplicateAttributes::Error)
+        {
+            let attr = attr?;
+            match attributes.iter_mut().find(|seen| seen.key == attr.key) {
+                Some(seen) => {
+                    let (message, keep_last) = match policy {
+                        DuplicateAttributes::Last => {
+                            ("Duplicate attribute, kept the last value", true)
+                        }
+                        _ => ("Duplicate attribute, kept the first value", false),
+                    };
+                    diagnostics.warning(
+                        location,
+                        message,
+                        Some(format!(
+                            "Found duplicate attribute: {}",
+                            String::from_utf8_lossy(attr.key.as_ref())
+                        )),
+                    );
+                    if keep_last {
+                        seen.value = attr.value;
+                    }
+                }
+                None => attributes.push(attr),
+            }
+        }
+
+        if options.canonical {
+            attributes.sort_by(|a, b| a.key.as_ref().cmp(b.key.as_ref()));
+        }
+
+        let apply_hints = options.apply_type_hints || options.fidelity;
+        let hints = match attributes
+            .iter()
+            .find(|attr| apply_hints && attr.key.as_ref() == TYPE_HINT_ATTRIBUTE.as_bytes())
+        {
+            Some(attr) => parse_type_hints(std::str::from_utf8(&attr.value)?)?,
+            None => Vec::new(),
+        };
+
+        // values moved to child elements, which can only follow the attributes
+        let mut spilled = Vec::new();
+        for attr in &attributes {
+            let attr_name = std::str::from_utf8(attr.key.as_ref())?;
+            let unescaped;
+            let attr_value = if options.fidelity {
+                unescaped = attr.unescape_value()?;
+                unescaped.as_ref()
+            } else {
+                std::str::from_utf8(&attr.value)?
+            };
+
+            if apply_hints
+                && (attr_name == TYPE_HINT_ATTRIBUTE
+                    || (attr_name == "xmlns:abx" && attr_value == TYPE_HINT_NAMESPACE))
+            {
+                continue;
+            }
+            let hint = hints
+                .iter()
+                .find(|(name, _)| *name == attr_name)
+                .map(|(_, type_name)| *type_name);
+
+            let Some(attr_name) = Self::attribute_name(attr_name, options, location, diagnostics)?
+            else {
+                continue;
+            };
+
+            let length = utf_len(attr_value, options.modified_utf8);
+            if length <= MAX_UNSIGNED_SHORT as usize
+                || options.long_attributes == LongAttributes::Error
+            {
+                let type_name = hint.or_else(|| {
+                    options
+                        .type_schema
+                        .as_ref()
+                        .and_then(|schema| schema.type_for(path, attr_name))
+                });
+                Self::write_attribute(serializer, attr_name, attr_value, type_name, options)?;
+                continue;
+            }
+
+            let detail = Some(format!("Found {} bytes in attribute {}", length, attr_name));
+            match options.long_attributes {
+                LongAttributes::Truncate => {
+                    diagnostics.warning(location, "Long attribute value truncated", detail);
+                    let end = char_boundary(
+                        attr_value,
+                        MAX_UNSIGNED_SHORT as usize,
+                        options.modified_utf8,
+                    );
+                    let value = &attr_value[..end];
+                    Self::write_attribute(serializer, attr_name, value, None, options)?;
+                }
+                LongAttributes::Text => {
+                    diagnostics.warning(
+                        location,
+                        "Long attribute value moved to a child element",
+                        detail,
+                    );
+                    spilled.push((attr_name, attr_value.to_string()));
+                }
+                _ => {
+                    diagnostics.warning(
+                        location,
+                        "Long attribute value split into several attributes",
+                        detail,
+                    );
+                    Self::write_attribute_chunks(
+                        serializer,
+                        attr_name,
+                        attr_value,
+                        options.modified_utf8,
+                    )?;
+                }
+            }
+        }
+
+        for (name, value) in spilled {
+            serializer.start_tag(name)?;
+            serializer.text(&value)?;
+            serializer.end_tag(name)?;
+        }
+        Ok(())
+    }
+
+    /// [`LongAttributes::Chunks`]: base64 as byte arrays in multiples of 3 bytes so
+    /// their encodings concatenate cleanly, anything else as strings
+    fn write_attribute_chunks<W: Write>(
+        serializer: &mut BinaryXmlSerializer<W>,
+        name: &str,
+        value: &str,
+        modified_utf8: bool,
+    ) -> Result<()> {
+        let chunk_name = |index: usize| match index {
+            0 => name.to_string(),
+            index => format!("{}.{}", name, index),
+        };
+
+        if let Some(bytes) = base64_decode(value)
+            && base64_string(&bytes) == value
+        {
+            // the largest multiple of 3 that fits
+            let chunk_size = MAX_UNSIGNED_SHORT as usize / 3 * 3;
+            for (index, chunk) in bytes.chunks(chunk_size).enumerate() {
+                serializer.attribute_bytes_base64(&chunk_name(index), chunk)?;
+            }
+            return Ok(());
+        }
+
+        let mut rest = value;
+        let mut index = 0;
+        while !rest.is_empty() {
+            let end = char_boundary(rest, MAX_UNSIGNED_SHORT as usize, modified_utf8);
+            serializer.attribute(&chunk_name(index), &rest[..end])?;
+            rest = &rest[end..];
+            index += 1;
+        }
+        Ok(())
+    }
+
+    fn local_name(name: &str) -> &str {
+        name.split_once(':').map_or(name, |(_, local)| local)
+    }
+
+    /// Applies [`Options::namespaces`] to a possibly prefixed element name
+    fn element_name<'a>(
+        name: &'a str,
+        options: &Options,
+        location: Location,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<&'a str> {
+        if !name.contains(':') {
+            return Ok(name);
+        }
+
+        match options.namespaces {
+            NamespaceStrategy::Keep => {
+                diagnostics.warning(
+                    location,
+                    "Namespaces and prefixes is not supported and might be lost",
+                    Some(format!("Found prefixed element: {}", name)),
+                );
+                Ok(name)
+            }
+            NamespaceStrategy::Strip => Ok(Self::local_name(name)),
+            NamespaceStrategy::Error => Err(ConversionError::ParseError(format!(
+                "Prefixed element at {}: {}",
+                location, name
+            ))),
+        }
+    }
+
+    /// Applies [`Options::namespaces`] to an attribute name; `None` means the
+    /// attribute is a namespace declaration that should be dropped
+    fn attribute_name<'a>(
+        name: &'a str,
+        options: &Options,
+        location: Location,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Option<&'a str>> {
+        let is_declaration = name == "xmlns" || name.starts_with("xmlns:");
+        if !is_declaration && !name.contains(':') {
+            return Ok(Some(name));
+        }
+
+        match options.namespaces {
+            NamespaceStrategy::Keep => {
+                diagnostics.warning(
+                    location,
+                    "Namespaces and prefixes is not supported and might be lost",
+                    Some(format!(
+                        "Found namespace declaration or prefixed attribute: {}",
+                        name
+                    )),
+                );
+                Ok(Some(name))
+            }
+            NamespaceStrategy::Strip if is_declaration => Ok(None),
+            NamespaceStrategy::Strip => Ok(Some(Self::local_name(name))),
+            NamespaceStrategy::Error => Err(ConversionError::ParseError(format!(
+                "Namespace declaration or prefixed attribute at {}: {}",
+                location, name
+            ))),
+        }
+    }
+
+    /// Writes an attribute as `type_name` if a hint or the schema gave one, else guesses
+    fn write_attribute<W: Write>(
+        serializer: &mut BinaryXmlSerializer<W>,
+        name: &str,
+        value: &str,
+        type_name: Option<&str>,
+        options: &Options,
+    ) -> Result<()> {
+        use type_detection::*;
+
+        if let Some(type_name) = type_name {
+            let typed = AttributeValue::parse_as(type_name, value).map_err(|e| match e {
+                ConversionError::ParseError(message) => ConversionError::ParseError(format!(
+                    "Attribute {} does not match its declared type: {}",
+                    name, message
+                )),
+                e => e,
+            })?;
+            serializer.attribute_value(name, &typed)?;
+        } else if options.type_inference && is_boolean(value) {
+            serializer.attribute_boolean(name, value == "true")?;
+        } else if options.type_inference
+            && let Some(number) = infer_number(value)
+        {
+            serializer.attribute_value(name, &number)?;
+        } else if serializer.interns(value, &options.intern_policy) {
+            serializer.attribute_interned(name, value)?;
+        } else {
+            serializer.attribute(name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// The character of a predefined entity or character reference
+fn resolve_reference(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        _ => {
+            let number = name.strip_prefix('#')?;
+            let code = match number.strip_prefix('x') {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+// ============================================================================
+// quick_xml Event Writer
+// ============================================================================
+
+/// Writes ABX from quick_xml [`Event`]s, applying [`Options`] the way
+/// [`XmlToAbxConverter`] does, so a pipeline already emitting quick_xml events
+/// can produce ABX without switching to [`BinaryXmlSerializer`].
+///
+/// [`Event::Eof`] ends the document, as does [`AbxWriter::end_document`].
+///
+/// ```
+/// use android_xml_converter::{AbxToXmlConverter, AbxWriter};
+/// use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+///
+/// let mut abx = Vec::new();
+/// let mut writer = AbxWriter::new(&mut abx).unwrap();
+/// let mut start = BytesStart::new("string");
+/// start.push_attribute(("name", "greeting"));
+/// writer.write_event(&Event::Start(start)).unwrap();
+/// writer.write_event(&Event::Text(BytesText::new("hi"))).unwrap();
+/// writer.write_event(&Event::End(BytesEnd::new("string"))).unwrap();
+/// writer.write_event(&Event::Eof).unwrap();
+///
+/// let xml = AbxToXmlConverter::convert_bytes(&abx).unwrap();
+/// assert!(xml.ends_with(r#"<string name="greeting">hi</string>"#));
+/// ```
+pub struct AbxWriter<W: Write> {
+    serializer: BinaryXmlSerializer<W>,
+    options: Options,
+    diagnostics: Diagnostics,
+    /// Open elements, for [`Options::type_schema`] and lenient mode, where end
+    /// tags are matched here
+    open: Vec<String>,
+    /// [`Options::fidelity`]: the text token being collected, whether the next
+    /// entity reference stays a token, and bytes to write after END_DOCUMENT
+    text: Option<(u8, String)>,
+    entity_ref_next: bool,
+    /// [`Options::canonical`]: the text node being collected, see
+    /// [`trim_text_node`]
+    text_node: Vec<(u8, String)>,
+    trailing: Vec<u8>,
+    events: usize,
+    finished: bool,
+}
+
+impl<W: Write> AbxWriter<W> {
+    pub fn new(writer: W) -> Result<Self> {
+        Self::with_options(writer, &Options::default())
+    }
+
+    /// Creates a writer and writes START_DOCUMENT. With [`Options::max_errors`]
+    /// set, mismatched end tags are repaired and recorded in
+    /// [`AbxWriter::diagnostics`] instead of written as they are.
+    pub fn with_options(writer: W, options: &Options) -> Result<Self> {
+        let mut serializer = BinaryXmlSerializer::from_options(writer, options)?;
+        serializer.start_document()?;
+        Ok(Self {
+            serializer,
+            options: options.clone(),
+            diagnostics: match options.max_errors {
+                Some(max_errors) => Diagnostics::with_max_errors(max_errors),
+                None => Diagnostics::new(),
+            },
+            open: Vec::new(),
+            text: None,
+            entity_ref_next: false,
+            text_node: Vec::new(),
+            trailing: Vec::new(),
+            events: 0,
+            finished: false,
+        })
+    }
+
+    /// Writes one event; diagnostics refer to it by its position in the stream
+    pub fn write_event(&mut self, event: &Event<'_>) -> Result<()> {
+        self.events += 1;
+        self.write_event_at(event, Location::Token(self.events))
+    }
+
+    /// Closes any elements left open in lenient mode and writes END_DOCUMENT;
+    /// does nothing once the document has ended
+    pub fn end_document(&mut self) -> Result<()> {
+        self.end_document_at(Location::Token(self.events))
+    }
+
+    fn end_document_at(&mut self, location: Location) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        while self.lenient()
+            && let Some(name) = self.open.pop()
+        {
+            self.diagnostics.error(
+                location,
+                &format!("Element <{}> not closed at end of input", name),
+            )?;
+            self.serializer.end_tag(&name)?;
+        }
+        self.flush_text()?;
+        self.flush_text_node()?;
+        self.finished = true;
+        self.serializer.end_document()?;
+        if !self.trailing.is_empty() {
+            self.serializer.raw_bytes(&self.trailing)?;
+            self.serializer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Warnings and lenient mode errors recorded so far
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    /// The intern pool built so far, see [`BinaryXmlSerializer::dictionary`]
+    pub fn dictionary(&self) -> Dictionary {
+        self.serializer.dictionary()
+    }
+
+    fn lenient(&self) -> bool {
+        self.options.max_errors.is_some()
+    }
+
+    /// Prefixes were already reported at the start tag, so end tags only need
+    /// the name the start tag was written with
+    fn end_name<'a>(name: &'a str, options: &Options) -> &'a str {
+        match options.namespaces {
+            NamespaceStrategy::Strip => XmlToAbxConverter::local_name(name),
+            _ => name,
+        }
+    }
+
+    /// Lenient mode: closes `name` and any elements left open inside it, or drops
+    /// the end tag if no such element is open
+    fn close_element(&mut self, name: &str, location: Location) -> Result<()> {
+        let name = Self::end_name(name, &self.options);
+        let Some(depth) = self.open.iter().rposition(|open| open == name) else {
+            return self.diagnostics.error(
+                location,
+                &format!("End tag </{}> without a matching start tag, dropped", name),
+            );
+        };
+        for inner in self.open.drain(depth + 1..).rev() {
+            self.diagnostics.error(
+                location,
+                &format!("Element <{}> closed implicitly by </{}>", inner, name),
+            )?;
+            self.serializer.end_tag(&inner)?;
+        }
+        self.open.pop();
+        self.serializer.end_tag(name)
+    }
+
+    /// [`Options::fidelity`]: collects text and predefined entities into whole
+    /// tokens and follows `<?abx ...?>` directives. Returns false for events
+    /// left to the normal handling, after writing any collected text.
+    fn write_fidelity_event(&mut self, event: &Event<'_>) -> Result<bool> {
+        match event {
+            Event::PI(e) if e.target() == b"abx" => {
+                self.flush_text()?;
+                let content = std::str::from_utf8(e.content())?.trim();
+                let (directive, argument) = content.split_once(' ').unwrap_or((content, ""));
+                match directive {
+                    "text" => self.text = Some((TEXT, String::new())),
+                    "ignorable" => self.text = Some((IGNORABLE_WHITESPACE, String::new())),
+                    "ref" => self.entity_ref_next = true,
+                    "null" => {
+                        let command = (0..16)
+                            .find(|command| token_name(*command) == Some(argument))
+                            .ok_or_else(|| {
+                                ConversionError::ParseError(format!(
+                                    "Unknown token in <?abx null?>: {}",
+                                    argument
+                                ))
+                            })?;
+                        self.serializer.write_token(command, None)?;
+                    }
+                    "trailing" => {
+                        self.trailing = hex_decode(argument).ok_or_else(|| {
+                            ConversionError::ParseError(
+                                "Invalid hex in <?abx trailing?>".to_string(),
+                            )
+                        })?;
+                    }
+                    _ => {
+                        return Err(ConversionError::ParseError(format!(
+                            "Unknown directive <?abx {}?>",
+                            content
+                        )));
+                    }
+                }
+            }
+            Event::Text(e) => {
+                let text = std::str::from_utf8(e)?;
+                self.text
+                    .get_or_insert_with(|| (TEXT, String::new()))
+                    .1
+                    .push_str(text);
+            }
+            Event::GeneralRef(e) => {
+                let name = std::str::from_utf8(e)?;
+                let keep = std::mem::take(&mut self.entity_ref_next);
+                match resolve_reference(name) {
+                    Some(c) if !keep => {
+                        self.text
+                            .get_or_insert_with(|| (TEXT, String::new()))
+                            .1
+                            .push(c);
+                    }
+                    _ => {
+                        self.flush_text()?;
+                        self.serializer.entity_ref(name)?;
+                    }
+                }
+            }
+            _ => {
+                self.flush_text()?;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn flush_text(&mut self) -> Result<()> {
+        match self.text.take() {
+            Some((TEXT, text)) => self.serializer.text(&text)?,
+            Some((command, text)) => self.serializer.write_token(command, Some(&text))?,
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// [`Options::canonical`]: collects text and entity references into a
+    /// whole text node. Returns false for events left to the normal handling,
+    /// after writing any collected node.
+    fn write_canonical_event(&mut self, event: &Event<'_>) -> Result<bool> {
+        match event {
+            Event::Text(e) => self
+                .text_node
+                .push((TEXT, std::str::from_utf8(e)?.to_string())),
+            Event::GeneralRef(e) => {
+                self.text_node
+                    .push((ENTITY_REF, std::str::from_utf8(e)?.to_string()));
+            }
+            _ => {
+                self.flush_text_node()?;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn flush_text_node(&mut self) -> Result<()> {
+        let mut pieces = std::mem::take(&mut self.text_node);
+        trim_text_node(&mut pieces);
+        for (command, text) in pieces {
+            if command == ENTITY_REF {
+                self.serializer.entity_ref(&text)?;
+            } else {
+                self.serializer.text(&text)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_event_at(&mut self, event: &Event<'_>, location: Location) -> Result<()> {
+        if self.finished {
+            return Err(ConversionError::ParseError(
+                "Event written after the end of the document".to_string(),
+            ));
+        }
+        if self.options.fidelity && self.write_fidelity_event(event)? {
+            return Ok(());
+        }
+        if self.options.canonical && self.write_canonical_event(event)? {
+            return Ok(());
+        }
+        let lenient = self.lenient();
+        let options = &self.options;
+        let serializer = &mut self.serializer;
+        let diagnostics = &mut self.diagnostics;
+
+        match event {
+            Event::Start(e) => {
+                let name_bytes = e.name();
+                let name = std::str::from_utf8(name_bytes.as_ref())?;
+                let name = XmlToAbxConverter::element_name(name, options, location, diagnostics)?;
+
+                serializer.start_tag(name)?;
+                self.open.push(name.to_string());
+                XmlToAbxConverter::write_attributes(
+                    serializer,
+                    e,
+                    options,
+                    &self.open,
+                    location,
+                    diagnostics,
+                )?;
+            }
+            Event::End(e) => {
+                let name_bytes = e.name();
+                let name = std::str::from_utf8(name_bytes.as_ref())?;
+                if lenient {
+                    self.close_element(name, location)?;
+                } else {
+                    let name = Self::end_name(name, options);
+                    self.open.pop();
+                    serializer.end_tag(name)?;
+                }
+            }
+            Event::Empty(e) => {
+                let name_bytes = e.name();
+                let name = std::str::from_utf8(name_bytes.as_ref())?;
+                let name = XmlToAbxConverter::element_name(name, options, location, diagnostics)?;
+
+                serializer.start_tag(name)?;
+                self.open.push(name.to_string());
+                let written = XmlToAbxConverter::write_attributes(
+                    serializer,
+                    e,
+                    options,
+                    &self.open,
+                    location,
+                    diagnostics,
+                );
+                self.open.pop();
+                written?;
+                serializer.end_tag(name)?;
+            }
+            Event::Text(e) => {
+                let text = std::str::from_utf8(e)?;
+                if type_detection::is_whitespace_only(text) {
+                    if serializer.preserve_whitespace() && !options.compact {
+                        serializer.ignorable_whitespace(text)?;
+                    }
+                } else {
+                    serializer.text(text)?;
+                }
+            }
+            Event::CData(e) => {
+                let text = std::str::from_utf8(e)?;
+                serializer.cdsect(text)?;
+            }
+            Event::Comment(e) => {
+                if !options.compact {
+                    serializer.comment(std::str::from_utf8(e)?)?;
+                }
+            }
+            Event::PI(e) => {
+                let target = std::str::from_utf8(e.target())?;
+                let raw = e.content();
+                let data = if raw.is_empty() {
+                    None
+                } else {
+                    Some(std::str::from_utf8(raw)?)
+                };
+
+                if target == "xml"
+                    && let Some(content) = data
+                    && content.contains("encoding")
+                    && !content.to_lowercase().contains("utf-8")
+                {
+                    diagnostics.warning(
+                        location,
+                        "Non-UTF-8 encoding is not supported and might be lost",
+                        Some(format!("Found in declaration: {}", content)),
+                    );
+                }
+
+                #[cfg(feature = "unstable")]
+                if let Some(extension) = options.extensions.for_target(target) {
+                    let (type_info, payload) =
+                        extension.encode(data.unwrap_or_default().trim_start())?;
+                    return serializer.extension_token(extension.command(), type_info, &payload);
+                }
+
+                serializer.processing_instruction(target, data)?;
+            }
+            Event::Decl(decl) => {
+                if let Some(enc_result) = decl.encoding() {
+                    let enc_bytes = enc_result?;
+                    let enc = std::str::from_utf8(enc_bytes.as_ref())?;
+                    if !enc.to_lowercase().contains("utf-8") {
+                        diagnostics.warning(
+                            location,
+                            "Non-UTF-8 encoding is not supported and might be lost",
+                            Some(format!("Found encoding: {}", enc)),
+                        );
+                    }
+                }
+            }
+            Event::DocType(e) => {
+                if !options.compact {
+                    serializer.docdecl(std::str::from_utf8(e)?)?;
+                }
+            }
+            Event::GeneralRef(e) => {
+                let text = std::str::from_utf8(e)?;
+                serializer.entity_ref(text)?;
+            }
+            Event::Eof => return self.end_document_at(location),
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Lenient XML
+// ============================================================================
+
+/// Escapes `&` and `<` in text that cannot start a reference or markup, as hand
+/// edits tend to leave behind, recording each as an error. Tags, comments, CDATA
+/// sections and processing instructions are copied unchanged.
+fn escape_stray_markup<'a>(xml: &'a str, diagnostics: &mut Diagnostics) -> Result<Cow<'a, str>> {
+    let bytes = xml.as_bytes();
+    let mut repaired = String::new();
+    // start of the input not yet copied to `repaired`
+    let mut copied = 0;
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'<' => {
+                let rest = &xml[index..];
+                let end = if rest.starts_with("<!--") {
+                    rest.find("-->").map(|end| end + 3)
+                } else if rest.starts_with("<![CDATA[") {
+                    rest.find("]]>").map(|end| end + 3)
+                } else if rest.starts_with("<?") {
+                    rest.find("?>").map(|end| end + 2)
+                } else if rest.starts_with("<!DOCTYPE") {
+                    doctype_end(rest)
+                } else if rest[1..]
+                    .starts_with(|c: char| c.is_alphabetic() || matches!(c, '_' | ':' | '/' | '!'))
+                {
+                    tag_end(rest)
+                } else {
+                    None
+                };
+                match end {
+                    Some(end) => index += end,
+                    None => {
+                        diagnostics
+                            .error(Location::Offset(index as u64), "Stray '<' in text, escaped")?;
+                        repaired.push_str(&xml[copied..index]);
+                        repaired.push_str("&lt;");
+                        index += 1;
+                        copied = index;
+                    }
+                }
+            }
+            b'&' => {
+                if !is_reference(&xml[index..]) {
+                    diagnostics
+                        .error(Location::Offset(index as u64), "Stray '&' in text, escaped")?;
+                    repaired.push_str(&xml[copied..index]);
+                    repaired.push_str("&amp;");
+                    copied = index + 1;
+                }
+                index += 1;
+            }
+            _ => index += 1,
+        }
+    }
+
+    if copied == 0 {
+        return Ok(Cow::Borrowed(xml));
+    }
+    repaired.push_str(&xml[copied..]);
+    Ok(Cow::Owned(repaired))
+}
+
+/// Length of the tag at the start of `rest`, skipping `>` inside quoted values
+fn tag_end(rest: &str) -> Option<usize> {
+    let mut quote = None;
+    for (index, c) in rest.char_indices().skip(1) {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(index + 1),
+            (None, '<') => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Length of the DOCTYPE declaration at the start of `rest`, including an internal
+/// subset in brackets
+fn doctype_end(rest: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (index, c) in rest.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth = depth.saturating_sub(1),
+            '>' if depth == 0 => return Some(index + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether `rest` starts with an entity or character reference such as `&amp;`
+/// or `&#x41;`
+fn is_reference(rest: &str) -> bool {
+    let Some(end) = rest[1..].find(';') else {
+        return false;
+    };
+    let body = &rest[1..1 + end];
+    if let Some(hex) = body.strip_prefix("#x") {
+        !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit())
+    } else if let Some(decimal) = body.strip_prefix('#') {
+        !decimal.is_empty() && decimal.chars().all(|c| c.is_ascii_digit())
+    } else {
+        body.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            && body
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'))
+    }
+}
+
+// ============================================================================
+// Synthetic Root
+// ============================================================================
+
+/// Wraps the top-level content of `xml` in `<root>` when it holds more than one
+/// element or stray text, as some vendor tools leave behind; anything else is
+/// returned unchanged
+fn wrap_roots<'a>(xml: &'a str, root: &str, diagnostics: &mut Diagnostics) -> Result<Cow<'a, str>> {
+    if root.is_empty() || root.contains(|c: char| c.is_whitespace() || "<>/&\"'=".contains(c)) {
+        return Err(ConversionError::ParseError(format!(
+            "Invalid synthetic root name: '{}'",
+            root
+        )));
+    }
+
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().check_end_names = false;
+    let mut depth = 0usize;
+    let mut elements = 0usize;
+    let mut stray_content = false;
+    // byte range from the first to the end of the last top-level item
+    let mut span: Option<(usize, usize)> = None;
+
+    loop {
+        let start = reader.buffer_position() as usize;
+        let event = reader.read_event()?;
+        let end = reader.buffer_position() as usize;
+        let top_level = match event {
+            Event::Start(_) => {
+                depth += 1;
+                if depth == 1 {
+                    elements += 1;
+                }
+                depth == 1
+            }
+            Event::End(_) => {
+                depth = depth.saturating_sub(1);
+                depth == 0
+            }
+            Event::Empty(_) if depth == 0 => {
+                elements += 1;
+                true
+            }
+            Event::Text(text) if depth == 0 => {
+                let stray = !type_detection::is_whitespace_only(std::str::from_utf8(&text)?);
+                stray_content |= stray;
+                stray
+            }
+            Event::CData(_) | Event::GeneralRef(_) if depth == 0 => {
+                stray_content = true;
+                true
+            }
+            Event::Eof => break,
+            _ => false,
+        };
+        if top_level {
+            let first = span.map_or(start, |(first, _)| first);
+            span = Some((first, end));
+        }
+    }
+
+    let Some((first, last)) = span else {
+        return Ok(Cow::Borrowed(xml));
+    };
+    if elements <= 1 && !stray_content {
+        return Ok(Cow::Borrowed(xml));
+    }
+
+    diagnostics.warning(
+        Location::Offset(first as u64),
+        "Multiple root elements or stray top-level content wrapped in a synthetic root",
+        Some(format!(
+            "Found {} top-level elements, wrapped in <{}>",
+            elements, root
+        )),
+    );
+    Ok(Cow::Owned(format!(
+        "{}<{root}>{}</{root}>{}",
+        &xml[..first],
+        &xml[first..last],
+        &xml[last..]
+    )))
+}
+
+// ============================================================================
+// Size Estimation
+// ============================================================================
+
+/// A writer that discards everything and only counts bytes
+#[derive(Debug, Default)]
+struct CountingWriter {
+    written: u64,
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the exact size in bytes that converting `xml` to ABX would produce.
+///
+/// Runs the full encoder against a counting sink, so nothing is allocated for the
+/// output. Useful for checking partition or quota limits before converting.
+///
+/// ```
+/// use android_xml_converter::{XmlToAbxConverter, estimate_abx_size};
+///
+/// let xml = r#"<map><int name="count" value="3" /></map>"#;
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+/// assert_eq!(estimate_abx_size(xml).unwrap(), abx.len() as u64);
+/// ```
+pub fn estimate_abx_size(xml: &str) -> Result<u64> {
+    estimate_abx_size_with(xml, &Options::default())
+}
+
+pub fn estimate_abx_size_with(xml: &str, options: &Options) -> Result<u64> {
+    let mut counter = CountingWriter::default();
+    XmlToAbxConverter::convert_from_string_with(xml, &mut counter, options)?;
+    Ok(counter.written)
+}
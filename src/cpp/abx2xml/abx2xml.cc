@@ -7,6 +7,7 @@
 #include <array>
 #include <algorithm>
 #include <bit>
+#include <utility>
 
 namespace abx2xml {
 
@@ -64,6 +65,38 @@ std::string base64_encode(const uint8_t* data, size_t len) {
 }
 
 
+// Android writes NUL as C0 80 and supplementary characters as surrogate pairs
+// (modified UTF-8), both invalid in UTF-8; turn them into plain UTF-8
+std::string from_modified_utf8(std::string text) {
+    if (text.find('\xC0') == std::string::npos && text.find('\xED') == std::string::npos) {
+        return text;
+    }
+
+    auto byte = [&text](size_t at) { return static_cast<uint8_t>(text[at]); };
+    std::string result;
+    result.reserve(text.size());
+    for (size_t i = 0; i < text.size(); ++i) {
+        if (byte(i) == 0xC0 && i + 1 < text.size() && byte(i + 1) == 0x80) {
+            result += '\0';
+            i += 1;
+        } else if (byte(i) == 0xED && i + 5 < text.size() && (byte(i + 1) & 0xF0) == 0xA0 &&
+                   byte(i + 3) == 0xED && (byte(i + 4) & 0xF0) == 0xB0) {
+            uint32_t high = ((byte(i + 1) & 0x0F) << 6) | (byte(i + 2) & 0x3F);
+            uint32_t low = ((byte(i + 4) & 0x0F) << 6) | (byte(i + 5) & 0x3F);
+            uint32_t code = 0x10000 + (high << 10) + low;
+            result += static_cast<char>(0xF0 | (code >> 18));
+            result += static_cast<char>(0x80 | ((code >> 12) & 0x3F));
+            result += static_cast<char>(0x80 | ((code >> 6) & 0x3F));
+            result += static_cast<char>(0x80 | (code & 0x3F));
+            i += 5;
+        } else {
+            result += text[i];
+        }
+    }
+    return result;
+}
+
+
 DataInput::DataInput(std::istream& input) 
     : reader(input), peeked_byte(std::nullopt) {}
 
@@ -157,13 +190,16 @@ std::string DataInput::read_utf() {
         result[i] = static_cast<char>(read_byte());
     }
     
-    return result;
+    return from_modified_utf8(std::move(result));
 }
 
 std::string DataInput::read_interned_utf() {
     uint16_t index = read_short();
     
     if (index == 0xFFFF) {
+        if (interned_strings.size() >= 0xFFFF) {
+            throw AbxError("Intern pool full: more than 65535 strings");
+        }
         std::string str = read_utf();
         interned_strings.push_back(str);
         return str;
@@ -53,6 +53,7 @@ public:
 [[nodiscard]] std::string encode_xml_entities(std::string_view text);
 [[nodiscard]] std::string hex_encode(const uint8_t* data, size_t len);
 [[nodiscard]] std::string base64_encode(const uint8_t* data, size_t len);
+[[nodiscard]] std::string from_modified_utf8(std::string text);
 
 
 class DataInput {
@@ -4,8 +4,11 @@
 #include <fstream>
 #include <sstream>
 #include <algorithm>
+#include <charconv>
+#include <cmath>
 #include <cstring>
 #include <functional>
+#include <string_view>
 
 namespace xml2abx {
 
@@ -84,6 +87,9 @@ void FastDataOutput::write_interned_utf(const std::string& s) {
     if (it != string_pool_.end()) {
         write_short(it->second);
     } else {
+        if (interned_strings_.size() >= 0xFFFF) {
+            throw ConversionError("Intern pool full: more than 65535 strings");
+        }
         write_short(0xFFFF);
         write_utf(s);
         uint16_t index = static_cast<uint16_t>(interned_strings_.size());
@@ -137,6 +143,9 @@ void BinaryXmlSerializer::start_tag(const std::string& name) {
 }
 
 void BinaryXmlSerializer::end_tag(const std::string& name) {
+    if (tag_count_ == 0) {
+        throw ConversionError("End tag " + name + " without an open element");
+    }
     tag_count_--;
     output_.write_byte(END_TAG | TYPE_STRING_INTERNED);
     output_.write_interned_utf(name);
@@ -172,6 +181,18 @@ void BinaryXmlSerializer::attribute_long(const std::string& name, int64_t value)
     output_.write_long(value);
 }
 
+void BinaryXmlSerializer::attribute_int_hex(const std::string& name, int32_t value) {
+    output_.write_byte(ATTRIBUTE | TYPE_INT_HEX);
+    output_.write_interned_utf(name);
+    output_.write_int(value);
+}
+
+void BinaryXmlSerializer::attribute_long_hex(const std::string& name, int64_t value) {
+    output_.write_byte(ATTRIBUTE | TYPE_LONG_HEX);
+    output_.write_interned_utf(name);
+    output_.write_long(value);
+}
+
 void BinaryXmlSerializer::attribute_float(const std::string& name, float value) {
     output_.write_byte(ATTRIBUTE | TYPE_FLOAT);
     output_.write_interned_utf(name);
@@ -249,6 +270,64 @@ namespace type_detection {
             return std::isspace(c);
         });
     }
+
+    // Shortest round-trip text in plain notation, like the Rust converter
+    template<typename T>
+    std::string shortest(T value) {
+        char buffer[64];
+        auto result = std::to_chars(buffer, buffer + sizeof(buffer), value, std::chars_format::fixed);
+        std::string text(buffer, result.ptr);
+        if (text.find('.') == std::string::npos) {
+            text += ".0";
+        }
+        return text;
+    }
+
+    // Writes s as a number if it is one that prints back as the same text, see
+    // type_detection::infer_number of the Rust converter. 0x hex stays a string:
+    // int_hex prints without the prefix and would read back as a decimal int
+    bool write_number(BinaryXmlSerializer& serializer, const std::string& name,
+                      const std::string& s) {
+        const char* end = s.data() + s.size();
+
+        int64_t integer;
+        auto result = std::from_chars(s.data(), end, integer);
+        if (result.ec == std::errc() && result.ptr == end) {
+            if (std::to_string(integer) != s) {
+                return false;
+            }
+            if (integer >= INT32_MIN && integer <= INT32_MAX) {
+                serializer.attribute_int(name, static_cast<int32_t>(integer));
+            } else {
+                serializer.attribute_long(name, integer);
+            }
+            return true;
+        }
+
+        // no exponents, infinities or NaN, which Java spells differently
+        if (s.find('.') == std::string::npos || !std::all_of(s.begin(), s.end(), [](unsigned char c) {
+                return std::isdigit(c) || c == '.' || c == '-';
+            })) {
+            return false;
+        }
+        double value;
+        result = std::from_chars(s.data(), end, value);
+        if (result.ec != std::errc() || result.ptr != end) {
+            return false;
+        }
+        double magnitude = std::abs(value);
+        if (magnitude != 0.0 && (magnitude < 1e-3 || magnitude >= 1e7)) {
+            return false;
+        }
+        if (shortest(static_cast<float>(value)) == s) {
+            serializer.attribute_float(name, static_cast<float>(value));
+        } else if (shortest(value) == s) {
+            serializer.attribute_double(name, value);
+        } else {
+            return false;
+        }
+        return true;
+    }
 }
 
 
@@ -258,6 +337,8 @@ void XmlToAbxConverter::write_attribute(BinaryXmlSerializer& serializer,
     
     if (type_detection::is_boolean(value)) {
         serializer.attribute_boolean(name, value == "true");
+    } else if (type_detection::write_number(serializer, name, value)) {
+        return;
     } else {
         
         
@@ -105,6 +105,8 @@ public:
     void attribute_boolean(const std::string& name, bool value);
     void attribute_int(const std::string& name, int32_t value);
     void attribute_long(const std::string& name, int64_t value);
+    void attribute_int_hex(const std::string& name, int32_t value);
+    void attribute_long_hex(const std::string& name, int64_t value);
     void attribute_float(const std::string& name, float value);
     void attribute_double(const std::string& name, double value);
     void attribute_bytes_hex(const std::string& name, const uint8_t* data, size_t length);
@@ -133,6 +135,8 @@ private:
 namespace type_detection {
     bool is_boolean(const std::string& s);
     bool is_whitespace_only(const std::string& s);
+    bool write_number(BinaryXmlSerializer& serializer, const std::string& name,
+                      const std::string& s);
 }
 
 
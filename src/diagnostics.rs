@@ -0,0 +1,180 @@
+use crate::*;
+use std::fmt;
+use std::io::Write;
+
+// ============================================================================
+// Diagnostics
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// Where a problem was found: a token number in ABX input or a byte offset in XML input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Token(usize),
+    Offset(u64),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Token(token) => write!(f, "token #{}", token),
+            Location::Offset(offset) => write!(f, "offset {}", offset),
+        }
+    }
+}
+
+/// A problem found during conversion.
+///
+/// Repeats of the same message are folded into one entry that keeps the count,
+/// the first and last location and the detail of the first occurrence.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub detail: Option<String>,
+    pub count: usize,
+    pub first: Location,
+    pub last: Location,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.count == 1 {
+            write!(f, "{}: {}: {}", self.severity, self.first, self.message)?;
+        } else {
+            write!(
+                f,
+                "{}: {} .. {}: {} ({} occurrences)",
+                self.severity, self.first, self.last, self.message, self.count
+            )?;
+        }
+        if let Some(detail) = &self.detail {
+            if self.count == 1 {
+                write!(f, "\n  {}", detail)?;
+            } else {
+                write!(f, "\n  first: {}", detail)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Problems collected during a conversion.
+///
+/// In lenient mode recoverable errors are recorded instead of aborting until more
+/// than `max_errors` have been seen.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    entries: Vec<Diagnostic>,
+    errors: usize,
+    max_errors: Option<usize>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows up to `max_errors` errors before [`Self::error`] fails
+    pub fn with_max_errors(max_errors: usize) -> Self {
+        Self {
+            max_errors: Some(max_errors),
+            ..Self::default()
+        }
+    }
+
+    pub fn warning(&mut self, location: Location, message: &str, detail: Option<String>) {
+        self.record(Severity::Warning, location, message, detail);
+    }
+
+    /// Records an error, failing with [`ConversionError::TooManyErrors`] once the
+    /// limit is exceeded
+    pub fn error(&mut self, location: Location, message: &str) -> Result<()> {
+        self.record(Severity::Error, location, message, None);
+        self.errors += 1;
+
+        match self.max_errors {
+            Some(max_errors) if self.errors > max_errors => {
+                Err(ConversionError::TooManyErrors(max_errors))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn record(
+        &mut self,
+        severity: Severity,
+        location: Location,
+        message: &str,
+        detail: Option<String>,
+    ) {
+        let existing = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.severity == severity && entry.message == message);
+
+        match existing {
+            Some(entry) => {
+                entry.count += 1;
+                entry.last = location;
+            }
+            None => self.entries.push(Diagnostic {
+                severity,
+                message: message.to_string(),
+                detail,
+                count: 1,
+                first: location,
+                last: location,
+            }),
+        }
+    }
+
+    /// One entry per distinct message, in order of first occurrence
+    pub fn entries(&self) -> &[Diagnostic] {
+        &self.entries
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.errors
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.severity == Severity::Warning)
+            .map(|entry| entry.count)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn write_to<W: Write>(&self, mut output: W) -> Result<()> {
+        for entry in &self.entries {
+            writeln!(output, "{}", entry)?;
+        }
+        writeln!(
+            output,
+            "{} error(s), {} warning(s)",
+            self.errors,
+            self.warning_count()
+        )?;
+        output.flush()?;
+        Ok(())
+    }
+}
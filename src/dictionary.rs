@@ -0,0 +1,297 @@
+use crate::*;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(feature = "android-dictionary")]
+use phf::phf_ordered_set;
+use smol_str::SmolStr;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+// ============================================================================
+// Shared Dictionary
+// ============================================================================
+
+/// Magic header of an exported dictionary file
+pub const DICTIONARY_MAGIC: [u8; 4] = [0x41, 0x42, 0x58, 0x44];
+
+/// A list of strings used to pre-seed the intern pool of a conversion.
+///
+/// Exported from one conversion and loaded into later ones, it keeps interning
+/// consistent across a homogeneous corpus and avoids re-defining the same names in
+/// every file. Both the writer and the reader have to be given the same dictionary;
+/// files written with one are not readable by Android.
+///
+/// The strings and their lookup table are built once and shared: clones are
+/// cheap and can be handed to any number of threads, and a writer seeded with a
+/// dictionary looks strings up in the shared table, keeping only the strings a
+/// file adds in its own pool. Parallel conversions of near-identical files then
+/// intern the common names once instead of once per file.
+///
+/// ```
+/// use android_xml_converter::dictionary::Dictionary;
+/// use android_xml_converter::{Options, XmlToAbxConverter};
+/// use smol_str::SmolStr;
+///
+/// let dictionary = Dictionary::new(vec![SmolStr::new("setting"), SmolStr::new("value")]);
+/// assert_eq!(dictionary.index_of("value"), Some(1));
+///
+/// let options = Options::new().dictionary(dictionary);
+/// let outputs: Vec<Vec<u8>> = std::thread::scope(|scope| {
+///     let workers: Vec<_> = ["a", "b"]
+///         .map(|id| {
+///             let options = &options;
+///             scope.spawn(move || {
+///                 let xml = format!(r#"<setting id="{}" value="1"/>"#, id);
+///                 let mut abx = Vec::new();
+///                 XmlToAbxConverter::convert_from_string_with(&xml, &mut abx, options).unwrap();
+///                 abx
+///             })
+///         })
+///         .into_iter()
+///         .collect();
+///     workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+/// });
+/// // the tag and attribute names are referenced by index, never defined
+/// assert!(outputs.iter().all(|abx| !abx.windows(7).any(|w| w == b"setting")));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Dictionary {
+    strings: Arc<[SmolStr]>,
+    /// Index of the first occurrence of each string
+    indices: Arc<FastHashMap<SmolStr, u16>>,
+}
+
+impl PartialEq for Dictionary {
+    fn eq(&self, other: &Self) -> bool {
+        self.strings == other.strings
+    }
+}
+
+impl Dictionary {
+    pub fn new(strings: Vec<SmolStr>) -> Self {
+        let mut indices = FastHashMap::default();
+        // entries past the addressable range are rejected when seeding
+        for (index, string) in (0..INTERNED_STRING_NEW_MARKER).zip(&strings) {
+            indices.entry(string.clone()).or_insert(index);
+        }
+        Self {
+            strings: strings.into(),
+            indices: Arc::new(indices),
+        }
+    }
+
+    pub fn strings(&self) -> &[SmolStr] {
+        &self.strings
+    }
+
+    /// Position of the first occurrence of `string`
+    pub fn index_of(&self, string: &str) -> Option<u16> {
+        self.indices.get(string).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// Reads a dictionary: the `ABXD` magic, a u16 count, then u16 length-prefixed UTF-8 strings
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|_| ConversionError::ReadError("dictionary header".to_string()))?;
+        if magic != DICTIONARY_MAGIC {
+            return Err(ConversionError::ParseError(
+                "Not a dictionary file (bad magic header)".to_string(),
+            ));
+        }
+
+        let count = reader
+            .read_u16::<BigEndian>()
+            .map_err(|_| ConversionError::ReadError("dictionary size".to_string()))?;
+        let mut strings = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let length = reader
+                .read_u16::<BigEndian>()
+                .map_err(|_| ConversionError::ReadError("dictionary entry".to_string()))?;
+            let mut buffer = vec![0u8; length as usize];
+            reader
+                .read_exact(&mut buffer)
+                .map_err(|_| ConversionError::ReadError("dictionary entry".to_string()))?;
+            strings.push(SmolStr::new(std::str::from_utf8(&buffer)?));
+        }
+        Ok(Self::new(strings))
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        if self.strings.len() >= INTERNED_STRING_NEW_MARKER as usize {
+            return Err(ConversionError::ParseError(format!(
+                "Dictionary has {} entries (max: {})",
+                self.strings.len(),
+                INTERNED_STRING_NEW_MARKER - 1
+            )));
+        }
+
+        writer.write_all(&DICTIONARY_MAGIC)?;
+        writer.write_u16::<BigEndian>(self.strings.len() as u16)?;
+        for string in self.strings.iter() {
+            let bytes = string.as_bytes();
+            if bytes.len() > MAX_UNSIGNED_SHORT as usize {
+                return Err(ConversionError::StringTooLong(
+                    bytes.len(),
+                    MAX_UNSIGNED_SHORT as usize,
+                ));
+            }
+            writer.write_u16::<BigEndian>(bytes.len() as u16)?;
+            writer.write_all(bytes)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+}
+
+// ============================================================================
+// Built-in Android Dictionary
+// ============================================================================
+
+/// Tag and attribute names that occur in most Android system files
+/// (`packages.xml`, `settings_*.xml`, `appops.xml`, `runtime-permissions.xml`, ...).
+///
+/// When enabled through [`crate::Options::android_dictionary`], both sides start
+/// with these strings in the intern pool, index = position in this set. Entries may
+/// only ever be appended, otherwise files written with an older list decode wrongly.
+#[cfg(feature = "android-dictionary")]
+pub static ANDROID_NAMES: phf::OrderedSet<&'static str> = phf_ordered_set! {
+    // packages.xml
+    "packages",
+    "package",
+    "updated-package",
+    "shared-user",
+    "name",
+    "realName",
+    "codePath",
+    "nativeLibraryPath",
+    "primaryCpuAbi",
+    "secondaryCpuAbi",
+    "publicFlags",
+    "privateFlags",
+    "pkgFlags",
+    "ft",
+    "ut",
+    "version",
+    "versionCode",
+    "versionName",
+    "userId",
+    "sharedUserId",
+    "installer",
+    "installerUid",
+    "installInitiator",
+    "installOriginator",
+    "isOrphaned",
+    "packageSource",
+    "loadingProgress",
+    "loadingCompletedTime",
+    "domainSetId",
+    "appMetadataFilePath",
+    "appMetadataSource",
+    "targetSdkVersion",
+    "categoryHint",
+    "scannedAsStoppedSystemApp",
+    "sigs",
+    "cert",
+    "index",
+    "key",
+    "count",
+    "perms",
+    "item",
+    "granted",
+    "flags",
+    "proper-signing-keyset",
+    "identifier",
+    "signing-keyset",
+    "upgrade-keyset",
+    "defined-keyset",
+    "keys",
+    "public-key",
+    "value",
+    "keyset-settings",
+    "keysets",
+    "keyset",
+    "lastIssuedKeyId",
+    "lastIssuedKeySetId",
+    "permission-trees",
+    "permissions",
+    "protection",
+    "type",
+    "read-external-storage",
+    "last-platform-version",
+    "internal",
+    "external",
+    "sdkVersion",
+    "databaseVersion",
+    "fingerprint",
+    "buildFingerprint",
+    "database-version",
+    "verifier",
+    "device",
+    "restored-ivi",
+    "preferred-activities",
+    "persistent-preferred-activities",
+    "crossProfile-intent-filters",
+    "default-browser",
+    "renamed-package",
+    "new",
+    "old",
+    "mime-group",
+    "split-version",
+    "uses-static-lib",
+    "uses-sdk-lib",
+    "overlay",
+    "enabled",
+    "disabled-components",
+    "enabled-components",
+    "install-time-permissions",
+    "runtime-permissions",
+    "pkg",
+    "shared-user-id",
+    // settings_*.xml
+    "settings",
+    "setting",
+    "id",
+    "defaultValue",
+    "defaultSysSet",
+    "preserve_in_restore",
+    "tag",
+    // appops.xml and friends
+    "app-ops",
+    "app-op",
+    "uid",
+    "op",
+    "n",
+    "m",
+    "st",
+    "t",
+    "r",
+    "d",
+    "pp",
+    "pu",
+    "v",
+    // common values
+    "true",
+    "false",
+    "0",
+    "1",
+    "-1",
+};
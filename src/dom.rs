@@ -0,0 +1,506 @@
+use crate::pipeline::{Sink, XmlSink};
+use crate::reader::AttributeValue;
+use crate::roundtrip::{RawDocument, Token};
+use crate::*;
+use smol_str::SmolStr;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+// ============================================================================
+// Document Tree
+// ============================================================================
+
+/// An attribute with its typed value, see the `to_*` conversions of
+/// [`AttributeValue`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub name: SmolStr,
+    pub value: AttributeValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Child {
+    Element(usize),
+    /// Text, comments and every other non-element token
+    Content {
+        command: u8,
+        text: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct ElementData {
+    name: SmolStr,
+    attributes: Vec<Attribute>,
+    parent: Option<usize>,
+    children: Vec<Child>,
+}
+
+/// An ABX document held in memory as a tree, for inspecting it without
+/// following the token stream.
+///
+/// Text, comments and other non-element content are kept in place and the
+/// intern pool order of the loaded file is remembered, so writing an unchanged
+/// document back produces the same bytes.
+///
+/// ```
+/// use android_xml_converter::XmlToAbxConverter;
+/// use android_xml_converter::dom::AbxDocument;
+///
+/// let xml = r#"<packages><package name="com.example" ut="18a4f3c2b10"><perms/></package></packages>"#;
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+///
+/// let document = AbxDocument::from_bytes(&abx).unwrap();
+/// let root = document.root().unwrap();
+/// let package = root.child("package").unwrap();
+/// assert_eq!(package.attribute("name").unwrap().to_string(), "com.example");
+/// assert_eq!(package.attribute("ut").unwrap().to_i64_hex().unwrap(), 0x18a4f3c2b10);
+///
+/// let perms = package.child("perms").unwrap();
+/// assert_eq!(perms.parent().unwrap().name(), "package");
+/// assert_eq!(document.to_bytes().unwrap(), abx);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AbxDocument {
+    elements: Vec<ElementData>,
+    /// Content outside of any element, including the root
+    top: Vec<Child>,
+    intern_order: Vec<SmolStr>,
+}
+
+impl AbxDocument {
+    pub fn decode<R: Read>(reader: R) -> Result<Self> {
+        Self::from_raw(RawDocument::decode(reader)?)
+    }
+
+    pub fn from_bytes(abx_data: &[u8]) -> Result<Self> {
+        Self::from_raw(RawDocument::from_bytes(abx_data)?)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::decode(BufReader::new(File::open(path)?))
+    }
+
+    /// Builds the tree from a token stream; fails on end tags that do not match
+    /// their start tag and on elements left open
+    pub fn from_raw(raw: RawDocument) -> Result<Self> {
+        let mut document = Self {
+            intern_order: raw.intern_order().to_vec(),
+            ..Self::default()
+        };
+        let mut open: Vec<usize> = Vec::new();
+
+        for (index, token) in raw.into_events().enumerate() {
+            match token {
+                Token::StartDocument | Token::EndDocument => {}
+                Token::StartTag(name) => {
+                    let element = document.elements.len();
+                    document.elements.push(ElementData {
+                        name,
+                        attributes: Vec::new(),
+                        parent: open.last().copied(),
+                        children: Vec::new(),
+                    });
+                    document.push_child(open.last().copied(), Child::Element(element));
+                    open.push(element);
+                }
+                Token::Attribute { name, value } => {
+                    let Some(&element) = open.last() else {
+                        return Err(ConversionError::ParseError(format!(
+                            "Attribute {} outside of an element in token #{}",
+                            name,
+                            index + 1
+                        )));
+                    };
+                    document.elements[element]
+                        .attributes
+                        .push(Attribute { name, value });
+                }
+                Token::EndTag(name) => match open.pop() {
+                    Some(element) if document.elements[element].name == name => {}
+                    _ => {
+                        return Err(ConversionError::ParseError(format!(
+                            "Unexpected end tag {} in token #{}",
+                            name,
+                            index + 1
+                        )));
+                    }
+                },
+                Token::Content { command, text } => {
+                    document.push_child(open.last().copied(), Child::Content { command, text });
+                }
+            }
+        }
+
+        if let Some(&element) = open.last() {
+            return Err(ConversionError::ParseError(format!(
+                "Element {} is never closed",
+                document.elements[element].name
+            )));
+        }
+        Ok(document)
+    }
+
+    /// Builds the tree from any token source, e.g. a
+    /// [`TokenReader`](crate::roundtrip::TokenReader) or a
+    /// filtered [`Self::into_events`], failing like [`Self::from_raw`];
+    /// interned strings are defined in first-use order
+    ///
+    /// ```
+    /// use android_xml_converter::XmlToAbxConverter;
+    /// use android_xml_converter::dom::AbxDocument;
+    /// use android_xml_converter::roundtrip::TokenReader;
+    ///
+    /// let mut abx = Vec::new();
+    /// XmlToAbxConverter::convert_from_string(r#"<a b="1"><c>text</c></a>"#, &mut abx).unwrap();
+    ///
+    /// let events = TokenReader::new(abx.as_slice()).unwrap().map(Result::unwrap);
+    /// let document = AbxDocument::from_events(events).unwrap();
+    /// assert_eq!(document.root().unwrap().child("c").unwrap().text(), "text");
+    ///
+    /// let copy = AbxDocument::from_events(document.into_events()).unwrap();
+    /// assert_eq!(copy.to_bytes().unwrap(), abx);
+    /// ```
+    pub fn from_events<I: IntoIterator<Item = Token>>(events: I) -> Result<Self> {
+        Self::from_raw(RawDocument::from_events(events))
+    }
+
+    /// Hands the tree over as tokens for streaming processing. The original
+    /// intern pool order is not part of the events.
+    pub fn into_events(self) -> std::vec::IntoIter<Token> {
+        self.to_raw().into_events()
+    }
+
+    fn push_child(&mut self, parent: Option<usize>, child: Child) {
+        match parent {
+            Some(parent) => self.elements[parent].children.push(child),
+            None => self.top.push(child),
+        }
+    }
+
+    /// The first top-level element
+    pub fn root(&self) -> Option<Element<'_>> {
+        self.top.iter().find_map(|child| match child {
+            Child::Element(index) => Some(self.element(*index)),
+            Child::Content { .. } => None,
+        })
+    }
+
+    /// All elements in document order
+    pub fn elements(&self) -> impl Iterator<Item = Element<'_>> {
+        self.top
+            .iter()
+            .filter_map(|child| match child {
+                Child::Element(index) => Some(self.element(*index)),
+                Child::Content { .. } => None,
+            })
+            .flat_map(|element| element.descendants())
+    }
+
+    /// Looks up an element by the id taken from [`Element::id`]
+    pub fn get(&self, id: ElementId) -> Element<'_> {
+        self.element(id.0)
+    }
+
+    fn element(&self, index: usize) -> Element<'_> {
+        Element {
+            document: self,
+            index,
+        }
+    }
+
+    /// Flattens the tree back into tokens, keeping the original intern pool order
+    pub fn to_raw(&self) -> RawDocument {
+        let mut tokens = vec![Token::StartDocument];
+        // children being written, the next one to write and the element they belong to
+        let mut stack: Vec<(&[Child], usize, Option<usize>)> = vec![(&self.top, 0, None)];
+
+        while let Some((children, next, parent)) = stack.last_mut() {
+            let Some(child) = children.get(*next) else {
+                if let Some(parent) = *parent {
+                    tokens.push(Token::EndTag(self.elements[parent].name.clone()));
+                }
+                stack.pop();
+                continue;
+            };
+            *next += 1;
+
+            match child {
+                Child::Element(index) => {
+                    let element = &self.elements[*index];
+                    tokens.push(Token::StartTag(element.name.clone()));
+                    tokens.extend(element.attributes.iter().map(|attribute| Token::Attribute {
+                        name: attribute.name.clone(),
+                        value: attribute.value.clone(),
+                    }));
+                    stack.push((&element.children, 0, Some(*index)));
+                }
+                Child::Content { command, text } => tokens.push(Token::Content {
+                    command: *command,
+                    text: text.clone(),
+                }),
+            }
+        }
+
+        tokens.push(Token::EndDocument);
+        RawDocument::with_intern_order(tokens, self.intern_order.clone())
+    }
+
+    pub fn encode<W: Write>(&self, writer: W) -> Result<()> {
+        self.to_raw().encode(writer)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.to_raw().to_bytes()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.encode(BufWriter::new(File::create(path)?))
+    }
+
+    /// Writes the document as XML, the same way [`AbxToXmlConverter`] does
+    pub fn write_xml<W: Write>(&self, writer: W) -> Result<()> {
+        let mut sink = XmlSink::new(writer);
+        for token in self.to_raw().events() {
+            sink.token(token)?;
+        }
+        sink.finish()
+    }
+
+    pub fn to_xml(&self) -> Result<String> {
+        let mut output = Vec::new();
+        self.write_xml(&mut output)?;
+        String::from_utf8(output)
+            .map_err(|_| ConversionError::ParseError("Invalid UTF-8 in output".to_string()))
+    }
+}
+
+// ============================================================================
+// Editing
+// ============================================================================
+
+/// Identifies an element of an [`AbxDocument`] across edits. Ids of removed
+/// elements stay valid but no longer refer to anything in the document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ElementId(usize);
+
+impl AbxDocument {
+    /// A document holding nothing but an empty root element
+    pub fn new(root: &str) -> Self {
+        Self {
+            elements: vec![ElementData {
+                name: SmolStr::new(root),
+                attributes: Vec::new(),
+                parent: None,
+                children: Vec::new(),
+            }],
+            top: vec![Child::Element(0)],
+            intern_order: Vec::new(),
+        }
+    }
+
+    /// Sets an attribute, keeping its position if it already exists, and returns
+    /// the old value. The variant of `value` decides the ABX type written.
+    ///
+    /// ```
+    /// use android_xml_converter::XmlToAbxConverter;
+    /// use android_xml_converter::dom::AbxDocument;
+    /// use android_xml_converter::reader::AttributeValue;
+    ///
+    /// let xml = r#"<packages><package name="com.example" stopped="false"/></packages>"#;
+    /// let mut abx = Vec::new();
+    /// XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+    ///
+    /// let mut document = AbxDocument::from_bytes(&abx).unwrap();
+    /// let package = document
+    ///     .elements()
+    ///     .find(|e| e.attribute("name").is_some_and(|v| v.to_string() == "com.example"))
+    ///     .unwrap()
+    ///     .id();
+    /// document.set_attribute(package, "stopped", AttributeValue::Boolean(true));
+    /// document.set_attribute(package, "userId", AttributeValue::Int(10003));
+    ///
+    /// let edited = AbxDocument::from_bytes(&document.to_bytes().unwrap()).unwrap();
+    /// let package = edited.root().unwrap().child("package").unwrap();
+    /// assert_eq!(package.attribute("stopped"), Some(&AttributeValue::Boolean(true)));
+    /// assert_eq!(package.attribute("userId"), Some(&AttributeValue::Int(10003)));
+    /// ```
+    pub fn set_attribute(
+        &mut self,
+        element: ElementId,
+        name: &str,
+        value: AttributeValue,
+    ) -> Option<AttributeValue> {
+        let attributes = &mut self.elements[element.0].attributes;
+        match attributes
+            .iter_mut()
+            .find(|attribute| attribute.name == name)
+        {
+            Some(attribute) => Some(std::mem::replace(&mut attribute.value, value)),
+            None => {
+                attributes.push(Attribute {
+                    name: SmolStr::new(name),
+                    value,
+                });
+                None
+            }
+        }
+    }
+
+    pub fn remove_attribute(&mut self, element: ElementId, name: &str) -> Option<AttributeValue> {
+        let attributes = &mut self.elements[element.0].attributes;
+        let position = attributes
+            .iter()
+            .position(|attribute| attribute.name == name)?;
+        Some(attributes.remove(position).value)
+    }
+
+    /// Adds an empty element after the last child of `parent`
+    pub fn append_element(&mut self, parent: ElementId, name: &str) -> ElementId {
+        let position = self.get(parent).children().count();
+        self.insert_element(parent, position, name)
+    }
+
+    /// Adds an empty element so that it becomes child element number `position`
+    /// of `parent`, or the last one if there are fewer
+    pub fn insert_element(&mut self, parent: ElementId, position: usize, name: &str) -> ElementId {
+        let index = self.elements.len();
+        self.elements.push(ElementData {
+            name: SmolStr::new(name),
+            attributes: Vec::new(),
+            parent: Some(parent.0),
+            children: Vec::new(),
+        });
+        let children = &mut self.elements[parent.0].children;
+        let at = child_slot(children, position);
+        children.insert(at, Child::Element(index));
+        ElementId(index)
+    }
+
+    /// Adds text after the last child of `element`
+    pub fn append_text(&mut self, element: ElementId, text: &str) {
+        self.elements[element.0].children.push(Child::Content {
+            command: TEXT,
+            text: Some(text.to_string()),
+        });
+    }
+
+    /// Detaches an element and everything below it from the document
+    pub fn remove_element(&mut self, element: ElementId) {
+        let parent = self.elements[element.0].parent.take();
+        let children = match parent {
+            Some(parent) => &mut self.elements[parent].children,
+            None => &mut self.top,
+        };
+        children.retain(|child| *child != Child::Element(element.0));
+    }
+
+    /// Moves child element number `from` of `parent` to position `to`, counting
+    /// only elements; text and comments stay where they are
+    pub fn move_element(&mut self, parent: ElementId, from: usize, to: usize) {
+        let children = &mut self.elements[parent.0].children;
+        let from = child_slot(children, from);
+        if from == children.len() {
+            return;
+        }
+        let child = children.remove(from);
+        let to = child_slot(children, to);
+        children.insert(to, child);
+    }
+}
+
+/// Index in `children` of element number `position`, or the end if there are
+/// fewer elements
+fn child_slot(children: &[Child], position: usize) -> usize {
+    children
+        .iter()
+        .enumerate()
+        .filter(|(_, child)| matches!(child, Child::Element(_)))
+        .nth(position)
+        .map_or(children.len(), |(slot, _)| slot)
+}
+
+/// An element of an [`AbxDocument`]
+#[derive(Debug, Clone, Copy)]
+pub struct Element<'a> {
+    document: &'a AbxDocument,
+    index: usize,
+}
+
+impl<'a> Element<'a> {
+    fn data(&self) -> &'a ElementData {
+        &self.document.elements[self.index]
+    }
+
+    pub fn id(&self) -> ElementId {
+        ElementId(self.index)
+    }
+
+    pub fn name(&self) -> &'a str {
+        &self.data().name
+    }
+
+    pub fn attributes(&self) -> &'a [Attribute] {
+        &self.data().attributes
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&'a AttributeValue> {
+        self.attributes()
+            .iter()
+            .find(|attribute| attribute.name == name)
+            .map(|attribute| &attribute.value)
+    }
+
+    pub fn parent(&self) -> Option<Element<'a>> {
+        self.data().parent.map(|index| self.document.element(index))
+    }
+
+    /// Child elements, skipping text and other content
+    pub fn children(self) -> impl Iterator<Item = Element<'a>> + 'a {
+        let document = self.document;
+        self.data()
+            .children
+            .iter()
+            .filter_map(move |child| match child {
+                Child::Element(index) => Some(document.element(*index)),
+                Child::Content { .. } => None,
+            })
+    }
+
+    /// The first child element named `name`
+    pub fn child(&self, name: &str) -> Option<Element<'a>> {
+        self.children().find(|child| child.name() == name)
+    }
+
+    /// The element and everything below it, in document order
+    pub fn descendants(self) -> impl Iterator<Item = Element<'a>> + 'a {
+        let document = self.document;
+        let mut pending = vec![self.index];
+        std::iter::from_fn(move || {
+            let index = pending.pop()?;
+            let children = &document.elements[index].children;
+            pending.extend(children.iter().rev().filter_map(|child| match child {
+                Child::Element(index) => Some(*index),
+                Child::Content { .. } => None,
+            }));
+            Some(document.element(index))
+        })
+    }
+
+    /// The text and CDATA directly inside this element, concatenated
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for child in &self.data().children {
+            if let Child::Content {
+                command: TEXT | CDSECT,
+                text: Some(content),
+            } = child
+            {
+                text.push_str(content);
+            }
+        }
+        text
+    }
+}
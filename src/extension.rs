@@ -0,0 +1,127 @@
+use crate::*;
+use std::fmt;
+use std::io::Read;
+use std::sync::Arc;
+
+// ============================================================================
+// Custom Token Extensions
+// ============================================================================
+
+/// Token commands the ABX protocol leaves unassigned
+pub const RESERVED_COMMANDS: std::ops::RangeInclusive<u8> = 11..=14;
+
+/// Handler for one reserved token command, for prototyping format extensions.
+///
+/// In XML an extension token is represented by a processing instruction with the
+/// extension's target, e.g. `<?x-checksum 1f2e?>`. When converting to ABX such
+/// an instruction is passed to [`Self::encode`] and written as the extension
+/// token; when converting to XML the token is passed to [`Self::decode`] and
+/// written back as the instruction.
+///
+/// This API is unstable and may change between minor versions.
+///
+/// ```
+/// use android_xml_converter::extension::{Extensions, TokenExtension};
+/// use android_xml_converter::*;
+/// use std::io::Read;
+///
+/// /// Token 11 carrying a big-endian u32 checksum
+/// struct Checksum;
+///
+/// impl TokenExtension for Checksum {
+///     fn command(&self) -> u8 { 11 }
+///     fn target(&self) -> &str { "x-checksum" }
+///
+///     fn decode(&self, _type_info: u8, input: &mut dyn Read) -> Result<String> {
+///         let mut value = [0u8; 4];
+///         input.read_exact(&mut value)?;
+///         Ok(format!("{:08x}", u32::from_be_bytes(value)))
+///     }
+///
+///     fn encode(&self, data: &str) -> Result<(u8, Vec<u8>)> {
+///         let value = u32::from_str_radix(data, 16)
+///             .map_err(|e| ConversionError::ParseError(e.to_string()))?;
+///         Ok((TYPE_INT, value.to_be_bytes().to_vec()))
+///     }
+/// }
+///
+/// let options = Options::new().extensions(Extensions::new().register(Checksum)?);
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string_with("<a><?x-checksum 1f2e3d4c?></a>", &mut abx, &options)?;
+///
+/// let mut xml = Vec::new();
+/// AbxToXmlConverter::convert_with_options(abx.as_slice(), &mut xml, &options)?;
+/// assert!(String::from_utf8(xml).unwrap().ends_with("<a><?x-checksum 1f2e3d4c?></a>"));
+/// # Ok::<(), ConversionError>(())
+/// ```
+pub trait TokenExtension: Send + Sync {
+    /// The reserved command handled, one of [`RESERVED_COMMANDS`]
+    fn command(&self) -> u8;
+
+    /// Processing instruction target standing for the token in XML
+    fn target(&self) -> &str;
+
+    /// Reads the payload following the token byte, whose upper 4 bits are
+    /// `type_info`, and returns the processing instruction data
+    fn decode(&self, type_info: u8, input: &mut dyn Read) -> Result<String>;
+
+    /// Returns the type bits and payload to write for processing instruction data
+    fn encode(&self, data: &str) -> Result<(u8, Vec<u8>)>;
+}
+
+/// The extensions registered with [`Options::extensions`]
+#[derive(Clone, Default)]
+pub struct Extensions {
+    handlers: Vec<Arc<dyn TokenExtension>>,
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.handlers.iter().map(|h| (h.command(), h.target())))
+            .finish()
+    }
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a handler, failing if its command is not reserved or is taken
+    pub fn register<E: TokenExtension + 'static>(mut self, extension: E) -> Result<Self> {
+        let command = extension.command();
+        if !RESERVED_COMMANDS.contains(&command) {
+            return Err(ConversionError::ParseError(format!(
+                "Token {} is not a reserved token",
+                command
+            )));
+        }
+        if self.for_command(command).is_some() {
+            return Err(ConversionError::ParseError(format!(
+                "Token {} already has an extension",
+                command
+            )));
+        }
+        self.handlers.push(Arc::new(extension));
+        Ok(self)
+    }
+
+    pub fn for_command(&self, command: u8) -> Option<&dyn TokenExtension> {
+        self.handlers
+            .iter()
+            .find(|h| h.command() == command)
+            .map(|h| h.as_ref())
+    }
+
+    pub fn for_target(&self, target: &str) -> Option<&dyn TokenExtension> {
+        self.handlers
+            .iter()
+            .find(|h| h.target() == target)
+            .map(|h| h.as_ref())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+}
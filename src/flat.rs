@@ -0,0 +1,200 @@
+use crate::roundtrip::{RawDocument, Token};
+use crate::writer::BinaryXmlSerializer;
+use crate::*;
+use std::io::{BufRead, Read, Write};
+
+// ============================================================================
+// Flat Settings
+// ============================================================================
+
+/// Element names used when rebuilding a document from flat lines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlatLayout {
+    pub root: String,
+    pub element: String,
+}
+
+impl Default for FlatLayout {
+    /// The layout of `settings_global.xml`, `settings_secure.xml` and friends
+    fn default() -> Self {
+        Self {
+            root: "settings".to_string(),
+            element: "setting".to_string(),
+        }
+    }
+}
+
+fn escape_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape_value(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Names are escaped like values, plus `=`, which would end the name, and a
+/// leading `#`, which would start a comment
+fn escape_name(name: &str) -> String {
+    let escaped = escape_value(name).replace('=', "\\=");
+    if escaped.starts_with('#') {
+        format!("\\{}", escaped)
+    } else {
+        escaped
+    }
+}
+
+/// Splits a line at its first unescaped `=`
+fn split_line(line: &str) -> Option<(&str, &str)> {
+    let mut escaped = false;
+    for (index, ch) in line.char_indices() {
+        match ch {
+            '\\' => escaped = !escaped,
+            '=' if !escaped => return Some((&line[..index], &line[index + 1..])),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// An element with a `name` attribute whose value is still being collected
+struct NamedFrame {
+    name: Option<String>,
+    value: Option<String>,
+    text: String,
+    has_children: bool,
+}
+
+/// Writes one `name=value` line per element carrying a `name` attribute.
+///
+/// The value is the element's `value` attribute or, failing that, its text
+/// content, which covers both settings files and shared preferences. Elements
+/// without either produce a bare `name` line. Backslashes and line breaks are
+/// escaped as `\\`, `\n` and `\r`, and names also escape `=` and a leading
+/// `#` as `\=` and `\#`, so [`unflatten`] gives back the same names and values:
+///
+/// ```
+/// use android_xml_converter::flat::{self, FlatLayout};
+/// use android_xml_converter::{AbxToXmlConverter, XmlToAbxConverter};
+///
+/// let xml = r##"<settings><setting name="b=c" value="v"></setting><setting name="#d\e" value="x=y"></setting></settings>"##;
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+///
+/// let mut lines = Vec::new();
+/// flat::flatten(&abx[..], &mut lines).unwrap();
+/// assert_eq!(String::from_utf8_lossy(&lines), "b\\=c=v\n\\#d\\\\e=x=y\n");
+///
+/// let mut rebuilt = Vec::new();
+/// flat::unflatten(&lines[..], &mut rebuilt, &FlatLayout::default()).unwrap();
+/// let mut output = Vec::new();
+/// AbxToXmlConverter::convert(&rebuilt[..], &mut output).unwrap();
+/// assert!(String::from_utf8(output).unwrap().ends_with(xml));
+/// ```
+pub fn flatten<R: Read, W: Write>(abx_reader: R, mut writer: W) -> Result<usize> {
+    let document = RawDocument::decode(abx_reader)?;
+    let mut stack: Vec<NamedFrame> = Vec::new();
+    let mut lines = 0;
+
+    for token in &document.tokens {
+        match token {
+            Token::StartTag(_) => {
+                if let Some(parent) = stack.last_mut() {
+                    parent.has_children = true;
+                }
+                stack.push(NamedFrame {
+                    name: None,
+                    value: None,
+                    text: String::new(),
+                    has_children: false,
+                });
+            }
+            Token::Attribute { name, value } => {
+                if let Some(frame) = stack.last_mut() {
+                    match name.as_str() {
+                        "name" => frame.name = Some(value.to_string()),
+                        "value" => frame.value = Some(value.to_string()),
+                        _ => {}
+                    }
+                }
+            }
+            Token::Content {
+                command: TEXT | CDSECT,
+                text: Some(text),
+            } => {
+                if let Some(frame) = stack.last_mut() {
+                    frame.text.push_str(text);
+                }
+            }
+            Token::EndTag(_) => {
+                let Some(frame) = stack.pop() else { continue };
+                let Some(name) = frame.name else { continue };
+                let value = frame
+                    .value
+                    .or((!frame.has_children && !frame.text.is_empty()).then_some(frame.text));
+                match value {
+                    Some(value) => {
+                        writeln!(writer, "{}={}", escape_name(&name), escape_value(&value))?
+                    }
+                    None => writeln!(writer, "{}", escape_name(&name))?,
+                }
+                lines += 1;
+            }
+            _ => {}
+        }
+    }
+
+    writer.flush()?;
+    Ok(lines)
+}
+
+/// Rebuilds an ABX document from `name=value` lines, one element per line with
+/// string `name` and `value` attributes. The line is split at its first `=`
+/// that is not escaped, and both sides are unescaped. A bare `name` line yields
+/// an element without a value; empty lines and lines starting with `#` are
+/// skipped.
+pub fn unflatten<R: BufRead, W: Write>(reader: R, writer: W, layout: &FlatLayout) -> Result<usize> {
+    let mut serializer = BinaryXmlSerializer::new(writer)?;
+    let mut elements = 0;
+
+    serializer.start_document()?;
+    serializer.start_tag(&layout.root)?;
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        serializer.start_tag(&layout.element)?;
+        match split_line(&line) {
+            Some((name, value)) => {
+                serializer.attribute("name", &unescape_value(name))?;
+                serializer.attribute("value", &unescape_value(value))?;
+            }
+            None => serializer.attribute("name", &unescape_value(&line))?,
+        }
+        serializer.end_tag(&layout.element)?;
+        elements += 1;
+    }
+
+    serializer.end_tag(&layout.root)?;
+    serializer.end_document()?;
+    Ok(elements)
+}
@@ -0,0 +1,193 @@
+use crate::query::PoolReport;
+use crate::reader::AttributeValue;
+use crate::roundtrip::{RawDocument, Token};
+use crate::*;
+use smol_str::SmolStr;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ============================================================================
+// Forensic Anomaly Report
+// ============================================================================
+
+/// Attributes Android stores as milliseconds since the epoch, e.g. the first
+/// install (`ft`), install (`it`) and update (`ut`) times in packages.xml
+pub const TIMESTAMP_ATTRIBUTES: &[&str] = &["ft", "it", "ut", "firstInstallTime", "lastUpdateTime"];
+
+/// Release of Android 1.0 (2008-09-23), the earliest plausible timestamp
+pub const EARLIEST_TIMESTAMP_MS: i64 = 1_222_128_000_000;
+
+/// How far into the future a timestamp may lie before it counts as impossible,
+/// allowing for devices with a skewed clock
+const CLOCK_SKEW_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// A timestamp attribute outside the range a real device could have written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimestampAnomaly {
+    pub element: SmolStr,
+    pub attribute: SmolStr,
+    pub value: i64,
+    /// 1-based number of the attribute's token in the stream
+    pub token: usize,
+}
+
+/// Traces of hand edits or foreign tools in an ABX document.
+///
+/// Everything reported here is valid enough to be read, but would never be
+/// produced by Android's own serializer.
+#[derive(Debug, Default)]
+pub struct ForensicReport {
+    /// Pool strings defined again later and never referenced by index, with their
+    /// pool index; Android defines each string exactly once, at its first use
+    pub unreferenced: Vec<(usize, SmolStr)>,
+    /// Bytes following END_DOCUMENT
+    pub trailing: Vec<u8>,
+    /// First offset at which the document differs from a fresh encoding of its
+    /// own tokens, i.e. where strings were interned or written out of order
+    pub nonstandard_encoding: Option<u64>,
+    pub timestamps: Vec<TimestampAnomaly>,
+    /// Why the token stream could not be decoded; the token based checks are
+    /// skipped in that case
+    pub decode_error: Option<String>,
+}
+
+impl ForensicReport {
+    pub fn collect(abx_data: &[u8]) -> Result<Self> {
+        let pool = PoolReport::collect(abx_data)?;
+        let mut report = Self::default();
+
+        for (_, indices) in &pool.duplicates {
+            for &index in indices {
+                let index = index as usize;
+                if pool.references[index] == 0 {
+                    report
+                        .unreferenced
+                        .push((index, pool.entries[index].clone()));
+                }
+            }
+        }
+        report.unreferenced.sort_by_key(|(index, _)| *index);
+
+        let document = match RawDocument::from_bytes(abx_data) {
+            Ok(document) => document,
+            Err(e) => {
+                report.decode_error = Some(e.to_string());
+                return Ok(report);
+            }
+        };
+        report.trailing = document.trailing_bytes().to_vec();
+
+        let original = &abx_data[..abx_data.len() - report.trailing.len()];
+        let fresh = RawDocument::from_tokens(document.tokens.clone()).to_bytes()?;
+        if original != fresh.as_slice() {
+            let offset = original
+                .iter()
+                .zip(&fresh)
+                .position(|(a, b)| a != b)
+                .unwrap_or(original.len().min(fresh.len()));
+            report.nonstandard_encoding = Some(offset as u64);
+        }
+
+        report.timestamps = timestamp_anomalies(&document.tokens, now_ms());
+        Ok(report)
+    }
+
+    /// Whether anything suspicious was found
+    pub fn has_anomalies(&self) -> bool {
+        !self.unreferenced.is_empty()
+            || !self.trailing.is_empty()
+            || self.nonstandard_encoding.is_some()
+            || !self.timestamps.is_empty()
+            || self.decode_error.is_some()
+    }
+
+    pub fn write_to<W: Write>(&self, mut output: W) -> Result<()> {
+        writeln!(
+            output,
+            "unreferenced pool strings: {}",
+            self.unreferenced.len()
+        )?;
+        for (index, entry) in &self.unreferenced {
+            writeln!(output, "  #{} {:?}", index, entry)?;
+        }
+
+        writeln!(output, "trailing bytes: {}", self.trailing.len())?;
+        if !self.trailing.is_empty() {
+            let preview = &self.trailing[..self.trailing.len().min(32)];
+            let ellipsis = if preview.len() < self.trailing.len() {
+                "..."
+            } else {
+                ""
+            };
+            writeln!(output, "  {}{}", hex_string(preview), ellipsis)?;
+        }
+
+        match self.nonstandard_encoding {
+            Some(offset) => writeln!(
+                output,
+                "nonstandard interning order: yes, first deviation at offset {}",
+                offset
+            )?,
+            None => writeln!(output, "nonstandard interning order: no")?,
+        }
+
+        writeln!(output, "impossible timestamps: {}", self.timestamps.len())?;
+        for anomaly in &self.timestamps {
+            writeln!(
+                output,
+                "  {}/@{} = {} in token #{}",
+                anomaly.element, anomaly.attribute, anomaly.value, anomaly.token
+            )?;
+        }
+
+        if let Some(error) = &self.decode_error {
+            writeln!(output, "token stream not decodable: {}", error)?;
+        }
+
+        output.flush()?;
+        Ok(())
+    }
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as i64)
+        .unwrap_or(i64::MAX)
+}
+
+/// Known timestamp attributes before Android existed or after `now_ms`; zero is
+/// the usual "never" and is not reported
+fn timestamp_anomalies(tokens: &[Token], now_ms: i64) -> Vec<TimestampAnomaly> {
+    let latest = now_ms.saturating_add(CLOCK_SKEW_MS);
+    let mut element = SmolStr::default();
+    let mut anomalies = Vec::new();
+
+    for (index, token) in tokens.iter().enumerate() {
+        match token {
+            Token::StartTag(name) => element = name.clone(),
+            Token::Attribute { name, value } if TIMESTAMP_ATTRIBUTES.contains(&name.as_str()) => {
+                let value = match value {
+                    AttributeValue::Int(v) | AttributeValue::IntHex(v) => Some(*v as i64),
+                    AttributeValue::Long(v) | AttributeValue::LongHex(v) => Some(*v),
+                    AttributeValue::String(v) => v.parse().ok(),
+                    AttributeValue::Interned(v) => v.parse().ok(),
+                    _ => None,
+                };
+                if let Some(value) = value
+                    && value != 0
+                    && !(EARLIEST_TIMESTAMP_MS..=latest).contains(&value)
+                {
+                    anomalies.push(TimestampAnomaly {
+                        element: element.clone(),
+                        attribute: name.clone(),
+                        value,
+                        token: index + 1,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    anomalies
+}
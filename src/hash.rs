@@ -0,0 +1,90 @@
+use crate::roundtrip::{Token, TokenReader};
+use crate::*;
+use digest::{Digest, Output};
+use std::fmt;
+use std::io::Read;
+
+// ============================================================================
+// Semantic Hashing
+// ============================================================================
+
+/// Feeds the canonical event stream of a document, the lines of
+/// [`roundtrip::RawDocument::dump`], into any [`Digest`] as the events go by.
+/// The hash only changes with the typed content, not with the intern pool
+/// layout, and nothing is buffered; it is the `# canonical` digest of
+/// `abx batch` manifests when used with SHA-256.
+///
+/// ```
+/// use android_xml_converter::hash::CanonicalHasher;
+/// use android_xml_converter::roundtrip::RawDocument;
+/// use android_xml_converter::{Options, XmlToAbxConverter};
+/// use sha2::{Digest, Sha256};
+///
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string(r#"<map><int name="a" value="1"/></map>"#, &mut abx).unwrap();
+///
+/// let hash = CanonicalHasher::<Sha256>::hash_abx(&abx[..], &Options::default()).unwrap();
+/// let dump = RawDocument::from_bytes(&abx).unwrap().dump();
+/// assert_eq!(hash, Sha256::digest(dump));
+/// ```
+pub struct CanonicalHasher<D: Digest> {
+    digest: D,
+}
+
+impl<D: Digest> Default for CanonicalHasher<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: Digest> CanonicalHasher<D> {
+    pub fn new() -> Self {
+        Self { digest: D::new() }
+    }
+
+    /// Hashes an ABX stream token by token, including bytes after END_DOCUMENT
+    pub fn hash_abx<R: Read>(reader: R, options: &Options) -> Result<Output<D>> {
+        let mut hasher = Self::new();
+        let mut reader = TokenReader::from_options(reader, options)?;
+        while let Some(token) = reader.next_token()? {
+            hasher.update(&token);
+            if token == Token::EndDocument {
+                hasher.update_trailing(&reader.read_remaining()?);
+                break;
+            }
+        }
+        Ok(hasher.finalize())
+    }
+
+    /// Adds the next event of the document
+    pub fn update(&mut self, token: &Token) {
+        self.write_line(format_args!("{}", token));
+    }
+
+    /// Adds the bytes that followed END_DOCUMENT, if any
+    pub fn update_trailing(&mut self, trailing: &[u8]) {
+        if !trailing.is_empty() {
+            self.write_line(format_args!("TRAILING {}", hex_string(trailing)));
+        }
+    }
+
+    pub fn finalize(self) -> Output<D> {
+        self.digest.finalize()
+    }
+
+    fn write_line(&mut self, line: fmt::Arguments<'_>) {
+        // hashing cannot fail, and neither can formatting tokens
+        let _ = fmt::write(&mut DigestWriter(&mut self.digest), line);
+        self.digest.update(b"\n");
+    }
+}
+
+/// Formats straight into a digest
+struct DigestWriter<'a, D: Digest>(&'a mut D);
+
+impl<D: Digest> fmt::Write for DigestWriter<'_, D> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.update(s.as_bytes());
+        Ok(())
+    }
+}
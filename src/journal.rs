@@ -0,0 +1,157 @@
+use crate::*;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ============================================================================
+// Change Journal
+// ============================================================================
+
+/// One modification of a document, as recorded in a [`Journal`]
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub timestamp: SystemTime,
+    /// Editing command, e.g. `rename` or `replace`
+    pub operation: String,
+    /// What was changed inside the document, e.g. an XPath
+    pub path: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl JournalEntry {
+    /// An entry stamped with the current time
+    pub fn new(operation: &str, path: &str) -> Self {
+        Self {
+            timestamp: SystemTime::now(),
+            operation: operation.to_string(),
+            path: path.to_string(),
+            old: None,
+            new: None,
+        }
+    }
+
+    pub fn change(mut self, old: impl Into<String>, new: impl Into<String>) -> Self {
+        self.old = Some(old.into());
+        self.new = Some(new.into());
+        self
+    }
+
+    /// The entry as one line of JSON, with the timestamp in RFC 3339 UTC
+    ///
+    /// ```
+    /// use android_xml_converter::journal::JournalEntry;
+    /// use std::time::{Duration, UNIX_EPOCH};
+    ///
+    /// let mut entry = JournalEntry::new("rename", "//@pkg").change("pkg", "package");
+    /// entry.timestamp = UNIX_EPOCH + Duration::from_millis(1_760_000_000_123);
+    /// assert_eq!(
+    ///     entry.to_json(),
+    ///     r#"{"timestamp":"2025-10-09T08:53:20.123Z","operation":"rename","path":"//@pkg","old":"pkg","new":"package"}"#
+    /// );
+    /// ```
+    pub fn to_json(&self) -> String {
+        let optional = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map_or_else(|| "null".to_string(), json_string)
+        };
+        format!(
+            r#"{{"timestamp":{},"operation":{},"path":{},"old":{},"new":{}}}"#,
+            json_string(&rfc3339(self.timestamp)),
+            json_string(&self.operation),
+            json_string(&self.path),
+            optional(&self.old),
+            optional(&self.new)
+        )
+    }
+}
+
+/// Audit trail of the modifications made to a file, kept next to it as
+/// `<file>.journal` with one JSON object per line (see [`JournalEntry::to_json`]).
+/// Entries are only ever appended.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    /// The journal of `file`
+    pub fn for_file<P: AsRef<Path>>(file: P) -> Self {
+        let mut name = file.as_ref().to_path_buf().into_os_string();
+        name.push(".journal");
+        Self { path: name.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&self, entries: &[JournalEntry]) -> Result<()> {
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&entry.to_json());
+            lines.push('\n');
+        }
+        // one write, so concurrent editors do not interleave within an entry
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(lines.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// `time` as e.g. `2025-10-09T08:53:20.123Z`; times before 1970 as the epoch
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // civil date of a day count, after Howard Hinnant's days_from_civil inverse
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
@@ -1,6 +1,54 @@
-use std::io;
+#[cfg(feature = "gzip")]
+use flate2::bufread::MultiGzDecoder;
+use std::fs::File;
+#[cfg(feature = "gzip")]
+use std::io::BufReader;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
 use thiserror::Error;
 
+#[cfg(feature = "archive")]
+pub mod archive;
+pub mod atomic;
+pub mod audit;
+#[cfg(feature = "batch")]
+pub mod batch;
+pub mod codec;
+pub mod compliance;
+pub mod convert;
+pub mod diagnostics;
+pub mod dictionary;
+pub mod dom;
+#[cfg(feature = "unstable")]
+pub mod extension;
+pub mod flat;
+pub mod forensics;
+#[cfg(feature = "digest")]
+pub mod hash;
+pub mod journal;
+mod macros;
+pub mod mapping;
+pub mod pipeline;
+pub mod prelude;
+pub mod privileged;
+pub mod profile;
+pub mod pull;
+pub mod query;
+pub mod reader;
+pub mod roundtrip;
+pub mod schema;
+pub mod sourcemap;
+pub mod timeout;
+#[cfg(feature = "serde")]
+pub mod transcode;
+pub mod verify;
+pub mod writer;
+pub mod xpath;
+
+pub use convert::{AbxToXmlConverter, AbxWriter, XmlToAbxConverter, estimate_abx_size};
+pub use reader::{BinaryXmlDeserializer, DataInput};
+pub use writer::{BinaryXmlSerializer, FastDataOutput};
+
 #[derive(Error, Debug)]
 pub enum ConversionError {
     #[error("IO error: {0}")]
@@ -11,9 +59,67 @@ pub enum ConversionError {
     )]
     InvalidMagicHeader { expected: [u8; 4], actual: [u8; 4] },
 
+    #[error("Unsupported ABX version {0} (this build reads version 0)")]
+    UnsupportedVersion(u8),
+
     #[error("Failed to read {0} from stream")]
     ReadError(String),
 
+    #[error(
+        "Declared length {declared} exceeds remaining {remaining} bytes at offset 0x{offset:X}"
+    )]
+    LengthExceedsInput {
+        declared: usize,
+        remaining: u64,
+        offset: u64,
+    },
+
+    /// The input ended at byte `offset` while reading `reading`
+    #[error("Input ends before END_DOCUMENT, reading {reading}")]
+    Truncated { offset: u64, reading: String },
+
+    /// An ABX decoding error with where it happened: the offset of the token
+    /// that failed, the last token read completely and the open elements,
+    /// outermost first. An error with an offset of its own shows only that one.
+    ///
+    /// ```
+    /// use android_xml_converter::{AbxToXmlConverter, ConversionError};
+    ///
+    /// // <a> followed by an attribute of the unknown type 14
+    /// let abx = [b'A', b'B', b'X', 0, 0x10, 0x32, 0xFF, 0xFF, 0, 1, b'a', 0xEF, 0xFF, 0xFF, 0, 1, b'n', 0x11];
+    /// let error = AbxToXmlConverter::convert_bytes(&abx).unwrap_err();
+    /// assert_eq!(error.to_string(), "Unknown attribute type: 224 at offset 0xB, after START_TAG, inside <a>");
+    ///
+    /// // a start tag naming pool string 5 of an empty pool
+    /// let abx = [b'A', b'B', b'X', 0, 0x10, 0x32, 0, 5, 0x11];
+    /// let Err(ConversionError::Decode { offset, last_token, elements, source }) =
+    ///     AbxToXmlConverter::convert_bytes(&abx)
+    /// else {
+    ///     panic!()
+    /// };
+    /// assert_eq!((offset, last_token, elements.len()), (5, Some("START_DOCUMENT"), 0));
+    /// assert!(matches!(*source, ConversionError::InvalidInternedStringIndex(5)));
+    ///
+    /// // <a> with text of 256 bytes, of which the input holds one
+    /// let abx = [b'A', b'B', b'X', 0, 0x10, 0x32, 0xFF, 0xFF, 0, 1, b'a', 0x24, 1, 0, b'x'];
+    /// let error = AbxToXmlConverter::convert_bytes(&abx).unwrap_err();
+    /// assert_eq!(
+    ///     error.to_string(),
+    ///     "Declared length 256 exceeds remaining 1 bytes at offset 0xE, after START_TAG, inside <a>"
+    /// );
+    /// ```
+    #[error(
+        "{source}{}{}",
+        decode_offset(*.offset, .source),
+        decode_context(*.last_token, .elements)
+    )]
+    Decode {
+        offset: u64,
+        last_token: Option<&'static str>,
+        elements: Vec<String>,
+        source: Box<ConversionError>,
+    },
+
     #[error("Invalid interned string index: {0}")]
     InvalidInternedStringIndex(u16),
 
@@ -40,12 +146,30 @@ pub enum ConversionError {
 
     #[error("UTF-8 conversion error: {0}")]
     Utf8Error(#[from] std::str::Utf8Error),
+
+    #[error("Too many errors: more than {0} recoverable errors")]
+    TooManyErrors(usize),
+
+    #[error("Intern pool full: more than {0} strings")]
+    InternPoolFull(usize),
+
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(String),
+
+    #[error("Invalid element or attribute name {name:?}: {reason}")]
+    InvalidName { name: String, reason: String },
 }
 
 // convert quick_xml errors
 impl From<quick_xml::Error> for ConversionError {
     fn from(err: quick_xml::Error) -> Self {
-        ConversionError::XmlParsing(err.to_string())
+        match err {
+            // a stalled source, see `timeout::IdleTimeoutReader`
+            quick_xml::Error::Io(e) if e.kind() == io::ErrorKind::TimedOut => {
+                ConversionError::Io(io::Error::new(e.kind(), e.to_string()))
+            }
+            err => ConversionError::XmlParsing(err.to_string()),
+        }
     }
 }
 
@@ -57,13 +181,1044 @@ impl From<quick_xml::events::attributes::AttrError> for ConversionError {
 
 pub type Result<T> = std::result::Result<T, ConversionError>;
 
+// ============================================================================
+// Options
+// ============================================================================
+
+/// How XML → ABX conversion treats namespace prefixes and `xmlns` declarations,
+/// which ABX has no notion of
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NamespaceStrategy {
+    /// Keep prefixed names and declarations as plain names, with a warning
+    #[default]
+    Keep,
+    /// Drop prefixes from names and remove `xmlns` declarations
+    Strip,
+    /// Fail on the first prefixed name or declaration
+    Error,
+}
+
+impl std::str::FromStr for NamespaceStrategy {
+    type Err = ConversionError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "strip" => Ok(Self::Strip),
+            "error" => Ok(Self::Error),
+            _ => Err(ConversionError::ParseError(format!(
+                "Unknown namespace strategy '{}' (expected keep, strip or error)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Which value XML → ABX conversion keeps when an element repeats an attribute,
+/// which XML forbids but hand-edited files contain
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateAttributes {
+    /// Fail on the repeated attribute
+    #[default]
+    Error,
+    /// Keep the first value, with a warning
+    First,
+    /// Keep the last value at the position of the first, with a warning
+    Last,
+}
+
+impl std::str::FromStr for DuplicateAttributes {
+    type Err = ConversionError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "error" => Ok(Self::Error),
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            _ => Err(ConversionError::ParseError(format!(
+                "Unknown duplicate attribute policy '{}' (expected error, first or last)",
+                s
+            ))),
+        }
+    }
+}
+
+/// What XML → ABX conversion does with attribute values longer than the 65,535
+/// bytes an ABX string can hold
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LongAttributes {
+    /// Fail with [`ConversionError::StringTooLong`]
+    #[default]
+    Error,
+    /// Cut the value at the limit, with a warning
+    Truncate,
+    /// Move the value into a child element named after the attribute, as TEXT,
+    /// with a warning
+    Text,
+    /// Split the value into attributes `name`, `name.1`, `name.2`, ... of at most
+    /// 65,535 bytes each, with a warning. Base64 values are stored as byte arrays,
+    /// whose base64 forms join up to the original value.
+    Chunks,
+}
+
+impl std::str::FromStr for LongAttributes {
+    type Err = ConversionError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "error" => Ok(Self::Error),
+            "truncate" => Ok(Self::Truncate),
+            "text" => Ok(Self::Text),
+            "chunks" => Ok(Self::Chunks),
+            _ => Err(ConversionError::ParseError(format!(
+                "Unknown long attribute strategy '{}' (expected error, truncate, text or chunks)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Handling of ABX strings that are valid neither as UTF-8 nor as modified
+/// UTF-8, as found in slightly corrupted files (ABX → XML)
+///
+/// ```
+/// use android_xml_converter::{AbxToXmlConverter, InvalidUtf8, Options};
+///
+/// // <a>, then TEXT "x\xFFy"
+/// let abx = b"ABX\x00\x10\x32\xFF\xFF\x00\x01a\x24\x00\x03x\xFFy\x33\x00\x00\x11";
+///
+/// assert!(AbxToXmlConverter::convert(&abx[..], Vec::new()).is_err());
+///
+/// let convert = |policy: InvalidUtf8| {
+///     let mut xml = Vec::new();
+///     let options = Options::new().invalid_utf8(policy);
+///     AbxToXmlConverter::convert_with_options(&abx[..], &mut xml, &options).unwrap();
+///     String::from_utf8(xml).unwrap()
+/// };
+/// assert!(convert(InvalidUtf8::Lossy).ends_with("<a>x\u{FFFD}y</a>"));
+/// assert!(convert(InvalidUtf8::Escape).ends_with("<a>x&#xFF;y</a>"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InvalidUtf8 {
+    /// Fail with [`ConversionError::ReadError`]
+    #[default]
+    Error,
+    /// Replace each invalid sequence with U+FFFD
+    Lossy,
+    /// Keep each invalid byte as U+F700 plus its value, which XML output writes
+    /// as a character reference to the byte value, e.g. `&#xFF;`
+    Escape,
+}
+
+impl std::str::FromStr for InvalidUtf8 {
+    type Err = ConversionError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "error" => Ok(Self::Error),
+            "lossy" => Ok(Self::Lossy),
+            "escape" => Ok(Self::Escape),
+            _ => Err(ConversionError::ParseError(format!(
+                "Unknown invalid UTF-8 handling '{}' (expected error, lossy or escape)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Which attribute values XML → ABX conversion writes interned, i.e. stored
+/// once in the intern pool and referenced by index afterwards. Names are always
+/// interned; values that are not are written inline.
+///
+/// The default interns values under 50 bytes without spaces, which pays off
+/// for the enum-like values of Android files. Documents with many unique short
+/// values only grow the pool, so the pool can be capped, after which new values
+/// are written inline and values already in the pool are still referenced.
+///
+/// ```
+/// use android_xml_converter::{InternPolicy, Options, XmlToAbxConverter};
+///
+/// let xml = r#"<a><b v="x"/><b v="y"/><b v="x"/></a>"#;
+/// let mut interned = Vec::new();
+/// XmlToAbxConverter::convert_from_string(xml, &mut interned).unwrap();
+/// let mut inline = Vec::new();
+/// let options = Options::new().intern_policy(InternPolicy::never());
+/// XmlToAbxConverter::convert_from_string_with(xml, &mut inline, &options).unwrap();
+/// assert_ne!(interned, inline);
+///
+/// assert!(InternPolicy::default().admits("enabled"));
+/// assert!(!InternPolicy::default().max_length(4).admits("enabled"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InternPolicy {
+    /// Values of this many bytes or more are written inline; 0 interns none
+    pub max_length: usize,
+    /// Values containing a space are written inline
+    pub intern_spaces: bool,
+    /// New values are written inline once the pool holds this many strings
+    pub max_pool_size: usize,
+}
+
+impl Default for InternPolicy {
+    fn default() -> Self {
+        Self {
+            max_length: 50,
+            intern_spaces: false,
+            max_pool_size: INTERNED_STRING_NEW_MARKER as usize,
+        }
+    }
+}
+
+impl InternPolicy {
+    /// Writes every value inline
+    pub fn never() -> Self {
+        Self::default().max_length(0)
+    }
+
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    pub fn intern_spaces(mut self, intern_spaces: bool) -> Self {
+        self.intern_spaces = intern_spaces;
+        self
+    }
+
+    pub fn max_pool_size(mut self, max_pool_size: usize) -> Self {
+        self.max_pool_size = max_pool_size;
+        self
+    }
+
+    /// Whether a value qualifies for interning by its text, regardless of the
+    /// pool size
+    pub fn admits(&self, value: &str) -> bool {
+        value.len() < self.max_length && (self.intern_spaces || !value.contains(' '))
+    }
+}
+
+/// Resource limits for decoding ABX from untrusted sources (ABX → XML), so a
+/// hostile file cannot make a service that converts uploads exhaust its
+/// memory. Exceeding one fails with [`ConversionError::LimitExceeded`], also in
+/// lenient mode. Nothing is limited by default.
+///
+/// ```
+/// use android_xml_converter::{AbxToXmlConverter, Limits, Options, XmlToAbxConverter};
+///
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string("<a><b><c/></b></a>", &mut abx).unwrap();
+/// let convert = |limits: Limits| {
+///     let options = Options::new().limits(limits);
+///     AbxToXmlConverter::convert_with_options(&abx[..], Vec::new(), &options)
+/// };
+/// assert!(convert(Limits::new().max_depth(3).max_interned_strings(3)).is_ok());
+/// assert!(convert(Limits::new().max_depth(2)).is_err());
+/// assert!(convert(Limits::new().max_interned_strings(2)).is_err());
+/// assert!(convert(Limits::new().max_output_bytes(50)).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limits {
+    /// Elements open at the same time
+    pub max_depth: Option<usize>,
+    /// Strings defined in the intern pool
+    pub max_interned_strings: Option<usize>,
+    /// Bytes of one string or byte array, checked before it is read
+    pub max_string_length: Option<usize>,
+    /// Bytes of XML written, checked after each token
+    pub max_output_bytes: Option<u64>,
+}
+
+impl Limits {
+    /// No limits
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn max_interned_strings(mut self, max_interned_strings: usize) -> Self {
+        self.max_interned_strings = Some(max_interned_strings);
+        self
+    }
+
+    pub fn max_string_length(mut self, max_string_length: usize) -> Self {
+        self.max_string_length = Some(max_string_length);
+        self
+    }
+
+    pub fn max_output_bytes(mut self, max_output_bytes: u64) -> Self {
+        self.max_output_bytes = Some(max_output_bytes);
+        self
+    }
+}
+
+/// Checks of element and attribute names while decoding (ABX → XML). Android
+/// only writes valid, reasonably short names, so a name that fails points to a
+/// corrupted intern pool, which would otherwise come out as invalid XML.
+/// Failures are [`ConversionError::InvalidName`], also in lenient mode. Nothing
+/// is checked by default.
+///
+/// ```
+/// use android_xml_converter::{NamePolicy, is_xml_name};
+///
+/// assert!(NamePolicy::strict().check("android:versionCode").is_ok());
+/// assert!(NamePolicy::strict().check("1st").is_err());
+/// assert!(NamePolicy::new().max_length(4).check("package").is_err());
+/// assert!(NamePolicy::new().check("a b").is_ok());
+/// assert!(!is_xml_name("a b"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NamePolicy {
+    /// Longest name in bytes
+    pub max_length: Option<usize>,
+    /// Names must match XML's `Name` production
+    pub xml_names: bool,
+}
+
+impl NamePolicy {
+    /// Longest name [`Self::strict`] accepts, far beyond any Android writes
+    pub const STRICT_MAX_LENGTH: usize = 256;
+
+    /// No checks
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// XML names of at most [`Self::STRICT_MAX_LENGTH`] bytes
+    pub fn strict() -> Self {
+        Self::new()
+            .max_length(Self::STRICT_MAX_LENGTH)
+            .xml_names(true)
+    }
+
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    pub fn xml_names(mut self, xml_names: bool) -> Self {
+        self.xml_names = xml_names;
+        self
+    }
+
+    pub fn check(&self, name: &str) -> Result<()> {
+        let reason = match self.max_length {
+            Some(max) if name.len() > max => format!("{} bytes, more than {}", name.len(), max),
+            _ if self.xml_names && !is_xml_name(name) => "not an XML name".to_string(),
+            _ => return Ok(()),
+        };
+        // only the start of a runaway name
+        let shown = &name[..char_boundary_at(name, 64)];
+        Err(ConversionError::InvalidName {
+            name: shown.to_string(),
+            reason,
+        })
+    }
+}
+
+/// Whether `name` matches the `Name` production of XML 1.0, which allows
+/// prefixed names like `android:name`
+pub fn is_xml_name(name: &str) -> bool {
+    let start = |c: char| {
+        matches!(c,
+            ':' | 'A'..='Z' | '_' | 'a'..='z'
+            | '\u{C0}'..='\u{D6}' | '\u{D8}'..='\u{F6}' | '\u{F8}'..='\u{2FF}'
+            | '\u{370}'..='\u{37D}' | '\u{37F}'..='\u{1FFF}' | '\u{200C}'..='\u{200D}'
+            | '\u{2070}'..='\u{218F}' | '\u{2C00}'..='\u{2FEF}' | '\u{3001}'..='\u{D7FF}'
+            | '\u{F900}'..='\u{FDCF}' | '\u{FDF0}'..='\u{FFFD}' | '\u{10000}'..='\u{EFFFF}')
+    };
+    let mut chars = name.chars();
+    chars.next().is_some_and(start)
+        && chars.all(|c| {
+            start(c)
+                || matches!(c,
+                    '-' | '.' | '0'..='9' | '\u{B7}'
+                    | '\u{300}'..='\u{36F}' | '\u{203F}'..='\u{2040}')
+        })
+}
+
+/// Largest char boundary of `text` at or below `index`
+fn char_boundary_at(text: &str, index: usize) -> usize {
+    (0..=index.min(text.len()))
+        .rev()
+        .find(|&i| text.is_char_boundary(i))
+        .unwrap_or(0)
+}
+
+/// How ABX → XML conversion writes float and double attributes.
+///
+/// The default writes the shortest text that reads back to the same value and
+/// never uses scientific notation, like [`reader::AttributeValue`]'s `Display`.
+/// [`FloatFormat::java`] matches Android's own rendering through
+/// `Float.toString`, e.g. `1.0E-4` and `Infinity`.
+///
+/// ```
+/// use android_xml_converter::FloatFormat;
+///
+/// assert_eq!(FloatFormat::default().format_f32(0.0001), "0.0001");
+/// assert_eq!(FloatFormat::java().format_f32(0.0001), "1.0E-4");
+/// assert_eq!(FloatFormat::java().format_f64(12345678.0), "1.2345678E7");
+/// assert_eq!(FloatFormat::fixed(2).format_f64(2.0 / 3.0), "0.67");
+/// assert_eq!(FloatFormat::default().scientific(1e-3, 1e3).format_f32(1500.0), "1.5e3");
+/// assert_eq!("java".parse::<FloatFormat>().unwrap(), FloatFormat::java());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FloatFormat {
+    /// Digits after the decimal point, or `None` for the shortest text that
+    /// reads back to the same value
+    pub precision: Option<usize>,
+    /// Nonzero magnitudes outside `[low, high)` are written in scientific notation
+    pub scientific: Option<(f64, f64)>,
+    /// Java spelling: an `E` exponent and `Infinity` instead of `inf`
+    pub java: bool,
+}
+
+impl FloatFormat {
+    /// Shortest round-trip text without scientific notation
+    pub fn shortest() -> Self {
+        Self::default()
+    }
+
+    /// `precision` digits after the decimal point
+    pub fn fixed(precision: usize) -> Self {
+        Self {
+            precision: Some(precision),
+            ..Self::default()
+        }
+    }
+
+    /// Java's `Float.toString` and `Double.toString`
+    pub fn java() -> Self {
+        Self {
+            precision: None,
+            scientific: Some((1e-3, 1e7)),
+            java: true,
+        }
+    }
+
+    pub fn scientific(mut self, low: f64, high: f64) -> Self {
+        self.scientific = Some((low, high));
+        self
+    }
+
+    pub fn format_f32(&self, value: f32) -> String {
+        self.format(value)
+    }
+
+    pub fn format_f64(&self, value: f64) -> String {
+        self.format(value)
+    }
+
+    fn format<T>(&self, value: T) -> String
+    where
+        T: Copy + Into<f64> + std::fmt::Display + std::fmt::LowerExp,
+    {
+        let number: f64 = value.into();
+        let magnitude = number.abs();
+        if number.is_nan() {
+            return "NaN".to_string();
+        }
+        if magnitude.is_infinite() {
+            let sign = if number < 0.0 { "-" } else { "" };
+            let name = if self.java { "Infinity" } else { "inf" };
+            return format!("{}{}", sign, name);
+        }
+
+        let scientific = self
+            .scientific
+            .is_some_and(|(low, high)| magnitude != 0.0 && (magnitude < low || magnitude >= high));
+        if scientific {
+            let text = match self.precision {
+                Some(precision) => format!("{:.*e}", precision, value),
+                None => format!("{:e}", value),
+            };
+            let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+            let point = if mantissa.contains('.') || self.precision.is_some() {
+                ""
+            } else {
+                ".0"
+            };
+            let e = if self.java { 'E' } else { 'e' };
+            format!("{}{}{}{}", mantissa, point, e, exponent)
+        } else {
+            match self.precision {
+                Some(precision) => format!("{:.*}", precision, value),
+                None => {
+                    let text = value.to_string();
+                    if text.contains('.') {
+                        text
+                    } else {
+                        text + ".0"
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for FloatFormat {
+    type Err = ConversionError;
+
+    /// `shortest`, `java` or a number of digits after the decimal point
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "shortest" => Ok(Self::shortest()),
+            "java" => Ok(Self::java()),
+            _ => s.parse().map(Self::fixed).map_err(|_| {
+                ConversionError::ParseError(format!(
+                    "Unknown float format '{}' (expected shortest, java or a precision)",
+                    s
+                ))
+            }),
+        }
+    }
+}
+
+/// Settings shared by both conversion directions
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Keep whitespace-only text as IGNORABLE_WHITESPACE tokens (XML → ABX)
+    pub preserve_whitespace: bool,
+    /// Pre-seed the intern pool with [`dictionary::ANDROID_NAMES`] on both sides.
+    ///
+    /// Files written this way are smaller but can only be read back with the same
+    /// dictionary enabled; Android itself cannot read them.
+    #[cfg(feature = "android-dictionary")]
+    pub android_dictionary: bool,
+    /// Pre-seed the intern pool with an exported [`dictionary::Dictionary`] on both sides
+    pub dictionary: Option<dictionary::Dictionary>,
+    /// Lenient mode: record up to this many recoverable errors instead of stopping
+    /// at the first one. In ABX these are unknown tokens, invalid pool indices
+    /// or attributes that cannot be decoded, after which decoding resumes at
+    /// the next element boundary; in XML mismatched or missing end tags and
+    /// stray `&` or `<` in text, which are repaired.
+    pub max_errors: Option<usize>,
+    /// Treat ABX input that ends before END_DOCUMENT as the end of the
+    /// document and keep what was converted (ABX → XML). Without it,
+    /// truncated files fail with [`ConversionError::Truncated`] instead of
+    /// becoming partial XML. Other decoding errors fail the conversion either
+    /// way; only lenient mode ([`Self::max_errors`]) and salvage mode carry on
+    /// past them.
+    pub allow_truncated: bool,
+    /// Decode ABX with a version byte other than 0 as if it were version 0,
+    /// with a warning, instead of failing with
+    /// [`ConversionError::UnsupportedVersion`] (ABX → XML). Best effort: a
+    /// real protocol change shows up as decoding errors.
+    pub allow_unknown_version: bool,
+    /// Salvage corrupted ABX (ABX → XML): skip tokens that cannot be decoded and
+    /// close the elements left open, with a warning for each, see
+    /// [`reader::BinaryXmlDeserializer::recover`]
+    pub salvage: bool,
+    /// Read concatenated ABX documents (ABX → XML): a magic header after
+    /// END_DOCUMENT starts another document with its own intern pool. Each is
+    /// written as an XML document of its own, or all roots go under
+    /// [`Self::wrap_roots`] if set.
+    pub multi_doc: bool,
+    /// Handling of namespace prefixes and declarations (XML → ABX)
+    pub namespaces: NamespaceStrategy,
+    /// Handling of repeated attributes (XML → ABX)
+    pub duplicate_attributes: DuplicateAttributes,
+    /// Handling of attribute values too long for ABX (XML → ABX)
+    pub long_attributes: LongAttributes,
+    /// Guess attribute types from their text (XML → ABX): `true`/`false` as
+    /// booleans and numbers as `int`, `long`, `float` or `double`, see
+    /// [`type_detection::infer_number`]. Without it every attribute that no
+    /// type hint or schema types is written as a string.
+    pub type_inference: bool,
+    /// Which string values are interned (XML → ABX)
+    pub intern_policy: InternPolicy,
+    /// ABX types of attributes by location, used instead of guessing (XML → ABX)
+    pub type_schema: Option<schema::TypeSchema>,
+    /// Rendering of float and double attributes (ABX → XML)
+    pub float_format: FloatFormat,
+    /// Annotate each element with the ABX types of its attributes, as
+    /// [`schema::TYPE_HINT_ATTRIBUTE`] (ABX → XML)
+    pub emit_type_hints: bool,
+    /// Write attributes with the types of [`schema::TYPE_HINT_ATTRIBUTE`]
+    /// annotations and drop the annotations (XML → ABX)
+    pub apply_type_hints: bool,
+    /// Byte-identical round trips (both directions). ABX → XML emits type hints
+    /// and records token boundaries XML cannot show as `<?abx ...?>` directives:
+    /// `text` and `ignorable` before text tokens that would otherwise merge or
+    /// change kind, `ref` before entity references, `null NAME` for tokens
+    /// without text and `trailing HEX` for bytes after END_DOCUMENT. XML → ABX
+    /// applies type hints, follows the directives, writes each run of text and
+    /// predefined entities as one TEXT token and unescapes attribute values.
+    ///
+    /// Intern pool order needs no record: ABX defines strings at their first
+    /// use, which a fresh encoding reproduces.
+    pub fidelity: bool,
+    /// Output that depends only on the content (both directions), so that
+    /// equal documents hash and diff equal: attributes sorted by name, floats
+    /// in [`FloatFormat::shortest`] form, text nodes trimmed at their edges and
+    /// whitespace-only text dropped. The intern pool of ABX output follows
+    /// from the sorted attributes. Overrides [`Self::float_format`] and
+    /// [`Self::preserve_whitespace`]; not meant to be combined with
+    /// [`Self::fidelity`].
+    pub canonical: bool,
+    /// The smallest ABX for a document (XML → ABX), as Android writes it:
+    /// whitespace-only text, comments and the DOCTYPE are dropped. Other text
+    /// is kept as it is, unlike with [`Self::preserve_whitespace`] off.
+    pub compact: bool,
+    /// Recovery for vendor-modified files (both directions): wrap several root
+    /// elements or stray top-level text in a synthetic root of this name, with a
+    /// warning, so the result is a single well-formed document
+    pub wrap_roots: Option<String>,
+    /// Capacity of the buffer around input files and streams
+    pub read_buffer_size: usize,
+    /// Capacity of the buffer around output files and streams; 0 writes straight
+    /// through, for destinations that buffer on their own
+    pub write_buffer_size: usize,
+    /// Fail with [`io::ErrorKind::TimedOut`] when an input file the library
+    /// opens delivers nothing for this long, e.g. a named pipe whose producer
+    /// stalled; see [`timeout::IdleTimeoutReader`] for other sources
+    pub idle_timeout: Option<std::time::Duration>,
+    /// Initial capacity of the buffer holding one XML event (XML → ABX); it grows
+    /// as larger events are read
+    pub event_buffer_capacity: usize,
+    /// Read and write strings as Java's modified UTF-8 like Android's
+    /// `FastDataInput` and `FastDataOutput` (both directions), so strings with
+    /// NUL or characters outside the Basic Multilingual Plane take the exact
+    /// bytes the platform writes; see [`mutf8`]. Reading recognizes such
+    /// strings without it, as they are never valid UTF-8.
+    pub modified_utf8: bool,
+    /// Handling of strings that are not valid UTF-8 (ABX → XML)
+    pub invalid_utf8: InvalidUtf8,
+    /// Resource limits for untrusted input (ABX → XML)
+    pub limits: Limits,
+    /// Checks of element and attribute names (ABX → XML)
+    pub name_policy: NamePolicy,
+    /// Handlers for reserved token values on both sides
+    #[cfg(feature = "unstable")]
+    pub extensions: extension::Extensions,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            preserve_whitespace: true,
+            #[cfg(feature = "android-dictionary")]
+            android_dictionary: false,
+            dictionary: None,
+            max_errors: None,
+            allow_truncated: false,
+            allow_unknown_version: false,
+            salvage: false,
+            multi_doc: false,
+            namespaces: NamespaceStrategy::Keep,
+            duplicate_attributes: DuplicateAttributes::Error,
+            long_attributes: LongAttributes::Error,
+            type_inference: true,
+            intern_policy: InternPolicy::default(),
+            type_schema: None,
+            float_format: FloatFormat::default(),
+            emit_type_hints: false,
+            apply_type_hints: false,
+            fidelity: false,
+            canonical: false,
+            compact: false,
+            wrap_roots: None,
+            read_buffer_size: DEFAULT_BUFFER_SIZE,
+            write_buffer_size: DEFAULT_BUFFER_SIZE,
+            idle_timeout: None,
+            event_buffer_capacity: INITIAL_EVENT_BUFFER_CAPACITY,
+            modified_utf8: false,
+            invalid_utf8: InvalidUtf8::Error,
+            limits: Limits::new(),
+            name_policy: NamePolicy::new(),
+            #[cfg(feature = "unstable")]
+            extensions: extension::Extensions::default(),
+        }
+    }
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether whitespace-only text is kept, see [`Self::canonical`]
+    pub(crate) fn keeps_whitespace(&self) -> bool {
+        self.preserve_whitespace && !self.canonical
+    }
+
+    /// Whether the XML parser trims each piece of text; canonical text nodes
+    /// are trimmed as a whole by [`convert::AbxWriter`] instead
+    pub(crate) fn trims_text(&self) -> bool {
+        !self.preserve_whitespace && !self.canonical
+    }
+
+    pub fn preserve_whitespace(mut self, preserve_whitespace: bool) -> Self {
+        self.preserve_whitespace = preserve_whitespace;
+        self
+    }
+
+    #[cfg(feature = "android-dictionary")]
+    pub fn android_dictionary(mut self, android_dictionary: bool) -> Self {
+        self.android_dictionary = android_dictionary;
+        self
+    }
+
+    pub fn dictionary(mut self, dictionary: dictionary::Dictionary) -> Self {
+        self.dictionary = Some(dictionary);
+        self
+    }
+
+    /// In lenient mode broken XML is repaired, with each repair recorded as an
+    /// error:
+    ///
+    /// ```
+    /// use android_xml_converter::diagnostics::Diagnostics;
+    /// use android_xml_converter::{AbxToXmlConverter, Options, XmlToAbxConverter};
+    ///
+    /// let repair = |xml: &str| {
+    ///     let mut abx = Vec::new();
+    ///     let mut diagnostics = Diagnostics::new();
+    ///     let options = Options::new().max_errors(10);
+    ///     XmlToAbxConverter::convert_from_string_with_diagnostics(xml, &mut abx, &options, &mut diagnostics)
+    ///         .unwrap();
+    ///     let xml = AbxToXmlConverter::convert_bytes(&abx).unwrap();
+    ///     let errors: Vec<String> = diagnostics.entries().iter().map(|e| e.to_string()).collect();
+    ///     (xml.trim_start_matches(r#"<?xml version="1.0" encoding="UTF-8"?>"#).to_string(), errors)
+    /// };
+    ///
+    /// // unclosed tags are closed by the enclosing end tag or at the end of input
+    /// assert_eq!(
+    ///     repair("<a><b>text</a>"),
+    ///     ("<a><b>text</b></a>".into(), vec!["error: offset 10: Element <b> closed implicitly by </a>".into()])
+    /// );
+    /// assert_eq!(
+    ///     repair(r#"<a><b x="1">"#),
+    ///     (
+    ///         r#"<a><b x="1"></b></a>"#.into(),
+    ///         vec![
+    ///             "error: offset 12: Element <b> not closed at end of input".into(),
+    ///             "error: offset 12: Element <a> not closed at end of input".into(),
+    ///         ]
+    ///     )
+    /// );
+    ///
+    /// // a bare `&` or `<` in text is escaped
+    /// assert_eq!(
+    ///     repair("<a>fish & chips</a>"),
+    ///     ("<a>fish &amp; chips</a>".into(), vec!["error: offset 8: Stray '&' in text, escaped".into()])
+    /// );
+    /// assert_eq!(
+    ///     repair("<a>1 < 2</a>"),
+    ///     ("<a>1 &lt; 2</a>".into(), vec!["error: offset 5: Stray '<' in text, escaped".into()])
+    /// );
+    ///
+    /// // a mismatched end tag is dropped
+    /// assert_eq!(
+    ///     repair("<a><b></c></a>"),
+    ///     (
+    ///         "<a><b></b></a>".into(),
+    ///         vec![
+    ///             "error: offset 6: End tag </c> without a matching start tag, dropped".into(),
+    ///             "error: offset 10: Element <b> closed implicitly by </a>".into(),
+    ///         ]
+    ///     )
+    /// );
+    /// ```
+    pub fn max_errors(mut self, max_errors: usize) -> Self {
+        self.max_errors = Some(max_errors);
+        self
+    }
+
+    pub fn namespaces(mut self, namespaces: NamespaceStrategy) -> Self {
+        self.namespaces = namespaces;
+        self
+    }
+
+    pub fn duplicate_attributes(mut self, duplicate_attributes: DuplicateAttributes) -> Self {
+        self.duplicate_attributes = duplicate_attributes;
+        self
+    }
+
+    pub fn long_attributes(mut self, long_attributes: LongAttributes) -> Self {
+        self.long_attributes = long_attributes;
+        self
+    }
+
+    /// Inferred types read back as the same text, so a document keeps its
+    /// values through any number of round trips:
+    ///
+    /// ```
+    /// use android_xml_converter::{AbxToXmlConverter, XmlToAbxConverter};
+    ///
+    /// let to_abx = |xml: &str| {
+    ///     let mut abx = Vec::new();
+    ///     XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+    ///     abx
+    /// };
+    /// let to_xml = |abx: &[u8]| {
+    ///     let mut xml = Vec::new();
+    ///     AbxToXmlConverter::convert(abx, &mut xml).unwrap();
+    ///     String::from_utf8(xml).unwrap()
+    /// };
+    ///
+    /// let abx = to_abx(r#"<p flags="0x1234" uid="10001" on="true" f="1.5" />"#);
+    /// let xml = to_xml(&abx);
+    /// assert!(xml.contains(r#"flags="0x1234" uid="10001" on="true" f="1.5""#));
+    /// assert_eq!(to_abx(&xml), abx);
+    /// ```
+    pub fn type_inference(mut self, type_inference: bool) -> Self {
+        self.type_inference = type_inference;
+        self
+    }
+
+    pub fn intern_policy(mut self, intern_policy: InternPolicy) -> Self {
+        self.intern_policy = intern_policy;
+        self
+    }
+
+    pub fn type_schema(mut self, type_schema: schema::TypeSchema) -> Self {
+        self.type_schema = Some(type_schema);
+        self
+    }
+
+    pub fn float_format(mut self, float_format: FloatFormat) -> Self {
+        self.float_format = float_format;
+        self
+    }
+
+    pub fn emit_type_hints(mut self, emit_type_hints: bool) -> Self {
+        self.emit_type_hints = emit_type_hints;
+        self
+    }
+
+    pub fn apply_type_hints(mut self, apply_type_hints: bool) -> Self {
+        self.apply_type_hints = apply_type_hints;
+        self
+    }
+
+    pub fn fidelity(mut self, fidelity: bool) -> Self {
+        self.fidelity = fidelity;
+        self
+    }
+
+    /// Text is trimmed as a whole node, so the whitespace around an entity
+    /// reference inside it is part of the content:
+    ///
+    /// ```
+    /// use android_xml_converter::{AbxToXmlConverter, Options, XmlToAbxConverter};
+    ///
+    /// let options = Options::new().canonical(true);
+    /// let to_abx = |xml: &str, options: &Options| {
+    ///     let mut abx = Vec::new();
+    ///     XmlToAbxConverter::convert_from_string_with(xml, &mut abx, options).unwrap();
+    ///     abx
+    /// };
+    /// let to_xml = |abx: &[u8]| {
+    ///     let mut xml = Vec::new();
+    ///     AbxToXmlConverter::convert_with_options(abx, &mut xml, &options).unwrap();
+    ///     String::from_utf8(xml).unwrap()
+    /// };
+    ///
+    /// // ABX → XML
+    /// let spaced = to_xml(&to_abx("<b> x &amp; y </b>", &Options::default()));
+    /// let tight = to_xml(&to_abx("<b>x&amp;y</b>", &Options::default()));
+    /// assert!(spaced.ends_with("<b>x &amp; y</b>"));
+    /// assert!(tight.ends_with("<b>x&amp;y</b>"));
+    ///
+    /// // XML → ABX
+    /// let spaced = to_abx("<b> x &amp; y </b>", &options);
+    /// assert_ne!(spaced, to_abx("<b>x&amp;y</b>", &options));
+    /// assert_eq!(spaced, to_abx("<b>x &amp; y</b>", &options));
+    /// ```
+    pub fn canonical(mut self, canonical: bool) -> Self {
+        self.canonical = canonical;
+        self
+    }
+
+    /// ```
+    /// use android_xml_converter::{AbxToXmlConverter, Options, XmlToAbxConverter};
+    ///
+    /// let xml = "<!DOCTYPE map>\n<map>\n    <!-- prefs -->\n    <string name=\"a\"> x </string>\n</map>";
+    /// let mut abx = Vec::new();
+    /// XmlToAbxConverter::convert_from_string_with(xml, &mut abx, &Options::new().compact(true))
+    ///     .unwrap();
+    /// assert!(AbxToXmlConverter::convert_bytes(&abx)
+    ///     .unwrap()
+    ///     .ends_with("?><map><string name=\"a\"> x </string></map>"));
+    /// ```
+    pub fn compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
+    }
+
+    pub fn modified_utf8(mut self, modified_utf8: bool) -> Self {
+        self.modified_utf8 = modified_utf8;
+        self
+    }
+
+    /// ```
+    /// use android_xml_converter::{AbxToXmlConverter, Options};
+    ///
+    /// let options = Options::new().allow_truncated(true);
+    /// let convert = |abx: &[u8]| {
+    ///     let mut xml = Vec::new();
+    ///     AbxToXmlConverter::convert_with_options(abx, &mut xml, &options).map(|_| xml)
+    /// };
+    ///
+    /// // <a> without END_DOCUMENT is kept
+    /// let abx = [b'A', b'B', b'X', 0, 0x10, 0x32, 0xFF, 0xFF, 0, 1, b'a'];
+    /// assert!(String::from_utf8(convert(&abx).unwrap()).unwrap().ends_with("<a>"));
+    ///
+    /// // <a></b> is an error, not a truncation
+    /// let abx = [b'A', b'B', b'X', 0, 0x10, 0x32, 0xFF, 0xFF, 0, 1, b'a', 0x33, 0xFF, 0xFF, 0, 1, b'b', 0x11];
+    /// assert!(convert(&abx).is_err());
+    ///
+    /// // so is a name that is not UTF-8 in the middle of the input
+    /// let abx = [b'A', b'B', b'X', 0, 0x10, 0x32, 0xFF, 0xFF, 0, 1, 0xFF, 0x33, 0, 0, 0x11];
+    /// assert!(convert(&abx).is_err());
+    /// ```
+    pub fn allow_truncated(mut self, allow_truncated: bool) -> Self {
+        self.allow_truncated = allow_truncated;
+        self
+    }
+
+    pub fn allow_unknown_version(mut self, allow_unknown_version: bool) -> Self {
+        self.allow_unknown_version = allow_unknown_version;
+        self
+    }
+
+    pub fn salvage(mut self, salvage: bool) -> Self {
+        self.salvage = salvage;
+        self
+    }
+
+    /// ```
+    /// use android_xml_converter::{AbxToXmlConverter, Options, XmlToAbxConverter};
+    ///
+    /// let mut abx = Vec::new();
+    /// XmlToAbxConverter::convert_from_string("<a/>", &mut abx).unwrap();
+    /// XmlToAbxConverter::convert_from_string("<b/>", &mut abx).unwrap();
+    ///
+    /// let mut xml = Vec::new();
+    /// let options = Options::new().multi_doc(true).wrap_roots("docs");
+    /// AbxToXmlConverter::convert_with_options(&abx[..], &mut xml, &options).unwrap();
+    /// assert!(String::from_utf8(xml).unwrap().ends_with("<docs><a></a><b></b></docs>"));
+    /// ```
+    pub fn multi_doc(mut self, multi_doc: bool) -> Self {
+        self.multi_doc = multi_doc;
+        self
+    }
+
+    pub fn invalid_utf8(mut self, invalid_utf8: InvalidUtf8) -> Self {
+        self.invalid_utf8 = invalid_utf8;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn name_policy(mut self, name_policy: NamePolicy) -> Self {
+        self.name_policy = name_policy;
+        self
+    }
+
+    pub fn wrap_roots(mut self, root: impl Into<String>) -> Self {
+        self.wrap_roots = Some(root.into());
+        self
+    }
+
+    pub fn idle_timeout(mut self, idle_timeout: std::time::Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    pub fn read_buffer_size(mut self, read_buffer_size: usize) -> Self {
+        self.read_buffer_size = read_buffer_size;
+        self
+    }
+
+    pub fn write_buffer_size(mut self, write_buffer_size: usize) -> Self {
+        self.write_buffer_size = write_buffer_size;
+        self
+    }
+
+    pub fn event_buffer_capacity(mut self, event_buffer_capacity: usize) -> Self {
+        self.event_buffer_capacity = event_buffer_capacity;
+        self
+    }
+
+    #[cfg(feature = "unstable")]
+    pub fn extensions(mut self, extensions: extension::Extensions) -> Self {
+        self.extensions = extensions;
+        self
+    }
+}
+
 // ============================================================================
 // Protocol Constants
 // ============================================================================
 
+// The names and values below mirror Android's BinaryXmlSerializer and are part
+// of the semver contract: they stay at the crate root and only change in a
+// major version.
+
 /// Magic header for ABX format version 0
 pub const PROTOCOL_MAGIC_VERSION_0: [u8; 4] = [0x41, 0x42, 0x58, 0x00];
 
+/// ABX protocol version, the last byte of the `ABX` magic header. Android has
+/// only written version 0 so far; later versions are recognized so that they
+/// fail with [`ConversionError::UnsupportedVersion`] instead of as a bad
+/// header, or are decoded as version 0 with [`Options::allow_unknown_version`].
+///
+/// ```
+/// use android_xml_converter::FormatVersion;
+///
+/// assert_eq!(FormatVersion::detect(b"ABX\0<rest>"), Some(FormatVersion::V0));
+/// assert_eq!(FormatVersion::detect(b"ABX\x01"), Some(FormatVersion::Unknown(1)));
+/// assert!(!FormatVersion::Unknown(1).is_supported());
+/// assert_eq!(FormatVersion::detect(b"<?xml"), None);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FormatVersion {
+    V0,
+    /// A version this build does not know
+    Unknown(u8),
+}
+
+impl FormatVersion {
+    /// The version of a document starting with `header`, `None` if it is not ABX
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        match header {
+            [b'A', b'B', b'X', version, ..] => Some(Self::from_byte(*version)),
+            _ => None,
+        }
+    }
+
+    pub fn from_byte(version: u8) -> Self {
+        match version {
+            0 => Self::V0,
+            version => Self::Unknown(version),
+        }
+    }
+
+    pub fn byte(self) -> u8 {
+        match self {
+            Self::V0 => 0,
+            Self::Unknown(version) => version,
+        }
+    }
+
+    pub fn magic(self) -> [u8; 4] {
+        [b'A', b'B', b'X', self.byte()]
+    }
+
+    /// Whether this build reads and writes the version, see [`protocol_versions`]
+    pub fn is_supported(self) -> bool {
+        protocol_versions().contains(&self.byte())
+    }
+}
+
 // Token types (lower 4 bits)
 pub const START_DOCUMENT: u8 = 0;
 pub const END_DOCUMENT: u8 = 1;
@@ -78,6 +1233,26 @@ pub const COMMENT: u8 = 9;
 pub const DOCDECL: u8 = 10;
 pub const ATTRIBUTE: u8 = 15;
 
+/// Returns the protocol name of a token command (lower 4 bits), e.g. `START_TAG`
+pub fn token_name(command: u8) -> Option<&'static str> {
+    let name = match command {
+        START_DOCUMENT => "START_DOCUMENT",
+        END_DOCUMENT => "END_DOCUMENT",
+        START_TAG => "START_TAG",
+        END_TAG => "END_TAG",
+        TEXT => "TEXT",
+        CDSECT => "CDSECT",
+        ENTITY_REF => "ENTITY_REF",
+        IGNORABLE_WHITESPACE => "IGNORABLE_WHITESPACE",
+        PROCESSING_INSTRUCTION => "PROCESSING_INSTRUCTION",
+        COMMENT => "COMMENT",
+        DOCDECL => "DOCDECL",
+        ATTRIBUTE => "ATTRIBUTE",
+        _ => return None,
+    };
+    Some(name)
+}
+
 // Type information (upper 4 bits)
 pub const TYPE_NULL: u8 = 1 << 4;
 pub const TYPE_STRING: u8 = 2 << 4;
@@ -93,6 +1268,136 @@ pub const TYPE_DOUBLE: u8 = 11 << 4;
 pub const TYPE_BOOLEAN_TRUE: u8 = 12 << 4;
 pub const TYPE_BOOLEAN_FALSE: u8 = 13 << 4;
 
+// ============================================================================
+// Format Detection
+// ============================================================================
+
+/// What a file holds, as far as its first bytes tell, see [`detect_format`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// ABX magic header of any version, see [`FormatVersion`]
+    Abx,
+    /// Text starting with markup, after an optional UTF-8 byte order mark and
+    /// whitespace
+    Xml,
+    Unknown,
+}
+
+/// Tells ABX from XML by the start of a file, so that callers can route files
+/// before converting them. A few bytes are enough for ABX; XML needs its
+/// first non-whitespace character and the one after it.
+///
+/// ```
+/// use android_xml_converter::{Format, detect_format};
+///
+/// assert_eq!(detect_format(b"ABX\0\x10"), Format::Abx);
+/// assert_eq!(detect_format(b"\xEF\xBB\xBF\n<?xml version='1.0'?>"), Format::Xml);
+/// assert_eq!(detect_format(b"  <map>"), Format::Xml);
+/// assert_eq!(detect_format(b"<3 text"), Format::Unknown);
+/// assert_eq!(detect_format(b"\x1F\x8B\x08"), Format::Unknown);
+/// ```
+pub fn detect_format(header: &[u8]) -> Format {
+    if FormatVersion::detect(header).is_some() {
+        return Format::Abx;
+    }
+    let text = header.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(header);
+    let start = text.iter().position(|b| !b.is_ascii_whitespace());
+    match start.map(|start| &text[start..]) {
+        // a declaration, comment, DOCTYPE or start tag
+        Some([b'<', next, ..])
+            if matches!(next, b'?' | b'!' | b'_' | b':')
+                || next.is_ascii_alphabetic()
+                || *next >= 0x80 =>
+        {
+            Format::Xml
+        }
+        _ => Format::Unknown,
+    }
+}
+
+/// Whether the file starts with the ABX magic header of any version,
+/// whatever its name
+pub fn is_abx_file<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    let mut magic = Vec::with_capacity(PROTOCOL_MAGIC_VERSION_0.len());
+    File::open(path)?
+        .take(PROTOCOL_MAGIC_VERSION_0.len() as u64)
+        .read_to_end(&mut magic)?;
+    Ok(detect_format(&magic) == Format::Abx)
+}
+
+// ============================================================================
+// Capabilities
+// ============================================================================
+
+/// ABX protocol versions this build reads and writes
+pub fn protocol_versions() -> &'static [u8] {
+    &[0]
+}
+
+/// Cargo features this build was compiled with, for runtime introspection by
+/// embedders
+pub fn supported_features() -> Vec<&'static str> {
+    let features: [(&'static str, bool); 13] = [
+        ("android-cli-compat", cfg!(feature = "android-cli-compat")),
+        ("android-dictionary", cfg!(feature = "android-dictionary")),
+        ("archive", cfg!(feature = "archive")),
+        ("base64", cfg!(feature = "base64")),
+        ("batch", cfg!(feature = "batch")),
+        ("cli", cfg!(feature = "cli")),
+        ("derive", cfg!(feature = "derive")),
+        ("digest", cfg!(feature = "digest")),
+        ("fast-hash", cfg!(feature = "fast-hash")),
+        ("fast-hex", cfg!(feature = "fast-hex")),
+        ("gzip", cfg!(feature = "gzip")),
+        ("serde", cfg!(feature = "serde")),
+        ("unstable", cfg!(feature = "unstable")),
+    ];
+    features
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Everything a build supports, as reported by [`capabilities`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub protocol_versions: &'static [u8],
+    /// Input formats, e.g. `abx`, `xml` and `gzip`
+    pub formats: Vec<&'static str>,
+    /// Token names as returned by [`token_name`]
+    pub tokens: Vec<&'static str>,
+    /// Attribute types as returned by [`reader::AttributeValue::type_name`]
+    pub attribute_types: &'static [&'static str],
+    pub features: Vec<&'static str>,
+}
+
+/// Describes the formats, token and attribute types and features of this build.
+///
+/// ```
+/// let capabilities = android_xml_converter::capabilities();
+/// assert!(capabilities.protocol_versions.contains(&0));
+/// assert!(capabilities.tokens.contains(&"START_TAG"));
+/// ```
+pub fn capabilities() -> Capabilities {
+    let mut formats: Vec<_> = codec::codecs().iter().map(|codec| codec.name()).collect();
+    formats.push("xml");
+    if cfg!(feature = "gzip") {
+        formats.push("gzip");
+    }
+    if cfg!(feature = "archive") {
+        formats.extend(["tar", "android-backup"]);
+    }
+
+    Capabilities {
+        protocol_versions: protocol_versions(),
+        formats,
+        tokens: (0..16).filter_map(token_name).collect(),
+        attribute_types: reader::AttributeValue::TYPE_NAMES,
+        features: supported_features(),
+    }
+}
+
 // ============================================================================
 // Shared Utilities
 // ============================================================================
@@ -109,6 +1414,9 @@ pub const INITIAL_STRING_POOL_CAPACITY: usize = 64;
 /// Initial capacity for XML event buffer
 pub const INITIAL_EVENT_BUFFER_CAPACITY: usize = 8192;
 
+/// Default capacity of the read and write buffers around files and streams
+pub const DEFAULT_BUFFER_SIZE: usize = 8192;
+
 #[inline]
 pub fn encode_xml_entities(text: &str) -> std::borrow::Cow<'_, str> {
     // Fast path: check if escaping is needed
@@ -133,6 +1441,316 @@ pub fn encode_xml_entities(text: &str) -> std::borrow::Cow<'_, str> {
     std::borrow::Cow::Owned(result)
 }
 
+/// [`Options::canonical`]: trims a text node, its TEXT and ENTITY_REF pieces in
+/// order, at its outer edges only, so whitespace next to a reference inside
+/// the node stays. Adjacent text pieces are joined; an all-whitespace node
+/// ends up empty.
+pub(crate) fn trim_text_node(pieces: &mut Vec<(u8, String)>) {
+    pieces.dedup_by(|next, previous| {
+        if previous.0 == TEXT && next.0 == TEXT {
+            previous.1.push_str(&next.1);
+            return true;
+        }
+        false
+    });
+    if let Some((TEXT, text)) = pieces.first_mut() {
+        *text = text.trim_start().to_string();
+    }
+    if let Some((TEXT, text)) = pieces.last_mut() {
+        *text = text.trim_end().to_string();
+    }
+    pieces.retain(|(command, text)| *command != TEXT || !text.is_empty());
+}
+
+/// First of the characters [`InvalidUtf8::Escape`] keeps invalid bytes as,
+/// which are all at least 0x80
+const ESCAPED_BYTE_BASE: u32 = 0xF700;
+
+/// Decodes `bytes` as UTF-8, keeping each invalid byte as a character
+/// [`encode_escaped_bytes`] turns back into a reference
+pub(crate) fn decode_escaping_bytes(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        result.push_str(chunk.valid());
+        result.extend(
+            chunk
+                .invalid()
+                .iter()
+                .filter_map(|&b| char::from_u32(ESCAPED_BYTE_BASE + b as u32)),
+        );
+    }
+    result
+}
+
+/// Writes the characters of [`decode_escaping_bytes`] as character references
+/// to the bytes they stand for
+pub(crate) fn encode_escaped_bytes(text: &str) -> std::borrow::Cow<'_, str> {
+    let escaped =
+        |ch: char| (ESCAPED_BYTE_BASE + 0x80..=ESCAPED_BYTE_BASE + 0xFF).contains(&(ch as u32));
+    if !text.chars().any(escaped) {
+        return std::borrow::Cow::Borrowed(text);
+    }
+
+    let mut result = String::with_capacity(text.len() + 16);
+    for ch in text.chars() {
+        if escaped(ch) {
+            result.push_str(&format!("&#x{:X};", ch as u32 - ESCAPED_BYTE_BASE));
+        } else {
+            result.push(ch);
+        }
+    }
+    std::borrow::Cow::Owned(result)
+}
+
+/// The offset part of a [`ConversionError::Decode`] message, empty if the
+/// wrapped error names an offset of its own
+fn decode_offset(offset: u64, source: &ConversionError) -> String {
+    match source {
+        ConversionError::LengthExceedsInput { .. } => String::new(),
+        _ => format!(" at offset 0x{:X}", offset),
+    }
+}
+
+/// The part of a [`ConversionError::Decode`] message after the offset, e.g.
+/// `, after ATTRIBUTE, inside <packages><package>`
+fn decode_context(last_token: Option<&str>, elements: &[String]) -> String {
+    let mut context = String::new();
+    if let Some(token) = last_token {
+        context.push_str(", after ");
+        context.push_str(token);
+    }
+    if !elements.is_empty() {
+        context.push_str(", inside ");
+        for element in elements {
+            context.push('<');
+            context.push_str(element);
+            context.push('>');
+        }
+    }
+    context
+}
+
+/// Magic bytes at the start of every gzip stream
+pub const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// Transparently decompresses `reader` if it starts with the gzip magic.
+///
+/// Used for stdin, where `adb exec-out cat file | gzip` style pipelines are common.
+/// Non-gzip input is passed through unchanged. Without the `gzip` feature,
+/// gzip input is rejected instead.
+pub fn decompress_if_gzip<'a, R: Read + 'a>(mut reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    let mut prefix = [0u8; 2];
+    let mut filled = 0;
+    while filled < prefix.len() {
+        match reader.read(&mut prefix[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let input = Cursor::new(prefix[..filled].to_vec()).chain(reader);
+    if prefix[..filled] == GZIP_MAGIC {
+        #[cfg(feature = "gzip")]
+        return Ok(Box::new(MultiGzDecoder::new(BufReader::new(input))));
+
+        #[cfg(not(feature = "gzip"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "gzip input requires the gzip feature",
+        ));
+    }
+    Ok(Box::new(input))
+}
+
+// ============================================================================
+// Optional Dependency Fallbacks
+// ============================================================================
+
+/// Hash map used throughout the crate; `ahash` with the `fast-hash` feature.
+///
+/// Both hash with per-process random keys, as the maps hold attribute values
+/// and pool strings from untrusted input; create maps with `default()`.
+#[cfg(feature = "fast-hash")]
+pub(crate) type FastHashMap<K, V> = std::collections::HashMap<K, V, ahash::RandomState>;
+#[cfg(feature = "fast-hash")]
+pub(crate) type FastHashSet<T> = std::collections::HashSet<T, ahash::RandomState>;
+#[cfg(not(feature = "fast-hash"))]
+pub(crate) type FastHashMap<K, V> = std::collections::HashMap<K, V>;
+#[cfg(not(feature = "fast-hash"))]
+pub(crate) type FastHashSet<T> = std::collections::HashSet<T>;
+
+/// Lowercase hex encoding; `faster-hex` with the `fast-hex` feature
+#[cfg(feature = "fast-hex")]
+pub(crate) fn hex_string(bytes: &[u8]) -> String {
+    faster_hex::hex_string(bytes)
+}
+
+#[cfg(not(feature = "fast-hex"))]
+pub(crate) fn hex_string(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut result = String::with_capacity(bytes.len() * 2);
+    for &byte in bytes {
+        result.push(DIGITS[(byte >> 4) as usize] as char);
+        result.push(DIGITS[(byte & 0x0F) as usize] as char);
+    }
+    result
+}
+
+/// Standard padded base64 encoding; the `base64` crate with the `base64` feature
+#[cfg(feature = "base64")]
+pub(crate) fn base64_string(bytes: &[u8]) -> String {
+    use base64::Engine;
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+#[cfg(not(feature = "base64"))]
+pub(crate) fn base64_string(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut result = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+/// Decodes hex digits of either case, `None` if the input is not valid hex
+#[cfg(feature = "fast-hex")]
+pub(crate) fn hex_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = vec![0u8; text.len() / 2];
+    faster_hex::hex_decode(text.as_bytes(), &mut bytes).ok()?;
+    Some(bytes)
+}
+
+#[cfg(not(feature = "fast-hex"))]
+pub(crate) fn hex_decode(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    let digit = |byte: u8| (byte as char).to_digit(16).map(|d| d as u8);
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| Some(digit(pair[0])? << 4 | digit(pair[1])?))
+        .collect()
+}
+
+/// Decodes standard padded base64, `None` if the input is not valid base64
+#[cfg(feature = "base64")]
+pub(crate) fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    use base64::Engine;
+    base64::engine::general_purpose::STANDARD.decode(text).ok()
+}
+
+#[cfg(not(feature = "base64"))]
+pub(crate) fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let sextet = |byte: u8| match byte {
+        b'A'..=b'Z' => Some(byte - b'A'),
+        b'a'..=b'z' => Some(byte - b'a' + 26),
+        b'0'..=b'9' => Some(byte - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    for (i, chunk) in text.as_bytes().chunks(4).enumerate() {
+        let last = i == text.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&b| b == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut n = 0u32;
+        for &byte in &chunk[..4 - padding] {
+            n = n << 6 | sextet(byte)? as u32;
+        }
+        n <<= 6 * padding;
+        bytes.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Some(bytes)
+}
+
+/// Advice to print after an error that is usually caused by the platform rather
+/// than the input.
+///
+/// On WASI a module can only open paths inside the directories the host preopened,
+/// and anything else fails like a missing file.
+pub fn error_hint(error: &ConversionError) -> Option<&'static str> {
+    match error {
+        ConversionError::Io(e)
+            if cfg!(target_os = "wasi")
+                && matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+                ) =>
+        {
+            Some(
+                "WASI modules can only access directories preopened by the host, e.g. `wasmtime run --dir . abx2xml.wasm ...`",
+            )
+        }
+        _ => None,
+    }
+}
+
+// ============================================================================
+// Android CLI Compatibility
+// ============================================================================
+
+/// Usage text printed by Android's `abx2xml` and `xml2abx`
+pub const ANDROID_CLI_USAGE: &str = "\
+usage: abx2xml [-i] input [output]
+usage: xml2abx [-i] input [output]
+
+Converts between human-readable XML and Android Binary XML.
+
+When invoked with the '-i' argument, the output of a successful conversion
+will overwrite the original input file. Input can be '-' to use stdin, and
+output can be '-' to use stdout.
+";
+
+/// Whether the tools run in `--android-cli-compat` mode, returning the
+/// arguments that follow the flag. The `android-cli-compat` feature makes it
+/// the default, for installing the tools as drop-in replacements.
+pub fn android_cli_compat(args: &[String]) -> Option<&[String]> {
+    match args.split_first() {
+        Some((first, rest)) if first == "--android-cli-compat" => Some(rest),
+        _ if cfg!(feature = "android-cli-compat") => Some(args),
+        _ => None,
+    }
+}
+
+/// Parses arguments exactly like Android's tools: `-i input` converts in place
+/// and `input output` converts between the two, with `-` for stdin or stdout.
+/// Anything else is a usage error.
+///
+/// ```
+/// use android_xml_converter::parse_android_cli_args;
+///
+/// let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+/// assert_eq!(parse_android_cli_args(&args(&["-i", "a.xml"])), Some(("a.xml", "a.xml")));
+/// assert_eq!(parse_android_cli_args(&args(&["-", "-"])), Some(("-", "-")));
+/// assert_eq!(parse_android_cli_args(&args(&["a.xml"])), None);
+/// assert_eq!(parse_android_cli_args(&args(&["-i", "-"])), None);
+/// ```
+pub fn parse_android_cli_args(args: &[String]) -> Option<(&str, &str)> {
+    match args {
+        [flag, input] if flag == "-i" && input != "-" => Some((input, input)),
+        [input, output] if input != "-i" => Some((input, output)),
+        _ => None,
+    }
+}
+
 /// Shows a warning message for unsupported XML features
 #[inline]
 pub fn show_warning(feature: &str, details: Option<&str>) {
@@ -142,11 +1760,128 @@ pub fn show_warning(feature: &str, details: Option<&str>) {
     }
 }
 
+// ============================================================================
+// Modified UTF-8
+// ============================================================================
+
+/// Java's modified UTF-8, as `DataOutput.writeUTF` and Android's
+/// `FastDataOutput` write strings: NUL as `C0 80` and characters outside the
+/// Basic Multilingual Plane as two 3-byte surrogates instead of one 4-byte
+/// sequence. Every other string encodes exactly like UTF-8. See
+/// [`Options::modified_utf8`].
+///
+/// ```
+/// use android_xml_converter::mutf8;
+///
+/// assert_eq!(mutf8::encode("a\0b").as_ref(), b"a\xC0\x80b");
+/// assert_eq!(mutf8::encode("😀").as_ref(), b"\xED\xA0\xBD\xED\xB8\x80");
+/// assert_eq!(mutf8::decode(b"\xED\xA0\xBD\xED\xB8\x80a\xC0\x80").unwrap(), "😀a\0");
+/// assert!(mutf8::decode(b"\xED\xA0\xBD").is_err());
+/// ```
+///
+/// Supplementary characters survive conversion either way, also where long
+/// text is split into several tokens, and strings Android wrote in modified
+/// UTF-8 are read without the option:
+///
+/// ```
+/// use android_xml_converter::{AbxToXmlConverter, Options, XmlToAbxConverter};
+///
+/// let xml = format!(r#"<s name="smile 😀">{}</s>"#, "😀".repeat(30_000));
+/// for modified_utf8 in [false, true] {
+///     let options = Options::new().modified_utf8(modified_utf8);
+///     let mut abx = Vec::new();
+///     XmlToAbxConverter::convert_from_string_with(&xml, &mut abx, &options).unwrap();
+///     assert_eq!(abx.windows(4).any(|w| w == "😀".as_bytes()), !modified_utf8);
+///     assert!(AbxToXmlConverter::convert_bytes(&abx).unwrap().ends_with(&xml));
+/// }
+/// ```
+pub mod mutf8 {
+    use crate::{ConversionError, Result};
+    use std::borrow::Cow;
+
+    /// Bytes `c` takes in modified UTF-8
+    pub fn char_len(c: char) -> usize {
+        match c {
+            '\0' => 2,
+            c if c > '\u{FFFF}' => 6,
+            c => c.len_utf8(),
+        }
+    }
+
+    /// Bytes `s` takes in modified UTF-8, the length Android stores
+    pub fn encoded_len(s: &str) -> usize {
+        s.len() + s.chars().map(|c| char_len(c) - c.len_utf8()).sum::<usize>()
+    }
+
+    pub fn encode(s: &str) -> Cow<'_, [u8]> {
+        if !s.chars().any(|c| c == '\0' || c > '\u{FFFF}') {
+            return Cow::Borrowed(s.as_bytes());
+        }
+        let mut bytes = Vec::with_capacity(s.len() + 8);
+        for c in s.chars() {
+            match c {
+                '\0' => bytes.extend_from_slice(&[0xC0, 0x80]),
+                c if c > '\u{FFFF}' => {
+                    let mut units = [0u16; 2];
+                    for unit in c.encode_utf16(&mut units) {
+                        bytes.extend_from_slice(&[
+                            0xE0 | (*unit >> 12) as u8,
+                            0x80 | ((*unit >> 6) & 0x3F) as u8,
+                            0x80 | (*unit & 0x3F) as u8,
+                        ]);
+                    }
+                }
+                c => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+        Cow::Owned(bytes)
+    }
+
+    /// Decodes modified UTF-8; plain 4-byte UTF-8 sequences are accepted too
+    pub fn decode(bytes: &[u8]) -> Result<String> {
+        let invalid = || ConversionError::ReadError("UTF string (invalid modified UTF-8)".into());
+        // only NUL and surrogates differ from UTF-8, led by 0xC0 and 0xED
+        if !bytes.iter().any(|&b| b == 0xC0 || b == 0xED) {
+            return String::from_utf8(bytes.to_vec()).map_err(|_| invalid());
+        }
+
+        let mut units = Vec::with_capacity(bytes.len());
+        let mut rest = bytes;
+        while let Some(&lead) = rest.first() {
+            let (length, initial) = match lead {
+                0x00..=0x7F => (1, lead as u32),
+                0xC0..=0xDF => (2, (lead & 0x1F) as u32),
+                0xE0..=0xEF => (3, (lead & 0x0F) as u32),
+                0xF0..=0xF7 => (4, (lead & 0x07) as u32),
+                _ => return Err(invalid()),
+            };
+            let sequence = rest.get(1..length).ok_or_else(invalid)?;
+            let mut code = initial;
+            for &byte in sequence {
+                if byte & 0xC0 != 0x80 {
+                    return Err(invalid());
+                }
+                code = code << 6 | (byte & 0x3F) as u32;
+            }
+            match char::from_u32(code) {
+                Some(c) if length == 4 => units.extend_from_slice(c.encode_utf16(&mut [0; 2])),
+                _ if length == 4 => return Err(invalid()),
+                _ => units.push(code as u16),
+            }
+            rest = &rest[length..];
+        }
+        String::from_utf16(&units).map_err(|_| invalid())
+    }
+}
+
 // ============================================================================
 // Type Detection Utilities
 // ============================================================================
 
 pub mod type_detection {
+    use crate::FloatFormat;
+    use crate::reader::AttributeValue;
+
     /// checks if a string represents a boolean value
     #[inline]
     pub fn is_boolean(s: &str) -> bool {
@@ -158,4 +1893,57 @@ pub mod type_detection {
     pub fn is_whitespace_only(s: &str) -> bool {
         s.bytes().all(|b| matches!(b, b' ' | b'\t' | b'\n' | b'\r'))
     }
+
+    /// The numeric value a string is written as, if any: decimal `int`s and
+    /// `long`s without leading zeros or `+`, and `float`s and `double`s in plain
+    /// notation between 1e-3 and 1e7, as long as they print back as the same
+    /// text (in Java too). `0x` hex stays a string: Android prints `int_hex`
+    /// and `long_hex` without the prefix, which would read back as a decimal
+    /// number, so hex types come only from a schema or type hints.
+    ///
+    /// ```
+    /// use android_xml_converter::reader::AttributeValue;
+    /// use android_xml_converter::type_detection::infer_number;
+    ///
+    /// assert_eq!(infer_number("10001"), Some(AttributeValue::Int(10001)));
+    /// assert_eq!(infer_number("1700000000000"), Some(AttributeValue::Long(1700000000000)));
+    /// assert_eq!(infer_number("1.5"), Some(AttributeValue::Float(1.5)));
+    /// assert_eq!(infer_number("0.1234567891"), Some(AttributeValue::Double(0.1234567891)));
+    /// for text in ["007", "+1", "1.50", "1e5", "0.0001", "NaN", "1.2.3", "0xff", "0x1234"] {
+    ///     assert_eq!(infer_number(text), None);
+    /// }
+    /// ```
+    pub fn infer_number(s: &str) -> Option<AttributeValue> {
+        if let Ok(value) = s.parse::<i64>() {
+            if value.to_string() != s {
+                return None;
+            }
+            return Some(match i32::try_from(value) {
+                Ok(value) => AttributeValue::Int(value),
+                Err(_) => AttributeValue::Long(value),
+            });
+        }
+
+        // no exponents, infinities or NaN, which Java spells differently
+        if !s.contains('.')
+            || !s
+                .bytes()
+                .all(|b| b.is_ascii_digit() || b == b'.' || b == b'-')
+        {
+            return None;
+        }
+        let value: f64 = s.parse().ok()?;
+        let magnitude = value.abs();
+        if magnitude != 0.0 && !(1e-3..1e7).contains(&magnitude) {
+            return None;
+        }
+        let format = FloatFormat::shortest();
+        if format.format_f32(value as f32) == s {
+            Some(AttributeValue::Float(value as f32))
+        } else if format.format_f64(value) == s {
+            Some(AttributeValue::Double(value))
+        } else {
+            None
+        }
+    }
 }
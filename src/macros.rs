@@ -0,0 +1,172 @@
+// ============================================================================
+// Document Builder Macro
+// ============================================================================
+
+/// Builds a [`RawDocument`](crate::roundtrip::RawDocument) from a declarative
+/// description of its elements, with explicit ABX types for every attribute.
+///
+/// ```
+/// use android_xml_converter::abx;
+///
+/// let doc = abx! {
+///     manifest(package = str "com.foo", versionCode = int 3) {
+///         uses-permission("android:name" = interned "android.permission.INTERNET")
+///         application(debuggable = bool false) {
+///             text("hello")
+///             comment("generated")
+///         }
+///     }
+/// };
+/// let bytes = doc.to_bytes().unwrap();
+/// assert_eq!(&bytes[..4], b"ABX\0");
+/// ```
+///
+/// Element and attribute names are identifiers (hyphens allowed) or string
+/// literals. Attribute types are `str`, `interned`, `int`, `int_hex`, `long`,
+/// `long_hex`, `float`, `double`, `bool`, `bytes_hex` and `bytes_base64`.
+/// Besides elements, a body may contain `text(..)`, `cdata(..)` and `comment(..)`.
+#[macro_export]
+macro_rules! abx {
+    ($($body:tt)+) => {{
+        let mut tokens = ::std::vec::Vec::new();
+        tokens.push($crate::roundtrip::Token::StartDocument);
+        $crate::__abx_nodes!(tokens; $($body)+);
+        tokens.push($crate::roundtrip::Token::EndDocument);
+        $crate::roundtrip::RawDocument::from_tokens(tokens)
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __abx_nodes {
+    ($tokens:ident;) => {};
+    ($tokens:ident; text($text:expr) $($rest:tt)*) => {
+        $crate::__abx_content!($tokens, $crate::TEXT, $text);
+        $crate::__abx_nodes!($tokens; $($rest)*);
+    };
+    ($tokens:ident; cdata($text:expr) $($rest:tt)*) => {
+        $crate::__abx_content!($tokens, $crate::CDSECT, $text);
+        $crate::__abx_nodes!($tokens; $($rest)*);
+    };
+    ($tokens:ident; comment($text:expr) $($rest:tt)*) => {
+        $crate::__abx_content!($tokens, $crate::COMMENT, $text);
+        $crate::__abx_nodes!($tokens; $($rest)*);
+    };
+    ($tokens:ident; $name:literal ($($attrs:tt)*) { $($children:tt)* } $($rest:tt)*) => {
+        $crate::__abx_element!($tokens, $name, ($($attrs)*), ($($children)*));
+        $crate::__abx_nodes!($tokens; $($rest)*);
+    };
+    ($tokens:ident; $name:literal ($($attrs:tt)*) $($rest:tt)*) => {
+        $crate::__abx_element!($tokens, $name, ($($attrs)*), ());
+        $crate::__abx_nodes!($tokens; $($rest)*);
+    };
+    ($tokens:ident; $first:ident $(- $part:ident)* ($($attrs:tt)*) { $($children:tt)* } $($rest:tt)*) => {
+        $crate::__abx_element!(
+            $tokens,
+            concat!(stringify!($first) $(, "-", stringify!($part))*),
+            ($($attrs)*),
+            ($($children)*)
+        );
+        $crate::__abx_nodes!($tokens; $($rest)*);
+    };
+    ($tokens:ident; $first:ident $(- $part:ident)* ($($attrs:tt)*) $($rest:tt)*) => {
+        $crate::__abx_element!(
+            $tokens,
+            concat!(stringify!($first) $(, "-", stringify!($part))*),
+            ($($attrs)*),
+            ()
+        );
+        $crate::__abx_nodes!($tokens; $($rest)*);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __abx_content {
+    ($tokens:ident, $command:expr, $text:expr) => {
+        $tokens.push($crate::roundtrip::Token::Content {
+            command: $command,
+            text: ::core::option::Option::Some(::std::string::String::from($text)),
+        });
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __abx_element {
+    ($tokens:ident, $name:expr, ($($attrs:tt)*), ($($children:tt)*)) => {{
+        let name: &str = $name;
+        $tokens.push($crate::roundtrip::Token::StartTag(::core::convert::From::from(name)));
+        $crate::__abx_attrs!($tokens; $($attrs)*);
+        $crate::__abx_nodes!($tokens; $($children)*);
+        $tokens.push($crate::roundtrip::Token::EndTag(::core::convert::From::from(name)));
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __abx_attrs {
+    ($tokens:ident;) => {};
+    ($tokens:ident; $name:literal = $ty:ident $value:expr $(, $($rest:tt)*)?) => {
+        $crate::__abx_attr!($tokens, $name, $ty, $value);
+        $crate::__abx_attrs!($tokens; $($($rest)*)?);
+    };
+    ($tokens:ident; $first:ident $(- $part:ident)* = $ty:ident $value:expr $(, $($rest:tt)*)?) => {
+        $crate::__abx_attr!(
+            $tokens,
+            concat!(stringify!($first) $(, "-", stringify!($part))*),
+            $ty,
+            $value
+        );
+        $crate::__abx_attrs!($tokens; $($($rest)*)?);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __abx_attr {
+    ($tokens:ident, $name:expr, $ty:ident, $value:expr) => {
+        $tokens.push($crate::roundtrip::Token::Attribute {
+            name: ::core::convert::From::from($name),
+            value: $crate::__abx_value!($ty, $value),
+        });
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __abx_value {
+    (str, $value:expr) => {
+        $crate::reader::AttributeValue::String(::std::string::String::from($value))
+    };
+    (interned, $value:expr) => {
+        $crate::reader::AttributeValue::Interned(::core::convert::From::from($value))
+    };
+    (int, $value:expr) => {
+        $crate::reader::AttributeValue::Int($value)
+    };
+    (int_hex, $value:expr) => {
+        $crate::reader::AttributeValue::IntHex($value)
+    };
+    (long, $value:expr) => {
+        $crate::reader::AttributeValue::Long($value)
+    };
+    (long_hex, $value:expr) => {
+        $crate::reader::AttributeValue::LongHex($value)
+    };
+    (float, $value:expr) => {
+        $crate::reader::AttributeValue::Float($value)
+    };
+    (double, $value:expr) => {
+        $crate::reader::AttributeValue::Double($value)
+    };
+    (bool, $value:expr) => {
+        $crate::reader::AttributeValue::Boolean($value)
+    };
+    (bytes_hex, $value:expr) => {
+        $crate::reader::AttributeValue::BytesHex(::core::convert::Into::into($value))
+    };
+    (bytes_base64, $value:expr) => {
+        $crate::reader::AttributeValue::BytesBase64(::core::convert::Into::into($value))
+    };
+}
@@ -0,0 +1,411 @@
+use crate::dom::{AbxDocument, Element, ElementId};
+use crate::reader::AttributeValue;
+use crate::*;
+use smol_str::SmolStr;
+
+#[cfg(feature = "derive")]
+pub use android_xml_converter_derive::{FromAbx, ToAbx};
+
+// ============================================================================
+// Struct Mapping
+// ============================================================================
+
+/// A type read from an element of an [`AbxDocument`], usually through
+/// `#[derive(FromAbx)]` (feature `derive`).
+///
+/// The derive reads every field from the attribute of the same name, converting
+/// it with [`AbxAttribute`]. Fields may be annotated with:
+///
+/// - `#[abx(attr = "versionCode")]` to read another attribute
+/// - `#[abx(type = "int_hex")]` to pick the ABX type, one of
+///   [`AttributeValue::TYPE_NAMES`]; it decides how strings are parsed here and
+///   what is written by [`ToAbx`]
+/// - `#[abx(child)]` for a field of another mapped type, or an `Option` or `Vec`
+///   of one, read from child elements named [`FromAbx::ELEMENT`]
+/// - `#[abx(text)]` for a `String` holding the text of the element
+/// - `#[abx(skip)]` to leave the field at its default
+///
+/// The element name is the struct name unless set with
+/// `#[abx(element = "package")]` on the struct.
+///
+/// ```
+/// # #[cfg(feature = "derive")] {
+/// use android_xml_converter::mapping::{FromAbx, ToAbx};
+/// use android_xml_converter::reader::AttributeValue;
+///
+/// #[derive(Debug, PartialEq, FromAbx, ToAbx)]
+/// #[abx(element = "package")]
+/// struct Package {
+///     name: String,
+///     #[abx(attr = "versionCode", type = "int_hex")]
+///     version_code: i32,
+///     #[abx(attr = "userId")]
+///     user_id: Option<i32>,
+///     #[abx(child)]
+///     perms: Vec<Permission>,
+/// }
+///
+/// #[derive(Debug, PartialEq, FromAbx, ToAbx)]
+/// #[abx(element = "item")]
+/// struct Permission {
+///     name: String,
+///     granted: bool,
+/// }
+///
+/// let package = Package {
+///     name: "com.example".to_string(),
+///     version_code: 0x2a,
+///     user_id: None,
+///     perms: vec![Permission { name: "INTERNET".to_string(), granted: true }],
+/// };
+/// let abx = package.to_abx_bytes().unwrap();
+///
+/// let document = android_xml_converter::dom::AbxDocument::from_bytes(&abx).unwrap();
+/// let root = document.root().unwrap();
+/// assert_eq!(root.attribute("versionCode"), Some(&AttributeValue::IntHex(0x2a)));
+/// assert_eq!(root.attribute("userId"), None);
+/// assert_eq!(Package::from_abx_bytes(&abx).unwrap(), package);
+/// # }
+/// ```
+pub trait FromAbx: Sized {
+    /// Name of the element the type is read from
+    const ELEMENT: &'static str;
+
+    fn from_element(element: Element<'_>) -> Result<Self>;
+
+    /// Reads the root element of a document, which must be named [`Self::ELEMENT`]
+    fn from_document(document: &AbxDocument) -> Result<Self> {
+        let root = document.root().ok_or_else(|| {
+            ConversionError::ParseError("Document has no root element".to_string())
+        })?;
+        if root.name() != Self::ELEMENT {
+            return Err(ConversionError::ParseError(format!(
+                "Expected root element {}, found {}",
+                Self::ELEMENT,
+                root.name()
+            )));
+        }
+        Self::from_element(root)
+    }
+
+    fn from_abx_bytes(abx_data: &[u8]) -> Result<Self> {
+        Self::from_document(&AbxDocument::from_bytes(abx_data)?)
+    }
+}
+
+/// A type written as an element of an [`AbxDocument`], usually through
+/// `#[derive(ToAbx)]`; see [`FromAbx`] for the field annotations
+pub trait ToAbx {
+    /// Name of the element the type is written as
+    const ELEMENT: &'static str;
+
+    /// Adds the attributes, text and children of the type to `element`
+    fn write_element(&self, document: &mut AbxDocument, element: ElementId) -> Result<()>;
+
+    /// A new document with the type as its root element
+    fn to_document(&self) -> Result<AbxDocument> {
+        let mut document = AbxDocument::new(Self::ELEMENT);
+        let root = document.root().map(|root| root.id()).expect("new root");
+        self.write_element(&mut document, root)?;
+        Ok(document)
+    }
+
+    fn to_abx_bytes(&self) -> Result<Vec<u8>> {
+        self.to_document()?.to_bytes()
+    }
+}
+
+// ============================================================================
+// Attribute Fields
+// ============================================================================
+
+/// A field type stored in a single attribute
+pub trait AbxAttribute: Sized {
+    /// ABX type written when the field does not name one
+    const TYPE: &'static str;
+
+    /// The attribute to write as `type_name`, or `None` to leave it out
+    fn to_attribute(&self, type_name: &str) -> Result<Option<AttributeValue>>;
+
+    /// Reads a stored value; `type_name` decides how strings are parsed
+    fn from_attribute(value: &AttributeValue, type_name: &str) -> Result<Self>;
+
+    /// The value of a missing attribute, an error unless the field is optional
+    fn missing(element: &str, name: &str) -> Result<Self> {
+        Err(ConversionError::ParseError(format!(
+            "Missing attribute {} on element {}",
+            name, element
+        )))
+    }
+}
+
+/// Reads attribute `name` of `element` into a field, used by the derive
+pub fn read_attribute<T: AbxAttribute>(
+    element: Element<'_>,
+    name: &str,
+    type_name: Option<&str>,
+) -> Result<T> {
+    match element.attribute(name) {
+        Some(value) => T::from_attribute(value, type_name.unwrap_or(T::TYPE)).map_err(|e| {
+            ConversionError::ParseError(format!(
+                "Attribute {} on element {}: {}",
+                name,
+                element.name(),
+                e
+            ))
+        }),
+        None => T::missing(element.name(), name),
+    }
+}
+
+/// Writes a field as attribute `name` of `element`, used by the derive
+pub fn write_attribute<T: AbxAttribute>(
+    document: &mut AbxDocument,
+    element: ElementId,
+    name: &str,
+    value: &T,
+    type_name: Option<&str>,
+) -> Result<()> {
+    if let Some(value) = value.to_attribute(type_name.unwrap_or(T::TYPE))? {
+        document.set_attribute(element, name, value);
+    }
+    Ok(())
+}
+
+/// Writes a `#[abx(text)]` field, leaving empty text out
+pub fn write_text(document: &mut AbxDocument, element: ElementId, text: &str) {
+    if !text.is_empty() {
+        document.append_text(element, text);
+    }
+}
+
+/// An integer attribute of type `type_name`
+fn integer_attribute(value: i64, type_name: &str) -> Result<AttributeValue> {
+    let int = || {
+        i32::try_from(value).map_err(|_| {
+            ConversionError::ParseError(format!("{} does not fit in an {}", value, type_name))
+        })
+    };
+    match type_name {
+        "int" => Ok(AttributeValue::Int(int()?)),
+        "int_hex" => Ok(AttributeValue::IntHex(int()?)),
+        "long" => Ok(AttributeValue::Long(value)),
+        "long_hex" => Ok(AttributeValue::LongHex(value)),
+        _ => AttributeValue::parse_as(type_name, &value.to_string()),
+    }
+}
+
+fn integer_from_attribute(value: &AttributeValue, type_name: &str) -> Result<i64> {
+    if type_name.ends_with("_hex") {
+        value.to_i64_hex()
+    } else {
+        value.to_i64()
+    }
+}
+
+macro_rules! integer_attribute {
+    ($($ty:ty => $default:literal),*) => {
+        $(
+            impl AbxAttribute for $ty {
+                const TYPE: &'static str = $default;
+
+                fn to_attribute(&self, type_name: &str) -> Result<Option<AttributeValue>> {
+                    integer_attribute(i64::from(*self), type_name).map(Some)
+                }
+
+                fn from_attribute(value: &AttributeValue, type_name: &str) -> Result<Self> {
+                    let value = integer_from_attribute(value, type_name)?;
+                    <$ty>::try_from(value).map_err(|_| {
+                        ConversionError::ParseError(format!(
+                            "{} does not fit in {}",
+                            value,
+                            stringify!($ty)
+                        ))
+                    })
+                }
+            }
+        )*
+    };
+}
+
+integer_attribute!(i8 => "int", i16 => "int", i32 => "int", u8 => "int", u16 => "int", u32 => "long", i64 => "long");
+
+impl AbxAttribute for bool {
+    const TYPE: &'static str = "boolean";
+
+    fn to_attribute(&self, type_name: &str) -> Result<Option<AttributeValue>> {
+        AttributeValue::parse_as(type_name, &self.to_string()).map(Some)
+    }
+
+    fn from_attribute(value: &AttributeValue, _type_name: &str) -> Result<Self> {
+        value.to_bool()
+    }
+}
+
+impl AbxAttribute for f32 {
+    const TYPE: &'static str = "float";
+
+    fn to_attribute(&self, type_name: &str) -> Result<Option<AttributeValue>> {
+        match type_name {
+            "float" => Ok(Some(AttributeValue::Float(*self))),
+            "double" => Ok(Some(AttributeValue::Double((*self).into()))),
+            _ => AttributeValue::parse_as(type_name, &self.to_string()).map(Some),
+        }
+    }
+
+    fn from_attribute(value: &AttributeValue, _type_name: &str) -> Result<Self> {
+        value.to_f32()
+    }
+}
+
+impl AbxAttribute for f64 {
+    const TYPE: &'static str = "double";
+
+    fn to_attribute(&self, type_name: &str) -> Result<Option<AttributeValue>> {
+        match type_name {
+            "float" => Ok(Some(AttributeValue::Float(*self as f32))),
+            "double" => Ok(Some(AttributeValue::Double(*self))),
+            _ => AttributeValue::parse_as(type_name, &self.to_string()).map(Some),
+        }
+    }
+
+    fn from_attribute(value: &AttributeValue, _type_name: &str) -> Result<Self> {
+        value.to_f64()
+    }
+}
+
+impl AbxAttribute for String {
+    const TYPE: &'static str = "string";
+
+    /// Other types than strings parse the text, so `type = "int"` on a `String`
+    /// field holding `"5"` writes an int
+    fn to_attribute(&self, type_name: &str) -> Result<Option<AttributeValue>> {
+        AttributeValue::parse_as(type_name, self).map(Some)
+    }
+
+    fn from_attribute(value: &AttributeValue, _type_name: &str) -> Result<Self> {
+        Ok(value.to_string())
+    }
+}
+
+impl AbxAttribute for SmolStr {
+    const TYPE: &'static str = "interned";
+
+    fn to_attribute(&self, type_name: &str) -> Result<Option<AttributeValue>> {
+        AttributeValue::parse_as(type_name, self).map(Some)
+    }
+
+    fn from_attribute(value: &AttributeValue, _type_name: &str) -> Result<Self> {
+        match value {
+            AttributeValue::Interned(value) => Ok(value.clone()),
+            value => Ok(SmolStr::new(value.to_string())),
+        }
+    }
+}
+
+impl AbxAttribute for Vec<u8> {
+    const TYPE: &'static str = "bytes_base64";
+
+    fn to_attribute(&self, type_name: &str) -> Result<Option<AttributeValue>> {
+        match type_name {
+            "bytes_hex" => Ok(Some(AttributeValue::BytesHex(self.clone()))),
+            "bytes_base64" => Ok(Some(AttributeValue::BytesBase64(self.clone()))),
+            _ => Err(ConversionError::ParseError(format!(
+                "Bytes cannot be written as {}",
+                type_name
+            ))),
+        }
+    }
+
+    fn from_attribute(value: &AttributeValue, type_name: &str) -> Result<Self> {
+        match type_name {
+            "bytes_hex" => value.to_bytes_hex(),
+            _ => value.to_bytes_base64(),
+        }
+    }
+}
+
+impl<T: AbxAttribute> AbxAttribute for Option<T> {
+    const TYPE: &'static str = T::TYPE;
+
+    fn to_attribute(&self, type_name: &str) -> Result<Option<AttributeValue>> {
+        match self {
+            Some(value) => value.to_attribute(type_name),
+            None => Ok(None),
+        }
+    }
+
+    fn from_attribute(value: &AttributeValue, type_name: &str) -> Result<Self> {
+        T::from_attribute(value, type_name).map(Some)
+    }
+
+    fn missing(_element: &str, _name: &str) -> Result<Self> {
+        Ok(None)
+    }
+}
+
+// ============================================================================
+// Child Fields
+// ============================================================================
+
+/// A `#[abx(child)]` field: a mapped type, or an `Option` or `Vec` of one
+pub trait FromAbxChildren: Sized {
+    fn from_children(parent: Element<'_>) -> Result<Self>;
+}
+
+impl<T: FromAbx> FromAbxChildren for T {
+    fn from_children(parent: Element<'_>) -> Result<Self> {
+        match parent.child(T::ELEMENT) {
+            Some(child) => T::from_element(child),
+            None => Err(ConversionError::ParseError(format!(
+                "Missing child element {} in {}",
+                T::ELEMENT,
+                parent.name()
+            ))),
+        }
+    }
+}
+
+impl<T: FromAbx> FromAbxChildren for Option<T> {
+    fn from_children(parent: Element<'_>) -> Result<Self> {
+        parent.child(T::ELEMENT).map(T::from_element).transpose()
+    }
+}
+
+impl<T: FromAbx> FromAbxChildren for Vec<T> {
+    fn from_children(parent: Element<'_>) -> Result<Self> {
+        parent
+            .children()
+            .filter(|child| child.name() == T::ELEMENT)
+            .map(T::from_element)
+            .collect()
+    }
+}
+
+/// Writing side of [`FromAbxChildren`]
+pub trait ToAbxChildren {
+    fn write_children(&self, document: &mut AbxDocument, parent: ElementId) -> Result<()>;
+}
+
+impl<T: ToAbx> ToAbxChildren for T {
+    fn write_children(&self, document: &mut AbxDocument, parent: ElementId) -> Result<()> {
+        let child = document.append_element(parent, T::ELEMENT);
+        self.write_element(document, child)
+    }
+}
+
+impl<T: ToAbx> ToAbxChildren for Option<T> {
+    fn write_children(&self, document: &mut AbxDocument, parent: ElementId) -> Result<()> {
+        match self {
+            Some(value) => value.write_children(document, parent),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T: ToAbx> ToAbxChildren for Vec<T> {
+    fn write_children(&self, document: &mut AbxDocument, parent: ElementId) -> Result<()> {
+        self.iter()
+            .try_for_each(|value| value.write_children(document, parent))
+    }
+}
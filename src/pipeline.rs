@@ -0,0 +1,594 @@
+use crate::query::DocumentSummary;
+use crate::reader::AttributeValue;
+use crate::roundtrip::{Token, TokenReader};
+use crate::writer::BinaryXmlSerializer;
+use crate::*;
+use smol_str::SmolStr;
+use std::io::{Read, Write};
+
+// ============================================================================
+// Normalization Steps
+// ============================================================================
+
+/// A rewrite applied to every token on its way from the decoder to the encoder
+pub trait Step {
+    /// Returns the token to encode in place of `token`, or `None` to drop it
+    fn apply(&mut self, token: Token) -> Result<Option<Token>>;
+}
+
+/// Drops IGNORABLE_WHITESPACE tokens and text consisting only of whitespace
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DropWhitespace;
+
+impl Step for DropWhitespace {
+    fn apply(&mut self, token: Token) -> Result<Option<Token>> {
+        let drop = match &token {
+            Token::Content {
+                command: IGNORABLE_WHITESPACE,
+                ..
+            } => true,
+            Token::Content {
+                command: TEXT,
+                text: Some(text),
+            } => type_detection::is_whitespace_only(text),
+            _ => false,
+        };
+        Ok((!drop).then_some(token))
+    }
+}
+
+/// Re-decides which string attribute values go through the intern pool by an
+/// [`InternPolicy`], by default the same rule as XML to ABX conversion. The
+/// pool size limit does not apply, as the pool is only built when writing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReinternValues {
+    policy: InternPolicy,
+}
+
+impl ReinternValues {
+    pub fn new(policy: InternPolicy) -> Self {
+        Self { policy }
+    }
+}
+
+impl Step for ReinternValues {
+    fn apply(&mut self, token: Token) -> Result<Option<Token>> {
+        let Token::Attribute { name, value } = token else {
+            return Ok(Some(token));
+        };
+        let value = match value {
+            AttributeValue::String(text) if self.policy.admits(&text) => {
+                AttributeValue::Interned(SmolStr::new(text))
+            }
+            AttributeValue::Interned(text) if !self.policy.admits(&text) => {
+                AttributeValue::String(text.to_string())
+            }
+            value => value,
+        };
+        Ok(Some(Token::Attribute { name, value }))
+    }
+}
+
+/// Re-encodes string attributes with the ABX type hinted for their name.
+///
+/// Type names are those of [`AttributeValue::type_name`]. Values that do not
+/// parse as the hinted type are an error rather than silently left as strings.
+#[derive(Debug, Clone, Default)]
+pub struct TypeHints {
+    hints: FastHashMap<SmolStr, String>,
+}
+
+impl TypeHints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn hint(mut self, attribute: &str, type_name: &str) -> Self {
+        self.hints
+            .insert(SmolStr::new(attribute), type_name.to_string());
+        self
+    }
+
+    /// Parses a `NAME=TYPE` hint as given on the command line
+    pub fn parse_hint(self, hint: &str) -> Result<Self> {
+        let (name, type_name) = hint.split_once('=').ok_or_else(|| {
+            ConversionError::ParseError(format!("Invalid type hint, expected NAME=TYPE: {}", hint))
+        })?;
+        if !AttributeValue::TYPE_NAMES.contains(&type_name) {
+            return Err(ConversionError::ParseError(format!(
+                "Unknown attribute type: {}",
+                type_name
+            )));
+        }
+        Ok(self.hint(name, type_name))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hints.is_empty()
+    }
+}
+
+impl Step for TypeHints {
+    fn apply(&mut self, token: Token) -> Result<Option<Token>> {
+        let Token::Attribute { name, value } = token else {
+            return Ok(Some(token));
+        };
+        let value = match (self.hints.get(&name), value) {
+            (Some(type_name), AttributeValue::String(text)) => {
+                AttributeValue::parse_as(type_name, &text)?
+            }
+            (Some(type_name), AttributeValue::Interned(text)) => {
+                AttributeValue::parse_as(type_name, &text)?
+            }
+            (_, value) => value,
+        };
+        Ok(Some(Token::Attribute { name, value }))
+    }
+}
+
+/// Renames elements and attributes, leaving attribute values, their types and
+/// their interning untouched, e.g. to migrate a config file between app
+/// versions. [`Rename::renamed`] counts the tokens changed so far, and
+/// [`Rename::renamed_element`] and [`Rename::renamed_attribute`] tell which
+/// renames changed any.
+///
+/// ```
+/// use android_xml_converter::{AbxToXmlConverter, XmlToAbxConverter};
+/// use android_xml_converter::pipeline::{Pipeline, Rename, Step};
+/// use android_xml_converter::roundtrip::TokenReader;
+///
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string(r#"<prefs><entry key="a" on="true"/></prefs>"#, &mut abx).unwrap();
+///
+/// let rename = Rename::new()
+///     .parse_element("entry=pref")
+///     .unwrap()
+///     .parse_attribute("on=enabled")
+///     .unwrap();
+/// let mut renamed = Vec::new();
+/// Pipeline::new().step(rename).run(abx.as_slice(), &mut renamed).unwrap();
+///
+/// let xml = AbxToXmlConverter::convert_bytes(&renamed).unwrap();
+/// assert!(xml.ends_with(r#"<prefs><pref key="a" enabled="true"></pref></prefs>"#));
+///
+/// let mut rename = Rename::new().attribute("on", "enabled").attribute("off", "disabled");
+/// for token in TokenReader::new(abx.as_slice()).unwrap() {
+///     rename.apply(token.unwrap()).unwrap();
+/// }
+/// assert!(rename.renamed_attribute("on"));
+/// assert!(!rename.renamed_attribute("off"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Rename {
+    elements: FastHashMap<SmolStr, SmolStr>,
+    attributes: FastHashMap<SmolStr, SmolStr>,
+    renamed: usize,
+    /// Old names of the renames that changed a token
+    renamed_elements: FastHashSet<SmolStr>,
+    renamed_attributes: FastHashSet<SmolStr>,
+}
+
+impl Rename {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn element(mut self, old: &str, new: &str) -> Self {
+        self.elements.insert(SmolStr::new(old), SmolStr::new(new));
+        self
+    }
+
+    pub fn attribute(mut self, old: &str, new: &str) -> Self {
+        self.attributes.insert(SmolStr::new(old), SmolStr::new(new));
+        self
+    }
+
+    /// Parses an `OLD=NEW` element rename as given on the command line
+    pub fn parse_element(self, rename: &str) -> Result<Self> {
+        let (old, new) = Self::parse(rename)?;
+        Ok(self.element(old, new))
+    }
+
+    /// Parses an `OLD=NEW` attribute rename as given on the command line
+    pub fn parse_attribute(self, rename: &str) -> Result<Self> {
+        let (old, new) = Self::parse(rename)?;
+        Ok(self.attribute(old, new))
+    }
+
+    fn parse(rename: &str) -> Result<(&str, &str)> {
+        match rename.split_once('=') {
+            Some((old, new)) if !old.is_empty() && !new.is_empty() => Ok((old, new)),
+            _ => Err(ConversionError::ParseError(format!(
+                "Invalid rename, expected OLD=NEW: {}",
+                rename
+            ))),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty() && self.attributes.is_empty()
+    }
+
+    /// Number of start tags, end tags and attributes renamed
+    pub fn renamed(&self) -> usize {
+        self.renamed
+    }
+
+    /// Whether the element rename of `old` changed a tag so far
+    pub fn renamed_element(&self, old: &str) -> bool {
+        self.renamed_elements.contains(old)
+    }
+
+    /// Whether the attribute rename of `old` changed an attribute so far
+    pub fn renamed_attribute(&self, old: &str) -> bool {
+        self.renamed_attributes.contains(old)
+    }
+}
+
+impl Step for Rename {
+    fn apply(&mut self, token: Token) -> Result<Option<Token>> {
+        let (renamed, old) = match token {
+            Token::StartTag(name) => match self.elements.get(&name) {
+                Some(new) => (Token::StartTag(new.clone()), name),
+                None => return Ok(Some(Token::StartTag(name))),
+            },
+            Token::EndTag(name) => match self.elements.get(&name) {
+                Some(new) => (Token::EndTag(new.clone()), name),
+                None => return Ok(Some(Token::EndTag(name))),
+            },
+            Token::Attribute { name, value } => match self.attributes.get(&name) {
+                Some(new) => (
+                    Token::Attribute {
+                        name: new.clone(),
+                        value,
+                    },
+                    name,
+                ),
+                None => return Ok(Some(Token::Attribute { name, value })),
+            },
+            token => return Ok(Some(token)),
+        };
+        match renamed {
+            Token::Attribute { .. } => self.renamed_attributes.insert(old),
+            _ => self.renamed_elements.insert(old),
+        };
+        self.renamed += 1;
+        Ok(Some(renamed))
+    }
+}
+
+// ============================================================================
+// Pipeline
+// ============================================================================
+
+/// Streams an ABX document straight into a new ABX document through a chain of
+/// [`Step`]s, without an XML intermediate.
+///
+/// The output always gets a fresh intern pool in first-use order, and anything
+/// following END_DOCUMENT is dropped, so running an empty pipeline already
+/// normalizes a file.
+///
+/// ```
+/// use android_xml_converter::XmlToAbxConverter;
+/// use android_xml_converter::pipeline::{DropWhitespace, Pipeline, TypeHints};
+///
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string("<a>\n  <b uid=\"1000\"/>\n</a>", &mut abx).unwrap();
+///
+/// let mut normalized = Vec::new();
+/// Pipeline::new()
+///     .step(DropWhitespace)
+///     .step(TypeHints::new().hint("uid", "int"))
+///     .run(abx.as_slice(), &mut normalized)
+///     .unwrap();
+/// assert!(normalized.len() < abx.len());
+/// ```
+#[derive(Default)]
+pub struct Pipeline {
+    steps: Vec<Box<dyn Step>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a step; steps run in the order they were added
+    pub fn step<S: Step + 'static>(mut self, step: S) -> Self {
+        self.steps.push(Box::new(step));
+        self
+    }
+
+    /// Runs the pipeline into an ABX writer, returning the number of tokens written
+    pub fn run<R: Read, W: Write>(&mut self, reader: R, writer: W) -> Result<usize> {
+        self.run_into(reader, &mut AbxSink::new(writer)?)
+    }
+
+    /// Runs the pipeline into any [`Sink`], e.g. a [`Tee`] feeding several
+    /// consumers from a single decoding pass
+    pub fn run_into<R: Read, S: Sink + ?Sized>(
+        &mut self,
+        reader: R,
+        sink: &mut S,
+    ) -> Result<usize> {
+        self.run_results(TokenReader::new(reader)?, sink)
+    }
+
+    /// Runs the pipeline over tokens from any source, such as the events of a
+    /// [`RawDocument`](crate::roundtrip::RawDocument) or a [`TokenReader`], so
+    /// streaming steps and in-memory edits can be mixed.
+    ///
+    /// A [`RawDocument`](crate::roundtrip::RawDocument) is itself a [`Sink`] that
+    /// collects the tokens.
+    ///
+    /// ```
+    /// use android_xml_converter::pipeline::{DropWhitespace, Pipeline};
+    /// use android_xml_converter::reader::AttributeValue;
+    /// use android_xml_converter::roundtrip::RawDocument;
+    /// use android_xml_converter::xpath::XPath;
+    /// use android_xml_converter::XmlToAbxConverter;
+    ///
+    /// let mut abx = Vec::new();
+    /// XmlToAbxConverter::convert_from_string("<a>\n  <b/>\n</a>", &mut abx).unwrap();
+    /// let document = RawDocument::from_bytes(&abx).unwrap();
+    ///
+    /// let mut filtered = RawDocument::default();
+    /// Pipeline::new()
+    ///     .step(DropWhitespace)
+    ///     .run_tokens(document.into_events(), &mut filtered)
+    ///     .unwrap();
+    ///
+    /// let b = filtered.select_elements(&XPath::parse("b").unwrap())[0];
+    /// filtered.set_attribute(b, "x", AttributeValue::Int(1)).unwrap();
+    /// assert_eq!(filtered.events().count(), 7);
+    /// ```
+    pub fn run_tokens<I, S>(&mut self, tokens: I, sink: &mut S) -> Result<usize>
+    where
+        I: IntoIterator<Item = Token>,
+        S: Sink + ?Sized,
+    {
+        self.run_results(tokens.into_iter().map(Ok), sink)
+    }
+
+    fn run_results<I, S>(&mut self, tokens: I, sink: &mut S) -> Result<usize>
+    where
+        I: IntoIterator<Item = Result<Token>>,
+        S: Sink + ?Sized,
+    {
+        let mut written = 0;
+
+        'tokens: for token in tokens {
+            let mut token = token?;
+            for step in &mut self.steps {
+                match step.apply(token)? {
+                    Some(next) => token = next,
+                    None => continue 'tokens,
+                }
+            }
+
+            sink.token(&token)?;
+            written += 1;
+            if token == Token::EndDocument {
+                break;
+            }
+        }
+
+        sink.finish()?;
+        Ok(written)
+    }
+}
+
+// ============================================================================
+// Sinks
+// ============================================================================
+
+/// A consumer of decoded tokens
+pub trait Sink {
+    fn token(&mut self, token: &Token) -> Result<()>;
+
+    /// Called once after the last token
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&Token) -> Result<()>> Sink for F {
+    fn token(&mut self, token: &Token) -> Result<()> {
+        self(token)
+    }
+}
+
+/// Fans every token out to several sinks, so one decoding pass can e.g. write
+/// XML, collect statistics and build an index.
+///
+/// ```
+/// use android_xml_converter::XmlToAbxConverter;
+/// use android_xml_converter::pipeline::{Pipeline, SummarySink, Tee, XmlSink};
+///
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string("<a><b/><b/></a>", &mut abx).unwrap();
+///
+/// let mut xml = XmlSink::new(Vec::new());
+/// let mut summary = SummarySink::default();
+/// let mut tee = Tee::new().sink(&mut xml).sink(&mut summary);
+/// Pipeline::new().run_into(abx.as_slice(), &mut tee).unwrap();
+///
+/// assert!(xml.into_inner().ends_with(b"<a><b></b><b></b></a>"));
+/// assert_eq!(summary.into_summary().elements, 3);
+/// ```
+#[derive(Default)]
+pub struct Tee<'a> {
+    sinks: Vec<&'a mut dyn Sink>,
+}
+
+impl<'a> Tee<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sink(mut self, sink: &'a mut dyn Sink) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+}
+
+impl Sink for Tee<'_> {
+    fn token(&mut self, token: &Token) -> Result<()> {
+        for sink in &mut self.sinks {
+            sink.token(token)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        for sink in &mut self.sinks {
+            sink.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes tokens as ABX with a fresh intern pool
+pub struct AbxSink<W: Write> {
+    serializer: BinaryXmlSerializer<W>,
+}
+
+impl<W: Write> AbxSink<W> {
+    pub fn new(writer: W) -> Result<Self> {
+        Ok(Self {
+            serializer: BinaryXmlSerializer::new(writer)?,
+        })
+    }
+}
+
+impl<W: Write> Sink for AbxSink<W> {
+    fn token(&mut self, token: &Token) -> Result<()> {
+        match token {
+            Token::StartDocument => self.serializer.start_document(),
+            Token::EndDocument => self.serializer.end_document(),
+            Token::StartTag(name) => self.serializer.start_tag(name),
+            Token::EndTag(name) => self.serializer.end_tag(name),
+            Token::Attribute { name, value } => self.serializer.attribute_value(name, value),
+            Token::Content { command, text } => {
+                self.serializer.write_token(*command, text.as_deref())
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.serializer.flush()
+    }
+}
+
+/// Writes tokens as XML, producing the same output as [`AbxToXmlConverter`]
+pub struct XmlSink<W: Write> {
+    output: W,
+    /// A start tag was written and its `>` is still pending attributes
+    in_start_tag: bool,
+}
+
+impl<W: Write> XmlSink<W> {
+    pub fn new(mut output: W) -> Self {
+        // like the deserializer, the declaration is written before any token
+        let _ = output.write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+        Self {
+            output,
+            in_start_tag: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+
+    fn close_start_tag(&mut self) -> Result<()> {
+        if self.in_start_tag {
+            self.output.write_all(b">")?;
+            self.in_start_tag = false;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Sink for XmlSink<W> {
+    fn token(&mut self, token: &Token) -> Result<()> {
+        if let Token::Attribute { name, value } = token {
+            write!(self.output, " {}=\"", name)?;
+            match value {
+                AttributeValue::String(value) => self
+                    .output
+                    .write_all(encode_xml_entities(value).as_bytes())?,
+                AttributeValue::Interned(value) => self
+                    .output
+                    .write_all(encode_xml_entities(value).as_bytes())?,
+                value => write!(self.output, "{}", value)?,
+            }
+            self.output.write_all(b"\"")?;
+            return Ok(());
+        }
+
+        self.close_start_tag()?;
+        match token {
+            Token::StartDocument | Token::EndDocument | Token::Attribute { .. } => {}
+            Token::StartTag(name) => {
+                write!(self.output, "<{}", name)?;
+                self.in_start_tag = true;
+            }
+            Token::EndTag(name) => write!(self.output, "</{}>", name)?,
+            Token::Content { text: None, .. } => {}
+            Token::Content {
+                command,
+                text: Some(text),
+            } => match *command {
+                TEXT => self
+                    .output
+                    .write_all(encode_xml_entities(text).as_bytes())?,
+                CDSECT => write!(self.output, "<![CDATA[{}]]>", text)?,
+                COMMENT => write!(self.output, "<!--{}-->", text)?,
+                PROCESSING_INSTRUCTION => write!(self.output, "<?{}?>", text)?,
+                DOCDECL => write!(self.output, "<!DOCTYPE {}>", text)?,
+                ENTITY_REF => write!(self.output, "&{};", text)?,
+                _ => self.output.write_all(text.as_bytes())?,
+            },
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.close_start_tag()?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/// Collects a [`DocumentSummary`] from tokens
+#[derive(Debug, Default)]
+pub struct SummarySink {
+    summary: DocumentSummary,
+    depth: usize,
+}
+
+impl SummarySink {
+    pub fn into_summary(self) -> DocumentSummary {
+        self.summary
+    }
+}
+
+impl Sink for SummarySink {
+    fn token(&mut self, token: &Token) -> Result<()> {
+        match token {
+            Token::StartTag(name) => {
+                self.depth += 1;
+                self.summary.elements += 1;
+                self.summary.max_depth = self.summary.max_depth.max(self.depth);
+                *self.summary.tag_counts.entry(name.clone()).or_insert(0) += 1;
+            }
+            Token::EndTag(_) => self.depth = self.depth.saturating_sub(1),
+            Token::Attribute { name, .. } => {
+                self.summary.attribute_names.insert(name.clone());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,34 @@
+//! The converters, the reader and writer types, [`Options`] and its policies,
+//! the error and diagnostics types and format detection under names that stay
+//! put across minor versions. Modules are reorganized as the crate grows;
+//! anything moved out of a path named here or in the crate root keeps a
+//! deprecated re-export there until the next major version, so code that
+//! imports from the prelude is not broken by it.
+//!
+//! The prelude leaves out [`crate::Result`], which would shadow the standard
+//! one.
+//!
+//! ```
+//! use android_xml_converter::prelude::*;
+//!
+//! let mut abx = Vec::new();
+//! XmlToAbxConverter::convert_from_string(r#"<map><int name="a" value="1"/></map>"#, &mut abx).unwrap();
+//! assert_eq!(detect_format(&abx), Format::Abx);
+//!
+//! let xml = AbxToXmlConverter::convert_bytes(&abx).unwrap();
+//! assert!(xml.contains(r#"<int name="a" value="1""#));
+//! ```
+
+pub use crate::convert::{
+    AbxToXmlConverter, AbxWriter, XmlToAbxConverter, estimate_abx_size, estimate_abx_size_with,
+};
+pub use crate::diagnostics::{Diagnostic, Diagnostics, Severity};
+pub use crate::pull::{AbxEvent, AbxReader};
+pub use crate::query::TypedAttributes;
+pub use crate::reader::{AttributeValue, BinaryXmlDeserializer, DataInput};
+pub use crate::writer::{BinaryXmlSerializer, FastDataOutput};
+pub use crate::{
+    ConversionError, DuplicateAttributes, FloatFormat, Format, FormatVersion, InternPolicy,
+    InvalidUtf8, Limits, LongAttributes, NamePolicy, NamespaceStrategy, Options,
+    decompress_if_gzip, detect_format, is_abx_file,
+};
@@ -0,0 +1,148 @@
+use crate::*;
+use std::io::{self, Read};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+// ============================================================================
+// Privileged Reads
+// ============================================================================
+
+/// Reads files through an external command, by default `su -c cat`, so
+/// protected files such as /data/system/packages.xml on a rooted device can be
+/// converted without copying them to shared storage first.
+///
+/// The template is split on whitespace into a program and its arguments, with
+/// `{}` replaced by the path, or the path appended if there is no `{}`. No
+/// shell is involved on this side. The words after a `-c` argument are a
+/// command for the root shell `su` starts, so they are passed as a single
+/// argument with the path quoted for that shell; spaces, `;` or `$(...)` in a
+/// path stay part of the file name.
+///
+/// ```
+/// use android_xml_converter::privileged::ReadCommand;
+///
+/// let command = ReadCommand::from_template("su 0 cat {}").unwrap();
+/// assert_eq!(command.arguments("/data/system/packages.xml"), ["0", "cat", "/data/system/packages.xml"]);
+/// assert_eq!(ReadCommand::default().arguments("a.xml"), ["-c", "cat 'a.xml'"]);
+/// assert_eq!(
+///     ReadCommand::default().arguments("it's $(reboot).xml"),
+///     ["-c", r#"cat 'it'\''s $(reboot).xml'"#]
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadCommand {
+    program: String,
+    arguments: Vec<String>,
+}
+
+impl ReadCommand {
+    pub const DEFAULT_TEMPLATE: &'static str = "su -c cat";
+
+    pub fn from_template(template: &str) -> Result<Self> {
+        let mut words = template.split_whitespace().map(str::to_string);
+        let program = words.next().ok_or_else(|| {
+            ConversionError::ParseError("Empty read command template".to_string())
+        })?;
+        Ok(Self {
+            program,
+            arguments: words.collect(),
+        })
+    }
+
+    pub fn program(&self) -> &str {
+        &self.program
+    }
+
+    /// The arguments for reading `path`
+    pub fn arguments(&self, path: &str) -> Vec<String> {
+        let shell = self.arguments.iter().position(|argument| argument == "-c");
+        let path = match shell {
+            Some(_) => shell_quote(path),
+            None => path.to_string(),
+        };
+        let mut arguments: Vec<String> = self
+            .arguments
+            .iter()
+            .map(|argument| argument.replace("{}", &path))
+            .collect();
+        if !self
+            .arguments
+            .iter()
+            .any(|argument| argument.contains("{}"))
+        {
+            arguments.push(path);
+        }
+        if let Some(index) = shell {
+            let command = arguments.split_off(index + 1).join(" ");
+            arguments.push(command);
+        }
+        arguments
+    }
+
+    /// Starts the command for `path`; its output is read as it arrives
+    pub fn open(&self, path: &str) -> Result<CommandOutput> {
+        let mut child = Command::new(&self.program)
+            .args(self.arguments(path))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| {
+                ConversionError::ParseError(format!("Cannot run {}: {}", self.program, e))
+            })?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(CommandOutput {
+            child,
+            stdout,
+            description: format!("{} {}", self.program, self.arguments(path).join(" ")),
+            finished: false,
+        })
+    }
+}
+
+/// Quotes `text` as one word for a POSIX shell: in single quotes, with each
+/// single quote in it closed, escaped and reopened
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+impl Default for ReadCommand {
+    fn default() -> Self {
+        Self::from_template(Self::DEFAULT_TEMPLATE).expect("valid default template")
+    }
+}
+
+/// The output of a [`ReadCommand`]. The end of the output is only reported once
+/// the command exited successfully, so a denied `su` request or a missing file
+/// fails the conversion instead of looking like an empty document.
+pub struct CommandOutput {
+    child: Child,
+    stdout: ChildStdout,
+    description: String,
+    finished: bool,
+}
+
+impl Read for CommandOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() && !self.finished {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "`{}` failed with {}",
+                    self.description, status
+                )));
+            }
+            self.finished = true;
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for CommandOutput {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::*;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+// ============================================================================
+// Profiling
+// ============================================================================
+
+/// Wall-clock timings of consecutive conversion phases, for `--profile`
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    start: Instant,
+    last: Instant,
+    phases: Vec<(&'static str, Duration)>,
+    input_size: Option<u64>,
+    output_size: Option<u64>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last: now,
+            phases: Vec::new(),
+            input_size: None,
+            output_size: None,
+        }
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ends the current phase, recording the time since the previous one ended
+    pub fn phase(&mut self, name: &'static str) {
+        let now = Instant::now();
+        self.phases.push((name, now - self.last));
+        self.last = now;
+    }
+
+    /// Records the input and output sizes for throughput figures
+    pub fn sizes(&mut self, input: u64, output: u64) {
+        self.input_size = Some(input);
+        self.output_size = Some(output);
+    }
+
+    pub fn phases(&self) -> &[(&'static str, Duration)] {
+        &self.phases
+    }
+
+    pub fn write_to<W: Write>(&self, mut output: W) -> Result<()> {
+        let total = self.last - self.start;
+        writeln!(output, "profile:")?;
+        for (name, duration) in &self.phases {
+            writeln!(output, "  {:<10} {:>10.3} ms", name, millis(*duration))?;
+        }
+        writeln!(output, "  {:<10} {:>10.3} ms", "total", millis(total))?;
+
+        if let (Some(input), Some(written)) = (self.input_size, self.output_size) {
+            writeln!(output, "  input      {:>10} bytes", input)?;
+            writeln!(output, "  output     {:>10} bytes", written)?;
+            if let Some(&(_, convert)) = self.phases.iter().find(|(name, _)| *name == "convert")
+                && !convert.is_zero()
+            {
+                let mib_per_s = input as f64 / convert.as_secs_f64() / (1024.0 * 1024.0);
+                writeln!(output, "  throughput {:>10.1} MiB/s", mib_per_s)?;
+            }
+        }
+
+        match peak_rss() {
+            Some(bytes) => writeln!(output, "  peak rss   {:>10} KiB", bytes / 1024)?,
+            None => writeln!(output, "  peak rss   unavailable on this platform")?,
+        }
+        output.flush()?;
+        Ok(())
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Peak resident set size of this process in bytes, read from `/proc/self/status`
+/// on Linux and Android
+pub fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
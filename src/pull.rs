@@ -0,0 +1,203 @@
+use crate::query::TypedAttributes;
+use crate::reader::AttributeValue;
+use crate::roundtrip::{Token, TokenReader};
+use crate::*;
+use smol_str::SmolStr;
+use std::io::Read;
+
+// ============================================================================
+// Pull Parser
+// ============================================================================
+
+/// A structural event of an ABX document, with the attributes of an element
+/// gathered into its start event.
+///
+/// Start events have the [`TypedAttributes`] getters mirroring Android's
+/// `TypedXmlPullParser`, so AOSP parsing code ports call for call:
+///
+/// ```
+/// use android_xml_converter::XmlToAbxConverter;
+/// use android_xml_converter::pull::{AbxEvent, AbxReader};
+/// use android_xml_converter::query::TypedAttributes;
+///
+/// let xml = r#"<package name="com.example" userId="10003" ft="18a4f3c2b10" stopped="true" />"#;
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+///
+/// let mut reader = AbxReader::new(abx.as_slice()).unwrap();
+/// let package = reader
+///     .find(|event| matches!(event, Ok(AbxEvent::StartElement { .. })))
+///     .unwrap()
+///     .unwrap();
+/// assert_eq!(package.get_attribute_int("userId").unwrap(), 10003);
+/// assert_eq!(package.get_attribute_long_hex("ft").unwrap(), 0x18a4f3c2b10);
+/// assert!(package.get_attribute_boolean("stopped").unwrap());
+/// assert!(package.get_attribute_int("stopped").is_err());
+/// assert!(package.get_attribute_int("missing").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum AbxEvent {
+    StartDocument,
+    EndDocument,
+    StartElement {
+        name: SmolStr,
+        attributes: Vec<(SmolStr, AttributeValue)>,
+    },
+    EndElement {
+        name: SmolStr,
+    },
+    Text(String),
+    CData(String),
+    Comment(String),
+    ProcessingInstruction(String),
+    DocType(String),
+    EntityRef(String),
+    IgnorableWhitespace(String),
+}
+
+impl TypedAttributes for AbxEvent {
+    fn element_name(&self) -> Option<&str> {
+        match self {
+            AbxEvent::StartElement { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    fn attributes(&self) -> &[(SmolStr, AttributeValue)] {
+        match self {
+            AbxEvent::StartElement { attributes, .. } => attributes,
+            _ => &[],
+        }
+    }
+}
+
+/// Reads an ABX document one [`AbxEvent`] at a time, like quick_xml's `Reader`
+/// does for text XML, without going through an XML rendering.
+///
+/// Content tokens written with a null string yield empty text.
+///
+/// ```
+/// use android_xml_converter::XmlToAbxConverter;
+/// use android_xml_converter::pull::{AbxEvent, AbxReader};
+/// use android_xml_converter::query::TypedAttributes;
+///
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string(r#"<map><int name="a" value="1" />hi<!--c--></map>"#, &mut abx)
+///     .unwrap();
+///
+/// let mut reader = AbxReader::new(abx.as_slice()).unwrap();
+/// let mut names = Vec::new();
+/// while let Some(event) = reader.next_event().unwrap() {
+///     match event {
+///         AbxEvent::StartElement { ref name, .. } if name == "int" => {
+///             names.push(event.attribute("name").unwrap().to_string());
+///         }
+///         AbxEvent::Text(text) => assert_eq!(text, "hi"),
+///         AbxEvent::Comment(comment) => assert_eq!(comment, "c"),
+///         _ => {}
+///     }
+/// }
+/// assert_eq!(names, ["a"]);
+/// ```
+pub struct AbxReader<R: Read> {
+    tokens: TokenReader<R>,
+    /// Token read past the attributes of a start tag
+    pending: Option<Token>,
+    finished: bool,
+}
+
+impl<R: Read> AbxReader<R> {
+    pub fn new(reader: R) -> Result<Self> {
+        Ok(Self {
+            tokens: TokenReader::new(reader)?,
+            pending: None,
+            finished: false,
+        })
+    }
+
+    /// Reads the next event, or `None` after END_DOCUMENT or at the end of the stream
+    pub fn next_event(&mut self) -> Result<Option<AbxEvent>> {
+        if self.finished {
+            return Ok(None);
+        }
+        let token = match self.pending.take() {
+            Some(token) => token,
+            None => match self.tokens.next_token()? {
+                Some(token) => token,
+                None => {
+                    self.finished = true;
+                    return Ok(None);
+                }
+            },
+        };
+
+        let event = match token {
+            Token::StartDocument => AbxEvent::StartDocument,
+            Token::EndDocument => {
+                self.finished = true;
+                AbxEvent::EndDocument
+            }
+            Token::StartTag(name) => {
+                let mut attributes = Vec::new();
+                while let Some(token) = self.tokens.next_token()? {
+                    match token {
+                        Token::Attribute { name, value } => attributes.push((name, value)),
+                        token => {
+                            self.pending = Some(token);
+                            break;
+                        }
+                    }
+                }
+                AbxEvent::StartElement { name, attributes }
+            }
+            Token::EndTag(name) => AbxEvent::EndElement { name },
+            Token::Attribute { name, .. } => {
+                return Err(ConversionError::ParseError(format!(
+                    "Attribute {} outside of a start tag",
+                    name
+                )));
+            }
+            Token::Content {
+                command: TEXT,
+                text,
+            } => {
+                // long text is split across consecutive TEXT tokens
+                let mut text = text.unwrap_or_default();
+                while let Some(token) = self.tokens.next_token()? {
+                    match token {
+                        Token::Content {
+                            command: TEXT,
+                            text: more,
+                        } => text.push_str(&more.unwrap_or_default()),
+                        token => {
+                            self.pending = Some(token);
+                            break;
+                        }
+                    }
+                }
+                AbxEvent::Text(text)
+            }
+            Token::Content { command, text } => {
+                let text = text.unwrap_or_default();
+                match command {
+                    CDSECT => AbxEvent::CData(text),
+                    COMMENT => AbxEvent::Comment(text),
+                    PROCESSING_INSTRUCTION => AbxEvent::ProcessingInstruction(text),
+                    DOCDECL => AbxEvent::DocType(text),
+                    ENTITY_REF => AbxEvent::EntityRef(text),
+                    _ => AbxEvent::IgnorableWhitespace(text),
+                }
+            }
+        };
+        Ok(Some(event))
+    }
+}
+
+/// Yields events until the end of the document or the first error
+impl<R: Read> Iterator for AbxReader<R> {
+    type Item = Result<AbxEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().transpose()
+    }
+}
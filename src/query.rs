@@ -0,0 +1,552 @@
+use crate::reader::{AttributeValue, DataInput, check_magic};
+use crate::*;
+use smol_str::SmolStr;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
+
+// ============================================================================
+// Element Queries
+// ============================================================================
+
+/// An open element seen while scanning, with its decoded attributes
+#[derive(Debug, Clone)]
+pub struct ElementFrame {
+    pub name: SmolStr,
+    pub attributes: Vec<(SmolStr, AttributeValue)>,
+}
+
+/// Typed attribute getters mirroring Android's `TypedXmlPullParser`, shared by
+/// [`ElementFrame`] and the start events of [`crate::pull::AbxReader`].
+///
+/// The stored ABX type is used when it fits and string values are parsed
+/// otherwise, so ported Java code maps one-to-one. A missing attribute or an
+/// incompatible stored type is an error naming the attribute and the element;
+/// `.unwrap_or(default)` gives the behavior of the Java overloads taking a default.
+/// The `attr_*` methods are short names for the same getters.
+///
+/// ```
+/// use android_xml_converter::query::{ElementScanner, TypedAttributes};
+/// use android_xml_converter::XmlToAbxConverter;
+///
+/// let mut abx = Vec::new();
+/// let xml = r#"<package name="com.foo" userId="10042" ft="18a2b3c4d5e" stopped="true"/>"#;
+/// XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+///
+/// ElementScanner::new(abx.as_slice()).unwrap().scan(|stack| {
+///     let package = &stack[stack.len() - 1];
+///     assert_eq!(package.attr_i32("userId")?, 10042);
+///     assert_eq!(package.attr_i64_hex("ft")?, 0x18a2b3c4d5e);
+///     assert!(package.attr_bool("stopped")?);
+///     assert_eq!(package.attr_i32("uidError").unwrap_or(-1), -1);
+///     assert_eq!(
+///         package.attr_i32("uidError").unwrap_err().to_string(),
+///         "Parse error: Missing attribute uidError on <package>"
+///     );
+///     Ok(())
+/// }).unwrap();
+/// ```
+pub trait TypedAttributes {
+    /// Name of the element the attributes belong to, `None` outside of a start tag
+    fn element_name(&self) -> Option<&str>;
+
+    /// The attributes in document order
+    fn attributes(&self) -> &[(SmolStr, AttributeValue)];
+
+    /// The attribute named `name`
+    fn attribute(&self, name: &str) -> Option<&AttributeValue> {
+        self.attributes()
+            .iter()
+            .find(|(attr_name, _)| attr_name.as_str() == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Looks up a required attribute and reads it with `read`, naming the
+    /// attribute and element in errors
+    fn typed<T>(&self, name: &str, read: impl FnOnce(&AttributeValue) -> Result<T>) -> Result<T> {
+        let Some(element) = self.element_name() else {
+            return Err(ConversionError::ParseError(format!(
+                "Attribute {} requested outside of a start tag",
+                name
+            )));
+        };
+        let value = self.attribute(name).ok_or_else(|| {
+            ConversionError::ParseError(format!("Missing attribute {} on <{}>", name, element))
+        })?;
+        read(value).map_err(|e| match e {
+            ConversionError::ParseError(message) => ConversionError::ParseError(format!(
+                "Attribute {} on <{}>: {}",
+                name, element, message
+            )),
+            e => e,
+        })
+    }
+
+    /// `getAttributeValue`: the attribute as text, whatever its stored type
+    fn get_attribute_value(&self, name: &str) -> Result<String> {
+        self.typed(name, |value| Ok(value.to_string()))
+    }
+
+    /// `getAttributeInt`: stored ints, or strings parsed as decimal; other stored
+    /// types are an error
+    fn get_attribute_int(&self, name: &str) -> Result<i32> {
+        self.typed(name, AttributeValue::to_i32)
+    }
+
+    fn get_attribute_int_hex(&self, name: &str) -> Result<i32> {
+        self.typed(name, AttributeValue::to_i32_hex)
+    }
+
+    fn get_attribute_long(&self, name: &str) -> Result<i64> {
+        self.typed(name, AttributeValue::to_i64)
+    }
+
+    fn get_attribute_long_hex(&self, name: &str) -> Result<i64> {
+        self.typed(name, AttributeValue::to_i64_hex)
+    }
+
+    fn get_attribute_float(&self, name: &str) -> Result<f32> {
+        self.typed(name, AttributeValue::to_f32)
+    }
+
+    fn get_attribute_double(&self, name: &str) -> Result<f64> {
+        self.typed(name, AttributeValue::to_f64)
+    }
+
+    fn get_attribute_boolean(&self, name: &str) -> Result<bool> {
+        self.typed(name, AttributeValue::to_bool)
+    }
+
+    /// Stored bytes of either encoding, strings decoded as hex like
+    /// `getAttributeBytesHex`
+    fn get_attribute_bytes(&self, name: &str) -> Result<Vec<u8>> {
+        self.get_attribute_bytes_hex(name)
+    }
+
+    fn get_attribute_bytes_hex(&self, name: &str) -> Result<Vec<u8>> {
+        self.typed(name, AttributeValue::to_bytes_hex)
+    }
+
+    fn get_attribute_bytes_base64(&self, name: &str) -> Result<Vec<u8>> {
+        self.typed(name, AttributeValue::to_bytes_base64)
+    }
+
+    fn attr_str(&self, name: &str) -> Result<String> {
+        self.get_attribute_value(name)
+    }
+
+    fn attr_i32(&self, name: &str) -> Result<i32> {
+        self.get_attribute_int(name)
+    }
+
+    fn attr_i32_hex(&self, name: &str) -> Result<i32> {
+        self.get_attribute_int_hex(name)
+    }
+
+    fn attr_i64(&self, name: &str) -> Result<i64> {
+        self.get_attribute_long(name)
+    }
+
+    fn attr_i64_hex(&self, name: &str) -> Result<i64> {
+        self.get_attribute_long_hex(name)
+    }
+
+    fn attr_f32(&self, name: &str) -> Result<f32> {
+        self.get_attribute_float(name)
+    }
+
+    fn attr_f64(&self, name: &str) -> Result<f64> {
+        self.get_attribute_double(name)
+    }
+
+    fn attr_bool(&self, name: &str) -> Result<bool> {
+        self.get_attribute_boolean(name)
+    }
+
+    fn attr_bytes(&self, name: &str) -> Result<Vec<u8>> {
+        self.get_attribute_bytes(name)
+    }
+
+    fn attr_bytes_base64(&self, name: &str) -> Result<Vec<u8>> {
+        self.get_attribute_bytes_base64(name)
+    }
+}
+
+impl TypedAttributes for ElementFrame {
+    fn element_name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+
+    fn attributes(&self) -> &[(SmolStr, AttributeValue)] {
+        &self.attributes
+    }
+}
+
+/// Walks an ABX document element by element without producing any output.
+///
+/// Text, comments and other non-element tokens are skipped.
+pub struct ElementScanner<R: Read> {
+    input: DataInput<R>,
+    stack: Vec<ElementFrame>,
+}
+
+impl<R: Read> ElementScanner<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        check_magic(&mut reader)?;
+
+        Ok(Self {
+            input: DataInput::new(reader),
+            stack: Vec::new(),
+        })
+    }
+
+    /// Calls `on_element` for every start tag with the stack of open elements,
+    /// innermost last.
+    pub fn scan<F>(&mut self, mut on_element: F) -> Result<()>
+    where
+        F: FnMut(&[ElementFrame]) -> Result<()>,
+    {
+        loop {
+            let token = match self.input.read_byte() {
+                Ok(token) => token,
+                Err(ConversionError::ReadError(_)) => break,
+                Err(e) => return Err(e),
+            };
+            let command = token & 0x0F;
+            let type_info = token & 0xF0;
+
+            match command {
+                START_DOCUMENT => {}
+                END_DOCUMENT => break,
+                START_TAG => {
+                    let name = self.input.read_interned_utf()?;
+                    let mut attributes = Vec::new();
+                    while let Ok(next_token) = self.input.peek_byte() {
+                        if (next_token & 0x0F) != ATTRIBUTE {
+                            break;
+                        }
+                        let _ = self.input.read_byte()?;
+                        let attr_name = self.input.read_interned_utf()?;
+                        let value = self.input.read_attribute_value(next_token & 0xF0)?;
+                        attributes.push((attr_name, value));
+                    }
+                    self.stack.push(ElementFrame { name, attributes });
+                    on_element(&self.stack)?;
+                }
+                END_TAG => {
+                    self.input.read_interned_utf()?;
+                    self.stack.pop();
+                }
+                _ => {
+                    if type_info == TYPE_STRING {
+                        self.input.read_utf()?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Per-tag counts and attribute names collected in a single pass
+#[derive(Debug, Default)]
+pub struct DocumentSummary {
+    pub elements: usize,
+    pub max_depth: usize,
+    pub tag_counts: BTreeMap<SmolStr, usize>,
+    pub attribute_names: BTreeSet<SmolStr>,
+}
+
+impl DocumentSummary {
+    pub fn collect<R: Read>(reader: R) -> Result<Self> {
+        let mut summary = Self::default();
+        ElementScanner::new(reader)?.scan(|stack| {
+            let frame = &stack[stack.len() - 1];
+            summary.elements += 1;
+            summary.max_depth = summary.max_depth.max(stack.len());
+            *summary.tag_counts.entry(frame.name.clone()).or_insert(0) += 1;
+            for (name, _) in &frame.attributes {
+                summary.attribute_names.insert(name.clone());
+            }
+            Ok(())
+        })?;
+        Ok(summary)
+    }
+
+    pub fn write_to<W: Write>(&self, mut output: W) -> Result<()> {
+        writeln!(output, "elements: {}", self.elements)?;
+        writeln!(output, "max depth: {}", self.max_depth)?;
+        writeln!(output, "tags ({}):", self.tag_counts.len())?;
+        let mut tags: Vec<_> = self.tag_counts.iter().collect();
+        tags.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        for (name, count) in tags {
+            writeln!(output, "  {:>8}  {}", count, name)?;
+        }
+        writeln!(output, "attributes ({}):", self.attribute_names.len())?;
+        for name in &self.attribute_names {
+            writeln!(output, "  {}", name)?;
+        }
+        output.flush()?;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Intern Pool Verification
+// ============================================================================
+
+/// Findings about the interned string pool of an ABX document
+#[derive(Debug, Default)]
+pub struct PoolReport {
+    /// All pool entries in definition order
+    pub entries: Vec<SmolStr>,
+    /// Number of back-references to each entry, indexed like `entries`
+    pub references: Vec<usize>,
+    /// Strings that were defined more than once, with all of their indices
+    pub duplicates: Vec<(SmolStr, Vec<u16>)>,
+    /// Back-references pointing past the end of the pool, with the token number they occurred in
+    pub invalid_indices: Vec<(u16, usize)>,
+    /// Set when more strings were defined than a u16 index can address
+    pub overflowed: bool,
+    /// Set when the input ended before END_DOCUMENT, so the pool may be incomplete
+    pub missing_end_document: bool,
+}
+
+impl PoolReport {
+    pub fn collect<R: Read>(mut reader: R) -> Result<Self> {
+        check_magic(&mut reader)?;
+        let mut input = DataInput::new(reader);
+        let mut report = Self::default();
+        let mut token_index = 0usize;
+
+        loop {
+            let token = match input.read_byte() {
+                Ok(token) => token,
+                Err(ConversionError::ReadError(_)) => {
+                    report.missing_end_document = true;
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            token_index += 1;
+            let command = token & 0x0F;
+            let type_info = token & 0xF0;
+
+            match command {
+                START_DOCUMENT => {}
+                END_DOCUMENT => break,
+                START_TAG | END_TAG => report.read_interned(&mut input, token_index)?,
+                ATTRIBUTE => {
+                    report.read_interned(&mut input, token_index)?;
+                    if type_info == TYPE_STRING_INTERNED {
+                        report.read_interned(&mut input, token_index)?;
+                    } else {
+                        input.read_attribute_value(type_info)?;
+                    }
+                }
+                _ => {
+                    if type_info == TYPE_STRING {
+                        input.read_utf()?;
+                    }
+                }
+            }
+        }
+
+        let mut seen: FastHashMap<&SmolStr, Vec<u16>> = FastHashMap::default();
+        // entries past the addressable range have no index
+        for (index, entry) in (0..INTERNED_STRING_NEW_MARKER).zip(&report.entries) {
+            seen.entry(entry).or_default().push(index);
+        }
+        let mut duplicates: Vec<_> = seen
+            .into_iter()
+            .filter(|(_, indices)| indices.len() > 1)
+            .map(|(entry, indices)| (entry.clone(), indices))
+            .collect();
+        duplicates.sort_by_key(|(_, indices)| indices[0]);
+        report.duplicates = duplicates;
+
+        Ok(report)
+    }
+
+    fn read_interned<R: Read>(
+        &mut self,
+        input: &mut DataInput<R>,
+        token_index: usize,
+    ) -> Result<()> {
+        let index = input.read_short()?;
+        if index == INTERNED_STRING_NEW_MARKER {
+            let string = input.read_utf()?;
+            if self.entries.len() >= INTERNED_STRING_NEW_MARKER as usize {
+                self.overflowed = true;
+            }
+            self.entries.push(SmolStr::new(string));
+            self.references.push(0);
+        } else if let Some(count) = self.references.get_mut(index as usize) {
+            *count += 1;
+        } else {
+            self.invalid_indices.push((index, token_index));
+        }
+        Ok(())
+    }
+
+    /// Entries that were defined but never referenced again by index
+    pub fn unreferenced(&self) -> impl Iterator<Item = (usize, &SmolStr)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| self.references[*index] == 0)
+    }
+
+    /// Whether the pool has problems a correct serializer would never produce
+    pub fn has_errors(&self) -> bool {
+        !self.duplicates.is_empty()
+            || !self.invalid_indices.is_empty()
+            || self.overflowed
+            || self.missing_end_document
+    }
+
+    pub fn write_to<W: Write>(&self, mut output: W) -> Result<()> {
+        writeln!(output, "pool entries: {}", self.entries.len())?;
+        writeln!(
+            output,
+            "back-references: {}",
+            self.references.iter().sum::<usize>()
+        )?;
+
+        writeln!(output, "duplicate entries: {}", self.duplicates.len())?;
+        for (entry, indices) in &self.duplicates {
+            writeln!(output, "  {:?} defined at indices {:?}", entry, indices)?;
+        }
+
+        writeln!(output, "invalid indices: {}", self.invalid_indices.len())?;
+        for (index, token_index) in &self.invalid_indices {
+            writeln!(
+                output,
+                "  index {} in token #{} (pool had {} entries)",
+                index,
+                token_index,
+                self.entries.len()
+            )?;
+        }
+
+        if self.overflowed {
+            writeln!(
+                output,
+                "pool overflow: more than {} entries, indices are ambiguous",
+                INTERNED_STRING_NEW_MARKER
+            )?;
+        }
+
+        if self.missing_end_document {
+            writeln!(output, "missing END_DOCUMENT: the input is truncated")?;
+        }
+
+        let unreferenced: Vec<_> = self.unreferenced().collect();
+        writeln!(output, "never referenced again: {}", unreferenced.len())?;
+        for (index, entry) in unreferenced {
+            writeln!(output, "  #{} {:?}", index, entry)?;
+        }
+
+        output.flush()?;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Pool Comparison
+// ============================================================================
+
+/// Differences between the intern pool of a document and a pool snapshot, for
+/// checking that two tools assign the same string to every index
+#[derive(Debug, Default)]
+pub struct PoolComparison {
+    pub snapshot_entries: usize,
+    pub document_entries: usize,
+    /// Indices holding different strings, with the snapshot's and the document's
+    pub mismatches: Vec<(usize, SmolStr, SmolStr)>,
+}
+
+impl PoolComparison {
+    pub fn new(snapshot: &[SmolStr], document: &[SmolStr]) -> Self {
+        Self {
+            snapshot_entries: snapshot.len(),
+            document_entries: document.len(),
+            mismatches: snapshot
+                .iter()
+                .zip(document)
+                .enumerate()
+                .filter(|(_, (expected, found))| expected != found)
+                .map(|(index, (expected, found))| (index, expected.clone(), found.clone()))
+                .collect(),
+        }
+    }
+
+    /// Whether every index both pools define holds the same string; either pool
+    /// may define more entries than the other
+    pub fn is_compatible(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    /// Whether the pools are the same
+    pub fn is_identical(&self) -> bool {
+        self.is_compatible() && self.snapshot_entries == self.document_entries
+    }
+
+    pub fn write_to<W: Write>(&self, mut output: W) -> Result<()> {
+        writeln!(output, "snapshot entries: {}", self.snapshot_entries)?;
+        writeln!(output, "document entries: {}", self.document_entries)?;
+        writeln!(output, "mismatched indices: {}", self.mismatches.len())?;
+        for (index, expected, found) in &self.mismatches {
+            writeln!(
+                output,
+                "  #{} expected {:?}, found {:?}",
+                index, expected, found
+            )?;
+        }
+        if self.snapshot_entries > self.document_entries {
+            writeln!(
+                output,
+                "only in snapshot: {}",
+                self.snapshot_entries - self.document_entries
+            )?;
+        } else if self.document_entries > self.snapshot_entries {
+            writeln!(
+                output,
+                "only in document: {}",
+                self.document_entries - self.snapshot_entries
+            )?;
+        }
+
+        output.flush()?;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Root Element
+// ============================================================================
+
+/// Returns the name of the first element of an ABX document without reading past it
+pub fn root_element<R: Read>(mut reader: R) -> Result<Option<SmolStr>> {
+    check_magic(&mut reader)?;
+    let mut input = DataInput::new(reader);
+
+    loop {
+        let token = match input.read_byte() {
+            Ok(token) => token,
+            Err(ConversionError::ReadError(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        match token & 0x0F {
+            START_DOCUMENT => {}
+            START_TAG => return input.read_interned_utf().map(Some),
+            END_DOCUMENT => return Ok(None),
+            _ => {
+                if token & 0xF0 == TYPE_STRING {
+                    input.read_utf()?;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1790 @@
+use crate::diagnostics::{Diagnostics, Location};
+use crate::query::ElementFrame;
+use crate::schema::{TYPE_HINT_ATTRIBUTE, TYPE_HINT_NAMESPACE, hint_type_name};
+use crate::*;
+use smol_str::SmolStr;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::ops::ControlFlow;
+
+// ============================================================================
+// Data Input Reader
+// ============================================================================
+
+pub struct DataInput<R: Read> {
+    reader: R,
+    interned_strings: Vec<SmolStr>,
+    /// Strings in the pool before the document defined any, see [`Self::seed`]
+    seeded: usize,
+    /// Bytes taken from `reader` but not consumed yet: a peeked byte, or the
+    /// payload of a length that exceeded the input, see [`Self::read_bytes`]
+    unread: VecDeque<u8>,
+    /// Bytes taken from `reader`, including unread ones
+    position: u64,
+    /// Strings are modified UTF-8, see [`Options::modified_utf8`]
+    modified_utf8: bool,
+    invalid_utf8: InvalidUtf8,
+    limits: Limits,
+}
+
+impl<R: Read> DataInput<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            interned_strings: Vec::with_capacity(INITIAL_STRING_POOL_CAPACITY),
+            seeded: 0,
+            unread: VecDeque::new(),
+            position: 0,
+            modified_utf8: false,
+            invalid_utf8: InvalidUtf8::Error,
+            limits: Limits::new(),
+        }
+    }
+
+    /// Creates an input configured from [`Options`]
+    pub fn from_options(reader: R, options: &Options) -> Self {
+        #[cfg(feature = "android-dictionary")]
+        let mut input = if options.android_dictionary {
+            Self::with_android_dictionary(reader)
+        } else {
+            Self::new(reader)
+        };
+        #[cfg(not(feature = "android-dictionary"))]
+        let mut input = Self::new(reader);
+
+        if let Some(dictionary) = &options.dictionary {
+            input.seed(dictionary.strings());
+        }
+        input.set_modified_utf8(options.modified_utf8);
+        input.set_invalid_utf8(options.invalid_utf8);
+        input.set_limits(options.limits);
+        input
+    }
+
+    /// Reads strings as modified UTF-8 from now on, see [`Options::modified_utf8`]
+    pub fn set_modified_utf8(&mut self, modified_utf8: bool) {
+        self.modified_utf8 = modified_utf8;
+    }
+
+    /// Handles strings that are not valid UTF-8 this way from now on
+    pub fn set_invalid_utf8(&mut self, invalid_utf8: InvalidUtf8) {
+        self.invalid_utf8 = invalid_utf8;
+    }
+
+    /// Enforces the string and intern pool limits from now on
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    fn check_string_length(&self, length: u16) -> Result<()> {
+        match self.limits.max_string_length {
+            Some(max) if length as usize > max => Err(ConversionError::LimitExceeded(format!(
+                "string of {} bytes, more than {}",
+                length, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Appends strings to the pool as if they had already been defined
+    pub fn seed(&mut self, strings: &[SmolStr]) {
+        self.interned_strings.extend_from_slice(strings);
+        self.seeded = self.interned_strings.len();
+    }
+
+    /// Creates an input whose pool starts with [`crate::dictionary::ANDROID_NAMES`]
+    #[cfg(feature = "android-dictionary")]
+    pub fn with_android_dictionary(reader: R) -> Self {
+        let mut input = Self::new(reader);
+        input.interned_strings.extend(
+            crate::dictionary::ANDROID_NAMES
+                .iter()
+                .map(|name| SmolStr::new_static(name)),
+        );
+        input.seeded = input.interned_strings.len();
+        input
+    }
+
+    /// Number of bytes consumed so far, not counting unread ones
+    pub fn position(&self) -> u64 {
+        self.position - self.unread.len() as u64
+    }
+
+    /// Fills `buf`, starting with the unread bytes
+    fn read_into(&mut self, buf: &mut [u8], what: &str) -> Result<()> {
+        let start_idx = self.unread.read(buf)?;
+        self.reader
+            .read_exact(&mut buf[start_idx..])
+            .map_err(|e| match e.kind() {
+                // a stalled source, see `timeout::IdleTimeoutReader`
+                io::ErrorKind::TimedOut => ConversionError::Io(e),
+                _ => ConversionError::ReadError(what.to_string()),
+            })?;
+        self.position += (buf.len() - start_idx) as u64;
+        Ok(())
+    }
+
+    pub fn read_byte(&mut self) -> Result<u8> {
+        let mut buf = [0u8; 1];
+        self.read_into(&mut buf, "byte")?;
+        Ok(buf[0])
+    }
+
+    pub fn peek_byte(&mut self) -> Result<u8> {
+        if let Some(&byte) = self.unread.front() {
+            return Ok(byte);
+        }
+        let byte = self.read_byte()?;
+        self.unread.push_back(byte);
+        Ok(byte)
+    }
+
+    pub fn read_short(&mut self) -> Result<u16> {
+        let mut buf = [0u8; 2];
+        self.read_into(&mut buf, "short")?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    pub fn read_int(&mut self) -> Result<i32> {
+        let mut buf = [0u8; 4];
+        self.read_into(&mut buf, "int")?;
+        Ok(i32::from_be_bytes(buf))
+    }
+
+    pub fn read_long(&mut self) -> Result<i64> {
+        let mut buf = [0u8; 8];
+        self.read_into(&mut buf, "long")?;
+        Ok(i64::from_be_bytes(buf))
+    }
+
+    pub fn read_float(&mut self) -> Result<f32> {
+        let int_value = self.read_int()? as u32;
+        Ok(f32::from_bits(int_value))
+    }
+
+    pub fn read_double(&mut self) -> Result<f64> {
+        let int_value = self.read_long()? as u64;
+        Ok(f64::from_bits(int_value))
+    }
+
+    pub fn read_utf(&mut self) -> Result<String> {
+        let length = self.read_short()?;
+        let buffer = self.read_bytes(length)?;
+        // NUL as C0 80 and surrogates are invalid UTF-8, so strings Android wrote
+        // as modified UTF-8 are recognized without the option
+        let buffer = if self.modified_utf8 {
+            buffer
+        } else {
+            match String::from_utf8(buffer) {
+                Ok(text) => return Ok(text),
+                Err(e) => e.into_bytes(),
+            }
+        };
+        let error = match mutf8::decode(&buffer) {
+            Ok(text) => return Ok(text),
+            Err(_) if !self.modified_utf8 => {
+                ConversionError::ReadError("UTF string (invalid UTF-8)".to_string())
+            }
+            Err(e) => e,
+        };
+        match self.invalid_utf8 {
+            InvalidUtf8::Error => Err(error),
+            InvalidUtf8::Lossy => Ok(String::from_utf8_lossy(&buffer).into_owned()),
+            InvalidUtf8::Escape => Ok(decode_escaping_bytes(&buffer)),
+        }
+    }
+
+    pub fn read_interned_utf(&mut self) -> Result<SmolStr> {
+        let index = self.read_interned_index()?;
+        self.interned_string(index).cloned()
+    }
+
+    /// Reads a reference to an interned string and returns its pool index, adding
+    /// the string to the pool first if it is defined here. The index is not
+    /// checked; see [`Self::interned_string`]. Defining more strings than a
+    /// `u16` index can address fails with [`ConversionError::InternPoolFull`].
+    ///
+    /// ```
+    /// use android_xml_converter::reader::DataInput;
+    /// use android_xml_converter::ConversionError;
+    ///
+    /// // 65,536 definitions of the empty string
+    /// let data = [0xFF, 0xFF, 0x00, 0x00].repeat(1 << 16);
+    /// let mut input = DataInput::new(&data[..]);
+    /// for index in 0..u16::MAX {
+    ///     assert_eq!(input.read_interned_index().unwrap(), index);
+    /// }
+    /// assert!(matches!(
+    ///     input.read_interned_index(),
+    ///     Err(ConversionError::InternPoolFull(65535))
+    /// ));
+    /// ```
+    pub fn read_interned_index(&mut self) -> Result<u16> {
+        let index = self.read_short()?;
+        self.resolve_interned_index(index)
+    }
+
+    /// [`Self::read_interned_index`] for an index that was already read
+    pub(crate) fn resolve_interned_index(&mut self, index: u16) -> Result<u16> {
+        if index != INTERNED_STRING_NEW_MARKER {
+            return Ok(index);
+        }
+        if let Some(max) = self.limits.max_interned_strings
+            && self.interned_strings.len() >= max
+        {
+            return Err(ConversionError::LimitExceeded(format!(
+                "more than {} interned strings",
+                max
+            )));
+        }
+        let index = u16::try_from(self.interned_strings.len())
+            .ok()
+            .filter(|&index| index != INTERNED_STRING_NEW_MARKER)
+            .ok_or(ConversionError::InternPoolFull(
+                INTERNED_STRING_NEW_MARKER as usize,
+            ))?;
+        // a definition that cannot be read still takes its pool index, so that
+        // lenient decoding resumes with later indices pointing at the right strings
+        let string = self.read_utf().inspect_err(|_| {
+            self.interned_strings.push(SmolStr::default());
+        })?;
+        self.interned_strings.push(SmolStr::new(string));
+        Ok(index)
+    }
+
+    pub fn interned_string(&self, index: u16) -> Result<&SmolStr> {
+        self.interned_strings
+            .get(index as usize)
+            .ok_or(ConversionError::InvalidInternedStringIndex(index))
+    }
+
+    /// Drops the strings a document defined, keeping the seeded ones, for the
+    /// next document of the same stream
+    pub fn reset_pool(&mut self) {
+        self.interned_strings.truncate(self.seeded);
+    }
+
+    /// Strings interned so far, in definition order
+    pub fn interned_strings(&self) -> &[SmolStr] {
+        &self.interned_strings
+    }
+
+    /// Reads everything left in the stream, including unread bytes
+    pub fn read_remaining(&mut self) -> Result<Vec<u8>> {
+        let mut data: Vec<u8> = self.unread.drain(..).collect();
+        self.position += self.reader.read_to_end(&mut data)? as u64;
+        Ok(data)
+    }
+
+    /// Reads a length-prefixed payload, failing with
+    /// [`ConversionError::LengthExceedsInput`] if fewer than `length` bytes are
+    /// left and with [`ConversionError::LimitExceeded`] if `length` is over
+    /// [`Limits::max_string_length`].
+    ///
+    /// The buffer only grows as data actually arrives, so a corrupt length never
+    /// allocates more than the input holds. On failure nothing is consumed: the
+    /// bytes that were there are read again next, so lenient mode can look for
+    /// the next element in them. Offsets in the error count the 4-byte magic
+    /// preceding the token stream.
+    ///
+    /// ```
+    /// use android_xml_converter::reader::DataInput;
+    /// use android_xml_converter::ConversionError;
+    ///
+    /// let mut input = DataInput::new(&[0x01, 0x00, 0xAB, 0xCD][..]);
+    /// let length = input.read_short().unwrap();
+    /// assert!(matches!(
+    ///     input.read_bytes(length),
+    ///     Err(ConversionError::LengthExceedsInput { declared: 256, remaining: 2, offset: 6 })
+    /// ));
+    /// assert_eq!(input.position(), 2);
+    /// assert_eq!(input.read_byte().unwrap(), 0xAB);
+    /// ```
+    pub fn read_bytes(&mut self, length: u16) -> Result<Vec<u8>> {
+        self.check_string_length(length)?;
+        let offset = PROTOCOL_MAGIC_VERSION_0.len() as u64 + self.position();
+        let length = length as usize;
+        let unread = length.min(self.unread.len());
+        let mut data: Vec<u8> = self.unread.drain(..unread).collect();
+        let wanted = (length - data.len()) as u64;
+        self.position += (&mut self.reader).take(wanted).read_to_end(&mut data)? as u64;
+
+        if data.len() < length {
+            // all unread bytes were taken, so these go back in order
+            self.unread.extend(&data);
+            return Err(ConversionError::LengthExceedsInput {
+                declared: length,
+                remaining: data.len() as u64,
+                offset,
+            });
+        }
+        Ok(data)
+    }
+
+    /// Reads the payload of an attribute whose type is given by the upper 4 bits of its token
+    pub fn read_attribute_value(&mut self, type_info: u8) -> Result<AttributeValue> {
+        let value = match type_info {
+            TYPE_STRING => AttributeValue::String(self.read_utf()?),
+            TYPE_STRING_INTERNED => AttributeValue::Interned(self.read_interned_utf()?),
+            TYPE_INT => AttributeValue::Int(self.read_int()?),
+            TYPE_INT_HEX => AttributeValue::IntHex(self.read_int()?),
+            TYPE_LONG => AttributeValue::Long(self.read_long()?),
+            TYPE_LONG_HEX => AttributeValue::LongHex(self.read_long()?),
+            TYPE_FLOAT => AttributeValue::Float(self.read_float()?),
+            TYPE_DOUBLE => AttributeValue::Double(self.read_double()?),
+            TYPE_BOOLEAN_TRUE => AttributeValue::Boolean(true),
+            TYPE_BOOLEAN_FALSE => AttributeValue::Boolean(false),
+            TYPE_BYTES_HEX => {
+                let length = self.read_short()?;
+                AttributeValue::BytesHex(self.read_bytes(length)?)
+            }
+            TYPE_BYTES_BASE64 => {
+                let length = self.read_short()?;
+                AttributeValue::BytesBase64(self.read_bytes(length)?)
+            }
+            _ => return Err(ConversionError::UnknownAttributeType(type_info)),
+        };
+        Ok(value)
+    }
+}
+
+/// Reads the raw bytes following the current position, starting with unread ones
+impl<R: Read> Read for DataInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.unread.is_empty() {
+            return self.unread.read(buf);
+        }
+        let read = self.reader.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+/// Consumes the 4 byte magic header, failing if it is not the ABX v0 magic
+pub fn check_magic<R: Read>(reader: &mut R) -> Result<FormatVersion> {
+    read_magic(reader, false)
+}
+
+/// Reads the magic header, accepting versions this build does not support if
+/// `allow_unknown_version` is set, see [`Options::allow_unknown_version`]
+pub fn read_magic<R: Read>(reader: &mut R, allow_unknown_version: bool) -> Result<FormatVersion> {
+    let mut magic = [0u8; 4];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|_| ConversionError::ReadError("magic header".to_string()))?;
+    magic_version(magic, allow_unknown_version)
+}
+
+fn magic_version(magic: [u8; 4], allow_unknown_version: bool) -> Result<FormatVersion> {
+    match FormatVersion::detect(&magic) {
+        Some(version) if version.is_supported() || allow_unknown_version => Ok(version),
+        Some(version) => Err(ConversionError::UnsupportedVersion(version.byte())),
+        None => Err(ConversionError::InvalidMagicHeader {
+            expected: PROTOCOL_MAGIC_VERSION_0,
+            actual: magic,
+        }),
+    }
+}
+
+// ============================================================================
+// Attribute Values
+// ============================================================================
+
+/// A decoded attribute value, keeping the ABX type it was stored with
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    Interned(SmolStr),
+    Int(i32),
+    IntHex(i32),
+    Long(i64),
+    LongHex(i64),
+    Float(f32),
+    Double(f64),
+    Boolean(bool),
+    BytesHex(Vec<u8>),
+    BytesBase64(Vec<u8>),
+}
+
+impl AttributeValue {
+    /// Every name [`Self::type_name`] can return
+    pub const TYPE_NAMES: &'static [&'static str] = &[
+        "string",
+        "interned",
+        "int",
+        "int_hex",
+        "long",
+        "long_hex",
+        "float",
+        "double",
+        "boolean",
+        "bytes_hex",
+        "bytes_base64",
+    ];
+
+    /// Stable lowercase name of the ABX type, e.g. `int_hex`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            AttributeValue::String(_) => "string",
+            AttributeValue::Interned(_) => "interned",
+            AttributeValue::Int(_) => "int",
+            AttributeValue::IntHex(_) => "int_hex",
+            AttributeValue::Long(_) => "long",
+            AttributeValue::LongHex(_) => "long_hex",
+            AttributeValue::Float(_) => "float",
+            AttributeValue::Double(_) => "double",
+            AttributeValue::Boolean(_) => "boolean",
+            AttributeValue::BytesHex(_) => "bytes_hex",
+            AttributeValue::BytesBase64(_) => "bytes_base64",
+        }
+    }
+
+    /// Parses `text` as a value of the ABX type named by [`Self::type_name`],
+    /// accepting what the [`fmt::Display`] impl produces.
+    ///
+    /// ```
+    /// use android_xml_converter::reader::AttributeValue;
+    ///
+    /// assert_eq!(AttributeValue::parse_as("int_hex", "ff").unwrap(), AttributeValue::IntHex(255));
+    /// assert_eq!(AttributeValue::parse_as("boolean", "true").unwrap(), AttributeValue::Boolean(true));
+    /// assert!(AttributeValue::parse_as("int", "ten").is_err());
+    /// ```
+    pub fn parse_as(type_name: &str, text: &str) -> Result<Self> {
+        let invalid =
+            || ConversionError::ParseError(format!("Invalid {} value: {:?}", type_name, text));
+        let value = match type_name {
+            "string" => AttributeValue::String(text.to_string()),
+            "interned" => AttributeValue::Interned(SmolStr::new(text)),
+            "int" => AttributeValue::Int(text.parse().map_err(|_| invalid())?),
+            "int_hex" => AttributeValue::IntHex(match text {
+                "-1" => -1,
+                _ => u32::from_str_radix(text, 16).map_err(|_| invalid())? as i32,
+            }),
+            "long" => AttributeValue::Long(text.parse().map_err(|_| invalid())?),
+            "long_hex" => AttributeValue::LongHex(match text {
+                "-1" => -1,
+                _ => u64::from_str_radix(text, 16).map_err(|_| invalid())? as i64,
+            }),
+            "float" => AttributeValue::Float(text.parse().map_err(|_| invalid())?),
+            "double" => AttributeValue::Double(text.parse().map_err(|_| invalid())?),
+            "boolean" => match text {
+                "true" => AttributeValue::Boolean(true),
+                "false" => AttributeValue::Boolean(false),
+                _ => return Err(invalid()),
+            },
+            "bytes_hex" => {
+                AttributeValue::BytesHex(hex_decode(text).ok_or(ConversionError::InvalidHex)?)
+            }
+            "bytes_base64" => AttributeValue::BytesBase64(
+                base64_decode(text).ok_or(ConversionError::InvalidBase64)?,
+            ),
+            _ => {
+                return Err(ConversionError::ParseError(format!(
+                    "Unknown attribute type: {}",
+                    type_name
+                )));
+            }
+        };
+        Ok(value)
+    }
+
+    /// The value as an `int`: stored ints directly, strings parsed as decimal
+    pub fn to_i32(&self) -> Result<i32> {
+        match self {
+            AttributeValue::Int(value) | AttributeValue::IntHex(value) => Ok(*value),
+            value => value.parse_text("int")?.to_i32(),
+        }
+    }
+
+    /// The value as an `int`: stored ints directly, strings parsed as hex
+    pub fn to_i32_hex(&self) -> Result<i32> {
+        match self {
+            AttributeValue::Int(value) | AttributeValue::IntHex(value) => Ok(*value),
+            value => value.parse_text("int_hex")?.to_i32_hex(),
+        }
+    }
+
+    /// The value as a `long`: stored ints and longs directly, strings parsed as decimal
+    pub fn to_i64(&self) -> Result<i64> {
+        match self {
+            AttributeValue::Int(value) | AttributeValue::IntHex(value) => Ok(*value as i64),
+            AttributeValue::Long(value) | AttributeValue::LongHex(value) => Ok(*value),
+            value => value.parse_text("long")?.to_i64(),
+        }
+    }
+
+    /// The value as a `long`: stored ints and longs directly, strings parsed as hex
+    pub fn to_i64_hex(&self) -> Result<i64> {
+        match self {
+            AttributeValue::Int(value) | AttributeValue::IntHex(value) => Ok(*value as i64),
+            AttributeValue::Long(value) | AttributeValue::LongHex(value) => Ok(*value),
+            value => value.parse_text("long_hex")?.to_i64_hex(),
+        }
+    }
+
+    pub fn to_f32(&self) -> Result<f32> {
+        match self {
+            AttributeValue::Float(value) => Ok(*value),
+            value => value.parse_text("float")?.to_f32(),
+        }
+    }
+
+    pub fn to_f64(&self) -> Result<f64> {
+        match self {
+            AttributeValue::Float(value) => Ok(*value as f64),
+            AttributeValue::Double(value) => Ok(*value),
+            value => value.parse_text("double")?.to_f64(),
+        }
+    }
+
+    /// The value as a `boolean`: stored booleans directly, strings when they are
+    /// `true` or `false` in any case
+    pub fn to_bool(&self) -> Result<bool> {
+        match self {
+            AttributeValue::Boolean(value) => Ok(*value),
+            AttributeValue::String(_) | AttributeValue::Interned(_) => {
+                let text = self.to_string();
+                match text.to_ascii_lowercase().as_str() {
+                    "true" => Ok(true),
+                    "false" => Ok(false),
+                    _ => Err(ConversionError::ParseError(format!(
+                        "Invalid boolean value: {:?}",
+                        text
+                    ))),
+                }
+            }
+            value => Err(value.type_mismatch("boolean")),
+        }
+    }
+
+    /// Stored bytes of either encoding, strings decoded as hex
+    pub fn to_bytes_hex(&self) -> Result<Vec<u8>> {
+        match self {
+            AttributeValue::BytesHex(bytes) | AttributeValue::BytesBase64(bytes) => {
+                Ok(bytes.clone())
+            }
+            value => value.parse_text("bytes_hex")?.to_bytes_hex(),
+        }
+    }
+
+    /// Stored bytes of either encoding, strings decoded as base64
+    pub fn to_bytes_base64(&self) -> Result<Vec<u8>> {
+        match self {
+            AttributeValue::BytesHex(bytes) | AttributeValue::BytesBase64(bytes) => {
+                Ok(bytes.clone())
+            }
+            value => value.parse_text("bytes_base64")?.to_bytes_base64(),
+        }
+    }
+
+    /// Parses a string value with [`Self::parse_as`], failing for other stored types
+    fn parse_text(&self, type_name: &str) -> Result<AttributeValue> {
+        match self {
+            AttributeValue::String(text) => Self::parse_as(type_name, text),
+            AttributeValue::Interned(text) => Self::parse_as(type_name, text),
+            _ => Err(self.type_mismatch(type_name)),
+        }
+    }
+
+    fn type_mismatch(&self, type_name: &str) -> ConversionError {
+        ConversionError::ParseError(format!(
+            "Attribute of type {} cannot be read as {}",
+            self.type_name(),
+            type_name
+        ))
+    }
+}
+
+impl fmt::Display for AttributeValue {
+    /// Formats the value the way it appears in XML output, without entity encoding
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttributeValue::String(value) => f.write_str(value),
+            AttributeValue::Interned(value) => f.write_str(value),
+            AttributeValue::Int(value) => write!(f, "{}", value),
+            AttributeValue::IntHex(value) => {
+                if *value == -1 {
+                    write!(f, "{}", value)
+                } else {
+                    write!(f, "{:x}", *value as u32)
+                }
+            }
+            AttributeValue::Long(value) => write!(f, "{}", value),
+            AttributeValue::LongHex(value) => {
+                if *value == -1 {
+                    write!(f, "{}", value)
+                } else {
+                    write!(f, "{:x}", *value as u64)
+                }
+            }
+            AttributeValue::Float(value) => {
+                if value.fract() == 0.0 && value.is_finite() {
+                    write!(f, "{:.1}", value)
+                } else {
+                    write!(f, "{}", value)
+                }
+            }
+            AttributeValue::Double(value) => {
+                if value.fract() == 0.0 && value.is_finite() {
+                    write!(f, "{:.1}", value)
+                } else {
+                    write!(f, "{}", value)
+                }
+            }
+            AttributeValue::Boolean(value) => write!(f, "{}", value),
+            AttributeValue::BytesHex(bytes) => f.write_str(&hex_string(bytes)),
+            AttributeValue::BytesBase64(bytes) => f.write_str(&base64_string(bytes)),
+        }
+    }
+}
+
+// ============================================================================
+// Binary XML Deserializer
+// ============================================================================
+
+const XML_DECLARATION: &[u8] = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>";
+
+/// Whether salvage mode may skip the token an error occurred in; output, limit
+/// and timeout errors end the conversion
+fn is_salvageable(e: &ConversionError) -> bool {
+    !matches!(
+        e,
+        ConversionError::Io(_)
+            | ConversionError::LimitExceeded(_)
+            | ConversionError::TooManyErrors(_)
+    )
+}
+
+/// Callback receiving the byte offset and the raw byte of every token read
+pub type TokenHook = Box<dyn FnMut(u64, u8)>;
+
+/// Callback receiving the open elements, innermost last, as each element ends
+pub type ElementHook = Box<dyn FnMut(&[ElementFrame]) -> ControlFlow<()>>;
+
+/// Output that counts the bytes written, for [`Limits::max_output_bytes`], and
+/// can hold back the output of a token until it decoded completely
+struct CountingWriter<W: Write> {
+    inner: W,
+    written: u64,
+    pending: Option<Vec<u8>>,
+}
+
+impl<W: Write> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            written: 0,
+            pending: None,
+        }
+    }
+
+    /// Holds back what is written until [`Self::commit`] or [`Self::discard`]
+    fn hold(&mut self) {
+        self.pending = Some(Vec::new());
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        match self.pending.take() {
+            Some(pending) => self.inner.write_all(&pending),
+            None => Ok(()),
+        }
+    }
+
+    fn discard(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            self.written -= pending.len() as u64;
+        }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = match &mut self.pending {
+            Some(pending) => {
+                pending.extend_from_slice(buf);
+                buf.len()
+            }
+            None => self.inner.write(buf)?,
+        };
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub struct BinaryXmlDeserializer<R: Read, W: Write> {
+    input: DataInput<R>,
+    output: CountingWriter<W>,
+    limits: Limits,
+    name_policy: NamePolicy,
+    /// Lenient mode, see [`Options::max_errors`]
+    lenient: bool,
+    diagnostics: Diagnostics,
+    float_format: FloatFormat,
+    /// Type hints of the current start tag, only kept with [`Options::emit_type_hints`]
+    type_hints: Option<String>,
+    /// [`Options::fidelity`]
+    fidelity: bool,
+    /// [`Options::canonical`]
+    canonical: bool,
+    /// [`InvalidUtf8::Escape`]
+    escape_bytes: bool,
+    /// [`Options::allow_truncated`]
+    allow_truncated: bool,
+    /// [`Options::salvage`]
+    salvage: bool,
+    /// [`Options::allow_unknown_version`]
+    allow_unknown_version: bool,
+    /// [`Options::multi_doc`]
+    multi_doc: bool,
+    /// Each further document gets an XML declaration of its own, as it is
+    /// not gathered under [`Options::wrap_roots`]
+    separate_documents: bool,
+    /// The last token written was TEXT or IGNORABLE_WHITESPACE, so more text
+    /// would merge with it
+    after_text: bool,
+    tokens: usize,
+    /// Offset of the token being processed and the one before it, for
+    /// [`ConversionError::Decode`]
+    token_offset: u64,
+    last_token: Option<u8>,
+    token: Option<u8>,
+    /// The XML declaration was written, see [`Self::step`]
+    started: bool,
+    /// END_DOCUMENT or the end of the input was reached
+    finished: bool,
+    /// Pool index and name of each open element, innermost last
+    open_tags: Vec<(u16, SmolStr)>,
+    /// Offset, token and raw pool index of a tag found by
+    /// [`Self::skip_to_element_boundary`], to be processed next
+    resumed: Option<(u64, u8, u16)>,
+    token_hooks: Vec<TokenHook>,
+    element_hooks: Vec<ElementHook>,
+    /// Open elements with their attributes, only kept while there are element hooks
+    frames: Vec<ElementFrame>,
+    #[cfg(feature = "unstable")]
+    extensions: crate::extension::Extensions,
+}
+
+impl<R: Read, W: Write> BinaryXmlDeserializer<R, W> {
+    pub fn new(reader: R, output: W) -> Result<Self> {
+        Self::with_options(reader, output, &Options::default())
+    }
+
+    pub fn with_options(mut reader: R, output: W, options: &Options) -> Result<Self> {
+        let version = read_magic(&mut reader, options.allow_unknown_version)?;
+
+        let mut deserializer = Self {
+            input: DataInput::from_options(reader, options),
+            output: CountingWriter::new(output),
+            limits: options.limits,
+            name_policy: options.name_policy,
+            lenient: options.max_errors.is_some(),
+            diagnostics: options
+                .max_errors
+                .map(Diagnostics::with_max_errors)
+                .unwrap_or_default(),
+            float_format: if options.canonical {
+                FloatFormat::shortest()
+            } else {
+                options.float_format
+            },
+            type_hints: (options.emit_type_hints || options.fidelity).then(String::new),
+            fidelity: options.fidelity,
+            canonical: options.canonical,
+            escape_bytes: options.invalid_utf8 == InvalidUtf8::Escape,
+            allow_truncated: options.allow_truncated,
+            salvage: options.salvage,
+            allow_unknown_version: options.allow_unknown_version,
+            multi_doc: options.multi_doc,
+            separate_documents: options.wrap_roots.is_none(),
+            after_text: false,
+            tokens: 0,
+            token_offset: 0,
+            last_token: None,
+            token: None,
+            started: false,
+            finished: false,
+            open_tags: Vec::new(),
+            resumed: None,
+            token_hooks: Vec::new(),
+            element_hooks: Vec::new(),
+            frames: Vec::new(),
+            #[cfg(feature = "unstable")]
+            extensions: options.extensions.clone(),
+        };
+        deserializer.check_version(version);
+        Ok(deserializer)
+    }
+
+    /// Warns that a document of an unknown version is decoded as version 0
+    fn check_version(&mut self, version: FormatVersion) {
+        if !version.is_supported() {
+            self.diagnostics.warning(
+                Location::Offset(self.position()),
+                &format!(
+                    "Unknown ABX version {}, decoded as version 0",
+                    version.byte()
+                ),
+                None,
+            );
+        }
+    }
+
+    /// Registers a hook called with the offset and raw byte of each token, attribute
+    /// tokens included, before the token is decoded. Offsets count from the start of
+    /// the document, so the first token is at offset 4 just after the magic.
+    ///
+    /// ```
+    /// use android_xml_converter::XmlToAbxConverter;
+    /// use android_xml_converter::reader::BinaryXmlDeserializer;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let mut abx = Vec::new();
+    /// XmlToAbxConverter::convert_from_string("<a b=\"1\"/>", &mut abx).unwrap();
+    ///
+    /// let seen = Arc::new(Mutex::new(Vec::new()));
+    /// let log = Arc::clone(&seen);
+    /// let mut deserializer = BinaryXmlDeserializer::new(abx.as_slice(), Vec::new()).unwrap();
+    /// deserializer.on_token(move |offset, token| log.lock().unwrap().push((offset, token & 0x0F)));
+    /// deserializer.deserialize().unwrap();
+    ///
+    /// assert_eq!(seen.lock().unwrap()[..2], [(4, 0), (5, 2)]);
+    /// ```
+    pub fn on_token<F: FnMut(u64, u8) + 'static>(&mut self, hook: F) -> &mut Self {
+        self.token_hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Registers a hook called as each element ends, after its end tag has been
+    /// written, with the open elements and their attributes; the completed element
+    /// is the last one and [`crate::xpath::element_location`] gives its path.
+    /// Returning [`ControlFlow::Break`] ends the conversion there, leaving the
+    /// output written so far.
+    ///
+    /// ```
+    /// use android_xml_converter::XmlToAbxConverter;
+    /// use android_xml_converter::query::TypedAttributes;
+    /// use android_xml_converter::reader::BinaryXmlDeserializer;
+    /// use android_xml_converter::xpath::element_location;
+    /// use std::ops::ControlFlow;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let xml = r#"<packages><package name="a"/><package name="b"/><package name="c"/></packages>"#;
+    /// let mut abx = Vec::new();
+    /// XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+    ///
+    /// let found = Arc::new(Mutex::new(None));
+    /// let target = Arc::clone(&found);
+    /// let mut xml = Vec::new();
+    /// let mut deserializer = BinaryXmlDeserializer::new(abx.as_slice(), &mut xml).unwrap();
+    /// deserializer.on_element(move |stack| {
+    ///     let element = stack.last().unwrap();
+    ///     match element.attribute("name") {
+    ///         Some(name) if name.to_string() == "b" => {
+    ///             *target.lock().unwrap() = Some(element_location(stack));
+    ///             ControlFlow::Break(())
+    ///         }
+    ///         _ => ControlFlow::Continue(()),
+    ///     }
+    /// });
+    /// deserializer.deserialize().unwrap();
+    ///
+    /// assert_eq!(found.lock().unwrap().as_deref(), Some("/packages/package"));
+    /// assert!(String::from_utf8(xml).unwrap().ends_with(r#"<package name="b"></package>"#));
+    /// ```
+    pub fn on_element<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: FnMut(&[ElementFrame]) -> ControlFlow<()> + 'static,
+    {
+        self.element_hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Runs the element hooks for the innermost element and drops its frame;
+    /// `false` if one of them asked to stop
+    fn complete_element(&mut self) -> bool {
+        if self.element_hooks.is_empty() {
+            return true;
+        }
+        let mut proceed = true;
+        for hook in &mut self.element_hooks {
+            if hook(&self.frames).is_break() {
+                proceed = false;
+            }
+        }
+        self.frames.pop();
+        proceed
+    }
+
+    fn read_token(&mut self) -> Result<u8> {
+        // the previous token was read completely
+        self.last_token = self.token.take();
+        let (offset, token) = match self.resumed {
+            Some((offset, token, _)) => (offset, token),
+            None => (self.position(), self.input.read_byte()?),
+        };
+        self.token_offset = offset;
+        self.token = Some(token);
+        for hook in &mut self.token_hooks {
+            hook(offset, token);
+        }
+        Ok(token)
+    }
+
+    /// Problems recorded so far in lenient mode
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    pub fn take_diagnostics(&mut self) -> Diagnostics {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    /// Converts the whole document. Input that ends before END_DOCUMENT fails
+    /// with [`ConversionError::Truncated`] unless [`Options::allow_truncated`]
+    /// is set. Every other decoding error fails the conversion as well, wrapped
+    /// in [`ConversionError::Decode`]; only lenient mode ([`Options::max_errors`])
+    /// and [`Self::recover`] carry on past them.
+    ///
+    /// ```
+    /// use android_xml_converter::reader::BinaryXmlDeserializer;
+    /// use android_xml_converter::{ConversionError, Options, XmlToAbxConverter};
+    ///
+    /// let mut abx = Vec::new();
+    /// XmlToAbxConverter::convert_from_string("<a><b/></a>", &mut abx).unwrap();
+    /// abx.truncate(abx.len() - 4);
+    ///
+    /// let mut xml = Vec::new();
+    /// let result = BinaryXmlDeserializer::new(&abx[..], &mut xml).unwrap().deserialize();
+    /// let Err(ConversionError::Decode { source, elements, .. }) = result else { panic!() };
+    /// assert!(matches!(*source, ConversionError::Truncated { offset, .. } if offset == abx.len() as u64));
+    /// assert_eq!(elements, ["a"]);
+    ///
+    /// let options = Options::new().allow_truncated(true);
+    /// let mut xml = Vec::new();
+    /// BinaryXmlDeserializer::with_options(&abx[..], &mut xml, &options)
+    ///     .unwrap()
+    ///     .deserialize()
+    ///     .unwrap();
+    /// assert!(String::from_utf8(xml).unwrap().ends_with("<a><b></b>"));
+    ///
+    /// // <a></b>
+    /// let abx = [b'A', b'B', b'X', 0, 0x10, 0x32, 0xFF, 0xFF, 0, 1, b'a', 0x33, 0xFF, 0xFF, 0, 1, b'b', 0x11];
+    /// let mut xml = Vec::new();
+    /// let result = BinaryXmlDeserializer::new(&abx[..], &mut xml).unwrap().deserialize();
+    /// let Err(ConversionError::Decode { source, .. }) = result else { panic!() };
+    /// assert!(source.to_string().contains("End tag b does not match open element a"));
+    ///
+    /// // </a> before any start tag
+    /// let abx = [b'A', b'B', b'X', 0, 0x10, 0x33, 0xFF, 0xFF, 0, 1, b'a', 0x11];
+    /// let result = BinaryXmlDeserializer::new(&abx[..], &mut Vec::new()).unwrap().deserialize();
+    /// let Err(ConversionError::Decode { source, .. }) = result else { panic!() };
+    /// assert!(source.to_string().contains("End tag a without an open element"));
+    ///
+    /// // 32,768 elements, each defining two empty pool strings, one more than fits
+    /// let mut abx = b"ABX\0\x10".to_vec();
+    /// for _ in 0..1 << 15 {
+    ///     abx.extend_from_slice(&[0x32, 0xFF, 0xFF, 0, 0, 0x33, 0xFF, 0xFF, 0, 0]);
+    /// }
+    /// let result = BinaryXmlDeserializer::new(&abx[..], &mut Vec::new()).unwrap().deserialize();
+    /// let Err(ConversionError::Decode { source, .. }) = result else { panic!() };
+    /// assert!(matches!(*source, ConversionError::InternPoolFull(65535)));
+    /// ```
+    pub fn deserialize(&mut self) -> Result<()> {
+        self.step(usize::MAX)?;
+        Ok(())
+    }
+
+    /// Converts the whole document, salvaging what can be read of a corrupted
+    /// one: a token that cannot be decoded is skipped with everything it wrote,
+    /// decoding resumes at the byte after it, and the elements still open at
+    /// the end are closed. Each is recorded as a warning in
+    /// [`Self::diagnostics`]. Only output, limit and timeout errors stop it.
+    /// [`Options::salvage`] makes [`Self::deserialize`] do the same.
+    ///
+    /// ```
+    /// use android_xml_converter::reader::BinaryXmlDeserializer;
+    /// use android_xml_converter::XmlToAbxConverter;
+    ///
+    /// let mut abx = Vec::new();
+    /// XmlToAbxConverter::convert_from_string(r#"<a><b c="1"/><d e="22"/></a>"#, &mut abx).unwrap();
+    /// abx.truncate(abx.len() - 8);
+    ///
+    /// let mut xml = Vec::new();
+    /// let mut deserializer = BinaryXmlDeserializer::new(&abx[..], &mut xml).unwrap();
+    /// deserializer.recover().unwrap();
+    /// assert_eq!(deserializer.diagnostics().warning_count(), 3);
+    /// drop(deserializer);
+    /// assert!(String::from_utf8(xml).unwrap().ends_with("<a><b c=\"1\"></b><d></d></a>"));
+    /// ```
+    pub fn recover(&mut self) -> Result<()> {
+        self.salvage = true;
+        self.deserialize()
+    }
+
+    /// Converts up to `tokens` more tokens and returns whether the document is
+    /// complete, so a conversion can run piecewise: a GUI calls it from its
+    /// event loop with a small budget, updates a progress bar from
+    /// [`Self::position`] in between and cancels by no longer calling it. A
+    /// start tag counts as one token with its attributes. An error ends the
+    /// conversion: later calls return `Ok(true)` without reading any further.
+    ///
+    /// [`crate::AbxWriter`] is the counterpart for XML to ABX, taking one event
+    /// at a time.
+    ///
+    /// ```
+    /// use android_xml_converter::{AbxToXmlConverter, XmlToAbxConverter};
+    /// use android_xml_converter::reader::BinaryXmlDeserializer;
+    ///
+    /// let mut abx = Vec::new();
+    /// XmlToAbxConverter::convert_from_string(r#"<map><int name="a" value="1"/><string name="b">x</string></map>"#, &mut abx)
+    ///     .unwrap();
+    ///
+    /// let mut xml = Vec::new();
+    /// let mut deserializer = BinaryXmlDeserializer::new(abx.as_slice(), &mut xml).unwrap();
+    /// let mut positions = Vec::new();
+    /// while !deserializer.step(2).unwrap() {
+    ///     positions.push(deserializer.position());
+    /// }
+    /// assert_eq!(positions.len(), 4);
+    /// assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+    /// assert_eq!(String::from_utf8(xml).unwrap(), AbxToXmlConverter::convert_bytes(&abx).unwrap());
+    /// ```
+    ///
+    /// Stepping on after an error, here a lenient conversion that allows no
+    /// errors:
+    ///
+    /// ```
+    /// use android_xml_converter::reader::BinaryXmlDeserializer;
+    /// use android_xml_converter::{ConversionError, Options};
+    ///
+    /// // <a></b><c>
+    /// let abx = [
+    ///     b'A', b'B', b'X', 0, 0x10, 0x32, 0xFF, 0xFF, 0, 1, b'a', 0x33, 0xFF, 0xFF, 0, 1, b'b',
+    ///     0x32, 0xFF, 0xFF, 0, 1, b'c',
+    /// ];
+    /// let options = Options::new().max_errors(0);
+    /// let mut xml = Vec::new();
+    /// let mut deserializer = BinaryXmlDeserializer::with_options(&abx[..], &mut xml, &options).unwrap();
+    /// assert!(matches!(deserializer.step(10), Err(ConversionError::TooManyErrors(_))));
+    /// let position = deserializer.position();
+    /// assert!(deserializer.step(10).unwrap());
+    /// assert_eq!(deserializer.position(), position);
+    /// drop(deserializer);
+    /// assert!(!String::from_utf8(xml).unwrap().contains("<c>"));
+    /// ```
+    pub fn step(&mut self, tokens: usize) -> Result<bool> {
+        if self.finished {
+            return Ok(true);
+        }
+        let result = self.step_tokens(tokens);
+        if result.is_err() {
+            // the input is at an unknown position within a token
+            self.finished = true;
+        }
+        result
+    }
+
+    fn step_tokens(&mut self, tokens: usize) -> Result<bool> {
+        if !self.started {
+            self.output.write_all(XML_DECLARATION)?;
+            self.started = true;
+        }
+
+        for _ in 0..tokens {
+            let result = if self.salvage {
+                self.salvage_token()
+            } else {
+                self.process_token()
+                    .and_then(|more| self.check_limits().map(|_| more))
+            };
+            match result {
+                Ok(true) => {}
+                Err(ConversionError::ReadError(reading)) => {
+                    // a string that could not be decoded is followed by more input
+                    let truncated = self.input.peek_byte().is_err();
+                    if truncated && self.allow_truncated {
+                        self.finished = true;
+                        break;
+                    }
+                    let e = if truncated {
+                        ConversionError::Truncated {
+                            offset: self.position(),
+                            reading,
+                        }
+                    } else {
+                        ConversionError::ReadError(reading)
+                    };
+                    let e = self.with_context(e);
+                    if self.lenient {
+                        let _ = self
+                            .diagnostics
+                            .error(Location::Token(self.tokens), &e.to_string());
+                    }
+                    return Err(e);
+                }
+                Ok(false) => {
+                    match self.next_document() {
+                        Ok(true) => continue,
+                        Ok(false) => {}
+                        Err(e) => return Err(self.with_context(e)),
+                    }
+                    self.finished = true;
+                    break;
+                }
+                Err(e @ ConversionError::TooManyErrors(_)) => return Err(e),
+                Err(
+                    e @ (ConversionError::LimitExceeded(_) | ConversionError::InvalidName { .. }),
+                ) => return Err(self.with_context(e)),
+                Err(ConversionError::Io(e)) if e.kind() == io::ErrorKind::TimedOut => {
+                    return Err(self.with_context(ConversionError::Io(e)));
+                }
+                Err(e @ ConversionError::LengthExceedsInput { .. })
+                    if self.allow_truncated && !self.lenient =>
+                {
+                    // a cut-off string, written up to where it started
+                    eprintln!("Warning: Error parsing token: {}", self.with_context(e));
+                    self.finished = true;
+                    break;
+                }
+                Err(e) if self.lenient => {
+                    // not recoverable, but still part of the report
+                    let e = self.with_context(e);
+                    let _ = self
+                        .diagnostics
+                        .error(Location::Token(self.tokens), &e.to_string());
+                    return Err(e);
+                }
+                Err(e) => return Err(self.with_context(e)),
+            }
+        }
+        Ok(self.finished)
+    }
+
+    /// After END_DOCUMENT with [`Options::multi_doc`], starts on the document
+    /// that follows in the stream with a fresh intern pool; `false` at the end
+    /// of the input
+    fn next_document(&mut self) -> Result<bool> {
+        let ended = self.token.map(|token| token & 0x0F) == Some(END_DOCUMENT);
+        if !self.multi_doc || !ended || self.input.peek_byte().is_err() {
+            return Ok(false);
+        }
+
+        let mut magic = [0u8; 4];
+        for byte in &mut magic {
+            *byte = self.input.read_byte()?;
+        }
+        let version = match magic_version(magic, self.allow_unknown_version) {
+            Err(ConversionError::InvalidMagicHeader { .. }) => {
+                return Err(ConversionError::ParseError(format!(
+                    "Data after END_DOCUMENT is not another ABX document (starts with {})",
+                    hex_string(&magic)
+                )));
+            }
+            version => version?,
+        };
+        self.check_version(version);
+        self.input.reset_pool();
+        if self.separate_documents {
+            self.output.write_all(b"\n")?;
+            self.output.write_all(XML_DECLARATION)?;
+        }
+        Ok(true)
+    }
+
+    /// Converts one token in salvage mode, see [`Self::recover`]
+    fn salvage_token(&mut self) -> Result<bool> {
+        // a token opens or closes at most one element
+        let open = self.open_tags.len();
+        let innermost = self.open_tags.last().cloned();
+        self.output.hold();
+        let result = self
+            .process_token()
+            .and_then(|more| self.check_limits().map(|_| more));
+
+        let more = match result {
+            Ok(more) => {
+                self.output.commit()?;
+                more
+            }
+            Err(e) if is_salvageable(&e) => {
+                self.output.discard();
+                self.open_tags.truncate(open);
+                if self.open_tags.len() < open {
+                    self.open_tags.extend(innermost);
+                }
+                if !self.element_hooks.is_empty() {
+                    self.frames.truncate(self.open_tags.len());
+                }
+
+                let at_end = self.input.peek_byte().is_err();
+                let message = if at_end {
+                    "Input ends before END_DOCUMENT"
+                } else {
+                    "Skipped a token that cannot be decoded"
+                };
+                let detail = self.with_context(e).to_string();
+                self.diagnostics
+                    .warning(Location::Token(self.tokens), message, Some(detail));
+                !at_end
+            }
+            Err(e) => {
+                self.output.discard();
+                return Err(e);
+            }
+        };
+
+        let ended = self.token.map(|token| token & 0x0F) == Some(END_DOCUMENT);
+        if ended && !self.open_tags.is_empty() && self.input.peek_byte().is_ok() {
+            // more likely a corrupted byte than the real end
+            let detail = self.with_context(ConversionError::ParseError(
+                "END_DOCUMENT inside an element".to_string(),
+            ));
+            self.diagnostics.warning(
+                Location::Token(self.tokens),
+                "Skipped a token that cannot be decoded",
+                Some(detail.to_string()),
+            );
+            return Ok(true);
+        }
+        if !more && (ended || self.input.peek_byte().is_err()) {
+            self.close_open_elements()?;
+        }
+        Ok(more)
+    }
+
+    /// Closes the elements a salvaged document left open, innermost first
+    fn close_open_elements(&mut self) -> Result<()> {
+        if self.open_tags.is_empty() {
+            return Ok(());
+        }
+        let names: String = self
+            .open_tags
+            .iter()
+            .map(|(_, name)| format!("<{}>", name))
+            .collect();
+        self.diagnostics.warning(
+            Location::Token(self.tokens),
+            &format!("Closed {} unclosed element(s)", self.open_tags.len()),
+            Some(names),
+        );
+        while let Some((_, name)) = self.open_tags.pop() {
+            write!(self.output, "</{}>", name)?;
+            self.complete_element();
+        }
+        Ok(())
+    }
+
+    /// Checks the limits that depend on more than one token
+    fn check_limits(&self) -> Result<()> {
+        if let Some(max) = self.limits.max_depth
+            && self.open_tags.len() > max
+        {
+            return Err(ConversionError::LimitExceeded(format!(
+                "more than {} nested elements",
+                max
+            )));
+        }
+        if let Some(max) = self.limits.max_output_bytes
+            && self.output.written > max
+        {
+            return Err(ConversionError::LimitExceeded(format!(
+                "more than {} bytes of output",
+                max
+            )));
+        }
+        Ok(())
+    }
+
+    /// Wraps a decoding error as [`ConversionError::Decode`] with the offset of
+    /// the failing token, the token before it and the open elements
+    fn with_context(&self, e: ConversionError) -> ConversionError {
+        ConversionError::Decode {
+            offset: self.token_offset,
+            last_token: self.last_token.and_then(|token| token_name(token & 0x0F)),
+            elements: self
+                .open_tags
+                .iter()
+                .map(|(_, name)| name.to_string())
+                .collect(),
+            source: Box::new(e),
+        }
+    }
+
+    /// Bytes of input consumed so far, counting the 4-byte magic, e.g. for
+    /// progress against the input size
+    pub fn position(&self) -> u64 {
+        PROTOCOL_MAGIC_VERSION_0.len() as u64 + self.input.position()
+    }
+
+    /// Reads an interned string; in lenient mode an invalid pool index is recorded
+    /// and replaced by a placeholder name
+    fn read_interned_utf(&mut self) -> Result<SmolStr> {
+        let index = self.input.read_interned_index()?;
+        self.interned_string(index)
+    }
+
+    fn interned_string(&mut self, index: u16) -> Result<SmolStr> {
+        match self.input.interned_string(index) {
+            Ok(string) => Ok(string.clone()),
+            Err(e) if self.lenient => {
+                self.diagnostics
+                    .error(Location::Token(self.tokens), &e.to_string())?;
+                Ok(SmolStr::new(format!("invalid-index-{}", index)))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads the pool index of a START_TAG or END_TAG, or takes the one
+    /// [`Self::skip_to_element_boundary`] found
+    fn read_tag_index(&mut self) -> Result<u16> {
+        match self.resumed.take() {
+            Some((_, _, index)) => self.input.resolve_interned_index(index),
+            None => self.input.read_interned_index(),
+        }
+    }
+
+    /// Skips input up to the next START_TAG that refers to a known or new pool
+    /// string or the END_TAG of the innermost open element, which is then
+    /// processed next; returns how many bytes were skipped. Lenient mode uses
+    /// it after an attribute failed to decode, as the attribute's length is
+    /// then unknown. At the end of the input nothing is found.
+    fn skip_to_element_boundary(&mut self) -> Result<u64> {
+        let start = self.position();
+        let mut window = [0u8; 3];
+        let mut filled = 0;
+        loop {
+            let byte = match self.input.read_byte() {
+                Ok(byte) => byte,
+                Err(ConversionError::ReadError(_)) => return Ok(self.position() - start),
+                Err(e) => return Err(e),
+            };
+            window = [window[1], window[2], byte];
+            filled += 1;
+            if filled < window.len() {
+                continue;
+            }
+
+            let [token, high, low] = window;
+            let index = u16::from_be_bytes([high, low]);
+            let fits = if token == START_TAG | TYPE_STRING_INTERNED {
+                index == INTERNED_STRING_NEW_MARKER
+                    || (index as usize) < self.input.interned_strings().len()
+            } else if token == END_TAG | TYPE_STRING_INTERNED {
+                self.open_tags
+                    .last()
+                    .is_some_and(|(open, _)| *open == index)
+            } else {
+                false
+            };
+            if fits {
+                let offset = self.position() - window.len() as u64;
+                self.resumed = Some((offset, token, index));
+                return Ok(offset - start);
+            }
+        }
+    }
+
+    /// Reads the name of an END_TAG and checks it against the innermost open
+    /// element; `None` if there is no element to close.
+    ///
+    /// An end tag normally refers to the same pool entry as its start tag, in which
+    /// case the name is taken from the stack without a lookup. In lenient and
+    /// salvage mode a mismatch is recorded and the open element is closed under
+    /// its own name.
+    fn read_end_tag(&mut self) -> Result<Option<SmolStr>> {
+        let index = self.read_tag_index()?;
+        let open = self.open_tags.pop();
+        if let Some((open_index, name)) = &open
+            && *open_index == index
+        {
+            return Ok(Some(name.clone()));
+        }
+
+        let found = self.interned_string(index)?;
+        let message = match open {
+            Some((_, name)) if name == found => return Ok(Some(name)),
+            Some((_, name)) => {
+                if self.lenient {
+                    self.diagnostics.error(
+                        Location::Token(self.tokens),
+                        &format!("End tag {} does not match open element {}", found, name),
+                    )?;
+                    return Ok(Some(name));
+                }
+                if self.salvage {
+                    self.diagnostics.warning(
+                        Location::Token(self.tokens),
+                        &format!("End tag {} does not match open element {}", found, name),
+                        None,
+                    );
+                    return Ok(Some(name));
+                }
+                format!(
+                    "End tag {} does not match open element {} in token #{}",
+                    found, name, self.tokens
+                )
+            }
+            None => {
+                if self.lenient {
+                    self.diagnostics.error(
+                        Location::Token(self.tokens),
+                        &format!("End tag {} without an open element", found),
+                    )?;
+                    return Ok(None);
+                }
+                format!(
+                    "End tag {} without an open element in token #{}",
+                    found, self.tokens
+                )
+            }
+        };
+        Err(ConversionError::ParseError(message))
+    }
+
+    fn process_token(&mut self) -> Result<bool> {
+        let token = self.read_token()?;
+        self.tokens += 1;
+        let command = token & 0x0F;
+        let type_info = token & 0xF0;
+
+        let after_text = std::mem::take(&mut self.after_text);
+        if self.fidelity && (TEXT..=DOCDECL).contains(&command) && type_info != TYPE_STRING {
+            let name = token_name(command).unwrap_or_default();
+            self.directive(&format!("null {}", name))?;
+            return Ok(true);
+        }
+
+        if self.canonical
+            && type_info == TYPE_STRING
+            && matches!(command, TEXT | ENTITY_REF | IGNORABLE_WHITESPACE)
+        {
+            return self.write_canonical_text(command);
+        }
+
+        match command {
+            START_DOCUMENT => Ok(true),
+            END_DOCUMENT => {
+                if self.fidelity {
+                    let trailing = self.input.read_remaining()?;
+                    if !trailing.is_empty() {
+                        self.directive(&format!("trailing {}", hex_string(&trailing)))?;
+                    }
+                }
+                Ok(false)
+            }
+            START_TAG => {
+                let index = self.read_tag_index()?;
+                let tag_name = self.interned_string(index)?;
+                self.name_policy.check(&tag_name)?;
+                self.open_tags.push((index, tag_name.clone()));
+                if !self.element_hooks.is_empty() {
+                    self.frames.push(ElementFrame {
+                        name: tag_name.clone(),
+                        attributes: Vec::new(),
+                    });
+                }
+                self.output.write_all(b"<")?;
+                self.output.write_all(tag_name.as_bytes())?;
+                if let Some(hints) = &mut self.type_hints {
+                    hints.clear();
+                    if self.open_tags.len() == 1 {
+                        write!(self.output, " xmlns:abx=\"{}\"", TYPE_HINT_NAMESPACE)?;
+                    }
+                }
+
+                let mut sorted = Vec::new();
+                while let Ok(next_token) = self.input.peek_byte() {
+                    if (next_token & 0x0F) != ATTRIBUTE {
+                        break;
+                    }
+
+                    let _ = self.read_token()?;
+                    let (name, value) = match self.read_attribute(next_token) {
+                        Ok(attribute) => attribute,
+                        // the element is kept without the attribute
+                        Err(e) if self.salvage && is_salvageable(&e) => {
+                            let detail = self.with_context(e).to_string();
+                            self.diagnostics.warning(
+                                Location::Token(self.tokens),
+                                "Skipped an attribute that cannot be decoded",
+                                Some(detail),
+                            );
+                            continue;
+                        }
+                        // resume at the next tag instead of giving up the document
+                        Err(e)
+                            if self.lenient
+                                && is_salvageable(&e)
+                                && !matches!(e, ConversionError::InvalidName { .. }) =>
+                        {
+                            let skipped = self.skip_to_element_boundary()?;
+                            self.diagnostics.error(
+                                Location::Token(self.tokens),
+                                &format!(
+                                    "{}; skipped {} bytes to the next element boundary",
+                                    e, skipped
+                                ),
+                            )?;
+                            break;
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    if self.canonical {
+                        sorted.push((name, value));
+                    } else {
+                        self.write_attribute(name, value)?;
+                    }
+                }
+                sorted.sort_by(|(a, _), (b, _)| a.cmp(b));
+                for (name, value) in sorted {
+                    self.write_attribute(name, value)?;
+                }
+
+                if let Some(hints) = &self.type_hints
+                    && !hints.is_empty()
+                {
+                    write!(self.output, " {}=\"{}\"", TYPE_HINT_ATTRIBUTE, hints)?;
+                }
+                self.output.write_all(b">")?;
+                Ok(true)
+            }
+            END_TAG => {
+                if let Some(tag_name) = self.read_end_tag()? {
+                    self.output.write_all(b"</")?;
+                    self.output.write_all(tag_name.as_bytes())?;
+                    self.output.write_all(b">")?;
+                    return Ok(self.complete_element());
+                }
+                Ok(true)
+            }
+            TEXT => {
+                if type_info == TYPE_STRING {
+                    let text = self.input.read_utf()?;
+                    if self.fidelity && (after_text || text.is_empty()) {
+                        self.directive("text")?;
+                    }
+                    self.after_text = true;
+                    if !text.is_empty() {
+                        let encoded = self.encode(&text);
+                        self.output.write_all(encoded.as_bytes())?;
+                    }
+                }
+                Ok(true)
+            }
+            CDSECT => {
+                if type_info == TYPE_STRING {
+                    let text = self.input.read_utf()?;
+                    self.output.write_all(b"<![CDATA[")?;
+                    self.output.write_all(text.as_bytes())?;
+                    self.output.write_all(b"]]>")?;
+                }
+                Ok(true)
+            }
+            COMMENT => {
+                if type_info == TYPE_STRING {
+                    let text = self.input.read_utf()?;
+                    self.output.write_all(b"<!--")?;
+                    self.output.write_all(text.as_bytes())?;
+                    self.output.write_all(b"-->")?;
+                }
+                Ok(true)
+            }
+            PROCESSING_INSTRUCTION => {
+                if type_info == TYPE_STRING {
+                    let text = self.input.read_utf()?;
+                    self.output.write_all(b"<?")?;
+                    self.output.write_all(text.as_bytes())?;
+                    self.output.write_all(b"?>")?;
+                }
+                Ok(true)
+            }
+            DOCDECL => {
+                if type_info == TYPE_STRING {
+                    let text = self.input.read_utf()?;
+                    self.output.write_all(b"<!DOCTYPE ")?;
+                    self.output.write_all(text.as_bytes())?;
+                    self.output.write_all(b">")?;
+                }
+                Ok(true)
+            }
+            ENTITY_REF => {
+                if type_info == TYPE_STRING {
+                    let text = self.input.read_utf()?;
+                    if self.fidelity {
+                        self.directive("ref")?;
+                    }
+                    self.output.write_all(b"&")?;
+                    self.output.write_all(text.as_bytes())?;
+                    self.output.write_all(b";")?;
+                }
+                Ok(true)
+            }
+            IGNORABLE_WHITESPACE => {
+                if type_info == TYPE_STRING {
+                    let text = self.input.read_utf()?;
+                    if self.fidelity {
+                        self.directive("ignorable")?;
+                        self.after_text = true;
+                        self.output.write_all(self.encode(&text).as_bytes())?;
+                    } else {
+                        self.output.write_all(text.as_bytes())?;
+                    }
+                }
+                Ok(true)
+            }
+            #[cfg(feature = "unstable")]
+            _ if self.extensions.for_command(command).is_some() => {
+                let extensions = self.extensions.clone();
+                let extension = extensions.for_command(command).unwrap();
+                let data = extension.decode(type_info, &mut self.input)?;
+                self.output.write_all(b"<?")?;
+                self.output.write_all(extension.target().as_bytes())?;
+                if !data.is_empty() {
+                    self.output.write_all(b" ")?;
+                    self.output.write_all(data.as_bytes())?;
+                }
+                self.output.write_all(b"?>")?;
+                Ok(true)
+            }
+            _ => {
+                if self.lenient {
+                    self.diagnostics.error(
+                        Location::Token(self.tokens),
+                        &format!("Unknown token: {}", command),
+                    )?;
+                } else {
+                    self.diagnostics.warning(
+                        Location::Token(self.tokens),
+                        &format!("Unknown token: {}", command),
+                        None,
+                    );
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    /// Escapes text or an attribute value for XML
+    /// [`Options::canonical`]: writes the text node starting with `command`,
+    /// all TEXT, ENTITY_REF and IGNORABLE_WHITESPACE tokens in a row, trimmed
+    /// as a whole by [`trim_text_node`]
+    fn write_canonical_text(&mut self, command: u8) -> Result<bool> {
+        let mut pieces = Vec::new();
+        let mut command = command;
+        loop {
+            let text = self.input.read_utf()?;
+            pieces.push((
+                if command == ENTITY_REF {
+                    ENTITY_REF
+                } else {
+                    TEXT
+                },
+                text,
+            ));
+            match self.input.peek_byte() {
+                Ok(next)
+                    if next & 0xF0 == TYPE_STRING
+                        && matches!(next & 0x0F, TEXT | ENTITY_REF | IGNORABLE_WHITESPACE) =>
+                {
+                    command = self.read_token()? & 0x0F;
+                    self.tokens += 1;
+                }
+                _ => break,
+            }
+        }
+
+        trim_text_node(&mut pieces);
+        for (command, text) in pieces {
+            if command == ENTITY_REF {
+                self.output.write_all(b"&")?;
+                self.output.write_all(text.as_bytes())?;
+                self.output.write_all(b";")?;
+            } else {
+                let encoded = self.encode(&text);
+                self.output.write_all(encoded.as_bytes())?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn encode<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let encoded = encode_xml_entities(text);
+        if !self.escape_bytes {
+            return encoded;
+        }
+        match encode_escaped_bytes(&encoded) {
+            Cow::Borrowed(_) => encoded,
+            Cow::Owned(escaped) => Cow::Owned(escaped),
+        }
+    }
+
+    /// Writes an [`Options::fidelity`] directive
+    fn directive(&mut self, directive: &str) -> Result<()> {
+        write!(self.output, "<?abx {}?>", directive)?;
+        Ok(())
+    }
+
+    fn read_attribute(&mut self, token: u8) -> Result<(SmolStr, AttributeValue)> {
+        let type_info = token & 0xF0;
+        let name = self.read_interned_utf()?;
+        self.name_policy.check(&name)?;
+        let value = if type_info == TYPE_STRING_INTERNED {
+            AttributeValue::Interned(self.read_interned_utf()?)
+        } else {
+            self.input.read_attribute_value(type_info)?
+        };
+        Ok((name, value))
+    }
+
+    fn write_attribute(&mut self, name: SmolStr, value: AttributeValue) -> Result<()> {
+        self.output.write_all(b" ")?;
+        self.output.write_all(name.as_bytes())?;
+        self.output.write_all(b"=\"")?;
+
+        if let Some(hints) = &mut self.type_hints {
+            if !hints.is_empty() {
+                hints.push(' ');
+            }
+            hints.push_str(&name);
+            hints.push('=');
+            hints.push_str(&hint_type_name(&value));
+        }
+        if let Some(frame) = self.frames.last_mut() {
+            frame.attributes.push((name, value.clone()));
+        }
+
+        match value {
+            AttributeValue::String(value) => {
+                let encoded = self.encode(&value);
+                self.output.write_all(encoded.as_bytes())?;
+            }
+            AttributeValue::Interned(value) => {
+                let encoded = self.encode(&value);
+                self.output.write_all(encoded.as_bytes())?;
+            }
+            AttributeValue::Float(value) => {
+                let text = self.float_format.format_f32(value);
+                self.output.write_all(text.as_bytes())?;
+            }
+            AttributeValue::Double(value) => {
+                let text = self.float_format.format_f64(value);
+                self.output.write_all(text.as_bytes())?;
+            }
+            value => write!(self.output, "{}", value)?,
+        }
+
+        self.output.write_all(b"\"")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,625 @@
+use crate::query::ElementFrame;
+use crate::reader::{AttributeValue, DataInput, check_magic};
+use crate::writer::BinaryXmlSerializer;
+use crate::xpath::XPath;
+use crate::*;
+use smol_str::SmolStr;
+use std::fmt;
+use std::io::{Cursor, Read, Write};
+
+// ============================================================================
+// Token Stream
+// ============================================================================
+
+/// A single ABX token with its payload decoded
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    StartDocument,
+    EndDocument,
+    StartTag(SmolStr),
+    EndTag(SmolStr),
+    Attribute {
+        name: SmolStr,
+        value: AttributeValue,
+    },
+    /// TEXT, CDSECT, COMMENT, PROCESSING_INSTRUCTION, DOCDECL, ENTITY_REF or
+    /// IGNORABLE_WHITESPACE; `text` is `None` when the token was written with TYPE_NULL
+    Content {
+        command: u8,
+        text: Option<String>,
+    },
+}
+
+impl fmt::Display for Token {
+    /// Formats the token as one line of the canonical dump.
+    ///
+    /// Strings are quoted and escaped, floats use the shortest round-trip form and
+    /// byte arrays are written as hex, so the output never depends on pool indices
+    /// or platform formatting.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::StartDocument => f.write_str("START_DOCUMENT"),
+            Token::EndDocument => f.write_str("END_DOCUMENT"),
+            Token::StartTag(name) => write!(f, "START_TAG {}", name),
+            Token::EndTag(name) => write!(f, "END_TAG {}", name),
+            Token::Attribute { name, value } => {
+                write!(f, "ATTRIBUTE {} {} ", name, value.type_name())?;
+                match value {
+                    AttributeValue::String(value) => write!(f, "{:?}", value),
+                    AttributeValue::Interned(value) => write!(f, "{:?}", value.as_str()),
+                    AttributeValue::IntHex(value) => write!(f, "{:#x}", value),
+                    AttributeValue::LongHex(value) => write!(f, "{:#x}", value),
+                    AttributeValue::Float(value) => write!(f, "{:?}", value),
+                    AttributeValue::Double(value) => write!(f, "{:?}", value),
+                    AttributeValue::BytesHex(bytes) | AttributeValue::BytesBase64(bytes) => {
+                        f.write_str(&hex_string(bytes))
+                    }
+                    value => write!(f, "{}", value),
+                }
+            }
+            Token::Content { command, text } => {
+                match token_name(*command) {
+                    Some(name) => f.write_str(name)?,
+                    None => write!(f, "TOKEN_{}", command)?,
+                }
+                match text {
+                    Some(text) => write!(f, " {:?}", text),
+                    None => f.write_str(" null"),
+                }
+            }
+        }
+    }
+}
+
+/// Decodes an ABX stream one [`Token`] at a time
+pub struct TokenReader<R: Read> {
+    input: DataInput<R>,
+}
+
+impl<R: Read> TokenReader<R> {
+    pub fn new(reader: R) -> Result<Self> {
+        Self::from_options(reader, &Options::default())
+    }
+
+    /// Creates a reader whose intern pool is seeded as [`Options`] configure
+    pub fn from_options(mut reader: R, options: &Options) -> Result<Self> {
+        check_magic(&mut reader)?;
+        Ok(Self {
+            input: DataInput::from_options(reader, options),
+        })
+    }
+
+    /// Reads the next token, or `None` at the end of the stream
+    pub fn next_token(&mut self) -> Result<Option<Token>> {
+        let token = match self.input.read_byte() {
+            Ok(token) => token,
+            Err(ConversionError::ReadError(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let command = token & 0x0F;
+        let type_info = token & 0xF0;
+
+        let token = match command {
+            START_DOCUMENT => Token::StartDocument,
+            END_DOCUMENT => Token::EndDocument,
+            START_TAG => Token::StartTag(self.input.read_interned_utf()?),
+            END_TAG => Token::EndTag(self.input.read_interned_utf()?),
+            ATTRIBUTE => {
+                let name = self.input.read_interned_utf()?;
+                let value = self.input.read_attribute_value(type_info)?;
+                Token::Attribute { name, value }
+            }
+            TEXT
+            | CDSECT
+            | COMMENT
+            | PROCESSING_INSTRUCTION
+            | DOCDECL
+            | ENTITY_REF
+            | IGNORABLE_WHITESPACE => {
+                let text = match type_info {
+                    TYPE_STRING => Some(self.input.read_utf()?),
+                    TYPE_NULL => None,
+                    _ => {
+                        return Err(ConversionError::ParseError(format!(
+                            "Unsupported type {:#04x} for token {}",
+                            type_info, command
+                        )));
+                    }
+                };
+                Token::Content { command, text }
+            }
+            _ => {
+                return Err(ConversionError::ParseError(format!(
+                    "Unknown token: {}",
+                    command
+                )));
+            }
+        };
+        Ok(Some(token))
+    }
+
+    /// Strings interned so far, in definition order
+    pub fn interned_strings(&self) -> &[SmolStr] {
+        self.input.interned_strings()
+    }
+
+    /// Reads everything left in the stream, e.g. bytes following END_DOCUMENT
+    pub fn read_remaining(&mut self) -> Result<Vec<u8>> {
+        self.input.read_remaining()
+    }
+}
+
+/// Yields tokens until the end of the stream or the first error
+impl<R: Read> Iterator for TokenReader<R> {
+    type Item = Result<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token().transpose()
+    }
+}
+
+// ============================================================================
+// Raw Document
+// ============================================================================
+
+/// An ABX document decoded to its token stream, remembering everything needed to
+/// write it back byte-for-byte: the original intern pool order and any bytes
+/// following END_DOCUMENT.
+///
+/// Tokens can be modified freely before encoding. Strings that are still used keep
+/// their original pool positions; new strings are appended as they are first used.
+#[derive(Debug, Clone, Default)]
+pub struct RawDocument {
+    pub tokens: Vec<Token>,
+    intern_order: Vec<SmolStr>,
+    trailing: Vec<u8>,
+}
+
+impl RawDocument {
+    pub fn decode<R: Read>(reader: R) -> Result<Self> {
+        Self::decode_with(reader, &Options::default())
+    }
+
+    /// Decodes a document written with a seeded intern pool, see
+    /// [`Options::dictionary`]. The seeded strings lead the intern order.
+    pub fn decode_with<R: Read>(reader: R, options: &Options) -> Result<Self> {
+        let mut reader = TokenReader::from_options(reader, options)?;
+        let mut tokens = Vec::new();
+        let mut trailing = Vec::new();
+
+        while let Some(token) = reader.next_token()? {
+            let end = token == Token::EndDocument;
+            tokens.push(token);
+            if end {
+                trailing = reader.read_remaining()?;
+                break;
+            }
+        }
+
+        Ok(Self {
+            tokens,
+            intern_order: reader.interned_strings().to_vec(),
+            trailing,
+        })
+    }
+
+    /// Creates a document from tokens; interned strings are defined in first-use order
+    pub fn from_tokens(tokens: Vec<Token>) -> Self {
+        Self {
+            tokens,
+            ..Self::default()
+        }
+    }
+
+    /// Creates a document whose strings keep the pool positions of `intern_order`
+    /// as far as the tokens still use them
+    pub(crate) fn with_intern_order(tokens: Vec<Token>, intern_order: Vec<SmolStr>) -> Self {
+        Self {
+            tokens,
+            intern_order,
+            ..Self::default()
+        }
+    }
+
+    /// Creates a document from any token source, e.g. a [`TokenReader`] or a
+    /// filtered [`Self::into_events`]; interned strings are defined in first-use order
+    pub fn from_events<I: IntoIterator<Item = Token>>(events: I) -> Self {
+        Self::from_tokens(events.into_iter().collect())
+    }
+
+    /// Hands the tokens over for streaming processing. The original intern pool
+    /// order and trailing bytes are not part of the events.
+    pub fn into_events(self) -> std::vec::IntoIter<Token> {
+        self.tokens.into_iter()
+    }
+
+    pub fn events(&self) -> std::slice::Iter<'_, Token> {
+        self.tokens.iter()
+    }
+
+    pub fn from_bytes(abx_data: &[u8]) -> Result<Self> {
+        Self::decode(Cursor::new(abx_data))
+    }
+
+    /// The intern pool of the original document, in definition order
+    pub fn intern_order(&self) -> &[SmolStr] {
+        &self.intern_order
+    }
+
+    /// Bytes that followed END_DOCUMENT in the original stream
+    pub fn trailing_bytes(&self) -> &[u8] {
+        &self.trailing
+    }
+
+    pub fn encode<W: Write>(&self, writer: W) -> Result<()> {
+        let mut serializer =
+            BinaryXmlSerializer::with_intern_order(writer, self.intern_order.clone())?;
+
+        for token in &self.tokens {
+            match token {
+                Token::StartDocument => serializer.start_document()?,
+                Token::EndDocument => serializer.end_document()?,
+                Token::StartTag(name) => serializer.start_tag(name)?,
+                Token::EndTag(name) => serializer.end_tag(name)?,
+                Token::Attribute { name, value } => serializer.attribute_value(name, value)?,
+                Token::Content { command, text } => {
+                    serializer.write_token(*command, text.as_deref())?
+                }
+            }
+        }
+
+        serializer.raw_bytes(&self.trailing)?;
+        serializer.flush()
+    }
+
+    /// Writes a deterministic textual dump of the token stream, one token per line.
+    ///
+    /// Meant for snapshot tests: the format only changes when the typed content of the
+    /// document changes, not when strings move around in the intern pool.
+    pub fn dump_to<W: Write>(&self, mut output: W) -> Result<()> {
+        for token in &self.tokens {
+            writeln!(output, "{}", token)?;
+        }
+        if !self.trailing.is_empty() {
+            writeln!(output, "TRAILING {}", hex_string(&self.trailing))?;
+        }
+        Ok(())
+    }
+
+    pub fn dump(&self) -> String {
+        let mut output = Vec::new();
+        // writing to a Vec cannot fail and tokens only contain valid UTF-8
+        let _ = self.dump_to(&mut output);
+        String::from_utf8(output).unwrap_or_default()
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        self.encode(&mut output)?;
+        Ok(output)
+    }
+}
+
+impl FromIterator<Token> for RawDocument {
+    fn from_iter<I: IntoIterator<Item = Token>>(events: I) -> Self {
+        Self::from_events(events)
+    }
+}
+
+impl IntoIterator for RawDocument {
+    type Item = Token;
+    type IntoIter = std::vec::IntoIter<Token>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_events()
+    }
+}
+
+/// Collects the tokens of a [`Pipeline`](crate::pipeline::Pipeline) run
+impl crate::pipeline::Sink for RawDocument {
+    fn token(&mut self, token: &Token) -> Result<()> {
+        self.tokens.push(token.clone());
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Editing
+// ============================================================================
+
+/// Elements are addressed by the index of their START_TAG in [`RawDocument::tokens`],
+/// as returned by [`RawDocument::select_elements`]; an index that does not point at
+/// a START_TAG is an error.
+///
+/// Indices are plain positions, not stable handles. Replacing the value of an
+/// existing attribute keeps every index valid, but adding or removing an attribute
+/// and removing, replacing or appending an element insert or remove tokens, which
+/// shifts the index of every element after the edited one. Apply several such edits
+/// from the last element to the first, or look elements up again after each one.
+///
+/// ```
+/// use android_xml_converter::reader::AttributeValue;
+/// use android_xml_converter::roundtrip::RawDocument;
+/// use android_xml_converter::xpath::XPath;
+/// use android_xml_converter::XmlToAbxConverter;
+///
+/// let mut abx = Vec::new();
+/// let xml = r#"<packages><package name="a" userId="10001"/><package name="b" userId="10002"/></packages>"#;
+/// XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+///
+/// let mut document = RawDocument::from_bytes(&abx).unwrap();
+/// let path = XPath::parse("package[@name='b']").unwrap();
+/// let package = document.select_elements(&path)[0];
+/// let old = document.set_attribute(package, "userId", AttributeValue::Int(10003)).unwrap();
+/// assert_eq!(old, Some(AttributeValue::Int(10002)));
+///
+/// // only the changed value differs, the intern pool is left as it was
+/// let edited = document.to_bytes().unwrap();
+/// assert_eq!(edited.len(), abx.len());
+/// assert_eq!(edited.iter().zip(&abx).filter(|(a, b)| a != b).count(), 1);
+///
+/// // adding attributes shifts later elements, so edit from the back
+/// let packages = document.select_elements(&XPath::parse("package").unwrap());
+/// for &package in packages.iter().rev() {
+///     document.set_attribute(package, "stopped", AttributeValue::Boolean(true)).unwrap();
+/// }
+/// // ...and look them up again afterwards
+/// let packages = document.select_elements(&XPath::parse("package[@stopped='true']").unwrap());
+/// assert_eq!(packages.len(), 2);
+///
+/// // index 0 is START_DOCUMENT
+/// assert_eq!(document.element_name(0), None);
+/// assert!(document.set_attribute(0, "stopped", AttributeValue::Boolean(true)).is_err());
+/// ```
+impl RawDocument {
+    /// Start tag indices of the elements an [`XPath`] selects, in document
+    /// order; a final attribute step is ignored
+    pub fn select_elements(&self, path: &XPath) -> Vec<usize> {
+        let mut stack: Vec<ElementFrame> = Vec::new();
+        let mut found = Vec::new();
+
+        for (index, token) in self.tokens.iter().enumerate() {
+            match token {
+                Token::StartTag(name) => {
+                    let attributes = self
+                        .attribute_tokens(index)
+                        .into_iter()
+                        .flatten()
+                        .map(|(name, value)| (name.clone(), value.clone()))
+                        .collect();
+                    stack.push(ElementFrame {
+                        name: name.clone(),
+                        attributes,
+                    });
+                    if path.matches(&stack) {
+                        found.push(index);
+                    }
+                }
+                Token::EndTag(_) => {
+                    stack.pop();
+                }
+                _ => {}
+            }
+        }
+        found
+    }
+
+    /// Name of the element starting at `element`, or `None` when the token there
+    /// is not a START_TAG
+    pub fn element_name(&self, element: usize) -> Option<&SmolStr> {
+        match self.tokens.get(element) {
+            Some(Token::StartTag(name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Value of attribute `name`, or `None` when the element lacks it or
+    /// `element` is not a START_TAG
+    pub fn attribute(&self, element: usize, name: &str) -> Option<&AttributeValue> {
+        self.attribute_tokens(element)
+            .ok()?
+            .find(|(attr_name, _)| attr_name.as_str() == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Replaces an attribute in place, or appends it after the element's other
+    /// attributes; returns the previous value. Appending shifts later indices.
+    pub fn set_attribute(
+        &mut self,
+        element: usize,
+        name: &str,
+        value: AttributeValue,
+    ) -> Result<Option<AttributeValue>> {
+        match self.attribute_index(element, name)? {
+            Some(index) => match &mut self.tokens[index] {
+                Token::Attribute { value: old, .. } => Ok(Some(std::mem::replace(old, value))),
+                _ => unreachable!(),
+            },
+            None => {
+                let end = self.attributes_end(element)?;
+                self.tokens.insert(
+                    end,
+                    Token::Attribute {
+                        name: SmolStr::new(name),
+                        value,
+                    },
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Removes an attribute and returns its value. Removing shifts later indices.
+    pub fn remove_attribute(
+        &mut self,
+        element: usize,
+        name: &str,
+    ) -> Result<Option<AttributeValue>> {
+        let Some(index) = self.attribute_index(element, name)? else {
+            return Ok(None);
+        };
+        match self.tokens.remove(index) {
+            Token::Attribute { value, .. } => Ok(Some(value)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Removes an element with everything inside it
+    pub fn remove_element(&mut self, element: usize) -> Result<()> {
+        let end = self.element_end(element)?;
+        self.tokens.drain(element..end);
+        Ok(())
+    }
+
+    /// A standalone document holding a copy of the element with everything
+    /// inside it. Its intern pool is rebuilt from scratch, so it only defines
+    /// the strings the element uses.
+    ///
+    /// ```
+    /// use android_xml_converter::roundtrip::RawDocument;
+    /// use android_xml_converter::xpath::XPath;
+    /// use android_xml_converter::{AbxToXmlConverter, XmlToAbxConverter};
+    ///
+    /// let mut abx = Vec::new();
+    /// let xml = r#"<packages><package name="a"><perms/></package><package name="b"/></packages>"#;
+    /// XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+    ///
+    /// let document = RawDocument::from_bytes(&abx).unwrap();
+    /// let package = document.select_elements(&XPath::parse("package[@name='a']").unwrap())[0];
+    /// let extracted = document.extract_element(package).unwrap().to_bytes().unwrap();
+    ///
+    /// let pool = RawDocument::from_bytes(&extracted).unwrap();
+    /// assert_eq!(pool.intern_order(), ["package", "name", "a", "perms"]);
+    /// let xml = AbxToXmlConverter::convert_bytes(&extracted).unwrap();
+    /// assert!(xml.ends_with(r#"<package name="a"><perms></perms></package>"#));
+    /// ```
+    pub fn extract_element(&self, element: usize) -> Result<RawDocument> {
+        let end = self.element_end(element)?;
+        let mut tokens = Vec::with_capacity(end - element + 2);
+        tokens.push(Token::StartDocument);
+        tokens.extend_from_slice(&self.tokens[element..end]);
+        tokens.push(Token::EndDocument);
+        Ok(Self::from_tokens(tokens))
+    }
+
+    /// Replaces an element with everything inside it by the root element of
+    /// `fragment`, e.g. one converted from XML, and returns the index of the new
+    /// element. Strings the document already interns keep their pool positions.
+    ///
+    /// ```
+    /// use android_xml_converter::roundtrip::RawDocument;
+    /// use android_xml_converter::xpath::XPath;
+    /// use android_xml_converter::{AbxToXmlConverter, XmlToAbxConverter};
+    ///
+    /// let mut abx = Vec::new();
+    /// let xml = r#"<packages><package name="a"/><package name="b"/></packages>"#;
+    /// XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+    /// let mut fragment = Vec::new();
+    /// XmlToAbxConverter::convert_from_string(r#"<package name="a" stopped="true"/>"#, &mut fragment)
+    ///     .unwrap();
+    ///
+    /// let mut document = RawDocument::from_bytes(&abx).unwrap();
+    /// let package = document.select_elements(&XPath::parse("package[@name='a']").unwrap())[0];
+    /// document
+    ///     .replace_element(package, &RawDocument::from_bytes(&fragment).unwrap())
+    ///     .unwrap();
+    ///
+    /// let xml = AbxToXmlConverter::convert_bytes(&document.to_bytes().unwrap()).unwrap();
+    /// assert!(xml.ends_with(r#"<packages><package name="a" stopped="true"></package><package name="b"></package></packages>"#));
+    /// ```
+    pub fn replace_element(&mut self, element: usize, fragment: &RawDocument) -> Result<usize> {
+        let root = fragment
+            .tokens
+            .iter()
+            .position(|token| matches!(token, Token::StartTag(_)))
+            .ok_or_else(|| {
+                ConversionError::ParseError("The fragment has no root element".to_string())
+            })?;
+        let root_end = fragment.element_end(root)?;
+        let end = self.element_end(element)?;
+        self.tokens.splice(
+            element..end,
+            fragment.tokens[root..root_end].iter().cloned(),
+        );
+        Ok(element)
+    }
+
+    /// Adds an empty element as the last child of `parent` and returns its index
+    pub fn append_child(
+        &mut self,
+        parent: usize,
+        name: &str,
+        attributes: Vec<(SmolStr, AttributeValue)>,
+    ) -> Result<usize> {
+        // insert before the parent's END_TAG, or at the end of an unclosed element
+        let end = self.element_end(parent)?;
+        let index = match self.tokens.get(end - 1) {
+            Some(Token::EndTag(_)) if end - 1 > parent => end - 1,
+            _ => end,
+        };
+
+        let name = SmolStr::new(name);
+        let mut tokens = vec![Token::StartTag(name.clone())];
+        tokens.extend(
+            attributes
+                .into_iter()
+                .map(|(name, value)| Token::Attribute { name, value }),
+        );
+        tokens.push(Token::EndTag(name));
+        self.tokens.splice(index..index, tokens);
+        Ok(index)
+    }
+
+    /// Name of the element starting at `element`, as an error when the token
+    /// there is not a START_TAG
+    fn start_tag(&self, element: usize) -> Result<&SmolStr> {
+        self.element_name(element).ok_or_else(|| {
+            ConversionError::ParseError(format!("Token {} is not a start tag", element))
+        })
+    }
+
+    fn attribute_tokens(
+        &self,
+        element: usize,
+    ) -> Result<impl Iterator<Item = (&SmolStr, &AttributeValue)> + '_> {
+        self.start_tag(element)?;
+        Ok(self.tokens[element + 1..]
+            .iter()
+            .map_while(|token| match token {
+                Token::Attribute { name, value } => Some((name, value)),
+                _ => None,
+            }))
+    }
+
+    fn attribute_index(&self, element: usize, name: &str) -> Result<Option<usize>> {
+        Ok(self
+            .attribute_tokens(element)?
+            .position(|(attr_name, _)| attr_name.as_str() == name)
+            .map(|offset| element + 1 + offset))
+    }
+
+    /// Index just past the element's attributes
+    fn attributes_end(&self, element: usize) -> Result<usize> {
+        Ok(element + 1 + self.attribute_tokens(element)?.count())
+    }
+
+    /// Index just past the element's END_TAG
+    fn element_end(&self, element: usize) -> Result<usize> {
+        self.start_tag(element)?;
+        let mut depth = 0usize;
+        for (index, token) in self.tokens.iter().enumerate().skip(element) {
+            match token {
+                Token::StartTag(_) => depth += 1,
+                Token::EndTag(_) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(index + 1);
+                    }
+                }
+                Token::EndDocument => return Ok(index),
+                _ => {}
+            }
+        }
+        Ok(self.tokens.len())
+    }
+}
@@ -0,0 +1,607 @@
+use crate::query::ElementScanner;
+use crate::reader::AttributeValue;
+use crate::*;
+use smol_str::SmolStr;
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+// ============================================================================
+// Type Schema
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    /// Starts at the root element instead of matching at any depth
+    absolute: bool,
+    /// Element names from the outermost to the attribute's element, `*` for any
+    elements: Vec<String>,
+    attribute: String,
+    type_name: &'static str,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.absolute {
+            f.write_str("/")?;
+        }
+        write!(f, "{}/@{}", self.elements.join("/"), self.attribute)
+    }
+}
+
+impl Rule {
+    fn matches(&self, path: &[String], attribute: &str) -> bool {
+        if self.attribute != "*" && self.attribute != attribute {
+            return false;
+        }
+        if path.len() < self.elements.len() || (self.absolute && path.len() != self.elements.len())
+        {
+            return false;
+        }
+        path[path.len() - self.elements.len()..]
+            .iter()
+            .zip(&self.elements)
+            .all(|(name, step)| step == "*" || step == name)
+    }
+}
+
+/// ABX types for attributes chosen by their location, consulted when converting
+/// XML to ABX instead of guessing from the text (see [`Options::type_schema`]).
+///
+/// A schema maps paths like `package/@userId` or `sigs/cert/@key` to the type
+/// names of [`AttributeValue::TYPE_NAMES`] (`-` may be used instead of `_`, as
+/// in `bytes-hex`). Paths match the innermost elements at any depth unless they
+/// start with `/`, and `*` stands for any element or attribute name. The first
+/// matching rule wins.
+///
+/// Schema files are either lines of `path = type`, with `#` comments and
+/// optionally quoted paths and types (so TOML files with a flat table, under
+/// any `[section]`, work), or a flat JSON object of path/type pairs.
+///
+/// ```
+/// use android_xml_converter::{Options, XmlToAbxConverter};
+/// use android_xml_converter::dom::AbxDocument;
+/// use android_xml_converter::reader::AttributeValue;
+/// use android_xml_converter::schema::TypeSchema;
+///
+/// let schema = TypeSchema::parse(
+///     r#"
+///     package/@userId = int  # not a guessed string
+///     "sigs/cert/@key" = "bytes-hex"
+///     "#,
+/// )
+/// .unwrap();
+///
+/// let xml = r#"<packages><package name="a" userId="10003"><sigs><cert key="0a0b"/></sigs></package></packages>"#;
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string_with(xml, &mut abx, &Options::new().type_schema(schema))
+///     .unwrap();
+///
+/// let document = AbxDocument::from_bytes(&abx).unwrap();
+/// let package = document.root().unwrap().child("package").unwrap();
+/// assert_eq!(package.attribute("userId"), Some(&AttributeValue::Int(10003)));
+/// let cert = package.child("sigs").unwrap().child("cert").unwrap();
+/// assert_eq!(cert.attribute("key"), Some(&AttributeValue::BytesHex(vec![0x0a, 0x0b])));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TypeSchema {
+    rules: Vec<Rule>,
+}
+
+impl TypeSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a rule after the existing ones
+    pub fn rule(mut self, path: &str, type_name: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            ConversionError::ParseError(format!("Invalid schema path '{}': {}", path, reason))
+        };
+
+        let type_name = known_type(type_name)?;
+
+        let (elements, attribute) = path
+            .rsplit_once("/@")
+            .ok_or_else(|| invalid("expected ELEMENT/@ATTRIBUTE"))?;
+        let absolute = elements.starts_with('/');
+        let elements: Vec<String> = elements
+            .trim_start_matches('/')
+            .split('/')
+            .map(str::to_string)
+            .collect();
+        if attribute.is_empty() || elements.iter().any(String::is_empty) {
+            return Err(invalid("empty step"));
+        }
+
+        self.rules.push(Rule {
+            absolute,
+            elements,
+            attribute: attribute.to_string(),
+            type_name,
+        });
+        Ok(self)
+    }
+
+    /// Parses a schema file in either supported format
+    pub fn parse(text: &str) -> Result<Self> {
+        if text.trim_start().starts_with('{') {
+            Self::parse_json(text)
+        } else {
+            Self::parse_lines(text)
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse_lines(text: &str) -> Result<Self> {
+        let unquote = |s: &str| {
+            let s = s.trim();
+            s.strip_prefix('"')
+                .and_then(|s| s.strip_suffix('"'))
+                .or_else(|| s.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')))
+                .unwrap_or(s)
+                .to_string()
+        };
+
+        let mut schema = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            // neither paths nor type names contain '#'
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() || (line.starts_with('[') && line.ends_with(']')) {
+                continue;
+            }
+            let (path, type_name) = line.split_once('=').ok_or_else(|| {
+                ConversionError::ParseError(format!(
+                    "Invalid schema line {}, expected PATH = TYPE: {}",
+                    index + 1,
+                    line
+                ))
+            })?;
+            schema = schema.rule(&unquote(path), &unquote(type_name))?;
+        }
+        Ok(schema)
+    }
+
+    fn parse_json(text: &str) -> Result<Self> {
+        type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+        fn invalid(reason: &str) -> ConversionError {
+            ConversionError::ParseError(format!("Invalid JSON schema: {}", reason))
+        }
+
+        fn skip_whitespace(chars: &mut Chars) {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        }
+
+        fn string(chars: &mut Chars) -> Result<String> {
+            if chars.next() != Some('"') {
+                return Err(invalid("expected a string"));
+            }
+            let mut value = String::new();
+            loop {
+                match chars.next().ok_or_else(|| invalid("unterminated string"))? {
+                    '"' => return Ok(value),
+                    '\\' => match chars.next() {
+                        Some(c @ ('"' | '\\' | '/')) => value.push(c),
+                        Some('u') => {
+                            let hex: String = chars.by_ref().take(4).collect();
+                            let c = u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| invalid("bad \\u escape"))?;
+                            value.push(c);
+                        }
+                        _ => return Err(invalid("unsupported escape")),
+                    },
+                    c => value.push(c),
+                }
+            }
+        }
+
+        let mut chars = text.trim().chars().peekable();
+        let mut schema = Self::new();
+        if chars.next() != Some('{') {
+            return Err(invalid("expected an object"));
+        }
+        skip_whitespace(&mut chars);
+        if chars.next_if_eq(&'}').is_none() {
+            loop {
+                skip_whitespace(&mut chars);
+                let path = string(&mut chars)?;
+                skip_whitespace(&mut chars);
+                if chars.next() != Some(':') {
+                    return Err(invalid("expected ':'"));
+                }
+                skip_whitespace(&mut chars);
+                let type_name = string(&mut chars)?;
+                schema = schema.rule(&path, &type_name)?;
+                skip_whitespace(&mut chars);
+                match chars.next() {
+                    Some(',') => {}
+                    Some('}') => break,
+                    _ => return Err(invalid("expected ',' or '}'")),
+                }
+            }
+        }
+        skip_whitespace(&mut chars);
+        if chars.next().is_some() {
+            return Err(invalid("text after the object"));
+        }
+        Ok(schema)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The type of `attribute` on the innermost element of `path`, which lists
+    /// the open element names from the root
+    pub fn type_for(&self, path: &[String], attribute: &str) -> Option<&'static str> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(path, attribute))
+            .map(|rule| rule.type_name)
+    }
+}
+
+// ============================================================================
+// Learned Profiles
+// ============================================================================
+
+impl TypeSchema {
+    /// Records the ABX type of every element/attribute pair of a document, so
+    /// the XML rendering can be edited and encoded again with the same types.
+    ///
+    /// A pair whose type differs between occurrences gets a rule for each
+    /// location from the root instead; where even those differ, the first
+    /// occurrence wins.
+    ///
+    /// ```
+    /// use android_xml_converter::{Options, XmlToAbxConverter};
+    /// use android_xml_converter::schema::TypeSchema;
+    ///
+    /// let mut original = Vec::new();
+    /// XmlToAbxConverter::convert_from_string(r#"<a><b v="x"/><c><b v="true"/></c></a>"#, &mut original)
+    ///     .unwrap();
+    ///
+    /// let profile = TypeSchema::learn(original.as_slice()).unwrap();
+    /// let mut text = Vec::new();
+    /// profile.write_to(&mut text).unwrap();
+    /// assert_eq!(
+    ///     String::from_utf8(text).unwrap(),
+    ///     "\"/a/b/@v\" = \"interned\"\n\"/a/c/b/@v\" = \"boolean\"\n"
+    /// );
+    /// assert_eq!(TypeSchema::parse(&profile.to_string()).unwrap(), profile);
+    /// ```
+    pub fn learn<R: Read>(reader: R) -> Result<Self> {
+        // element/attribute pairs with their first type and whether others occur
+        let mut pairs: Vec<(SmolStr, SmolStr, &'static str, bool)> = Vec::new();
+        let mut pair_index: FastHashMap<(SmolStr, SmolStr), usize> = FastHashMap::default();
+        // the same by location from the root
+        let mut locations: Vec<(Vec<String>, SmolStr, &'static str)> = Vec::new();
+        let mut seen_locations: FastHashSet<(Vec<String>, SmolStr)> = FastHashSet::default();
+
+        ElementScanner::new(reader)?.scan(|stack| {
+            let Some(frame) = stack.last() else {
+                return Ok(());
+            };
+            let path: Vec<String> = stack.iter().map(|frame| frame.name.to_string()).collect();
+            for (attribute, value) in &frame.attributes {
+                let type_name = value.type_name();
+
+                let key = (frame.name.clone(), attribute.clone());
+                match pair_index.get(&key) {
+                    Some(&index) => pairs[index].3 |= pairs[index].2 != type_name,
+                    None => {
+                        pair_index.insert(key, pairs.len());
+                        pairs.push((frame.name.clone(), attribute.clone(), type_name, false));
+                    }
+                }
+
+                if seen_locations.insert((path.clone(), attribute.clone())) {
+                    locations.push((path.clone(), attribute.clone(), type_name));
+                }
+            }
+            Ok(())
+        })?;
+
+        let mut schema = Self::new();
+        for (element, attribute, type_name, mixed) in pairs {
+            if !mixed {
+                schema.rules.push(Rule {
+                    absolute: false,
+                    elements: vec![element.to_string()],
+                    attribute: attribute.to_string(),
+                    type_name,
+                });
+                continue;
+            }
+            for (path, _, type_name) in locations.iter().filter(|(path, name, _)| {
+                *name == attribute && path.last().is_some_and(|last| *last == element)
+            }) {
+                schema.rules.push(Rule {
+                    absolute: true,
+                    elements: path.clone(),
+                    attribute: attribute.to_string(),
+                    type_name,
+                });
+            }
+        }
+        Ok(schema)
+    }
+
+    /// Writes the schema as `"path" = "type"` lines, readable by [`Self::parse`]
+    /// and as a TOML table
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        write!(writer, "{}", self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for TypeSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for rule in &self.rules {
+            writeln!(f, "\"{}\" = \"{}\"", rule, rule.type_name)?;
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Bundled Profiles
+// ============================================================================
+
+/// Attributes of PackageManager's `package` and `updated-package` elements
+macro_rules! package_attributes {
+    ($element:literal) => {
+        concat!(
+            $element,
+            "/@ft = long-hex\n",
+            $element,
+            "/@it = long-hex\n",
+            $element,
+            "/@ut = long-hex\n",
+            $element,
+            "/@version = long\n",
+            $element,
+            "/@userId = int\n",
+            $element,
+            "/@sharedUserId = int\n",
+            $element,
+            "/@publicFlags = int\n",
+            $element,
+            "/@privateFlags = int\n",
+            $element,
+            "/@pkgFlags = int\n",
+            $element,
+            "/@isOrphaned = boolean\n",
+            $element,
+            "/@packageSource = int\n",
+            $element,
+            "/@loadingProgress = float\n",
+            $element,
+            "/@loadingCompletedTime = long\n",
+            $element,
+            "/@updateAvailable = boolean\n",
+            $element,
+            "/@forceQueryable = boolean\n",
+            $element,
+            "/@scannedAsStoppedSystemApp = boolean\n",
+            $element,
+            "/@categoryHint = int\n",
+            $element,
+            "/@* = string\n",
+        )
+    };
+}
+
+/// `/data/system/packages.xml`, as written by PackageManager
+const PACKAGES_PROFILE: &str = concat!(
+    "version/@sdkVersion = int\n",
+    "version/@databaseVersion = int\n",
+    "version/@* = string\n",
+    package_attributes!("package"),
+    package_attributes!("updated-package"),
+    "shared-user/@userId = int\n",
+    "shared-user/@pkgFlags = int\n",
+    "shared-user/@pkgPrivateFlags = int\n",
+    "shared-user/@* = string\n",
+    "sigs/@count = int\n",
+    "sigs/@schemeVersion = int\n",
+    "cert/@index = int\n",
+    "cert/@key = bytes-hex\n",
+    "cert/@flags = int\n",
+    "perms/item/@granted = boolean\n",
+    "perms/item/@flags = int-hex\n",
+    "perms/item/@* = string\n",
+    "public-key/@identifier = long\n",
+    "public-key/@value = bytes-base64\n",
+    "keyset/@identifier = long\n",
+    "key-id/@identifier = long\n",
+    "proper-signing-keyset/@identifier = long\n",
+    "signing-keyset/@identifier = long\n",
+    "upgrade-keyset/@identifier = long\n",
+    "defined-keyset/@identifier = long\n",
+    "defined-keyset/@alias = string\n",
+    "lastIssuedKeyId/@value = long\n",
+    "lastIssuedKeySetId/@value = long\n",
+);
+
+/// `/data/system/users/N/package-restrictions.xml`
+const PACKAGE_RESTRICTIONS_PROFILE: &str = "
+    pkg/@ceDataInode = long
+    pkg/@inst = boolean
+    pkg/@stopped = boolean
+    pkg/@nl = boolean
+    pkg/@hidden = boolean
+    pkg/@distraction_flags = int
+    pkg/@suspended = boolean
+    pkg/@instant-app = boolean
+    pkg/@virtual-preload = boolean
+    pkg/@enabled = int
+    pkg/@install-reason = int
+    pkg/@uninstall-reason = int
+    pkg/@first-install-time = long-hex
+    pkg/@* = string
+    item/@name = string
+";
+
+/// `settings_global.xml`, `settings_secure.xml` and `settings_system.xml`, as
+/// written by SettingsProvider
+const SETTINGS_PROFILE: &str = "
+    settings/@version = int
+    setting/@defaultSysSet = boolean
+    setting/@preserve_in_restore = boolean
+    setting/@* = string
+";
+
+/// `/data/system/appops.xml`, as written by AppOpsService
+const APPOPS_PROFILE: &str = "
+    app-ops/@v = int
+    uid/@n = int
+    uid/@p = boolean
+    pkg/@n = string
+    op/@n = int
+    op/@m = int
+    st/@n = long
+    st/@t = long
+    st/@r = long
+    st/@d = long
+    st/@pu = int
+    st/@* = string
+";
+
+/// Names accepted by [`TypeSchema::bundled`]
+pub const BUNDLED_PROFILES: &[&str] = &[
+    "appops",
+    "package-restrictions",
+    "packages",
+    "settings",
+    "settings_global",
+    "settings_secure",
+    "settings_system",
+];
+
+impl TypeSchema {
+    /// A profile for a well-known Android system file, giving its attributes
+    /// the types the system service writes them with, so converted files match
+    /// what PackageManager or SettingsProvider would have written. Attributes a
+    /// profile does not know are guessed as usual.
+    ///
+    /// ```
+    /// use android_xml_converter::{Options, XmlToAbxConverter};
+    /// use android_xml_converter::dom::AbxDocument;
+    /// use android_xml_converter::reader::AttributeValue;
+    /// use android_xml_converter::schema::TypeSchema;
+    ///
+    /// let schema = TypeSchema::bundled("settings_secure").unwrap();
+    /// let xml = r#"<settings version="213"><setting id="5" name="x" value="1" defaultSysSet="true" /></settings>"#;
+    /// let mut abx = Vec::new();
+    /// XmlToAbxConverter::convert_from_string_with(xml, &mut abx, &Options::new().type_schema(schema))
+    ///     .unwrap();
+    ///
+    /// let document = AbxDocument::from_bytes(&abx).unwrap();
+    /// let root = document.root().unwrap();
+    /// assert_eq!(root.attribute("version"), Some(&AttributeValue::Int(213)));
+    /// let setting = root.child("setting").unwrap();
+    /// assert_eq!(setting.attribute("id"), Some(&AttributeValue::String("5".to_string())));
+    /// assert_eq!(setting.attribute("defaultSysSet"), Some(&AttributeValue::Boolean(true)));
+    /// assert!(TypeSchema::bundled("contacts").is_none());
+    /// ```
+    pub fn bundled(name: &str) -> Option<Self> {
+        let text = match name {
+            "appops" => APPOPS_PROFILE,
+            "package-restrictions" => PACKAGE_RESTRICTIONS_PROFILE,
+            "packages" => PACKAGES_PROFILE,
+            "settings" | "settings_global" | "settings_secure" | "settings_system" => {
+                SETTINGS_PROFILE
+            }
+            _ => return None,
+        };
+        Some(Self::parse(text).expect("valid bundled profile"))
+    }
+
+    /// Adds the rules of `other` after the existing ones
+    pub fn append(mut self, other: Self) -> Self {
+        self.rules.extend(other.rules);
+        self
+    }
+}
+
+/// One of [`AttributeValue::TYPE_NAMES`], accepting `-` for `_`
+fn known_type(type_name: &str) -> Result<&'static str> {
+    let normalized = type_name.replace('-', "_");
+    AttributeValue::TYPE_NAMES
+        .iter()
+        .find(|name| **name == normalized)
+        .copied()
+        .ok_or_else(|| {
+            ConversionError::ParseError(format!("Unknown attribute type: {}", type_name))
+        })
+}
+
+// ============================================================================
+// Type Hints
+// ============================================================================
+
+/// Namespace of the type hints written by [`Options::emit_type_hints`], declared
+/// with the `abx` prefix on the root element
+pub const TYPE_HINT_NAMESPACE: &str = "urn:android-xml-converter:type-hints";
+
+/// Attribute holding the type hints of an element as `name=type` pairs
+/// separated by spaces, e.g. `abx:type="userId=int ft=long-hex"`
+pub const TYPE_HINT_ATTRIBUTE: &str = "abx:type";
+
+/// The spelling of a type in hints, `long-hex` for `long_hex`
+pub fn hint_type_name(value: &AttributeValue) -> String {
+    value.type_name().replace('_', "-")
+}
+
+/// Parses the value of a [`TYPE_HINT_ATTRIBUTE`] into attribute names and types.
+///
+/// Hints let ABX → XML → ABX keep every attribute's binary type, with both
+/// [`Options::emit_type_hints`] and [`Options::apply_type_hints`] set:
+///
+/// ```
+/// use android_xml_converter::{AbxToXmlConverter, Options, XmlToAbxConverter};
+/// use android_xml_converter::schema::{TypeSchema, parse_type_hints};
+///
+/// let schema = TypeSchema::new().rule("package/@ft", "long-hex").unwrap();
+/// let mut original = Vec::new();
+/// let xml = r#"<package name="a" ft="18a4f3c2b10"/>"#;
+/// XmlToAbxConverter::convert_from_string_with(xml, &mut original, &Options::new().type_schema(schema))
+///     .unwrap();
+///
+/// let mut xml = Vec::new();
+/// AbxToXmlConverter::convert_with_options(original.as_slice(), &mut xml, &Options::new().emit_type_hints(true))
+///     .unwrap();
+/// let xml = String::from_utf8(xml).unwrap();
+/// assert!(xml.contains(r#"abx:type="name=interned ft=long-hex""#));
+///
+/// let mut encoded = Vec::new();
+/// XmlToAbxConverter::convert_from_string_with(&xml, &mut encoded, &Options::new().apply_type_hints(true))
+///     .unwrap();
+/// assert_eq!(encoded, original);
+///
+/// assert_eq!(parse_type_hints("ft=long-hex").unwrap(), [("ft", "long_hex")]);
+/// assert!(parse_type_hints("ft=date").is_err());
+/// ```
+pub fn parse_type_hints(value: &str) -> Result<Vec<(&str, &'static str)>> {
+    value
+        .split_whitespace()
+        .map(|hint| {
+            let (name, type_name) = hint.rsplit_once('=').ok_or_else(|| {
+                ConversionError::ParseError(format!(
+                    "Invalid type hint '{}', expected NAME=TYPE",
+                    hint
+                ))
+            })?;
+            Ok((name, known_type(type_name)?))
+        })
+        .collect()
+}
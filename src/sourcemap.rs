@@ -0,0 +1,218 @@
+use crate::reader::{DataInput, check_magic};
+use crate::*;
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use smol_str::SmolStr;
+use std::io::Write;
+use std::ops::Range;
+
+// ============================================================================
+// Source Maps
+// ============================================================================
+
+/// Where one element lives in both representations of a document.
+///
+/// Ranges are byte offsets from the start of each file, covering the element
+/// from its start tag through its end tag. ABX ranges include the magic, so the
+/// first token is at offset 4.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElementMapping {
+    pub name: SmolStr,
+    /// Nesting depth, 1 for the root element
+    pub depth: usize,
+    pub abx: Range<u64>,
+    pub xml: Range<u64>,
+}
+
+/// Element-level mapping between an ABX document and its XML form, in document order
+///
+/// ```
+/// use android_xml_converter::Options;
+/// use android_xml_converter::sourcemap::xml_to_abx_with_source_map;
+///
+/// let xml = "<a><b x=\"1\"/></a>";
+/// let (abx, map) = xml_to_abx_with_source_map(xml, &Options::new()).unwrap();
+///
+/// let b = map.find_xml(xml.find("x=").unwrap() as u64).unwrap();
+/// assert_eq!(b.name, "b");
+/// assert_eq!(&xml[b.xml.start as usize..b.xml.end as usize], "<b x=\"1\"/>");
+/// assert!(b.abx.end <= abx.len() as u64);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    pub elements: Vec<ElementMapping>,
+}
+
+impl SourceMap {
+    /// Pairs the elements of an ABX document with those of the equivalent XML.
+    ///
+    /// Elements are matched by document order, so both inputs must describe the
+    /// same tree, as they do after a conversion in either direction.
+    pub fn build(abx: &[u8], xml: &[u8]) -> Result<Self> {
+        let abx_elements = abx_element_ranges(abx)?;
+        let xml_ranges = xml_element_ranges(xml)?;
+
+        if abx_elements.len() != xml_ranges.len() {
+            return Err(ConversionError::ParseError(format!(
+                "Documents differ: {} ABX elements but {} XML elements",
+                abx_elements.len(),
+                xml_ranges.len()
+            )));
+        }
+
+        let elements = abx_elements
+            .into_iter()
+            .zip(xml_ranges)
+            .map(|((name, depth, abx), xml)| ElementMapping {
+                name,
+                depth,
+                abx,
+                xml,
+            })
+            .collect();
+
+        Ok(Self { elements })
+    }
+
+    /// Innermost element whose ABX range contains `offset`
+    pub fn find_abx(&self, offset: u64) -> Option<&ElementMapping> {
+        self.elements
+            .iter()
+            .rev()
+            .find(|element| element.abx.contains(&offset))
+    }
+
+    /// Innermost element whose XML range contains `offset`
+    pub fn find_xml(&self, offset: u64) -> Option<&ElementMapping> {
+        self.elements
+            .iter()
+            .rev()
+            .find(|element| element.xml.contains(&offset))
+    }
+
+    /// Writes one tab-separated `depth name abx_start abx_end xml_start xml_end` line
+    /// per element
+    pub fn write_to<W: Write>(&self, mut output: W) -> Result<()> {
+        for element in &self.elements {
+            writeln!(
+                output,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                element.depth,
+                element.name,
+                element.abx.start,
+                element.abx.end,
+                element.xml.start,
+                element.xml.end
+            )?;
+        }
+        output.flush()?;
+        Ok(())
+    }
+}
+
+/// Converts ABX to XML and maps the elements of both
+pub fn abx_to_xml_with_source_map(abx: &[u8], options: &Options) -> Result<(Vec<u8>, SourceMap)> {
+    let mut xml = Vec::new();
+    AbxToXmlConverter::convert_with_options(abx, &mut xml, options)?;
+    let map = SourceMap::build(abx, &xml)?;
+    Ok((xml, map))
+}
+
+/// Converts XML to ABX and maps the elements of both
+pub fn xml_to_abx_with_source_map(xml: &str, options: &Options) -> Result<(Vec<u8>, SourceMap)> {
+    let mut abx = Vec::new();
+    XmlToAbxConverter::convert_from_string_with(xml, &mut abx, options)?;
+    let map = SourceMap::build(&abx, xml.as_bytes())?;
+    Ok((abx, map))
+}
+
+// ============================================================================
+// Element Ranges
+// ============================================================================
+
+/// Name, depth and byte range of every ABX element in document order
+fn abx_element_ranges(mut abx: &[u8]) -> Result<Vec<(SmolStr, usize, Range<u64>)>> {
+    let header = PROTOCOL_MAGIC_VERSION_0.len() as u64;
+    check_magic(&mut abx)?;
+    let mut input = DataInput::new(abx);
+    let mut elements = Vec::new();
+    let mut open: Vec<usize> = Vec::new();
+
+    loop {
+        let offset = header + input.position();
+        let token = match input.read_byte() {
+            Ok(token) => token,
+            Err(ConversionError::ReadError(_)) => break,
+            Err(e) => return Err(e),
+        };
+
+        match token & 0x0F {
+            START_DOCUMENT => {}
+            END_DOCUMENT => break,
+            START_TAG => {
+                let name = input.read_interned_utf()?;
+                while let Ok(next_token) = input.peek_byte() {
+                    if (next_token & 0x0F) != ATTRIBUTE {
+                        break;
+                    }
+                    let _ = input.read_byte()?;
+                    input.read_interned_utf()?;
+                    input.read_attribute_value(next_token & 0xF0)?;
+                }
+                open.push(elements.len());
+                elements.push((name, open.len(), offset..offset));
+            }
+            END_TAG => {
+                input.read_interned_utf()?;
+                if let Some(index) = open.pop() {
+                    elements[index].2.end = header + input.position();
+                }
+            }
+            _ => {
+                if token & 0xF0 == TYPE_STRING {
+                    input.read_utf()?;
+                }
+            }
+        }
+    }
+
+    // unclosed elements run to the end of the input
+    let end = header + input.position();
+    for index in open {
+        elements[index].2.end = end;
+    }
+    Ok(elements)
+}
+
+/// Byte range of every XML element in document order
+fn xml_element_ranges(xml: &[u8]) -> Result<Vec<Range<u64>>> {
+    let mut reader = Reader::from_reader(xml);
+    let mut buf = Vec::new();
+    let mut ranges = Vec::new();
+    let mut open: Vec<usize> = Vec::new();
+
+    loop {
+        let start = reader.buffer_position();
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(_) => {
+                open.push(ranges.len());
+                ranges.push(start..start);
+            }
+            Event::End(_) => {
+                if let Some(index) = open.pop() {
+                    ranges[index].end = reader.buffer_position();
+                }
+            }
+            Event::Empty(_) => ranges.push(start..reader.buffer_position()),
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    let end = reader.buffer_position();
+    for index in open {
+        ranges[index].end = end;
+    }
+    Ok(ranges)
+}
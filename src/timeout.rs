@@ -0,0 +1,110 @@
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+// ============================================================================
+// Idle Timeout
+// ============================================================================
+
+/// Size of the chunks read from the source
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Chunks read ahead of the consumer
+const CHUNKS_AHEAD: usize = 4;
+
+/// Reader that fails with [`io::ErrorKind::TimedOut`] once its source delivers
+/// nothing for longer than a deadline, for pipes, sockets and `su` commands
+/// whose producer may stall. Conversions stop with that error instead of
+/// hanging with their output files open.
+///
+/// The source is read on a thread of its own, since a blocked read cannot be
+/// interrupted; after a timeout that thread ends once the source returns.
+///
+/// ```
+/// use android_xml_converter::timeout::IdleTimeoutReader;
+/// use std::io::{self, Read};
+/// use std::time::Duration;
+///
+/// // a producer that writes a little and then stalls
+/// let (reader, mut writer) = io::pipe().unwrap();
+/// std::io::Write::write_all(&mut writer, b"ABX\0").unwrap();
+///
+/// let mut input = IdleTimeoutReader::new(reader, Duration::from_millis(50));
+/// let mut data = Vec::new();
+/// let error = input.read_to_end(&mut data).unwrap_err();
+/// assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+/// assert_eq!(data, b"ABX\0");
+/// # drop(writer);
+/// ```
+pub struct IdleTimeoutReader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+    idle: Duration,
+}
+
+impl IdleTimeoutReader {
+    pub fn new<R: Read + Send + 'static>(mut source: R, idle: Duration) -> Self {
+        let (sender, chunks) = mpsc::sync_channel(CHUNKS_AHEAD);
+        thread::spawn(move || {
+            let mut buffer = vec![0; CHUNK_SIZE];
+            loop {
+                let chunk = match source.read(&mut buffer) {
+                    // the end of the input is the dropped sender
+                    Ok(0) => return,
+                    Ok(read) => Ok(buffer[..read].to_vec()),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => Err(e),
+                };
+                let failed = chunk.is_err();
+                if sender.send(chunk).is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        Self {
+            chunks,
+            chunk: Vec::new(),
+            position: 0,
+            idle,
+        }
+    }
+
+    /// `source` with a deadline if one is given, as is otherwise
+    pub fn wrap<R: Read + Send + 'static>(
+        source: R,
+        idle: Option<Duration>,
+    ) -> Box<dyn Read + Send> {
+        match idle {
+            Some(idle) => Box::new(Self::new(source, idle)),
+            None => Box::new(source),
+        }
+    }
+}
+
+impl Read for IdleTimeoutReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.chunk.len() && !buf.is_empty() {
+            match self.chunks.recv_timeout(self.idle) {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.position = 0;
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("no input for {:?}", self.idle),
+                    ));
+                }
+            }
+        }
+
+        let read = buf.len().min(self.chunk.len() - self.position);
+        buf[..read].copy_from_slice(&self.chunk[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
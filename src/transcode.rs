@@ -0,0 +1,1563 @@
+use crate::reader::{AttributeValue, DataInput, check_magic};
+use crate::writer::BinaryXmlSerializer;
+use crate::*;
+use serde::de::{self, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Impossible, Serialize};
+use smol_str::SmolStr;
+use std::fmt::Display;
+use std::io::{Read, Write};
+
+impl de::Error for ConversionError {
+    fn custom<T: Display>(msg: T) -> Self {
+        ConversionError::ParseError(msg.to_string())
+    }
+}
+
+impl ser::Error for ConversionError {
+    fn custom<T: Display>(msg: T) -> Self {
+        ConversionError::ParseError(msg.to_string())
+    }
+}
+
+// ============================================================================
+// Transcoding
+// ============================================================================
+
+/// Streams an ABX document into any serde serializer, e.g. `serde_json::Serializer`
+///
+/// ```
+/// use android_xml_converter::XmlToAbxConverter;
+/// use android_xml_converter::transcode::{transcode_from, transcode_to};
+///
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string(r#"<map><int name="a" value="1" />hi</map>"#, &mut abx)
+///     .unwrap();
+///
+/// let mut json = Vec::new();
+/// transcode_to(abx.as_slice(), &mut serde_json::Serializer::new(&mut json)).unwrap();
+/// assert_eq!(
+///     String::from_utf8(json.clone()).unwrap(),
+///     r#"{"name":"map","attributes":{},"children":[{"name":"int","attributes":{"name":"a","value":1},"children":[]},"hi"]}"#
+/// );
+///
+/// let mut back = Vec::new();
+/// transcode_from(&mut serde_json::Deserializer::from_slice(&json), &mut back).unwrap();
+/// assert_eq!(back, abx);
+/// ```
+pub fn transcode_to<R: Read, S: ser::Serializer>(reader: R, serializer: S) -> Result<S::Ok> {
+    let mut deserializer = Deserializer::new(reader)?;
+    serde_transcode::transcode(&mut deserializer, serializer)
+        .map_err(|e| ConversionError::ParseError(e.to_string()))
+}
+
+/// Streams a document from any serde deserializer, e.g. `serde_json::Deserializer`,
+/// into ABX
+pub fn transcode_from<'de, D: de::Deserializer<'de>, W: Write>(
+    deserializer: D,
+    writer: W,
+) -> Result<()> {
+    let mut serializer = Serializer::new(writer)?;
+    serde_transcode::transcode(deserializer, &mut serializer)?;
+    serializer.finish()
+}
+
+// ============================================================================
+// Deserializer
+// ============================================================================
+
+/// The structural events the serde view is built from
+enum Event {
+    Start(SmolStr, Vec<(SmolStr, AttributeValue)>),
+    End,
+    Text(String),
+    Eof,
+}
+
+/// Reads an ABX document on demand as serde data, presenting it as nested maps.
+/// [`Serializer`] accepts the same shape and writes ABX.
+///
+/// Every element is a map with up to three keys, in this order:
+///
+/// ```json
+/// {"name": "package", "attributes": {"name": "com.foo", "userId": 10001}, "children": []}
+/// ```
+///
+/// `children` holds nested element maps and text strings. Comments, processing
+/// instructions and ignorable whitespace are skipped. Attribute values keep their
+/// numeric and boolean types; the hex and interned flavours are not represented.
+/// Nothing is buffered beyond the attributes of the current element, so
+/// [`serde_transcode`] can stream huge files into JSON, CBOR or YAML and back.
+pub struct Deserializer<R: Read> {
+    input: DataInput<R>,
+}
+
+impl<R: Read> Deserializer<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        check_magic(&mut reader)?;
+        Ok(Self {
+            input: DataInput::new(reader),
+        })
+    }
+
+    fn next_event(&mut self) -> Result<Event> {
+        loop {
+            let token = match self.input.read_byte() {
+                Ok(token) => token,
+                Err(ConversionError::ReadError(_)) => return Ok(Event::Eof),
+                Err(e) => return Err(e),
+            };
+            let command = token & 0x0F;
+            let type_info = token & 0xF0;
+
+            match command {
+                START_DOCUMENT => {}
+                END_DOCUMENT => return Ok(Event::Eof),
+                START_TAG => {
+                    let name = self.input.read_interned_utf()?;
+                    let mut attributes = Vec::new();
+                    while let Ok(next_token) = self.input.peek_byte() {
+                        if (next_token & 0x0F) != ATTRIBUTE {
+                            break;
+                        }
+                        let _ = self.input.read_byte()?;
+                        let attr_name = self.input.read_interned_utf()?;
+                        let value = self.input.read_attribute_value(next_token & 0xF0)?;
+                        attributes.push((attr_name, value));
+                    }
+                    return Ok(Event::Start(name, attributes));
+                }
+                END_TAG => {
+                    self.input.read_interned_utf()?;
+                    return Ok(Event::End);
+                }
+                TEXT | CDSECT if type_info == TYPE_STRING => {
+                    let mut text = self.input.read_utf()?;
+                    // long text is split across consecutive TEXT tokens
+                    while command == TEXT
+                        && self
+                            .input
+                            .peek_byte()
+                            .is_ok_and(|next| next == TEXT | TYPE_STRING)
+                    {
+                        self.input.read_byte()?;
+                        text.push_str(&self.input.read_utf()?);
+                    }
+                    return Ok(Event::Text(text));
+                }
+                _ => {
+                    if type_info == TYPE_STRING {
+                        self.input.read_utf()?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
+    type Error = ConversionError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        loop {
+            match self.next_event()? {
+                Event::Start(name, attributes) => {
+                    return ElementDeserializer {
+                        de: self,
+                        name,
+                        attributes,
+                    }
+                    .deserialize_any(visitor);
+                }
+                Event::Text(_) => {}
+                Event::End | Event::Eof => {
+                    return Err(ConversionError::ParseError(
+                        "Document has no root element".to_string(),
+                    ));
+                }
+            }
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct ElementDeserializer<'a, R: Read> {
+    de: &'a mut Deserializer<R>,
+    name: SmolStr,
+    attributes: Vec<(SmolStr, AttributeValue)>,
+}
+
+impl<'de, R: Read> de::Deserializer<'de> for ElementDeserializer<'_, R> {
+    type Error = ConversionError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_map(ElementAccess {
+            de: self.de,
+            name: Some(self.name),
+            attributes: Some(self.attributes),
+            children: true,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Yields the `name`, `attributes` and `children` entries of one element
+struct ElementAccess<'a, R: Read> {
+    de: &'a mut Deserializer<R>,
+    name: Option<SmolStr>,
+    attributes: Option<Vec<(SmolStr, AttributeValue)>>,
+    children: bool,
+}
+
+impl<'de, R: Read> MapAccess<'de> for ElementAccess<'_, R> {
+    type Error = ConversionError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let key = if self.name.is_some() {
+            "name"
+        } else if self.attributes.is_some() {
+            "attributes"
+        } else if self.children {
+            "children"
+        } else {
+            return Ok(None);
+        };
+        seed.deserialize(key.into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        if let Some(name) = self.name.take() {
+            seed.deserialize(name.as_str().into_deserializer())
+        } else if let Some(attributes) = self.attributes.take() {
+            seed.deserialize(AttributesDeserializer { attributes })
+        } else {
+            self.children = false;
+            seed.deserialize(ChildrenDeserializer { de: self.de })
+        }
+    }
+}
+
+struct AttributesDeserializer {
+    attributes: Vec<(SmolStr, AttributeValue)>,
+}
+
+impl<'de> de::Deserializer<'de> for AttributesDeserializer {
+    type Error = ConversionError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_map(AttributesAccess {
+            attributes: self.attributes.into_iter(),
+            value: None,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct AttributesAccess {
+    attributes: std::vec::IntoIter<(SmolStr, AttributeValue)>,
+    value: Option<AttributeValue>,
+}
+
+impl<'de> MapAccess<'de> for AttributesAccess {
+    type Error = ConversionError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        match self.attributes.next() {
+            Some((name, value)) => {
+                self.value = Some(value);
+                seed.deserialize(name.as_str().into_deserializer())
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| ConversionError::ParseError("Attribute value without name".into()))?;
+        seed.deserialize(ValueDeserializer(value))
+    }
+}
+
+struct ValueDeserializer(AttributeValue);
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer {
+    type Error = ConversionError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.0 {
+            AttributeValue::String(value) => visitor.visit_string(value),
+            AttributeValue::Interned(value) => visitor.visit_str(&value),
+            AttributeValue::Int(value) | AttributeValue::IntHex(value) => visitor.visit_i32(value),
+            AttributeValue::Long(value) | AttributeValue::LongHex(value) => {
+                visitor.visit_i64(value)
+            }
+            AttributeValue::Float(value) => visitor.visit_f32(value),
+            AttributeValue::Double(value) => visitor.visit_f64(value),
+            AttributeValue::Boolean(value) => visitor.visit_bool(value),
+            AttributeValue::BytesHex(bytes) | AttributeValue::BytesBase64(bytes) => {
+                visitor.visit_byte_buf(bytes)
+            }
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct ChildrenDeserializer<'a, R: Read> {
+    de: &'a mut Deserializer<R>,
+}
+
+impl<'de, R: Read> de::Deserializer<'de> for ChildrenDeserializer<'_, R> {
+    type Error = ConversionError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let mut access = ChildrenAccess {
+            de: self.de,
+            done: false,
+        };
+        let value = visitor.visit_seq(&mut access)?;
+        // a visitor that stopped early still has to leave the element behind it
+        while !access.done {
+            access.next_element::<de::IgnoredAny>()?;
+        }
+        Ok(value)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct ChildrenAccess<'a, R: Read> {
+    de: &'a mut Deserializer<R>,
+    done: bool,
+}
+
+impl<'de, R: Read> SeqAccess<'de> for ChildrenAccess<'_, R> {
+    type Error = ConversionError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.done {
+            return Ok(None);
+        }
+        match self.de.next_event()? {
+            Event::Start(name, attributes) => seed
+                .deserialize(ElementDeserializer {
+                    de: &mut *self.de,
+                    name,
+                    attributes,
+                })
+                .map(Some),
+            Event::Text(text) => seed.deserialize(text.into_deserializer()).map(Some),
+            Event::End | Event::Eof => {
+                self.done = true;
+                Ok(None)
+            }
+        }
+    }
+}
+
+// ============================================================================
+// Serializer
+// ============================================================================
+
+fn unsupported(what: &str) -> ConversionError {
+    ConversionError::ParseError(format!("Cannot represent {} in ABX", what))
+}
+
+/// Generates serializer methods that reject their input
+macro_rules! reject {
+    ($what:expr; $($method:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            fn $method(self, $($arg: $ty),*) -> Result<Self::Ok> {
+                let _ = ($($arg,)*);
+                Err(unsupported($what))
+            }
+        )*
+    };
+}
+
+/// Writes serde data shaped like the [`Deserializer`] output as an ABX document
+pub struct Serializer<W: Write> {
+    output: BinaryXmlSerializer<W>,
+}
+
+impl<W: Write> Serializer<W> {
+    pub fn new(writer: W) -> Result<Self> {
+        let mut output = BinaryXmlSerializer::new(writer)?;
+        output.start_document()?;
+        Ok(Self { output })
+    }
+
+    /// Ends the document and flushes the writer
+    pub fn finish(mut self) -> Result<()> {
+        self.output.end_document()
+    }
+}
+
+/// Serializes `value` as a complete ABX document
+pub fn to_writer<T: Serialize + ?Sized, W: Write>(value: &T, writer: W) -> Result<()> {
+    let mut serializer = Serializer::new(writer)?;
+    value.serialize(&mut serializer)?;
+    serializer.finish()
+}
+
+/// A node: a map or struct becomes an element, a string becomes text
+impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
+    type Ok = ();
+    type Error = ConversionError;
+    type SerializeSeq = Impossible<(), ConversionError>;
+    type SerializeTuple = Impossible<(), ConversionError>;
+    type SerializeTupleStruct = Impossible<(), ConversionError>;
+    type SerializeTupleVariant = Impossible<(), ConversionError>;
+    type SerializeMap = ElementSerializer<'a, W>;
+    type SerializeStruct = ElementSerializer<'a, W>;
+    type SerializeStructVariant = Impossible<(), ConversionError>;
+
+    fn serialize_str(self, value: &str) -> Result<()> {
+        self.output.text(value)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Ok(ElementSerializer {
+            ser: self,
+            key: None,
+            name: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        self.serialize_map(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()> {
+        Err(unsupported("an enum variant as a node"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(unsupported("a sequence as a node"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(unsupported("a tuple as a node"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(unsupported("a tuple as a node"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(unsupported("an enum variant as a node"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(unsupported("an enum variant as a node"))
+    }
+
+    reject! { "a scalar as a node";
+        serialize_bool(v: bool);
+        serialize_i8(v: i8);
+        serialize_i16(v: i16);
+        serialize_i32(v: i32);
+        serialize_i64(v: i64);
+        serialize_u8(v: u8);
+        serialize_u16(v: u16);
+        serialize_u32(v: u32);
+        serialize_u64(v: u64);
+        serialize_f32(v: f32);
+        serialize_f64(v: f64);
+        serialize_char(v: char);
+        serialize_bytes(v: &[u8]);
+        serialize_none();
+        serialize_unit();
+        serialize_unit_struct(name: &'static str);
+        serialize_unit_variant(name: &'static str, index: u32, variant: &'static str);
+    }
+}
+
+/// Writes one element from its `name`, `attributes` and `children` entries
+pub struct ElementSerializer<'a, W: Write> {
+    ser: &'a mut Serializer<W>,
+    key: Option<String>,
+    name: Option<String>,
+}
+
+impl<W: Write> ElementSerializer<'_, W> {
+    fn entry<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<()> {
+        match key {
+            "name" => {
+                let Scalar::Str(name) = value.serialize(ScalarSerializer)? else {
+                    return Err(unsupported("a non-string element name"));
+                };
+                self.ser.output.start_tag(&name)?;
+                self.name = Some(name);
+                Ok(())
+            }
+            "attributes" | "children" if self.name.is_none() => Err(ConversionError::ParseError(
+                format!("Element '{}' given before 'name'", key),
+            )),
+            "attributes" => value.serialize(AttributesSerializer { ser: self.ser }),
+            "children" => value.serialize(ChildrenSerializer { ser: self.ser }),
+            _ => Err(ConversionError::ParseError(format!(
+                "Unknown element key '{}'",
+                key
+            ))),
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self.name {
+            Some(name) => self.ser.output.end_tag(&name),
+            None => Err(ConversionError::ParseError(
+                "Element without a name".to_string(),
+            )),
+        }
+    }
+}
+
+impl<W: Write> ser::SerializeMap for ElementSerializer<'_, W> {
+    type Ok = ();
+    type Error = ConversionError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        match key.serialize(ScalarSerializer)? {
+            Scalar::Str(key) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            _ => Err(unsupported("a non-string key")),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| ConversionError::ParseError("Value without key".to_string()))?;
+        self.entry(&key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<W: Write> ser::SerializeStruct for ElementSerializer<'_, W> {
+    type Ok = ();
+    type Error = ConversionError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.entry(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+/// Accepts the `attributes` map of an element
+struct AttributesSerializer<'a, W: Write> {
+    ser: &'a mut Serializer<W>,
+}
+
+impl<'a, W: Write> ser::Serializer for AttributesSerializer<'a, W> {
+    type Ok = ();
+    type Error = ConversionError;
+    type SerializeSeq = Impossible<(), ConversionError>;
+    type SerializeTuple = Impossible<(), ConversionError>;
+    type SerializeTupleStruct = Impossible<(), ConversionError>;
+    type SerializeTupleVariant = Impossible<(), ConversionError>;
+    type SerializeMap = AttributeMapSerializer<'a, W>;
+    type SerializeStruct = AttributeMapSerializer<'a, W>;
+    type SerializeStructVariant = Impossible<(), ConversionError>;
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Ok(AttributeMapSerializer {
+            ser: self.ser,
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        self.serialize_map(None)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()> {
+        Err(unsupported("an enum variant as attributes"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(unsupported("a sequence as attributes"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(unsupported("a tuple as attributes"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(unsupported("a tuple as attributes"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(unsupported("an enum variant as attributes"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(unsupported("an enum variant as attributes"))
+    }
+
+    reject! { "a scalar as attributes";
+        serialize_bool(v: bool);
+        serialize_i8(v: i8);
+        serialize_i16(v: i16);
+        serialize_i32(v: i32);
+        serialize_i64(v: i64);
+        serialize_u8(v: u8);
+        serialize_u16(v: u16);
+        serialize_u32(v: u32);
+        serialize_u64(v: u64);
+        serialize_f32(v: f32);
+        serialize_f64(v: f64);
+        serialize_char(v: char);
+        serialize_str(v: &str);
+        serialize_bytes(v: &[u8]);
+        serialize_unit_struct(name: &'static str);
+        serialize_unit_variant(name: &'static str, index: u32, variant: &'static str);
+    }
+}
+
+struct AttributeMapSerializer<'a, W: Write> {
+    ser: &'a mut Serializer<W>,
+    key: Option<String>,
+}
+
+impl<W: Write> AttributeMapSerializer<'_, W> {
+    fn attribute<T: Serialize + ?Sized>(&mut self, name: &str, value: &T) -> Result<()> {
+        let value = match value.serialize(ScalarSerializer)? {
+            Scalar::None => return Ok(()),
+            Scalar::Str(value) => string_value(value),
+            Scalar::Bool(value) => AttributeValue::Boolean(value),
+            Scalar::Int(value) => match i32::try_from(value) {
+                Ok(value) => AttributeValue::Int(value),
+                Err(_) => AttributeValue::Long(value),
+            },
+            Scalar::Float(value) => AttributeValue::Float(value),
+            Scalar::Double(value) => AttributeValue::Double(value),
+            Scalar::Bytes(value) => AttributeValue::BytesBase64(value),
+        };
+        self.ser.output.attribute_value(name, &value)
+    }
+}
+
+impl<W: Write> ser::SerializeMap for AttributeMapSerializer<'_, W> {
+    type Ok = ();
+    type Error = ConversionError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        match key.serialize(ScalarSerializer)? {
+            Scalar::Str(key) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            _ => Err(unsupported("a non-string attribute name")),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| ConversionError::ParseError("Value without key".to_string()))?;
+        self.attribute(&key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> ser::SerializeStruct for AttributeMapSerializer<'_, W> {
+    type Ok = ();
+    type Error = ConversionError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.attribute(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Accepts the `children` sequence of an element
+struct ChildrenSerializer<'a, W: Write> {
+    ser: &'a mut Serializer<W>,
+}
+
+impl<'a, W: Write> ser::Serializer for ChildrenSerializer<'a, W> {
+    type Ok = ();
+    type Error = ConversionError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Impossible<(), ConversionError>;
+    type SerializeTupleVariant = Impossible<(), ConversionError>;
+    type SerializeMap = Impossible<(), ConversionError>;
+    type SerializeStruct = Impossible<(), ConversionError>;
+    type SerializeStructVariant = Impossible<(), ConversionError>;
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Ok(self)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()> {
+        Err(unsupported("an enum variant as children"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(unsupported("a tuple struct as children"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(unsupported("an enum variant as children"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(unsupported("a map as children"))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(unsupported("a struct as children"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(unsupported("an enum variant as children"))
+    }
+
+    reject! { "a scalar as children";
+        serialize_bool(v: bool);
+        serialize_i8(v: i8);
+        serialize_i16(v: i16);
+        serialize_i32(v: i32);
+        serialize_i64(v: i64);
+        serialize_u8(v: u8);
+        serialize_u16(v: u16);
+        serialize_u32(v: u32);
+        serialize_u64(v: u64);
+        serialize_f32(v: f32);
+        serialize_f64(v: f64);
+        serialize_char(v: char);
+        serialize_str(v: &str);
+        serialize_bytes(v: &[u8]);
+        serialize_unit_struct(name: &'static str);
+        serialize_unit_variant(name: &'static str, index: u32, variant: &'static str);
+    }
+}
+
+impl<W: Write> ser::SerializeSeq for ChildrenSerializer<'_, W> {
+    type Ok = ();
+    type Error = ConversionError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> ser::SerializeTuple for ChildrenSerializer<'_, W> {
+    type Ok = ();
+    type Error = ConversionError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Same choice as the XML converter by default: short single words are interned
+fn string_value(value: String) -> AttributeValue {
+    if InternPolicy::default().admits(&value) {
+        AttributeValue::Interned(SmolStr::new(value))
+    } else {
+        AttributeValue::String(value)
+    }
+}
+
+/// A single value captured from serde, used for names and attribute values
+enum Scalar {
+    None,
+    Str(String),
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    Double(f64),
+    Bytes(Vec<u8>),
+}
+
+struct ScalarSerializer;
+
+impl ser::Serializer for ScalarSerializer {
+    type Ok = Scalar;
+    type Error = ConversionError;
+    type SerializeSeq = Impossible<Scalar, ConversionError>;
+    type SerializeTuple = Impossible<Scalar, ConversionError>;
+    type SerializeTupleStruct = Impossible<Scalar, ConversionError>;
+    type SerializeTupleVariant = Impossible<Scalar, ConversionError>;
+    type SerializeMap = Impossible<Scalar, ConversionError>;
+    type SerializeStruct = Impossible<Scalar, ConversionError>;
+    type SerializeStructVariant = Impossible<Scalar, ConversionError>;
+
+    fn serialize_bool(self, v: bool) -> Result<Scalar> {
+        Ok(Scalar::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Scalar> {
+        Ok(Scalar::Int(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Scalar> {
+        Ok(Scalar::Int(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Scalar> {
+        Ok(Scalar::Int(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Scalar> {
+        Ok(Scalar::Int(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Scalar> {
+        Ok(Scalar::Int(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Scalar> {
+        Ok(Scalar::Int(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Scalar> {
+        Ok(Scalar::Int(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Scalar> {
+        i64::try_from(v)
+            .map(Scalar::Int)
+            .map_err(|_| unsupported("an integer above i64::MAX"))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Scalar> {
+        Ok(Scalar::Float(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Scalar> {
+        Ok(Scalar::Double(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Scalar> {
+        Ok(Scalar::Str(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Scalar> {
+        Ok(Scalar::Str(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Scalar> {
+        Ok(Scalar::Bytes(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Scalar> {
+        Ok(Scalar::None)
+    }
+
+    fn serialize_unit(self) -> Result<Scalar> {
+        Ok(Scalar::None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Scalar> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Scalar> {
+        Ok(Scalar::None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Scalar> {
+        Ok(Scalar::Str(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Scalar> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Scalar> {
+        Err(unsupported("an enum variant as a value"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(unsupported("a sequence as a value"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(unsupported("a tuple as a value"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(unsupported("a tuple as a value"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(unsupported("an enum variant as a value"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(unsupported("a map as a value"))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(unsupported("a struct as a value"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(unsupported("an enum variant as a value"))
+    }
+}
+
+// ============================================================================
+// Typed Serializer
+// ============================================================================
+
+/// Serializes any Rust value as an ABX document, keeping the type of each field.
+///
+/// A struct becomes an element named after the struct. Its scalar fields become
+/// typed attributes (`i8` to `i32` and `u8`, `u16` as int, `i64` and `u32` as long,
+/// `f32` as float, `f64` as double, `bool` as boolean, byte slices and `Vec<u8>`
+/// as base64 bytes, strings and unit variants as strings). Nested structs and
+/// maps become child elements named after the field, sequences one child per
+/// item, with scalar items written as their `value` attribute like Android's
+/// `<item value="..."/>`. `None` fields and empty sequences are left out.
+///
+/// ```
+/// use android_xml_converter::dom::AbxDocument;
+/// use android_xml_converter::reader::AttributeValue;
+/// use android_xml_converter::transcode::to_abx_bytes;
+/// use serde::Serialize;
+///
+/// #[derive(Serialize)]
+/// struct Package {
+///     name: String,
+///     uid: i32,
+///     system: bool,
+///     signature: Vec<u8>,
+///     permissions: Vec<Permission>,
+/// }
+///
+/// #[derive(Serialize)]
+/// struct Permission {
+///     name: String,
+/// }
+///
+/// let abx = to_abx_bytes(&Package {
+///     name: "com.example".to_string(),
+///     uid: 10123,
+///     system: false,
+///     signature: vec![0xca, 0xfe],
+///     permissions: vec![Permission { name: "INTERNET".to_string() }],
+/// })
+/// .unwrap();
+///
+/// let document = AbxDocument::from_bytes(&abx).unwrap();
+/// let package = document.root().unwrap();
+/// assert_eq!(package.name(), "Package");
+/// assert_eq!(package.attribute("uid"), Some(&AttributeValue::Int(10123)));
+/// assert_eq!(package.attribute("system"), Some(&AttributeValue::Boolean(false)));
+/// assert_eq!(
+///     package.attribute("signature"),
+///     Some(&AttributeValue::BytesBase64(vec![0xca, 0xfe]))
+/// );
+/// assert_eq!(package.child("permissions").unwrap().attributes().len(), 1);
+/// ```
+pub fn to_abx_writer<T: Serialize + ?Sized, W: Write>(value: &T, writer: W) -> Result<()> {
+    let Value::Elements(mut roots) = value.serialize(ValueSerializer { name: None })? else {
+        return Err(unsupported("a scalar as the document"));
+    };
+    let root = match (roots.pop(), roots.is_empty()) {
+        (Some(root), true) => root,
+        _ => return Err(unsupported("a document without a single root element")),
+    };
+
+    let mut output = BinaryXmlSerializer::new(writer)?;
+    output.start_document()?;
+    write_node(&mut output, &root)?;
+    output.end_document()
+}
+
+/// Serializes `value` like [`to_abx_writer`] into a new buffer
+pub fn to_abx_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut abx = Vec::new();
+    to_abx_writer(value, &mut abx)?;
+    Ok(abx)
+}
+
+/// An element collected before writing, since fields holding attributes may
+/// follow fields holding children
+struct Node {
+    name: String,
+    attributes: Vec<(String, AttributeValue)>,
+    children: Vec<Node>,
+}
+
+fn write_node<W: Write>(output: &mut BinaryXmlSerializer<W>, node: &Node) -> Result<()> {
+    output.start_tag(&node.name)?;
+    for (name, value) in &node.attributes {
+        output.attribute_value(name, value)?;
+    }
+    for child in &node.children {
+        write_node(output, child)?;
+    }
+    output.end_tag(&node.name)
+}
+
+/// What one serde value turns into
+enum Value {
+    None,
+    /// Kept apart from other integers so a sequence of them can become bytes
+    Byte(u8),
+    Attribute(AttributeValue),
+    Elements(Vec<Node>),
+}
+
+impl Value {
+    fn into_attribute(self) -> Option<AttributeValue> {
+        match self {
+            Value::Byte(value) => Some(AttributeValue::Int(value.into())),
+            Value::Attribute(value) => Some(value),
+            Value::None | Value::Elements(_) => None,
+        }
+    }
+}
+
+/// Serializes a value found under `name`, the field or map key holding it
+struct ValueSerializer<'a> {
+    name: Option<&'a str>,
+}
+
+impl ValueSerializer<'_> {
+    fn node(&self, type_name: Option<&str>) -> Result<Node> {
+        let name = self
+            .name
+            .or(type_name)
+            .ok_or_else(|| unsupported("a map as the document"))?;
+        Ok(Node {
+            name: name.to_string(),
+            attributes: Vec::new(),
+            children: Vec::new(),
+        })
+    }
+}
+
+fn attribute(value: AttributeValue) -> Result<Value> {
+    Ok(Value::Attribute(value))
+}
+
+impl ser::Serializer for ValueSerializer<'_> {
+    type Ok = Value;
+    type Error = ConversionError;
+    type SerializeSeq = ItemsSerializer;
+    type SerializeTuple = ItemsSerializer;
+    type SerializeTupleStruct = ItemsSerializer;
+    type SerializeTupleVariant = Impossible<Value, ConversionError>;
+    type SerializeMap = NodeSerializer;
+    type SerializeStruct = NodeSerializer;
+    type SerializeStructVariant = Impossible<Value, ConversionError>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value> {
+        attribute(AttributeValue::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value> {
+        attribute(AttributeValue::Int(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value> {
+        attribute(AttributeValue::Int(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value> {
+        attribute(AttributeValue::Int(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value> {
+        attribute(AttributeValue::Long(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value> {
+        Ok(Value::Byte(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value> {
+        attribute(AttributeValue::Int(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value> {
+        attribute(AttributeValue::Long(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value> {
+        i64::try_from(v)
+            .map(|v| Value::Attribute(AttributeValue::Long(v)))
+            .map_err(|_| unsupported("an integer above i64::MAX"))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value> {
+        attribute(AttributeValue::Float(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value> {
+        attribute(AttributeValue::Double(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value> {
+        attribute(string_value(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value> {
+        attribute(string_value(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value> {
+        attribute(AttributeValue::BytesBase64(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Value> {
+        Ok(Value::None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value> {
+        Ok(Value::None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value> {
+        Ok(Value::None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value> {
+        attribute(string_value(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Value> {
+        Err(unsupported("an enum variant with data"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        let name = self
+            .name
+            .ok_or_else(|| unsupported("a sequence as the document"))?;
+        Ok(ItemsSerializer {
+            name: name.to_string(),
+            bytes: Vec::new(),
+            nodes: Vec::new(),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(unsupported("an enum variant with data"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Ok(NodeSerializer {
+            node: self.node(None)?,
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Ok(NodeSerializer {
+            node: self.node(Some(name))?,
+            key: None,
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(unsupported("an enum variant with data"))
+    }
+}
+
+/// Collects the fields of a struct or the entries of a map into one element
+struct NodeSerializer {
+    node: Node,
+    key: Option<String>,
+}
+
+impl NodeSerializer {
+    fn field<T: Serialize + ?Sized>(&mut self, name: &str, value: &T) -> Result<()> {
+        match value.serialize(ValueSerializer { name: Some(name) })? {
+            Value::Elements(nodes) => self.node.children.extend(nodes),
+            value => {
+                if let Some(value) = value.into_attribute() {
+                    self.node.attributes.push((name.to_string(), value));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for NodeSerializer {
+    type Ok = Value;
+    type Error = ConversionError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        match key.serialize(ScalarSerializer)? {
+            Scalar::Str(key) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            _ => Err(unsupported("a non-string key")),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| ConversionError::ParseError("Value without key".to_string()))?;
+        self.field(&key, value)
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::Elements(vec![self.node]))
+    }
+}
+
+impl ser::SerializeStruct for NodeSerializer {
+    type Ok = Value;
+    type Error = ConversionError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::Elements(vec![self.node]))
+    }
+}
+
+/// Collects the items of a sequence, which become bytes when all are `u8`
+struct ItemsSerializer {
+    name: String,
+    bytes: Vec<u8>,
+    nodes: Vec<Node>,
+}
+
+impl ItemsSerializer {
+    fn item<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let value = value.serialize(ValueSerializer {
+            name: Some(&self.name),
+        })?;
+        match value {
+            Value::Byte(byte) if self.nodes.is_empty() => self.bytes.push(byte),
+            Value::Elements(nodes) if self.bytes.is_empty() => self.nodes.extend(nodes),
+            Value::None => {}
+            value if self.bytes.is_empty() => {
+                if let Some(value) = value.into_attribute() {
+                    self.nodes.push(Node {
+                        name: self.name.clone(),
+                        attributes: vec![("value".to_string(), value)],
+                        children: Vec::new(),
+                    });
+                }
+            }
+            _ => return Err(unsupported("a sequence mixing bytes and other items")),
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value> {
+        if !self.bytes.is_empty() {
+            attribute(AttributeValue::BytesBase64(self.bytes))
+        } else if !self.nodes.is_empty() {
+            Ok(Value::Elements(self.nodes))
+        } else {
+            Ok(Value::None)
+        }
+    }
+}
+
+impl ser::SerializeSeq for ItemsSerializer {
+    type Ok = Value;
+    type Error = ConversionError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for ItemsSerializer {
+    type Ok = Value;
+    type Error = ConversionError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for ItemsSerializer {
+    type Ok = Value;
+    type Error = ConversionError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<Value> {
+        self.finish()
+    }
+}
@@ -0,0 +1,291 @@
+use crate::reader::{AttributeValue, DataInput, check_magic};
+use crate::schema::TypeSchema;
+use crate::*;
+use smol_str::SmolStr;
+use std::io::{Read, Write};
+
+// ============================================================================
+// Android Invariants
+// ============================================================================
+
+/// One token Android's `BinaryXmlPullParser` would reject
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// Position of the token in the stream, counting from 1
+    pub token: usize,
+    pub message: String,
+}
+
+/// Read-only check of an ABX document against what the platform parser
+/// enforces, for files that decode here but might not on a device.
+///
+/// Checked are:
+/// - `START_DOCUMENT` first and `END_DOCUMENT` last, both without payload
+/// - tag names stored interned, end tags matching their start tags, and no
+///   element left open
+/// - attributes only directly after their start tag, and no attribute name
+///   repeated on one element
+/// - text-like tokens holding a plain string or nothing
+/// - intern pool references that were defined before
+/// - attribute values readable as the type the system service reads them with,
+///   taken from a [`TypeSchema`] or from the bundled profile for the root
+///   element (`packages`, `package-restrictions`, `settings`, `app-ops`)
+///
+/// Decoding stops at the first token that cannot be read at all.
+///
+/// ```
+/// use android_xml_converter::XmlToAbxConverter;
+/// use android_xml_converter::verify::AndroidVerification;
+///
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string(r#"<packages><package name="a" userId="x"/></packages>"#, &mut abx)
+///     .unwrap();
+/// let verification = AndroidVerification::check(&abx[..]).unwrap();
+/// assert_eq!(verification.violations.len(), 1);
+/// assert!(verification.violations[0].message.contains("userId"));
+///
+/// abx.truncate(abx.len() - 1);
+/// let verification = AndroidVerification::check(&abx[..]).unwrap();
+/// assert!(verification.violations.last().unwrap().message.contains("END_DOCUMENT"));
+/// ```
+#[derive(Debug, Default)]
+pub struct AndroidVerification {
+    pub tokens: usize,
+    /// Bundled profile chosen for the typed checks when no schema was given
+    pub profile: Option<&'static str>,
+    pub violations: Vec<Violation>,
+}
+
+impl AndroidVerification {
+    /// Verifies a document, typing attributes with the bundled profile for its
+    /// root element if there is one
+    pub fn check<R: Read>(reader: R) -> Result<Self> {
+        Self::check_with_schema(reader, None)
+    }
+
+    /// Verifies a document, typing attributes with `schema` if given. Fails
+    /// only if the input is not ABX at all.
+    pub fn check_with_schema<R: Read>(mut reader: R, schema: Option<&TypeSchema>) -> Result<Self> {
+        check_magic(&mut reader)?;
+        let mut verifier = Verifier {
+            input: DataInput::new(reader),
+            report: Self::default(),
+            schema: schema.cloned(),
+            detect_profile: schema.is_none(),
+            open: Vec::new(),
+            attributes: None,
+        };
+        verifier.run();
+        Ok(verifier.report)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    pub fn write_to<W: Write>(&self, mut output: W) -> Result<()> {
+        for violation in &self.violations {
+            writeln!(output, "token #{}: {}", violation.token, violation.message)?;
+        }
+        if let Some(profile) = self.profile {
+            writeln!(output, "typed with bundled profile {}", profile)?;
+        }
+        writeln!(
+            output,
+            "{} tokens, {} violation(s)",
+            self.tokens,
+            self.violations.len()
+        )?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
+struct Verifier<R: Read> {
+    input: DataInput<R>,
+    report: AndroidVerification,
+    schema: Option<TypeSchema>,
+    detect_profile: bool,
+    /// Names of the open elements, from the root
+    open: Vec<String>,
+    /// Attribute names of the current start tag while attributes may follow
+    attributes: Option<FastHashSet<SmolStr>>,
+}
+
+impl<R: Read> Verifier<R> {
+    fn violation(&mut self, message: String) {
+        self.report.violations.push(Violation {
+            token: self.report.tokens,
+            message,
+        });
+    }
+
+    fn run(&mut self) {
+        let mut ended = false;
+        while let Ok(token) = self.input.read_byte() {
+            self.report.tokens += 1;
+            if ended {
+                self.violation("data after END_DOCUMENT".to_string());
+                return;
+            }
+            if let Err(e) = self.token(token, &mut ended) {
+                self.violation(format!("unreadable token: {}", e));
+                return;
+            }
+        }
+
+        if !ended {
+            self.report.tokens += 1;
+            self.violation("document ends without END_DOCUMENT".to_string());
+        }
+        if !self.open.is_empty() {
+            self.violation(format!("<{}> is never closed", self.open.join("/")));
+        }
+    }
+
+    fn token(&mut self, token: u8, ended: &mut bool) -> Result<()> {
+        let command = token & 0x0F;
+        let type_info = token & 0xF0;
+        let name = token_name(command).unwrap_or("unknown");
+
+        if self.report.tokens == 1 && command != START_DOCUMENT {
+            self.violation(format!(
+                "document starts with {} instead of START_DOCUMENT",
+                name
+            ));
+        }
+        if command != ATTRIBUTE {
+            self.attributes = None;
+        }
+
+        match command {
+            START_DOCUMENT | END_DOCUMENT => {
+                if type_info != TYPE_NULL {
+                    self.violation(format!("{} carries a payload", name));
+                }
+                if command == START_DOCUMENT && self.report.tokens != 1 {
+                    self.violation("START_DOCUMENT repeated".to_string());
+                }
+                *ended = command == END_DOCUMENT;
+            }
+            START_TAG => {
+                let tag = self.tag_name(type_info, name)?;
+                if self.open.is_empty() && self.detect_profile {
+                    self.detect_profile(&tag);
+                }
+                self.open.push(tag);
+                self.attributes = Some(FastHashSet::default());
+            }
+            END_TAG => {
+                let tag = self.tag_name(type_info, name)?;
+                match self.open.pop() {
+                    Some(open) if open == tag => {}
+                    Some(open) => self.violation(format!("</{}> closes <{}>", tag, open)),
+                    None => self.violation(format!("</{}> without an open element", tag)),
+                }
+            }
+            ATTRIBUTE => self.attribute(type_info)?,
+            TEXT
+            | CDSECT
+            | ENTITY_REF
+            | IGNORABLE_WHITESPACE
+            | PROCESSING_INSTRUCTION
+            | COMMENT
+            | DOCDECL => match type_info {
+                TYPE_NULL => {}
+                TYPE_STRING => {
+                    self.input.read_utf()?;
+                }
+                _ => {
+                    return Err(ConversionError::ParseError(format!(
+                        "{} of type {:#04x}",
+                        name, type_info
+                    )));
+                }
+            },
+            _ => {
+                return Err(ConversionError::ParseError(format!(
+                    "Unknown token: {}",
+                    command
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn tag_name(&mut self, type_info: u8, name: &str) -> Result<String> {
+        if type_info != TYPE_STRING_INTERNED {
+            return Err(ConversionError::ParseError(format!(
+                "{} name of type {:#04x} instead of interned",
+                name, type_info
+            )));
+        }
+        self.interned().map(|tag| tag.to_string())
+    }
+
+    fn interned(&mut self) -> Result<SmolStr> {
+        let index = self.input.read_interned_index()?;
+        self.input.interned_string(index).cloned()
+    }
+
+    fn attribute(&mut self, type_info: u8) -> Result<()> {
+        let name = self.interned()?;
+        let value = self.input.read_attribute_value(type_info)?;
+
+        let element = self.open.last().cloned().unwrap_or_default();
+        match self
+            .attributes
+            .as_mut()
+            .map(|seen| seen.insert(name.clone()))
+        {
+            None => self.violation(format!(
+                "attribute {} outside a start tag, after <{}>",
+                name, element
+            )),
+            Some(false) => self.violation(format!("<{}> repeats attribute {}", element, name)),
+            Some(true) => {}
+        }
+
+        let expected = self
+            .schema
+            .as_ref()
+            .and_then(|schema| schema.type_for(&self.open, &name));
+        if let Some(type_name) = expected
+            && let Err(e) = read_as(&value, type_name)
+        {
+            self.violation(format!(
+                "<{}> {} is read as {}: {}",
+                element, name, type_name, e
+            ));
+        }
+        Ok(())
+    }
+
+    fn detect_profile(&mut self, root: &str) {
+        let profile = match root {
+            "packages" => "packages",
+            "package-restrictions" => "package-restrictions",
+            "settings" => "settings",
+            "app-ops" => "appops",
+            _ => return,
+        };
+        self.schema = TypeSchema::bundled(profile);
+        self.report.profile = Some(profile);
+    }
+}
+
+/// Reads `value` the way the typed `TypedXmlPullParser` getter for `type_name` would
+fn read_as(value: &AttributeValue, type_name: &str) -> Result<()> {
+    match type_name {
+        "int" => value.to_i32().map(drop),
+        "int_hex" => value.to_i32_hex().map(drop),
+        "long" => value.to_i64().map(drop),
+        "long_hex" => value.to_i64_hex().map(drop),
+        "float" => value.to_f32().map(drop),
+        "double" => value.to_f64().map(drop),
+        "boolean" => value.to_bool().map(drop),
+        "bytes_hex" => value.to_bytes_hex().map(drop),
+        "bytes_base64" => value.to_bytes_base64().map(drop),
+        _ => Ok(()),
+    }
+}
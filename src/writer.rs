@@ -0,0 +1,615 @@
+use crate::dictionary::Dictionary;
+use crate::reader::AttributeValue;
+use crate::*;
+use byteorder::{BigEndian, WriteBytesExt};
+use smol_str::SmolStr;
+use std::io::Write;
+
+// ============================================================================
+// Fast Data Output Writer
+// ============================================================================
+
+/// Big-endian primitive writer with the ABX intern pool.
+///
+/// Strings are defined in the pool at their first use; the hash map only finds
+/// their indices. Every map gets its own random keys, and the same input gives
+/// the same bytes whatever they are, with or without the `fast-hash` feature.
+///
+/// ```
+/// use android_xml_converter::XmlToAbxConverter;
+///
+/// let to_abx = |xml: &str| {
+///     let mut abx = Vec::new();
+///     XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+///     abx
+/// };
+/// assert_eq!(
+///     to_abx(r#"<a b="x" c="x"><d b="x"/></a>"#),
+///     b"ABX\0\x10\x32\xff\xff\0\x01a\x3f\xff\xff\0\x01b\xff\xff\0\x01x\x3f\xff\xff\0\x01c\0\x02\
+///       \x32\xff\xff\0\x01d\x3f\0\x01\0\x02\x33\0\x04\x33\0\0\x11"
+/// );
+///
+/// // each conversion hashes with different keys
+/// let xml: String = (0..500).map(|i| format!(r#"<e{} a{}="v{}"/>"#, i % 37, i % 41, i)).collect();
+/// let xml = format!("<root>{}</root>", xml);
+/// let first = to_abx(&xml);
+/// for _ in 0..20 {
+///     assert_eq!(to_abx(&xml), first);
+/// }
+/// ```
+pub struct FastDataOutput<W: Write> {
+    writer: W,
+    string_pool: FastHashMap<SmolStr, u16>,
+    interned_strings: Vec<SmolStr>,
+    intern_order: Vec<SmolStr>,
+    builtin_len: usize,
+    /// Dictionary seeded with [`Self::seed_dictionary`] and the pool index of its
+    /// first string; its strings are looked up in its own shared table
+    shared: Option<(Dictionary, u16)>,
+    /// Strings are written as modified UTF-8, see [`Options::modified_utf8`]
+    modified_utf8: bool,
+    #[cfg(feature = "android-dictionary")]
+    dictionary: Option<&'static phf::OrderedSet<&'static str>>,
+}
+
+impl<W: Write> FastDataOutput<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_intern_order(writer, Vec::new())
+    }
+
+    /// Creates an output that defines interned strings in the given order where possible.
+    ///
+    /// Whenever the next pool slot is expected to hold the string being written, it is
+    /// defined again even if an earlier copy exists, so pools recorded from existing
+    /// files (including ones with duplicate entries) are reproduced exactly.
+    pub fn with_intern_order(writer: W, intern_order: Vec<SmolStr>) -> Self {
+        Self {
+            writer,
+            string_pool: FastHashMap::default(),
+            interned_strings: Vec::with_capacity(INITIAL_STRING_POOL_CAPACITY),
+            intern_order,
+            builtin_len: 0,
+            shared: None,
+            modified_utf8: false,
+            #[cfg(feature = "android-dictionary")]
+            dictionary: None,
+        }
+    }
+
+    /// Creates an output whose pool starts with [`crate::dictionary::ANDROID_NAMES`].
+    ///
+    /// Dictionary names are resolved through a perfect hash and never written out.
+    #[cfg(feature = "android-dictionary")]
+    pub fn with_android_dictionary(writer: W) -> Self {
+        let mut output = Self::new(writer);
+        output.dictionary = Some(&crate::dictionary::ANDROID_NAMES);
+        output.interned_strings.extend(
+            crate::dictionary::ANDROID_NAMES
+                .iter()
+                .map(|name| SmolStr::new_static(name)),
+        );
+        output.builtin_len = output.interned_strings.len();
+        output
+    }
+
+    /// Appends strings to the pool as if they had already been written
+    pub fn seed(&mut self, strings: &[SmolStr]) -> Result<()> {
+        if self.interned_strings.len() + strings.len() >= INTERNED_STRING_NEW_MARKER as usize {
+            return Err(ConversionError::ParseError(format!(
+                "Seeded intern pool would exceed {} entries",
+                INTERNED_STRING_NEW_MARKER - 1
+            )));
+        }
+        for string in strings {
+            let index = self.interned_strings.len() as u16;
+            self.string_pool.entry(string.clone()).or_insert(index);
+            self.interned_strings.push(string.clone());
+        }
+        Ok(())
+    }
+
+    /// Appends a dictionary to the pool like [`Self::seed`], but resolves its
+    /// strings through the dictionary's shared lookup table instead of hashing
+    /// them into this output's pool. Only one dictionary can be shared.
+    pub fn seed_dictionary(&mut self, dictionary: &Dictionary) -> Result<()> {
+        if self.shared.is_some() {
+            return self.seed(dictionary.strings());
+        }
+        let base = self.interned_strings.len();
+        if base + dictionary.len() >= INTERNED_STRING_NEW_MARKER as usize {
+            return Err(ConversionError::ParseError(format!(
+                "Seeded intern pool would exceed {} entries",
+                INTERNED_STRING_NEW_MARKER - 1
+            )));
+        }
+        self.interned_strings
+            .extend(dictionary.strings().iter().cloned());
+        self.shared = Some((dictionary.clone(), base as u16));
+        Ok(())
+    }
+
+    /// Pool index of `s` if it was written or seeded before
+    fn pooled_index(&self, s: &str) -> Option<u16> {
+        if let Some((dictionary, base)) = &self.shared
+            && let Some(index) = dictionary.index_of(s)
+        {
+            return Some(base + index);
+        }
+        self.string_pool.get(s).copied()
+    }
+
+    /// The pool built so far, excluding built-in names, for reuse in later conversions
+    pub fn dictionary(&self) -> Dictionary {
+        Dictionary::new(self.interned_strings[self.builtin_len..].to_vec())
+    }
+
+    /// Number of strings in the pool, seeded ones included
+    pub fn pool_len(&self) -> usize {
+        self.interned_strings.len()
+    }
+
+    /// Whether writing `s` interned would reference an existing pool entry
+    pub fn is_pooled(&self, s: &str) -> bool {
+        #[cfg(feature = "android-dictionary")]
+        if self
+            .dictionary
+            .is_some_and(|dictionary| dictionary.contains(s))
+        {
+            return true;
+        }
+        self.pooled_index(s).is_some()
+    }
+
+    pub fn write_byte(&mut self, value: u8) -> Result<()> {
+        self.writer.write_u8(value)?;
+        Ok(())
+    }
+
+    pub fn write_short(&mut self, value: u16) -> Result<()> {
+        self.writer.write_u16::<BigEndian>(value)?;
+        Ok(())
+    }
+
+    pub fn write_int(&mut self, value: i32) -> Result<()> {
+        self.writer.write_i32::<BigEndian>(value)?;
+        Ok(())
+    }
+
+    pub fn write_long(&mut self, value: i64) -> Result<()> {
+        self.writer.write_i64::<BigEndian>(value)?;
+        Ok(())
+    }
+
+    pub fn write_float(&mut self, value: f32) -> Result<()> {
+        self.writer.write_f32::<BigEndian>(value)?;
+        Ok(())
+    }
+
+    pub fn write_double(&mut self, value: f64) -> Result<()> {
+        self.writer.write_f64::<BigEndian>(value)?;
+        Ok(())
+    }
+
+    /// Writes strings as modified UTF-8 from now on, see [`Options::modified_utf8`]
+    pub fn set_modified_utf8(&mut self, modified_utf8: bool) {
+        self.modified_utf8 = modified_utf8;
+    }
+
+    pub fn write_utf(&mut self, s: &str) -> Result<()> {
+        let encoded = if self.modified_utf8 {
+            mutf8::encode(s)
+        } else {
+            s.as_bytes().into()
+        };
+        let bytes = encoded.as_ref();
+        if bytes.len() > MAX_UNSIGNED_SHORT as usize {
+            return Err(ConversionError::StringTooLong(
+                bytes.len(),
+                MAX_UNSIGNED_SHORT as usize,
+            ));
+        }
+        self.write_short(bytes.len() as u16)?;
+        self.writer.write_all(bytes)?;
+        Ok(())
+    }
+
+    pub fn write_interned_utf(&mut self, s: &str) -> Result<()> {
+        self.write_interned(s).map(|_| ())
+    }
+
+    /// Writes an interned string like [`Self::write_interned_utf`] and returns its
+    /// pool index
+    fn write_interned(&mut self, s: &str) -> Result<u16> {
+        #[cfg(feature = "android-dictionary")]
+        if let Some(index) = self
+            .dictionary
+            .and_then(|dictionary| dictionary.get_index(s))
+        {
+            self.write_short(index as u16)?;
+            return Ok(index as u16);
+        }
+
+        let expected_next = self
+            .intern_order
+            .get(self.interned_strings.len())
+            .is_some_and(|next| next == s);
+        if let Some(index) = self.pooled_index(s)
+            && !expected_next
+        {
+            self.write_short(index)?;
+            Ok(index)
+        } else {
+            let index = u16::try_from(self.interned_strings.len())
+                .ok()
+                .filter(|&index| index != INTERNED_STRING_NEW_MARKER)
+                .ok_or(ConversionError::InternPoolFull(
+                    INTERNED_STRING_NEW_MARKER as usize,
+                ))?;
+            self.write_short(INTERNED_STRING_NEW_MARKER)?;
+            self.write_utf(s)?;
+            let smol = SmolStr::new(s);
+            self.string_pool.insert(smol.clone(), index);
+            self.interned_strings.push(smol);
+            Ok(index)
+        }
+    }
+
+    pub fn write_bytes(&mut self, data: &[u8]) -> Result<()> {
+        self.writer.write_all(data)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Binary XML Serializer
+// ============================================================================
+
+pub struct BinaryXmlSerializer<W: Write> {
+    output: FastDataOutput<W>,
+    preserve_whitespace: bool,
+    /// Pool index and name of each open element, innermost last
+    open_tags: Vec<(u16, SmolStr)>,
+}
+
+impl<W: Write> BinaryXmlSerializer<W> {
+    pub fn new(writer: W) -> Result<Self> {
+        Self::with_options(writer, true)
+    }
+
+    pub fn with_options(writer: W, preserve_whitespace: bool) -> Result<Self> {
+        Self::with_output(FastDataOutput::new(writer), preserve_whitespace)
+    }
+
+    /// Creates a serializer configured from [`Options`]
+    pub fn from_options(writer: W, options: &Options) -> Result<Self> {
+        #[cfg(feature = "android-dictionary")]
+        let mut output = if options.android_dictionary {
+            FastDataOutput::with_android_dictionary(writer)
+        } else {
+            FastDataOutput::new(writer)
+        };
+        #[cfg(not(feature = "android-dictionary"))]
+        let mut output = FastDataOutput::new(writer);
+
+        if let Some(dictionary) = &options.dictionary {
+            output.seed_dictionary(dictionary)?;
+        }
+        output.set_modified_utf8(options.modified_utf8);
+        Self::with_output(output, options.keeps_whitespace())
+    }
+
+    /// The intern pool built so far, see [`FastDataOutput::dictionary`]
+    pub fn dictionary(&self) -> Dictionary {
+        self.output.dictionary()
+    }
+
+    /// Whether a value is written interned under `policy` at this point
+    pub fn interns(&self, value: &str, policy: &InternPolicy) -> bool {
+        policy.admits(value)
+            && (self.output.pool_len() < policy.max_pool_size || self.output.is_pooled(value))
+    }
+
+    /// Creates a serializer that reuses a recorded intern pool order, see
+    /// [`FastDataOutput::with_intern_order`]
+    pub fn with_intern_order(writer: W, intern_order: Vec<SmolStr>) -> Result<Self> {
+        Self::with_output(
+            FastDataOutput::with_intern_order(writer, intern_order),
+            true,
+        )
+    }
+
+    fn with_output(mut output: FastDataOutput<W>, preserve_whitespace: bool) -> Result<Self> {
+        output.write_bytes(&PROTOCOL_MAGIC_VERSION_0)?;
+        Ok(Self {
+            output,
+            preserve_whitespace,
+            open_tags: Vec::new(),
+        })
+    }
+
+    pub fn preserve_whitespace(&self) -> bool {
+        self.preserve_whitespace
+    }
+
+    pub(crate) fn write_token(&mut self, token: u8, text: Option<&str>) -> Result<()> {
+        if let Some(text) = text {
+            self.output.write_byte(token | TYPE_STRING)?;
+            self.output.write_utf(text)?;
+        } else {
+            self.output.write_byte(token | TYPE_NULL)?;
+        }
+        Ok(())
+    }
+
+    pub fn start_document(&mut self) -> Result<()> {
+        self.output.write_byte(START_DOCUMENT | TYPE_NULL)
+    }
+
+    pub fn end_document(&mut self) -> Result<()> {
+        self.output.write_byte(END_DOCUMENT | TYPE_NULL)?;
+        self.output.flush()
+    }
+
+    pub fn start_tag(&mut self, name: &str) -> Result<()> {
+        self.output.write_byte(START_TAG | TYPE_STRING_INTERNED)?;
+        let index = self.output.write_interned(name)?;
+        let name = self.output.interned_strings[index as usize].clone();
+        self.open_tags.push((index, name));
+        Ok(())
+    }
+
+    /// Closes the innermost open element, reusing the pool index of its start
+    /// tag. A recorded intern order may define the name again at this point, so
+    /// the pool is always consulted in that case. Fails without writing anything
+    /// if no element is open or `name` is not the innermost one.
+    ///
+    /// ```
+    /// use android_xml_converter::writer::BinaryXmlSerializer;
+    ///
+    /// let mut serializer = BinaryXmlSerializer::new(Vec::new()).unwrap();
+    /// assert!(serializer.end_tag("a").is_err());
+    /// serializer.start_tag("a").unwrap();
+    /// assert!(serializer.end_tag("b").is_err());
+    /// serializer.end_tag("a").unwrap();
+    /// ```
+    pub fn end_tag(&mut self, name: &str) -> Result<()> {
+        let Some((index, open)) = self.open_tags.pop() else {
+            return Err(ConversionError::ParseError(format!(
+                "End tag {} without an open element",
+                name
+            )));
+        };
+        if open != name {
+            let message = format!("End tag {} does not match open element {}", name, open);
+            self.open_tags.push((index, open));
+            return Err(ConversionError::ParseError(message));
+        }
+
+        self.output.write_byte(END_TAG | TYPE_STRING_INTERNED)?;
+        if self.output.intern_order.is_empty() {
+            self.output.write_short(index)
+        } else {
+            self.output.write_interned_utf(name)
+        }
+    }
+
+    pub fn attribute(&mut self, name: &str, value: &str) -> Result<()> {
+        self.output.write_byte(ATTRIBUTE | TYPE_STRING)?;
+        self.output.write_interned_utf(name)?;
+        self.output.write_utf(value)
+    }
+
+    pub fn attribute_interned(&mut self, name: &str, value: &str) -> Result<()> {
+        self.output.write_byte(ATTRIBUTE | TYPE_STRING_INTERNED)?;
+        self.output.write_interned_utf(name)?;
+        self.output.write_interned_utf(value)
+    }
+
+    pub fn attribute_bytes_hex(&mut self, name: &str, value: &[u8]) -> Result<()> {
+        if value.len() > MAX_UNSIGNED_SHORT as usize {
+            return Err(ConversionError::BinaryDataTooLong(
+                value.len(),
+                MAX_UNSIGNED_SHORT as usize,
+            ));
+        }
+        self.output.write_byte(ATTRIBUTE | TYPE_BYTES_HEX)?;
+        self.output.write_interned_utf(name)?;
+        self.output.write_short(value.len() as u16)?;
+        self.output.write_bytes(value)
+    }
+
+    pub fn attribute_bytes_base64(&mut self, name: &str, value: &[u8]) -> Result<()> {
+        if value.len() > MAX_UNSIGNED_SHORT as usize {
+            return Err(ConversionError::BinaryDataTooLong(
+                value.len(),
+                MAX_UNSIGNED_SHORT as usize,
+            ));
+        }
+        self.output.write_byte(ATTRIBUTE | TYPE_BYTES_BASE64)?;
+        self.output.write_interned_utf(name)?;
+        self.output.write_short(value.len() as u16)?;
+        self.output.write_bytes(value)
+    }
+
+    pub fn attribute_int(&mut self, name: &str, value: i32) -> Result<()> {
+        self.output.write_byte(ATTRIBUTE | TYPE_INT)?;
+        self.output.write_interned_utf(name)?;
+        self.output.write_int(value)
+    }
+
+    pub fn attribute_int_hex(&mut self, name: &str, value: i32) -> Result<()> {
+        self.output.write_byte(ATTRIBUTE | TYPE_INT_HEX)?;
+        self.output.write_interned_utf(name)?;
+        self.output.write_int(value)
+    }
+
+    pub fn attribute_long(&mut self, name: &str, value: i64) -> Result<()> {
+        self.output.write_byte(ATTRIBUTE | TYPE_LONG)?;
+        self.output.write_interned_utf(name)?;
+        self.output.write_long(value)
+    }
+
+    pub fn attribute_long_hex(&mut self, name: &str, value: i64) -> Result<()> {
+        self.output.write_byte(ATTRIBUTE | TYPE_LONG_HEX)?;
+        self.output.write_interned_utf(name)?;
+        self.output.write_long(value)
+    }
+
+    pub fn attribute_float(&mut self, name: &str, value: f32) -> Result<()> {
+        self.output.write_byte(ATTRIBUTE | TYPE_FLOAT)?;
+        self.output.write_interned_utf(name)?;
+        self.output.write_float(value)
+    }
+
+    pub fn attribute_double(&mut self, name: &str, value: f64) -> Result<()> {
+        self.output.write_byte(ATTRIBUTE | TYPE_DOUBLE)?;
+        self.output.write_interned_utf(name)?;
+        self.output.write_double(value)
+    }
+
+    pub fn attribute_boolean(&mut self, name: &str, value: bool) -> Result<()> {
+        let token = if value {
+            ATTRIBUTE | TYPE_BOOLEAN_TRUE
+        } else {
+            ATTRIBUTE | TYPE_BOOLEAN_FALSE
+        };
+        self.output.write_byte(token)?;
+        self.output.write_interned_utf(name)
+    }
+
+    /// Writes an attribute using the ABX type carried by `value`
+    pub fn attribute_value(&mut self, name: &str, value: &AttributeValue) -> Result<()> {
+        match value {
+            AttributeValue::String(value) => self.attribute(name, value),
+            AttributeValue::Interned(value) => self.attribute_interned(name, value),
+            AttributeValue::Int(value) => self.attribute_int(name, *value),
+            AttributeValue::IntHex(value) => self.attribute_int_hex(name, *value),
+            AttributeValue::Long(value) => self.attribute_long(name, *value),
+            AttributeValue::LongHex(value) => self.attribute_long_hex(name, *value),
+            AttributeValue::Float(value) => self.attribute_float(name, *value),
+            AttributeValue::Double(value) => self.attribute_double(name, *value),
+            AttributeValue::Boolean(value) => self.attribute_boolean(name, *value),
+            AttributeValue::BytesHex(value) => self.attribute_bytes_hex(name, value),
+            AttributeValue::BytesBase64(value) => self.attribute_bytes_base64(name, value),
+        }
+    }
+
+    /// Writes a token for a reserved command followed by an extension payload
+    #[cfg(feature = "unstable")]
+    pub fn extension_token(&mut self, command: u8, type_info: u8, payload: &[u8]) -> Result<()> {
+        if !crate::extension::RESERVED_COMMANDS.contains(&command) || type_info & 0x0F != 0 {
+            return Err(ConversionError::ParseError(format!(
+                "Invalid extension token: command {}, type {:#04x}",
+                command, type_info
+            )));
+        }
+        self.output.write_byte(command | type_info)?;
+        self.output.write_bytes(payload)
+    }
+
+    /// Writes bytes verbatim, bypassing token framing
+    pub(crate) fn raw_bytes(&mut self, data: &[u8]) -> Result<()> {
+        self.output.write_bytes(data)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.output.flush()
+    }
+
+    /// Writes text as a TEXT token, or like Android as several consecutive ones
+    /// split at character boundaries if it is longer than the 65,535 bytes a
+    /// token can hold. Readers render consecutive TEXT tokens as one text.
+    ///
+    /// ```
+    /// use android_xml_converter::{AbxToXmlConverter, BinaryXmlSerializer};
+    ///
+    /// let text = "ä".repeat(40_000);
+    /// let mut abx = Vec::new();
+    /// let mut serializer = BinaryXmlSerializer::new(&mut abx).unwrap();
+    /// serializer.start_document().unwrap();
+    /// serializer.start_tag("note").unwrap();
+    /// serializer.text(&text).unwrap();
+    /// serializer.end_tag("note").unwrap();
+    /// serializer.end_document().unwrap();
+    ///
+    /// let xml = AbxToXmlConverter::convert_bytes(&abx).unwrap();
+    /// assert!(xml.ends_with(&format!("<note>{}</note>", text)));
+    /// ```
+    pub fn text(&mut self, text: &str) -> Result<()> {
+        let modified_utf8 = self.output.modified_utf8;
+        let mut rest = text;
+        while utf_len(rest, modified_utf8) > MAX_UNSIGNED_SHORT as usize {
+            let end = char_boundary(rest, MAX_UNSIGNED_SHORT as usize, modified_utf8);
+            self.write_token(TEXT, Some(&rest[..end]))?;
+            rest = &rest[end..];
+        }
+        self.write_token(TEXT, Some(rest))
+    }
+
+    pub fn cdsect(&mut self, text: &str) -> Result<()> {
+        self.write_token(CDSECT, Some(text))
+    }
+
+    pub fn comment(&mut self, text: &str) -> Result<()> {
+        self.write_token(COMMENT, Some(text))
+    }
+
+    pub fn processing_instruction(&mut self, target: &str, data: Option<&str>) -> Result<()> {
+        if let Some(data) = data
+            && !data.is_empty()
+        {
+            let full_pi = format!("{} {}", target, data);
+            return self.write_token(PROCESSING_INSTRUCTION, Some(&full_pi));
+        }
+        self.write_token(PROCESSING_INSTRUCTION, Some(target))
+    }
+
+    pub fn docdecl(&mut self, text: &str) -> Result<()> {
+        self.write_token(DOCDECL, Some(text))
+    }
+
+    pub fn ignorable_whitespace(&mut self, text: &str) -> Result<()> {
+        self.write_token(IGNORABLE_WHITESPACE, Some(text))
+    }
+
+    pub fn entity_ref(&mut self, text: &str) -> Result<()> {
+        self.write_token(ENTITY_REF, Some(text))
+    }
+}
+
+/// Bytes `text` takes as an ABX string
+pub(crate) fn utf_len(text: &str, modified_utf8: bool) -> usize {
+    if modified_utf8 {
+        mutf8::encoded_len(text)
+    } else {
+        text.len()
+    }
+}
+
+/// The largest char boundary of `text` whose prefix takes at most `limit` bytes
+/// as an ABX string, so that no character (or surrogate pair) is split
+pub(crate) fn char_boundary(text: &str, limit: usize, modified_utf8: bool) -> usize {
+    if modified_utf8 {
+        let mut length = 0;
+        for (index, c) in text.char_indices() {
+            length += mutf8::char_len(c);
+            if length > limit {
+                return index;
+            }
+        }
+        return text.len();
+    }
+    if text.len() <= limit {
+        return text.len();
+    }
+    let mut end = limit;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    end
+}
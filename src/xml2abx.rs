@@ -1,486 +1,13 @@
-use ahash::AHashMap;
+use android_xml_converter::atomic::AtomicFile;
+use android_xml_converter::dictionary::Dictionary;
+use android_xml_converter::profile::Profiler;
+use android_xml_converter::schema::{BUNDLED_PROFILES, TypeSchema};
+use android_xml_converter::timeout::IdleTimeoutReader;
 use android_xml_converter::*;
-use byteorder::{BigEndian, WriteBytesExt};
-use quick_xml::Reader;
-use quick_xml::events::Event;
-use smol_str::SmolStr;
 use std::env;
 use std::fs::File;
-use std::io::{self, BufRead, BufWriter, Read, Write};
-
-// ============================================================================
-// Fast Data Output Writer
-// ============================================================================
-
-pub struct FastDataOutput<W: Write> {
-    writer: W,
-    string_pool: AHashMap<SmolStr, u16>,
-    interned_strings: Vec<SmolStr>,
-}
-
-impl<W: Write> FastDataOutput<W> {
-    pub fn new(writer: W) -> Self {
-        Self {
-            writer,
-            string_pool: AHashMap::new(),
-            interned_strings: Vec::with_capacity(INITIAL_STRING_POOL_CAPACITY),
-        }
-    }
-
-    pub fn write_byte(&mut self, value: u8) -> Result<()> {
-        self.writer.write_u8(value)?;
-        Ok(())
-    }
-
-    pub fn write_short(&mut self, value: u16) -> Result<()> {
-        self.writer.write_u16::<BigEndian>(value)?;
-        Ok(())
-    }
-
-    pub fn write_int(&mut self, value: i32) -> Result<()> {
-        self.writer.write_i32::<BigEndian>(value)?;
-        Ok(())
-    }
-
-    pub fn write_long(&mut self, value: i64) -> Result<()> {
-        self.writer.write_i64::<BigEndian>(value)?;
-        Ok(())
-    }
-
-    pub fn write_float(&mut self, value: f32) -> Result<()> {
-        self.writer.write_f32::<BigEndian>(value)?;
-        Ok(())
-    }
-
-    pub fn write_double(&mut self, value: f64) -> Result<()> {
-        self.writer.write_f64::<BigEndian>(value)?;
-        Ok(())
-    }
-
-    pub fn write_utf(&mut self, s: &str) -> Result<()> {
-        let bytes = s.as_bytes();
-        if bytes.len() > MAX_UNSIGNED_SHORT as usize {
-            return Err(ConversionError::StringTooLong(
-                bytes.len(),
-                MAX_UNSIGNED_SHORT as usize,
-            ));
-        }
-        self.write_short(bytes.len() as u16)?;
-        self.writer.write_all(bytes)?;
-        Ok(())
-    }
-
-    pub fn write_interned_utf(&mut self, s: &str) -> Result<()> {
-        if let Some(&index) = self.string_pool.get(s) {
-            self.write_short(index)?;
-        } else {
-            self.write_short(INTERNED_STRING_NEW_MARKER)?;
-            self.write_utf(s)?;
-            let index = self.interned_strings.len() as u16;
-            let smol = SmolStr::new(s);
-            self.string_pool.insert(smol.clone(), index);
-            self.interned_strings.push(smol);
-        }
-        Ok(())
-    }
-
-    pub fn write_bytes(&mut self, data: &[u8]) -> Result<()> {
-        self.writer.write_all(data)?;
-        Ok(())
-    }
-
-    pub fn flush(&mut self) -> Result<()> {
-        self.writer.flush()?;
-        Ok(())
-    }
-}
-
-// ============================================================================
-// Binary XML Serializer
-// ============================================================================
-
-pub struct BinaryXmlSerializer<W: Write> {
-    output: FastDataOutput<W>,
-    preserve_whitespace: bool,
-}
-
-impl<W: Write> BinaryXmlSerializer<W> {
-    pub fn new(writer: W) -> Result<Self> {
-        Self::with_options(writer, true)
-    }
-
-    pub fn with_options(writer: W, preserve_whitespace: bool) -> Result<Self> {
-        let mut output = FastDataOutput::new(writer);
-        output.write_bytes(&PROTOCOL_MAGIC_VERSION_0)?;
-        Ok(Self {
-            output,
-            preserve_whitespace,
-        })
-    }
-
-    fn write_token(&mut self, token: u8, text: Option<&str>) -> Result<()> {
-        if let Some(text) = text {
-            self.output.write_byte(token | TYPE_STRING)?;
-            self.output.write_utf(text)?;
-        } else {
-            self.output.write_byte(token | TYPE_NULL)?;
-        }
-        Ok(())
-    }
-
-    pub fn start_document(&mut self) -> Result<()> {
-        self.output.write_byte(START_DOCUMENT | TYPE_NULL)
-    }
-
-    pub fn end_document(&mut self) -> Result<()> {
-        self.output.write_byte(END_DOCUMENT | TYPE_NULL)?;
-        self.output.flush()
-    }
-
-    pub fn start_tag(&mut self, name: &str) -> Result<()> {
-        self.output.write_byte(START_TAG | TYPE_STRING_INTERNED)?;
-        self.output.write_interned_utf(name)
-    }
-
-    pub fn end_tag(&mut self, name: &str) -> Result<()> {
-        self.output.write_byte(END_TAG | TYPE_STRING_INTERNED)?;
-        self.output.write_interned_utf(name)
-    }
-
-    pub fn attribute(&mut self, name: &str, value: &str) -> Result<()> {
-        self.output.write_byte(ATTRIBUTE | TYPE_STRING)?;
-        self.output.write_interned_utf(name)?;
-        self.output.write_utf(value)
-    }
-
-    pub fn attribute_interned(&mut self, name: &str, value: &str) -> Result<()> {
-        self.output.write_byte(ATTRIBUTE | TYPE_STRING_INTERNED)?;
-        self.output.write_interned_utf(name)?;
-        self.output.write_interned_utf(value)
-    }
-
-    pub fn attribute_bytes_hex(&mut self, name: &str, value: &[u8]) -> Result<()> {
-        if value.len() > MAX_UNSIGNED_SHORT as usize {
-            return Err(ConversionError::BinaryDataTooLong(
-                value.len(),
-                MAX_UNSIGNED_SHORT as usize,
-            ));
-        }
-        self.output.write_byte(ATTRIBUTE | TYPE_BYTES_HEX)?;
-        self.output.write_interned_utf(name)?;
-        self.output.write_short(value.len() as u16)?;
-        self.output.write_bytes(value)
-    }
-
-    pub fn attribute_bytes_base64(&mut self, name: &str, value: &[u8]) -> Result<()> {
-        if value.len() > MAX_UNSIGNED_SHORT as usize {
-            return Err(ConversionError::BinaryDataTooLong(
-                value.len(),
-                MAX_UNSIGNED_SHORT as usize,
-            ));
-        }
-        self.output.write_byte(ATTRIBUTE | TYPE_BYTES_BASE64)?;
-        self.output.write_interned_utf(name)?;
-        self.output.write_short(value.len() as u16)?;
-        self.output.write_bytes(value)
-    }
-
-    pub fn attribute_int(&mut self, name: &str, value: i32) -> Result<()> {
-        self.output.write_byte(ATTRIBUTE | TYPE_INT)?;
-        self.output.write_interned_utf(name)?;
-        self.output.write_int(value)
-    }
-
-    pub fn attribute_int_hex(&mut self, name: &str, value: i32) -> Result<()> {
-        self.output.write_byte(ATTRIBUTE | TYPE_INT_HEX)?;
-        self.output.write_interned_utf(name)?;
-        self.output.write_int(value)
-    }
-
-    pub fn attribute_long(&mut self, name: &str, value: i64) -> Result<()> {
-        self.output.write_byte(ATTRIBUTE | TYPE_LONG)?;
-        self.output.write_interned_utf(name)?;
-        self.output.write_long(value)
-    }
-
-    pub fn attribute_long_hex(&mut self, name: &str, value: i64) -> Result<()> {
-        self.output.write_byte(ATTRIBUTE | TYPE_LONG_HEX)?;
-        self.output.write_interned_utf(name)?;
-        self.output.write_long(value)
-    }
-
-    pub fn attribute_float(&mut self, name: &str, value: f32) -> Result<()> {
-        self.output.write_byte(ATTRIBUTE | TYPE_FLOAT)?;
-        self.output.write_interned_utf(name)?;
-        self.output.write_float(value)
-    }
-
-    pub fn attribute_double(&mut self, name: &str, value: f64) -> Result<()> {
-        self.output.write_byte(ATTRIBUTE | TYPE_DOUBLE)?;
-        self.output.write_interned_utf(name)?;
-        self.output.write_double(value)
-    }
-
-    pub fn attribute_boolean(&mut self, name: &str, value: bool) -> Result<()> {
-        let token = if value {
-            ATTRIBUTE | TYPE_BOOLEAN_TRUE
-        } else {
-            ATTRIBUTE | TYPE_BOOLEAN_FALSE
-        };
-        self.output.write_byte(token)?;
-        self.output.write_interned_utf(name)
-    }
-
-    pub fn text(&mut self, text: &str) -> Result<()> {
-        self.write_token(TEXT, Some(text))
-    }
-
-    pub fn cdsect(&mut self, text: &str) -> Result<()> {
-        self.write_token(CDSECT, Some(text))
-    }
-
-    pub fn comment(&mut self, text: &str) -> Result<()> {
-        self.write_token(COMMENT, Some(text))
-    }
-
-    pub fn processing_instruction(&mut self, target: &str, data: Option<&str>) -> Result<()> {
-        if let Some(data) = data
-            && !data.is_empty() {
-                let full_pi = format!("{} {}", target, data);
-                return self.write_token(PROCESSING_INSTRUCTION, Some(&full_pi));
-            }
-        self.write_token(PROCESSING_INSTRUCTION, Some(target))
-    }
-
-    pub fn docdecl(&mut self, text: &str) -> Result<()> {
-        self.write_token(DOCDECL, Some(text))
-    }
-
-    pub fn ignorable_whitespace(&mut self, text: &str) -> Result<()> {
-        self.write_token(IGNORABLE_WHITESPACE, Some(text))
-    }
-
-    pub fn entity_ref(&mut self, text: &str) -> Result<()> {
-        self.write_token(ENTITY_REF, Some(text))
-    }
-}
-
-// ============================================================================
-// Converter API
-// ============================================================================
-
-pub struct XmlToAbxConverter;
-
-impl XmlToAbxConverter {
-    pub fn convert_from_string<W: Write>(xml: &str, writer: W) -> Result<()> {
-        Self::convert_from_string_with_options(xml, writer, true)
-    }
-
-    pub fn convert_from_string_with_options<W: Write>(
-        xml: &str,
-        writer: W,
-        preserve_whitespace: bool,
-    ) -> Result<()> {
-        let mut reader = Reader::from_str(xml);
-        reader.config_mut().trim_text(!preserve_whitespace);
-        Self::convert_reader_with_options(reader, writer, preserve_whitespace)
-    }
-
-    pub fn convert_from_file<W: Write>(input_path: &str, writer: W) -> Result<()> {
-        Self::convert_from_file_with_options(input_path, writer, true)
-    }
-
-    pub fn convert_from_file_with_options<W: Write>(
-        input_path: &str,
-        writer: W,
-        preserve_whitespace: bool,
-    ) -> Result<()> {
-        let mut reader = Reader::from_file(input_path)?;
-        reader.config_mut().trim_text(!preserve_whitespace);
-        Self::convert_reader_with_options(reader, writer, preserve_whitespace)
-    }
-
-    pub fn convert_from_reader<R: BufRead, W: Write>(input: R, writer: W) -> Result<()> {
-        Self::convert_from_reader_with_options(input, writer, true)
-    }
-
-    pub fn convert_from_reader_with_options<R: BufRead, W: Write>(
-        input: R,
-        writer: W,
-        preserve_whitespace: bool,
-    ) -> Result<()> {
-        let mut reader = Reader::from_reader(input);
-        reader.config_mut().trim_text(!preserve_whitespace);
-        Self::convert_reader_with_options(reader, writer, preserve_whitespace)
-    }
-
-    fn convert_reader_with_options<R: BufRead, W: Write>(
-        mut reader: Reader<R>,
-        writer: W,
-        preserve_whitespace: bool,
-    ) -> Result<()> {
-        let mut serializer = BinaryXmlSerializer::with_options(writer, preserve_whitespace)?;
-        let mut buf = Vec::with_capacity(INITIAL_EVENT_BUFFER_CAPACITY);
-
-        serializer.start_document()?;
-
-        loop {
-            match reader.read_event_into(&mut buf)? {
-                Event::Start(e) => {
-                    let name_bytes = e.name();
-                    let name = std::str::from_utf8(name_bytes.as_ref())?;
-
-                    if name.contains(':') {
-                        show_warning(
-                            "Namespaces and prefixes",
-                            Some(&format!("Found prefixed element: {}", name)),
-                        );
-                    }
-
-                    serializer.start_tag(name)?;
-
-                    for attr in e.attributes() {
-                        let attr = attr?;
-                        let attr_name = std::str::from_utf8(attr.key.as_ref())?;
-                        let attr_value = std::str::from_utf8(&attr.value)?;
-
-                        if attr_name.starts_with("xmlns") || attr_name.contains(':') {
-                            show_warning(
-                                "Namespaces and prefixes",
-                                Some(&format!(
-                                    "Found namespace declaration or prefixed attribute: {}",
-                                    attr_name
-                                )),
-                            );
-                        }
-
-                        Self::write_attribute(&mut serializer, attr_name, attr_value)?;
-                    }
-                }
-                Event::End(e) => {
-                    let name_bytes = e.name();
-                    let name = std::str::from_utf8(name_bytes.as_ref())?;
-                    serializer.end_tag(name)?;
-                }
-                Event::Empty(e) => {
-                    let name_bytes = e.name();
-                    let name = std::str::from_utf8(name_bytes.as_ref())?;
-
-                    if name.contains(':') {
-                        show_warning(
-                            "Namespaces and prefixes",
-                            Some(&format!("Found prefixed element: {}", name)),
-                        );
-                    }
-
-                    serializer.start_tag(name)?;
-
-                    for attr in e.attributes() {
-                        let attr = attr?;
-                        let attr_name = std::str::from_utf8(attr.key.as_ref())?;
-                        let attr_value = std::str::from_utf8(&attr.value)?;
-
-                        if attr_name.starts_with("xmlns") || attr_name.contains(':') {
-                            show_warning(
-                                "Namespaces and prefixes",
-                                Some(&format!(
-                                    "Found namespace declaration or prefixed attribute: {}",
-                                    attr_name
-                                )),
-                            );
-                        }
-
-                        Self::write_attribute(&mut serializer, attr_name, attr_value)?;
-                    }
-
-                    serializer.end_tag(name)?;
-                }
-                Event::Text(e) => {
-                    let text = std::str::from_utf8(&e)?;
-                    if type_detection::is_whitespace_only(text) {
-                        if serializer.preserve_whitespace {
-                            serializer.ignorable_whitespace(text)?;
-                        }
-                    } else {
-                        serializer.text(text)?;
-                    }
-                }
-                Event::CData(e) => {
-                    let text = std::str::from_utf8(&e)?;
-                    serializer.cdsect(text)?;
-                }
-                Event::Comment(e) => {
-                    let text = std::str::from_utf8(&e)?;
-                    serializer.comment(text)?;
-                }
-                Event::PI(e) => {
-                    let target = std::str::from_utf8(e.target())?;
-                    let raw = e.content();
-                    let data = if raw.is_empty() {
-                        None
-                    } else {
-                        Some(std::str::from_utf8(raw)?)
-                    };
-
-                    if target == "xml"
-                        && let Some(content) = data
-                        && content.contains("encoding")
-                        && !content.to_lowercase().contains("utf-8")
-                    {
-                        show_warning(
-                            "Non-UTF-8 encoding",
-                            Some(&format!("Found in declaration: {}", content)),
-                        );
-                    }
-
-                    serializer.processing_instruction(target, data)?;
-                }
-                Event::Decl(decl) => {
-                    if let Some(enc_result) = decl.encoding() {
-                        let enc_bytes = enc_result?;
-                        let enc = std::str::from_utf8(enc_bytes.as_ref())?;
-                        if !enc.to_lowercase().contains("utf-8") {
-                            show_warning(
-                                "Non-UTF-8 encoding",
-                                Some(&format!("Found encoding: {}", enc)),
-                            );
-                        }
-                    }
-                }
-                Event::DocType(e) => {
-                    let text = std::str::from_utf8(&e)?;
-                    serializer.docdecl(text)?;
-                }
-                Event::GeneralRef(e) => {
-                    let text = std::str::from_utf8(&e)?;
-                    serializer.entity_ref(text)?;
-                }
-                Event::Eof => break,
-            }
-            buf.clear();
-        }
-
-        serializer.end_document()?;
-        Ok(())
-    }
-
-    fn write_attribute<W: Write>(
-        serializer: &mut BinaryXmlSerializer<W>,
-        name: &str,
-        value: &str,
-    ) -> Result<()> {
-        use type_detection::*;
-
-        if is_boolean(value) {
-            serializer.attribute_boolean(name, value == "true")?;
-        } else if value.len() < 50 && !value.contains(' ') {
-            serializer.attribute_interned(name, value)?;
-        } else {
-            serializer.attribute(name, value)?;
-        }
-        Ok(())
-    }
-}
+use std::io::{self, BufWriter, Read, Write};
+use std::time::Duration;
 
 // ============================================================================
 // CLI
@@ -492,13 +19,74 @@ fn print_help(program_name: &str) {
     eprintln!("Converts human-readable XML to Android Binary XML (ABX).");
     eprintln!();
     eprintln!("Arguments:");
-    eprintln!("  input.xml          Input XML file path (use '-' for stdin)");
+    eprintln!("  input.xml          Input XML file path (use '-' for stdin, may be gzipped)");
     eprintln!("  output.abx         Output ABX file path (use '-' for stdout)");
     eprintln!("                     If not specified, defaults to stdout or in-place");
     eprintln!();
     eprintln!("Options:");
     eprintln!("  -i, --in-place            Overwrite input file with output");
     eprintln!("  -c, --collapse-whitespace Collapse whitespace in text content");
+    eprintln!("      --namespaces MODE     keep (default), strip or error on namespace prefixes");
+    eprintln!("                            and xmlns declarations");
+    eprintln!("      --max-errors N        Repair up to N errors (mismatched or missing end");
+    eprintln!("                            tags, stray '&' or '<' in text) and report them");
+    eprintln!("                            instead of stopping at the first one");
+    eprintln!("      --duplicate-attributes POLICY");
+    eprintln!("                            error (default), first or last: which value of a");
+    eprintln!("                            repeated attribute to keep");
+    eprintln!("      --long-attributes STRATEGY");
+    eprintln!("                            Values over 65535 bytes: error (default), truncate,");
+    eprintln!("                            text (child element) or chunks (name, name.1, ...)");
+    eprintln!("      --intern-max-length BYTES");
+    eprintln!("                            Intern attribute values shorter than this (default");
+    eprintln!("                            50, 0 for none)");
+    eprintln!("      --intern-pool-limit N Stop interning new values once the pool holds N");
+    eprintln!("                            strings (default 65535)");
+    eprintln!("      --no-intern-values    Write every attribute value inline");
+    eprintln!("      --dictionary FILE     Pre-seed the intern pool from an exported dictionary");
+    eprintln!("      --export-dictionary FILE");
+    eprintln!("                            Save the final intern pool for later conversions");
+    eprintln!("      --schema FILE         Write attributes with the ABX types a schema file");
+    eprintln!("                            maps their paths to ('package/@userId = int')");
+    eprintln!("                            instead of guessing");
+    eprintln!("      --type PATH=TYPE      Write the attributes at PATH as TYPE, as a schema");
+    eprintln!("                            rule would ('package/@flags=int_hex'); may be");
+    eprintln!("                            repeated and wins over --schema and --type-profile");
+    eprintln!("      --type-profile NAME   Write attributes with the types Android uses for a");
+    eprintln!(
+        "                            well-known file: {}",
+        BUNDLED_PROFILES.join(", ")
+    );
+    eprintln!("      --fidelity            Follow the type hints and directives of");
+    eprintln!("                            abx2xml --fidelity to reproduce the original ABX");
+    eprintln!("                            byte for byte");
+    eprintln!("      --canonical           Write output that depends only on the content:");
+    eprintln!("                            sorted attributes, trimmed text, no whitespace-only");
+    eprintln!("                            text");
+    eprintln!("      --compact             Write the smallest ABX, as Android does: drop");
+    eprintln!("                            whitespace-only text, comments and the DOCTYPE");
+    eprintln!("      --modified-utf8       Write strings as Java's modified UTF-8 like Android");
+    eprintln!("                            (NUL as C0 80, emoji as surrogate pairs)");
+    eprintln!("      --no-infer-types      Write every attribute without a type hint or schema");
+    eprintln!("                            type as a string, not as a guessed boolean or number");
+    eprintln!("      --apply-type-hints    Write attributes with the types recorded by");
+    eprintln!("                            abx2xml --emit-type-hints and drop the annotations");
+    eprintln!("      --wrap-roots NAME     Wrap multiple root elements or stray top-level text");
+    eprintln!("                            in a synthetic <NAME> root instead of passing them on");
+    eprintln!("      --buffer-size BYTES   Output buffer size (default 8192, 0 for none)");
+    eprintln!("      --idle-timeout SECONDS");
+    eprintln!("                            Fail when the input delivers nothing for this long,");
+    eprintln!("                            e.g. a stalled pipe, instead of waiting forever");
+    eprintln!("      --profile             Print per-phase timing and peak memory to stderr");
+    eprintln!("      --android-atomic      Read and write files like Android's AtomicFile:");
+    eprintln!("                            restore a leftover .bak, write via .new, fsync and");
+    eprintln!("                            rename");
+    if cfg!(feature = "android-dictionary") {
+        eprintln!("      --android-dictionary  Pre-seed the intern pool with common Android names");
+        eprintln!("                            (output is NOT readable by Android)");
+    }
+    eprintln!("      --android-cli-compat  As the first argument: accept exactly the arguments");
+    eprintln!("                            of Android's xml2abx ('-i input' or 'input output')");
     eprintln!("  -h, --help                Show this help message");
 }
 
@@ -514,6 +102,14 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
 
     let args: Vec<String> = args.collect();
 
+    if let Some(args) = android_cli_compat(&args) {
+        if let Err(e) = run_android_compat(args) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     if args.is_empty() || args.iter().any(|a| a == "-h" || a == "--help") {
         print_help(&bin_name);
         std::process::exit(if args.is_empty() { 1 } else { 0 });
@@ -521,17 +117,190 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
 
     let mut in_place = false;
     let mut collapse_whitespace = false;
+    let mut android_dictionary = false;
+    let mut dictionary_path = None;
+    let mut export_dictionary_path = None;
+    let mut schema_path = None;
+    let mut type_profile = None;
+    let mut type_overrides = TypeSchema::new();
+    let mut apply_type_hints = false;
+    let mut fidelity = false;
+    let mut type_inference = true;
+    let mut canonical = false;
+    let mut compact = false;
+    let mut modified_utf8 = false;
+    let mut namespaces = NamespaceStrategy::Keep;
+    let mut wrap_roots = None;
+    let mut buffer_size = None;
+    let mut idle_timeout = None;
+    let mut intern_policy = InternPolicy::default();
+    let mut max_errors = None;
+    let mut duplicate_attributes = DuplicateAttributes::Error;
+    let mut long_attributes = LongAttributes::Error;
+    let mut profile = false;
+    let mut atomic = false;
     let mut input_path = None;
     let mut output_path = None;
     let mut after_double_dash = false;
 
-    for arg in &args {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
         if !after_double_dash && arg == "--" {
             after_double_dash = true;
         } else if !after_double_dash && (arg == "-i" || arg == "--in-place") {
             in_place = true;
         } else if !after_double_dash && (arg == "-c" || arg == "--collapse-whitespace") {
             collapse_whitespace = true;
+        } else if !after_double_dash && arg == "--profile" {
+            profile = true;
+        } else if !after_double_dash && arg == "--no-intern-values" {
+            intern_policy = InternPolicy::never();
+        } else if !after_double_dash && arg == "--no-infer-types" {
+            type_inference = false;
+        } else if !after_double_dash && arg == "--apply-type-hints" {
+            apply_type_hints = true;
+        } else if !after_double_dash && arg == "--fidelity" {
+            fidelity = true;
+        } else if !after_double_dash && arg == "--canonical" {
+            canonical = true;
+        } else if !after_double_dash && arg == "--compact" {
+            compact = true;
+        } else if !after_double_dash && arg == "--modified-utf8" {
+            modified_utf8 = true;
+        } else if !after_double_dash && arg == "--android-atomic" {
+            atomic = true;
+        } else if !after_double_dash && arg == "--android-dictionary" {
+            android_dictionary = true;
+        } else if !after_double_dash && (arg == "--dictionary" || arg == "--export-dictionary") {
+            let Some(path) = args.next() else {
+                eprintln!("Error: Missing value for {}", arg);
+                std::process::exit(1);
+            };
+            if arg == "--dictionary" {
+                dictionary_path = Some(path.as_str());
+            } else {
+                export_dictionary_path = Some(path.as_str());
+            }
+        } else if !after_double_dash && arg == "--schema" {
+            let Some(path) = args.next() else {
+                eprintln!("Error: Missing value for {}", arg);
+                std::process::exit(1);
+            };
+            schema_path = Some(path.as_str());
+        } else if !after_double_dash && arg == "--type" {
+            let Some(rule) = args.next() else {
+                eprintln!("Error: Missing value for {}", arg);
+                std::process::exit(1);
+            };
+            let Some((path, type_name)) = rule.rsplit_once('=') else {
+                eprintln!(
+                    "Error: Invalid value for {}: {} (expected PATH=TYPE)",
+                    arg, rule
+                );
+                std::process::exit(1);
+            };
+            type_overrides = match type_overrides.rule(path.trim(), type_name.trim()) {
+                Ok(schema) => schema,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            };
+        } else if !after_double_dash && arg == "--type-profile" {
+            let Some(name) = args.next() else {
+                eprintln!("Error: Missing value for {}", arg);
+                std::process::exit(1);
+            };
+            let Some(profile) = TypeSchema::bundled(name) else {
+                eprintln!(
+                    "Error: Unknown type profile '{}' (expected one of {})",
+                    name,
+                    BUNDLED_PROFILES.join(", ")
+                );
+                std::process::exit(1);
+            };
+            type_profile = Some(profile);
+        } else if !after_double_dash && arg == "--wrap-roots" {
+            let Some(root) = args.next() else {
+                eprintln!("Error: Missing value for {}", arg);
+                std::process::exit(1);
+            };
+            wrap_roots = Some(root.as_str());
+        } else if !after_double_dash
+            && (arg == "--buffer-size"
+                || arg == "--max-errors"
+                || arg == "--intern-max-length"
+                || arg == "--intern-pool-limit")
+        {
+            let Some(value) = args.next() else {
+                eprintln!("Error: Missing value for {}", arg);
+                std::process::exit(1);
+            };
+            let value = match value.parse::<usize>() {
+                Ok(value) => value,
+                Err(_) => {
+                    eprintln!("Error: Invalid value for {}: {}", arg, value);
+                    std::process::exit(1);
+                }
+            };
+            match arg.as_str() {
+                "--buffer-size" => buffer_size = Some(value),
+                "--max-errors" => max_errors = Some(value),
+                "--intern-max-length" => intern_policy = intern_policy.max_length(value),
+                _ => intern_policy = intern_policy.max_pool_size(value),
+            }
+        } else if !after_double_dash && arg == "--idle-timeout" {
+            let Some(value) = args.next() else {
+                eprintln!("Error: Missing value for {}", arg);
+                std::process::exit(1);
+            };
+            idle_timeout = match value
+                .parse::<f64>()
+                .ok()
+                .and_then(|s| Duration::try_from_secs_f64(s).ok())
+            {
+                Some(timeout) => Some(timeout),
+                None => {
+                    eprintln!("Error: Invalid value for {}: {}", arg, value);
+                    std::process::exit(1);
+                }
+            };
+        } else if !after_double_dash && arg == "--long-attributes" {
+            let Some(value) = args.next() else {
+                eprintln!("Error: Missing value for {}", arg);
+                std::process::exit(1);
+            };
+            long_attributes = match value.parse() {
+                Ok(strategy) => strategy,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            };
+        } else if !after_double_dash && arg == "--duplicate-attributes" {
+            let Some(value) = args.next() else {
+                eprintln!("Error: Missing value for {}", arg);
+                std::process::exit(1);
+            };
+            duplicate_attributes = match value.parse() {
+                Ok(policy) => policy,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            };
+        } else if !after_double_dash && arg == "--namespaces" {
+            let Some(value) = args.next() else {
+                eprintln!("Error: Missing value for {}", arg);
+                std::process::exit(1);
+            };
+            namespaces = match value.parse() {
+                Ok(namespaces) => namespaces,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            };
         } else if input_path.is_none() {
             input_path = Some(arg.as_str());
         } else if output_path.is_none() {
@@ -550,77 +319,215 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
         }
     };
 
+    if fidelity && canonical {
+        eprintln!("Error: --canonical cannot be combined with --fidelity");
+        std::process::exit(1);
+    }
+    if fidelity && compact {
+        eprintln!("Error: --compact cannot be combined with --fidelity");
+        std::process::exit(1);
+    }
+
     // preserve_whitespace is the inverse of collapse_whitespace
-    let preserve_whitespace = !collapse_whitespace;
+    let options = Options::new()
+        .preserve_whitespace(!collapse_whitespace)
+        .namespaces(namespaces)
+        .duplicate_attributes(duplicate_attributes)
+        .long_attributes(long_attributes)
+        .type_inference(type_inference)
+        .intern_policy(intern_policy)
+        .apply_type_hints(apply_type_hints)
+        .fidelity(fidelity)
+        .canonical(canonical)
+        .compact(compact)
+        .modified_utf8(modified_utf8);
+
+    #[cfg(feature = "android-dictionary")]
+    let options = options.android_dictionary(android_dictionary);
+
+    #[cfg(not(feature = "android-dictionary"))]
+    if android_dictionary {
+        eprintln!("Error: --android-dictionary requires the android-dictionary feature");
+        std::process::exit(1);
+    }
+
+    let options = match dictionary_path {
+        Some(path) => options.dictionary(Dictionary::load(path)?),
+        None => options,
+    };
+
+    // rules of --type take precedence over --schema, which takes precedence
+    // over the profile
+    let schema = match (schema_path, type_profile) {
+        (Some(path), profile) => Some(TypeSchema::load(path)?.append(profile.unwrap_or_default())),
+        (None, profile) => profile,
+    };
+    let schema = match schema {
+        Some(schema) => Some(type_overrides.append(schema)),
+        None if !type_overrides.is_empty() => Some(type_overrides),
+        None => None,
+    };
+    let options = match schema {
+        Some(schema) => options.type_schema(schema),
+        None => options,
+    };
+
+    let options = match wrap_roots {
+        Some(root) => options.wrap_roots(root),
+        None => options,
+    };
+
+    let options = match max_errors {
+        Some(max_errors) => options.max_errors(max_errors),
+        None => options,
+    };
+
+    let options = match buffer_size {
+        Some(size) => options.read_buffer_size(size).write_buffer_size(size),
+        None => options,
+    };
+    let options = match idle_timeout {
+        Some(timeout) => options.idle_timeout(timeout),
+        None => options,
+    };
 
     let final_output_path = if in_place {
         if input_path == "-" {
             eprintln!("Error: Cannot overwrite stdin, output path is required");
             std::process::exit(1);
         }
-        Some(input_path)
+        input_path
     } else if let Some(output) = output_path {
-        Some(output)
+        output
     } else {
         eprintln!("Error: Output path is required (use '-' for stdout or specify a file)");
         std::process::exit(1);
     };
 
-    let result = if input_path == "-" {
-        let mut xml_content = String::new();
-        io::stdin().read_to_string(&mut xml_content)?;
+    let mut profiler = profile.then(Profiler::new);
 
-        if let Some(output_path) = final_output_path {
-            if output_path == "-" {
-                XmlToAbxConverter::convert_from_string_with_options(
-                    &xml_content,
-                    io::stdout(),
-                    preserve_whitespace,
-                )
-            } else {
-                let file = File::create(output_path)?;
-                let writer = BufWriter::new(file);
-                XmlToAbxConverter::convert_from_string_with_options(
-                    &xml_content,
-                    writer,
-                    preserve_whitespace,
-                )
-            }
-        } else {
-            eprintln!("Error: Output path is required");
-            std::process::exit(1);
-        }
-    } else {
-        // for in-place editing, we need to read the file completely first
-        let xml_content = std::fs::read_to_string(input_path)?;
-
-        if let Some(output_path) = final_output_path {
-            if output_path == "-" {
-                XmlToAbxConverter::convert_from_string_with_options(
-                    &xml_content,
-                    io::stdout(),
-                    preserve_whitespace,
-                )
-            } else {
-                let file = File::create(output_path)?;
-                let writer = BufWriter::new(file);
-                XmlToAbxConverter::convert_from_string_with_options(
-                    &xml_content,
-                    writer,
-                    preserve_whitespace,
-                )
-            }
-        } else {
-            eprintln!("Error: Output path is required");
-            std::process::exit(1);
+    // for in-place editing, we need to read the file completely first
+    let result = read_input(input_path, atomic, idle_timeout).and_then(|xml_content| {
+        if let Some(profiler) = &mut profiler {
+            profiler.phase("read");
         }
-    };
+        convert(
+            &xml_content,
+            final_output_path,
+            &options,
+            atomic,
+            &mut profiler,
+        )
+    });
+    let result = result.and_then(|dictionary| match export_dictionary_path {
+        Some(path) => dictionary.save(path),
+        None => Ok(()),
+    });
+
+    if let Some(profiler) = profiler {
+        profiler.write_to(io::stderr().lock())?;
+    }
 
     match result {
         Ok(_) => Ok(()),
         Err(e) => {
             eprintln!("Error: {}", e);
+            if let Some(hint) = error_hint(&e) {
+                eprintln!("Note: {}", hint);
+            }
             std::process::exit(1);
         }
     }
 }
+
+/// `--android-cli-compat`: Android's argument handling and defaults, with the
+/// input read completely so `-i` can overwrite it
+fn run_android_compat(args: &[String]) -> Result<()> {
+    let Some((input_path, output_path)) = parse_android_cli_args(args) else {
+        eprint!("{}", ANDROID_CLI_USAGE);
+        std::process::exit(1);
+    };
+
+    let mut xml = String::new();
+    if input_path == "-" {
+        io::stdin().lock().read_to_string(&mut xml)?;
+    } else {
+        File::open(input_path)?.read_to_string(&mut xml)?;
+    }
+    let mut output = Vec::new();
+    XmlToAbxConverter::convert_from_string(&xml, &mut output)?;
+
+    if output_path == "-" {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&output)?;
+        stdout.flush()?;
+    } else {
+        File::create(output_path)?.write_all(&output)?;
+    }
+    Ok(())
+}
+
+fn read_input(input_path: &str, atomic: bool, idle_timeout: Option<Duration>) -> Result<String> {
+    let mut xml_content = String::new();
+    if input_path == "-" {
+        decompress_if_gzip(IdleTimeoutReader::wrap(io::stdin(), idle_timeout))?
+            .read_to_string(&mut xml_content)?;
+    } else if atomic {
+        AtomicFile::new(input_path)
+            .open_read()?
+            .read_to_string(&mut xml_content)?;
+    } else {
+        IdleTimeoutReader::wrap(File::open(input_path)?, idle_timeout)
+            .read_to_string(&mut xml_content)?;
+    }
+    Ok(xml_content)
+}
+
+fn open_output(output_path: &str, options: &Options) -> Result<Box<dyn Write>> {
+    if output_path == "-" {
+        Ok(Box::new(io::stdout()))
+    } else {
+        let file = File::create(output_path)?;
+        Ok(Box::new(BufWriter::with_capacity(
+            options.write_buffer_size,
+            file,
+        )))
+    }
+}
+
+/// Converts straight into the output, or into memory first when profiling so
+/// conversion and writing are timed separately, or when the output is an
+/// `atomic` file
+fn convert(
+    xml_content: &str,
+    output_path: &str,
+    options: &Options,
+    atomic: bool,
+    profiler: &mut Option<Profiler>,
+) -> Result<Dictionary> {
+    let atomic = atomic && output_path != "-";
+    if profiler.is_none() && !atomic {
+        let writer = open_output(output_path, options)?;
+        return XmlToAbxConverter::convert_and_export_dictionary(xml_content, writer, options);
+    }
+
+    let mut output = Vec::new();
+    let dictionary =
+        XmlToAbxConverter::convert_and_export_dictionary(xml_content, &mut output, options)?;
+    if let Some(profiler) = profiler {
+        profiler.phase("convert");
+    }
+
+    if atomic {
+        AtomicFile::new(output_path).write_with(|writer| Ok(writer.write_all(&output)?))?;
+    } else {
+        let mut writer = open_output(output_path, options)?;
+        writer.write_all(&output)?;
+        writer.flush()?;
+    }
+    if let Some(profiler) = profiler {
+        profiler.phase("write");
+        profiler.sizes(xml_content.len() as u64, output.len() as u64);
+    }
+    Ok(dictionary)
+}
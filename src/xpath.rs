@@ -0,0 +1,365 @@
+use crate::query::{ElementFrame, ElementScanner, TypedAttributes};
+use crate::reader::AttributeValue;
+use crate::*;
+use smol_str::SmolStr;
+use std::io::{Read, Write};
+
+// ============================================================================
+// XPath Subset
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Predicate {
+    /// `[@name]`
+    Exists(String),
+    /// `[@name='value']`
+    Equals(String, String),
+    /// `[@name!='value']`
+    NotEquals(String, String),
+}
+
+impl Predicate {
+    fn matches(&self, frame: &ElementFrame) -> bool {
+        match self {
+            Predicate::Exists(name) => frame.attribute(name).is_some(),
+            Predicate::Equals(name, expected) => frame
+                .attribute(name)
+                .is_some_and(|value| value.to_string() == *expected),
+            Predicate::NotEquals(name, expected) => frame
+                .attribute(name)
+                .is_none_or(|value| value.to_string() != *expected),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    /// Preceded by `//`, so any number of elements may lie between this step and
+    /// the previous one
+    descendant: bool,
+    name: String,
+    predicates: Vec<Predicate>,
+}
+
+impl Step {
+    fn matches(&self, frame: &ElementFrame) -> bool {
+        (self.name == "*" || self.name == frame.name.as_str())
+            && self.predicates.iter().all(|p| p.matches(frame))
+    }
+}
+
+/// A practical XPath subset, matched against the stack of open elements while
+/// streaming so documents of any size can be searched without building a tree.
+///
+/// Supported are child (`a/b`) and descendant (`a//b`) steps, absolute paths
+/// (`/packages/package`), the `*` wildcard, attribute predicates (`[@name]`,
+/// `[@name='v']`, `[@name!='v']`, several in a row) and a final attribute
+/// selection (`/@name` or `/@*`). A relative path matches at any depth, as if it
+/// started with `//`. The older `[name=v]` form, without `@` and quotes, is
+/// accepted as well; every subcommand taking a path uses this syntax.
+///
+/// ```
+/// use android_xml_converter::xpath::XPath;
+/// use android_xml_converter::XmlToAbxConverter;
+///
+/// let xml = r#"<packages><package name="a" codePath="/data/app/a"><perms><item name="INTERNET"/></perms></package></packages>"#;
+/// let mut abx = Vec::new();
+/// XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+///
+/// let path = XPath::parse("/packages/package[@codePath='/data/app/a']//item/@name").unwrap();
+/// let mut names = Vec::new();
+/// path.scan(abx.as_slice(), |_, selection| {
+///     names.push(selection.to_string());
+///     Ok(())
+/// })
+/// .unwrap();
+/// assert_eq!(names, ["INTERNET"]);
+///
+/// let alias = XPath::parse("package[codePath=/data/app/a]/perms/item").unwrap();
+/// assert_eq!(alias.scan(abx.as_slice(), |_, _| Ok(())).unwrap(), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct XPath {
+    steps: Vec<Step>,
+    /// Selected attribute, `*` for all of them
+    attribute: Option<String>,
+}
+
+/// What a path selected in one element
+#[derive(Debug, Clone, Copy)]
+pub enum Selection<'a> {
+    Element(&'a ElementFrame),
+    Attribute(&'a SmolStr, &'a AttributeValue),
+}
+
+impl std::fmt::Display for Selection<'_> {
+    /// Elements are shown as an empty tag with their attributes, attributes by
+    /// their value
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Selection::Element(frame) => {
+                write!(f, "<{}", frame.name)?;
+                for (name, value) in &frame.attributes {
+                    write!(
+                        f,
+                        " {}=\"{}\"",
+                        name,
+                        encode_xml_entities(&value.to_string())
+                    )?;
+                }
+                f.write_str("/>")
+            }
+            Selection::Attribute(_, value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl XPath {
+    pub fn parse(path: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            ConversionError::ParseError(format!("Invalid path '{}': {}", path, reason))
+        };
+
+        let segments = split_segments(path).ok_or_else(|| invalid("unbalanced '[' or quote"))?;
+        let mut steps = Vec::new();
+        let mut attribute = None;
+        // a relative path may start anywhere in the document
+        let mut descendant = !path.starts_with('/');
+
+        for (index, segment) in segments.iter().enumerate() {
+            if segment.is_empty() {
+                if index == 0 {
+                    continue;
+                }
+                if descendant && index > 1 {
+                    return Err(invalid("'///' is not a valid step"));
+                }
+                descendant = true;
+                continue;
+            }
+
+            if let Some(name) = segment.strip_prefix('@') {
+                if index != segments.len() - 1 {
+                    return Err(invalid("'@attribute' must be the last step"));
+                }
+                if name.is_empty() || name.contains(['[', ']', '=']) {
+                    return Err(invalid("bad attribute name"));
+                }
+                if steps.is_empty() || descendant {
+                    steps.push(Step {
+                        descendant,
+                        name: "*".to_string(),
+                        predicates: Vec::new(),
+                    });
+                }
+                attribute = Some(name.to_string());
+                break;
+            }
+
+            steps.push(parse_step(segment, descendant).map_err(invalid)?);
+            descendant = false;
+        }
+
+        if steps.is_empty() {
+            return Err(invalid("no element step"));
+        }
+        if descendant && attribute.is_none() {
+            return Err(invalid("trailing '/'"));
+        }
+
+        Ok(Self { steps, attribute })
+    }
+
+    /// Whether the innermost element of `stack` is selected by the element steps
+    pub fn matches(&self, stack: &[ElementFrame]) -> bool {
+        match_steps(&self.steps, stack)
+    }
+
+    /// Calls `on_match` with the selections of the innermost element of `stack`
+    pub fn select<F>(&self, stack: &[ElementFrame], mut on_match: F) -> Result<usize>
+    where
+        F: FnMut(Selection) -> Result<()>,
+    {
+        let Some(frame) = stack.last() else {
+            return Ok(0);
+        };
+        if !self.matches(stack) {
+            return Ok(0);
+        }
+
+        match self.attribute.as_deref() {
+            None => {
+                on_match(Selection::Element(frame))?;
+                Ok(1)
+            }
+            Some(wanted) => {
+                let mut matches = 0;
+                for (name, value) in &frame.attributes {
+                    if wanted == "*" || wanted == name.as_str() {
+                        on_match(Selection::Attribute(name, value))?;
+                        matches += 1;
+                    }
+                }
+                Ok(matches)
+            }
+        }
+    }
+
+    /// Streams an ABX document, calling `on_match` with the open elements and
+    /// each selection; returns the number of selections
+    pub fn scan<R, F>(&self, reader: R, mut on_match: F) -> Result<usize>
+    where
+        R: Read,
+        F: FnMut(&[ElementFrame], Selection) -> Result<()>,
+    {
+        let mut matches = 0;
+        ElementScanner::new(reader)?.scan(|stack| {
+            matches += self.select(stack, |selection| on_match(stack, selection))?;
+            Ok(())
+        })?;
+        Ok(matches)
+    }
+
+    /// Writes every selection on its own line, returning how many there were.
+    ///
+    /// Attribute values are prefixed with `name=` when the path selects `@*`.
+    pub fn write_matches<R: Read, W: Write>(&self, reader: R, mut writer: W) -> Result<usize> {
+        let with_names = self.attribute.as_deref() == Some("*");
+        let matches = self.scan(reader, |_, selection| {
+            match selection {
+                Selection::Attribute(name, value) if with_names => {
+                    writeln!(writer, "{}={}", name, value)?
+                }
+                selection => writeln!(writer, "{}", selection)?,
+            }
+            Ok(())
+        })?;
+        writer.flush()?;
+        Ok(matches)
+    }
+}
+
+/// Location of the innermost element, e.g. `/packages/package`
+pub fn element_location(stack: &[ElementFrame]) -> String {
+    let mut location = String::new();
+    for frame in stack {
+        location.push('/');
+        location.push_str(&frame.name);
+    }
+    location
+}
+
+/// Splits on `/` outside predicates and quotes; `None` if they are unbalanced
+fn split_segments(path: &str) -> Option<Vec<&str>> {
+    let mut segments = Vec::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut start = 0;
+
+    for (index, c) in path.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') if depth > 0 => quote = Some(c),
+            (None, '[') => depth += 1,
+            (None, ']') => depth = depth.checked_sub(1)?,
+            (None, '/') if depth == 0 => {
+                segments.push(&path[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 || quote.is_some() {
+        return None;
+    }
+    segments.push(&path[start..]);
+    Some(segments)
+}
+
+fn parse_step(segment: &str, descendant: bool) -> std::result::Result<Step, &'static str> {
+    let (name, mut rest) = match segment.find('[') {
+        Some(index) => (&segment[..index], &segment[index..]),
+        None => (segment, ""),
+    };
+    if name.is_empty() || name.contains(['@', ']', '=']) {
+        return Err("bad element step");
+    }
+
+    let mut predicates = Vec::new();
+    while !rest.is_empty() {
+        let body = rest.strip_prefix('[').ok_or("text after ']'")?;
+        let end = predicate_end(body).ok_or("unterminated '['")?;
+        predicates.push(parse_predicate(&body[..end])?);
+        rest = &body[end + 1..];
+    }
+
+    Ok(Step {
+        descendant,
+        name: name.to_string(),
+        predicates,
+    })
+}
+
+/// Index of the `]` closing a predicate, skipping quoted values
+fn predicate_end(body: &str) -> Option<usize> {
+    let mut quote = None;
+    for (index, c) in body.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, ']') => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_predicate(predicate: &str) -> std::result::Result<Predicate, &'static str> {
+    let unquote = |value: &str| {
+        let value = value.trim();
+        value
+            .strip_prefix('\'')
+            .and_then(|v| v.strip_suffix('\''))
+            .or_else(|| value.strip_prefix('"').and_then(|v| v.strip_suffix('"')))
+            .unwrap_or(value)
+            .to_string()
+    };
+    let attribute = |name: &str| {
+        let name = name.trim().trim_start_matches('@');
+        if name.is_empty() {
+            Err("empty attribute name in predicate")
+        } else {
+            Ok(name.to_string())
+        }
+    };
+
+    // attribute names cannot contain '=', so the first one is the operator
+    if let Some(index) = predicate.find('=') {
+        let value = unquote(&predicate[index + 1..]);
+        match predicate[..index].strip_suffix('!') {
+            Some(name) => Ok(Predicate::NotEquals(attribute(name)?, value)),
+            None => Ok(Predicate::Equals(attribute(&predicate[..index])?, value)),
+        }
+    } else if predicate.trim().starts_with('@') {
+        Ok(Predicate::Exists(attribute(predicate)?))
+    } else {
+        Err("predicate must be [@attr], [@attr='value'] or [@attr!='value']")
+    }
+}
+
+/// Matches steps against the open elements so that the last step falls on the
+/// innermost element
+fn match_steps(steps: &[Step], frames: &[ElementFrame]) -> bool {
+    let Some((step, rest)) = steps.split_first() else {
+        return frames.is_empty();
+    };
+    let candidates = if step.descendant {
+        frames.len()
+    } else {
+        frames.len().min(1)
+    };
+    (0..candidates)
+        .any(|index| step.matches(&frames[index]) && match_steps(rest, &frames[index + 1..]))
+}
@@ -0,0 +1,128 @@
+//! Tar streams and `adb backup` archives with embedded ABX documents
+#![cfg(feature = "archive")]
+
+use android_xml_converter::Options;
+use android_xml_converter::XmlToAbxConverter;
+use android_xml_converter::archive::{
+    EntryConversion, EntryFormat, convert_android_backup, convert_tar, list_android_backup,
+    list_tar,
+};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use std::io::{Read, Write};
+
+const PREFS: &[u8] = b"./apps/com.example/sp/prefs.xml";
+const MANIFEST: &[u8] = b"./apps/com.example/_manifest";
+
+fn abx(xml: &str) -> Vec<u8> {
+    let mut abx = Vec::new();
+    XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+    abx
+}
+
+/// Appends a regular file whose header name is `name`, byte for byte
+fn append(builder: &mut tar::Builder<Vec<u8>>, name: &[u8], data: &[u8]) {
+    let mut header = tar::Header::new_ustar();
+    header.as_old_mut().name[..name.len()].copy_from_slice(name);
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_mode(0o600);
+    header.set_size(data.len() as u64);
+    header.set_cksum();
+    builder.append(&header, data).unwrap();
+}
+
+/// A tar stream laid out like the payload of `adb backup`, with names starting
+/// with `./` and one name too long for the header
+fn fixture() -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    append(&mut builder, MANIFEST, b"1\ncom.example\n");
+    append(
+        &mut builder,
+        PREFS,
+        &abx(r#"<map><string name="a">hi</string></map>"#),
+    );
+    let long = format!("apps/com.example/f/{}.xml", "x".repeat(100));
+    let data = abx("<settings />");
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o600);
+    builder
+        .append_data(&mut header, long, data.as_slice())
+        .unwrap();
+    builder.into_inner().unwrap()
+}
+
+/// Names and contents of the files in a tar stream, as stored
+fn members(tar: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut archive = tar::Archive::new(tar);
+    archive
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let mut entry = entry.unwrap();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            (entry.path_bytes().into_owned(), data)
+        })
+        .collect()
+}
+
+#[test]
+fn lists_documents_by_their_stored_names() {
+    let entries = list_tar(fixture().as_slice()).unwrap();
+    let names: Vec<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+    assert_eq!(names[0].as_bytes(), PREFS);
+    assert!(names[1].ends_with(&format!("{}.xml", "x".repeat(100))));
+    assert!(entries.iter().all(|entry| entry.format == EntryFormat::Abx));
+}
+
+#[test]
+fn converts_documents_and_keeps_member_names() {
+    let original = fixture();
+    let mut converted = Vec::new();
+    let count = convert_tar(
+        original.as_slice(),
+        &mut converted,
+        EntryConversion::AbxToXml,
+        &Options::default(),
+    )
+    .unwrap();
+    assert_eq!(count, 2);
+
+    let before = members(&original);
+    let after = members(&converted);
+    let names = |members: &[(Vec<u8>, Vec<u8>)]| -> Vec<Vec<u8>> {
+        members.iter().map(|(name, _)| name.clone()).collect()
+    };
+    assert_eq!(names(&after), names(&before));
+    assert_eq!(after[0], before[0]);
+    assert!(
+        String::from_utf8_lossy(&after[1].1)
+            .ends_with(r#"<map><string name="a">hi</string></map>"#)
+    );
+}
+
+#[test]
+fn converts_compressed_android_backups() {
+    let mut backup = b"ANDROID BACKUP\n5\n1\nnone\n".to_vec();
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&fixture()).unwrap();
+    backup.extend(encoder.finish().unwrap());
+
+    let mut converted = Vec::new();
+    let count = convert_android_backup(
+        backup.as_slice(),
+        &mut converted,
+        EntryConversion::AbxToXml,
+        &Options::default(),
+    )
+    .unwrap();
+    assert_eq!(count, 2);
+    assert!(converted.starts_with(b"ANDROID BACKUP\n5\n1\nnone\n"));
+
+    // only the manifest is neither ABX nor named .xml; the rest is XML now
+    let entries = list_android_backup(converted.as_slice()).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].path.as_bytes(), PREFS);
+    assert!(entries.iter().all(|entry| entry.format == EntryFormat::Xml));
+}
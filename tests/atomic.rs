@@ -0,0 +1,98 @@
+//! `AtomicFile` recovery from interrupted writes and stale backups
+
+use android_xml_converter::ConversionError;
+use android_xml_converter::atomic::AtomicFile;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("abx-atomic-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn read(file: &AtomicFile) -> String {
+    let mut content = String::new();
+    file.open_read()
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    content
+}
+
+#[test]
+fn reads_the_old_content_after_an_interrupted_write() {
+    let path = temp_dir("interrupted").join("packages.xml");
+    fs::write(&path, "old").unwrap();
+    let file = AtomicFile::new(&path);
+
+    // a crash between start_write and finish_write
+    let mut new = file.start_write().unwrap();
+    new.write_all(b"half of the n").unwrap();
+    drop(new);
+    assert!(with_suffix(&path, ".new").exists());
+
+    assert_eq!(read(&file), "old");
+    assert!(!with_suffix(&path, ".new").exists());
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn restores_a_stale_backup_before_reading() {
+    let path = temp_dir("backup-read").join("packages.xml");
+    // older Android versions moved the file to .bak and then died writing it anew
+    fs::write(&path, "partial").unwrap();
+    fs::write(with_suffix(&path, ".bak"), "complete").unwrap();
+    let file = AtomicFile::new(&path);
+
+    assert_eq!(read(&file), "complete");
+    assert!(!with_suffix(&path, ".bak").exists());
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn restores_a_stale_backup_before_writing() {
+    let path = temp_dir("backup-write").join("packages.xml");
+    fs::write(with_suffix(&path, ".bak"), "complete").unwrap();
+    let file = AtomicFile::new(&path);
+
+    // a failed write leaves the restored content in place
+    let result = file.write_with(|writer| {
+        writer.write_all(b"never seen")?;
+        Err(ConversionError::ParseError("interrupted".to_string()))
+    });
+    assert!(result.is_err());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "complete");
+    assert!(!with_suffix(&path, ".bak").exists());
+    assert!(!with_suffix(&path, ".new").exists());
+
+    file.write_with(|writer| Ok(writer.write_all(b"new")?))
+        .unwrap();
+    assert_eq!(read(&file), "new");
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn keeps_the_permissions_of_the_replaced_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = temp_dir("permissions").join("packages.xml");
+    fs::write(&path, "old").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+    AtomicFile::new(&path)
+        .write_with(|writer| Ok(writer.write_all(b"new")?))
+        .unwrap();
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,170 @@
+#!/usr/bin/env python3
+"""Differential test: runs every input through both the Rust and the C++
+implementation and reports where their outputs diverge."""
+import argparse
+import os
+import sys
+import tempfile
+import xml.etree.ElementTree as ET
+from pathlib import Path
+
+from diff import compare_elements
+from test import (Colors, compile_cpp, compile_rust, get_python_command, print_error,
+                  print_header, print_info, print_success, run_command)
+
+ABX_MAGIC = b"ABX\0"
+EXE = ".exe" if os.name == 'nt' else ""
+
+
+class Backend:
+    def __init__(self, name, bin_dir):
+        self.name = name
+        self.xml2abx = os.path.abspath(os.path.join(bin_dir, f"xml2abx{EXE}"))
+        self.abx2xml = os.path.abspath(os.path.join(bin_dir, f"abx2xml{EXE}"))
+
+    def available(self):
+        return os.path.exists(self.xml2abx) and os.path.exists(self.abx2xml)
+
+    def convert(self, tool, source, target):
+        """Run one conversion, returning an error message or None."""
+        success, _, stderr = run_command(f'"{tool}" "{source}" "{target}"')
+        if not success or not os.path.exists(target):
+            return f"{self.name} {os.path.basename(tool)} failed: {stderr.strip()}"
+        return None
+
+
+def collect_inputs(paths):
+    """Expand directories into the files they contain, sorted for stable reports."""
+    files = []
+    for path in map(Path, paths):
+        if path.is_dir():
+            files.extend(sorted(p for p in path.rglob("*") if p.is_file()))
+        else:
+            files.append(path)
+    return files
+
+
+def generate_inputs(work_dir, count):
+    python_cmd = get_python_command()
+    print_info(f"Generating {count} random XML file(s)")
+    success, _, stderr = run_command(f"{python_cmd} gen_xml.py random {count}")
+    if not success:
+        print_error(f"Failed to run gen_xml.py: {stderr}")
+        sys.exit(1)
+
+    files = []
+    for i in range(1, count + 1):
+        target = Path(work_dir) / f"random_{i}.xml"
+        os.replace(f"test_{i}.xml", target)
+        files.append(target)
+    return files
+
+
+def first_difference(data1, data2):
+    for offset, (byte1, byte2) in enumerate(zip(data1, data2)):
+        if byte1 != byte2:
+            return offset
+    return min(len(data1), len(data2))
+
+
+def compare_xml(file1, file2, label):
+    """Semantic comparison using diff.py, prefixed with what was compared."""
+    try:
+        root1 = ET.parse(file1).getroot()
+        root2 = ET.parse(file2).getroot()
+    except ET.ParseError as e:
+        return [f"{label}: unparsable output: {e}"]
+    return [f"{label}: {diff}" for diff in compare_elements(root1, root2)]
+
+
+def check_abx(source, rust, cpp, work_dir):
+    """Both decoders must produce the same document from one ABX file."""
+    rust_xml = os.path.join(work_dir, "rust.xml")
+    cpp_xml = os.path.join(work_dir, "cpp.xml")
+
+    errors = [e for e in (rust.convert(rust.abx2xml, source, rust_xml),
+                          cpp.convert(cpp.abx2xml, source, cpp_xml)) if e]
+    if errors:
+        return errors
+    return compare_xml(rust_xml, cpp_xml, "abx2xml rust vs c++")
+
+
+def check_xml(source, rust, cpp, work_dir):
+    """Both encoders must produce the same bytes, and each decoder must read the
+    other encoder's output back to the input document."""
+    rust_abx = os.path.join(work_dir, "rust.abx")
+    cpp_abx = os.path.join(work_dir, "cpp.abx")
+
+    errors = [e for e in (rust.convert(rust.xml2abx, source, rust_abx),
+                          cpp.convert(cpp.xml2abx, source, cpp_abx)) if e]
+    if errors:
+        return errors
+
+    diffs = []
+    rust_data = Path(rust_abx).read_bytes()
+    cpp_data = Path(cpp_abx).read_bytes()
+    if rust_data != cpp_data:
+        diffs.append(f"xml2abx rust vs c++: outputs differ from byte {first_difference(rust_data, cpp_data)} "
+                     f"({len(rust_data)} vs {len(cpp_data)} bytes)")
+
+    for decoder, encoded, label in ((rust, cpp_abx, "rust abx2xml of c++ abx"),
+                                    (cpp, rust_abx, "c++ abx2xml of rust abx")):
+        decoded = os.path.join(work_dir, "cross.xml")
+        error = decoder.convert(decoder.abx2xml, encoded, decoded)
+        diffs.extend([error] if error else compare_xml(source, decoded, label))
+
+    return diffs
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__)
+    parser.add_argument("inputs", nargs="*", help="XML or ABX files or directories (default: random XML)")
+    parser.add_argument("--count", type=int, default=3, help="random files to generate without inputs")
+    parser.add_argument("--no-build", action="store_true", help="use existing binaries")
+    parser.add_argument("--rust-dir", default="../target/release", help="directory with the Rust binaries")
+    parser.add_argument("--cpp-dir", default="../builddir", help="directory with the C++ binaries")
+    args = parser.parse_args()
+
+    if not os.path.exists("gen_xml.py") or not os.path.exists("diff.py"):
+        print_error("Please run this script from the tests/ directory")
+        sys.exit(1)
+
+    print_header("Rust / C++ Differential Test")
+
+    if not args.no_build and not (compile_rust() and compile_cpp()):
+        sys.exit(1)
+
+    rust = Backend("rust", args.rust_dir)
+    cpp = Backend("c++", args.cpp_dir)
+    for backend in (rust, cpp):
+        if not backend.available():
+            print_error(f"Missing {backend.name} binaries: {backend.xml2abx}, {backend.abx2xml}")
+            sys.exit(1)
+
+    with tempfile.TemporaryDirectory() as work_dir:
+        inputs = collect_inputs(args.inputs) if args.inputs else generate_inputs(work_dir, args.count)
+        print_header(f"Checking {len(inputs)} input(s)")
+
+        diverged = 0
+        for source in inputs:
+            with open(source, "rb") as f:
+                is_abx = f.read(4) == ABX_MAGIC
+            check = check_abx if is_abx else check_xml
+            diffs = check(str(source), rust, cpp, work_dir)
+
+            if diffs:
+                diverged += 1
+                print_error(f"{source}: {len(diffs)} divergence(s)")
+                for diff in diffs[:20]:
+                    print(f"    • {diff}")
+                if len(diffs) > 20:
+                    print(f"    … {len(diffs) - 20} more")
+            else:
+                print_success(f"{source}")
+
+    print(f"\n{Colors.BOLD}{len(inputs) - diverged}/{len(inputs)} input(s) agree{Colors.END}\n")
+    sys.exit(1 if diverged else 0)
+
+
+if __name__ == "__main__":
+    main()
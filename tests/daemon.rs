@@ -0,0 +1,129 @@
+//! `abx daemon` over a real socket: conversions, the limits applied to
+//! untrusted payloads and the handling of the socket file
+#![cfg(all(unix, feature = "cli"))]
+
+use android_xml_converter::XmlToAbxConverter;
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::Duration;
+
+struct Daemon {
+    child: Child,
+    socket: PathBuf,
+}
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("abx-{}-{}.sock", name, std::process::id()))
+}
+
+fn daemon_command(socket: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_abx"));
+    command.args(["daemon", "--socket"]).arg(socket);
+    command
+}
+
+impl Daemon {
+    fn start(socket: PathBuf) -> Self {
+        let child = daemon_command(&socket).spawn().unwrap();
+        let daemon = Self { child, socket };
+        for _ in 0..200 {
+            if UnixStream::connect(&daemon.socket).is_ok() {
+                return daemon;
+            }
+            thread::sleep(Duration::from_millis(25));
+        }
+        panic!("the daemon did not start listening");
+    }
+
+    fn request(&self, mode: u8, payload: &[u8]) -> (u8, Vec<u8>) {
+        let mut stream = UnixStream::connect(&self.socket).unwrap();
+        stream.write_all(&[mode]).unwrap();
+        stream
+            .write_all(&(payload.len() as u32).to_be_bytes())
+            .unwrap();
+        stream.write_all(payload).unwrap();
+        let mut header = [0; 5];
+        stream.read_exact(&mut header).unwrap();
+        let length = u32::from_be_bytes(header[1..].try_into().unwrap());
+        let mut body = vec![0; length as usize];
+        stream.read_exact(&mut body).unwrap();
+        (header[0], body)
+    }
+
+    fn terminate(mut self) {
+        let status = Command::new("kill")
+            .arg(self.child.id().to_string())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        self.child.wait().unwrap();
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+fn abx(xml: &str) -> Vec<u8> {
+    let mut abx = Vec::new();
+    XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+    abx
+}
+
+#[test]
+fn converts_both_ways() {
+    let daemon = Daemon::start(socket_path("convert"));
+    let xml = r#"<map><int name="a" value="1"></int></map>"#;
+
+    let (status, converted) = daemon.request(b'x', xml.as_bytes());
+    assert_eq!(status, 0);
+    assert_eq!(converted, abx(xml));
+
+    let (status, converted) = daemon.request(b'a', &converted);
+    assert_eq!(status, 0);
+    let converted = String::from_utf8(converted).unwrap();
+    assert!(converted.trim_end().ends_with(xml), "{}", converted);
+
+    let (status, message) = daemon.request(b'?', b"");
+    assert_eq!(status, 1);
+    assert_eq!(message, b"Parse error: Unknown request mode: 0x3f");
+}
+
+#[test]
+fn limits_untrusted_payloads() {
+    let daemon = Daemon::start(socket_path("limits"));
+    let deep = "<a>".repeat(2000) + &"</a>".repeat(2000);
+
+    let (status, message) = daemon.request(b'a', &abx(&deep));
+    assert_eq!(status, 1);
+    let message = String::from_utf8(message).unwrap();
+    assert!(
+        message.contains("more than 1024 nested elements"),
+        "{}",
+        message
+    );
+}
+
+#[test]
+fn replaces_stale_sockets_and_removes_its_own() {
+    let socket = socket_path("stale");
+    drop(UnixListener::bind(&socket).unwrap());
+    assert!(socket.exists());
+
+    let daemon = Daemon::start(socket.clone());
+    assert_eq!(daemon.request(b'x', b"<a/>").0, 0);
+    // a live daemon keeps its socket
+    let second = daemon_command(&socket).output().unwrap();
+    assert!(!second.status.success());
+    assert_eq!(daemon.request(b'x', b"<a/>").0, 0);
+
+    daemon.terminate();
+    assert!(!socket.exists());
+}
@@ -0,0 +1,83 @@
+//! Lenient ABX decoding of a document with a corrupt token in the middle
+
+use android_xml_converter::diagnostics::Diagnostics;
+use android_xml_converter::{ATTRIBUTE, AbxToXmlConverter, Options, TYPE_INT, XmlToAbxConverter};
+
+const XML: &str = r#"<a><b x="hello"></b><c y="1"></c><d e="world">text</d></a>"#;
+
+/// [`XML`] as ABX, with `corrupt` applied
+fn fixture(corrupt: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+    let mut abx = Vec::new();
+    XmlToAbxConverter::convert_from_string(XML, &mut abx).unwrap();
+    corrupt(&mut abx);
+    abx
+}
+
+fn find(abx: &[u8], bytes: &[u8]) -> usize {
+    abx.windows(bytes.len())
+        .position(|window| window == bytes)
+        .unwrap()
+}
+
+/// Converts leniently, returning the XML and the recorded errors
+fn convert(abx: &[u8]) -> (String, Vec<String>) {
+    let mut xml = Vec::new();
+    let mut diagnostics = Diagnostics::new();
+    AbxToXmlConverter::convert_with_diagnostics(
+        abx,
+        &mut xml,
+        &Options::new().max_errors(10),
+        &mut diagnostics,
+    )
+    .unwrap();
+    let errors = diagnostics
+        .entries()
+        .iter()
+        .map(|e| e.to_string())
+        .collect();
+    (String::from_utf8(xml).unwrap(), errors)
+}
+
+#[test]
+fn resumes_after_an_attribute_of_unknown_type() {
+    // the type of `y`, whose int value is then skipped
+    let abx = fixture(|abx| {
+        let token = find(abx, &[ATTRIBUTE | TYPE_INT, 0xFF, 0xFF, 0, 1, b'y']);
+        abx[token] = ATTRIBUTE | 0xE0;
+    });
+
+    let (xml, errors) = convert(&abx);
+    assert!(
+        xml.ends_with(r#"<a><b x="hello"></b><c></c><d e="world">text</d></a>"#),
+        "{}",
+        xml
+    );
+    assert_eq!(
+        errors,
+        [
+            "error: token #5: Unknown attribute type: 224; skipped 4 bytes to the next element boundary"
+        ]
+    );
+}
+
+#[test]
+fn resumes_after_a_new_pool_string_of_impossible_length() {
+    // the length of `hello`, which is defined in the pool right there
+    let abx = fixture(|abx| {
+        let hello = find(abx, b"hello");
+        abx[hello - 2..hello].copy_from_slice(&[0x7F, 0xFF]);
+    });
+
+    let (xml, errors) = convert(&abx);
+    assert!(
+        xml.ends_with(r#"<a><b></b><c y="1"></c><d e="world">text</d></a>"#),
+        "{}",
+        xml
+    );
+    assert_eq!(
+        errors,
+        [
+            "error: token #3: Declared length 32767 exceeds remaining 62 bytes at offset 0x1B; skipped 5 bytes to the next element boundary"
+        ]
+    );
+}
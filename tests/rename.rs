@@ -0,0 +1,53 @@
+//! `abx rename --journal` over several files
+#![cfg(feature = "cli")]
+
+use android_xml_converter::XmlToAbxConverter;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("abx-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write_abx(path: &PathBuf, xml: &str) {
+    let mut abx = Vec::new();
+    XmlToAbxConverter::convert_from_string(xml, &mut abx).unwrap();
+    fs::write(path, abx).unwrap();
+}
+
+#[test]
+fn journals_only_the_renames_applied_to_each_file() {
+    let dir = temp_dir("rename");
+    let (first, second) = (dir.join("first.abx"), dir.join("second.abx"));
+    write_abx(&first, r#"<a x="1" />"#);
+    write_abx(&second, r#"<b y="2" />"#);
+
+    let status = Command::new(env!("CARGO_BIN_EXE_abx"))
+        .args(["rename", "--attr", "x=x2", "--attr", "y=y2", "--journal"])
+        .args([&first, &second])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let journal = |file: &str| fs::read_to_string(dir.join(file)).unwrap();
+    let first = journal("first.abx.journal");
+    let second = journal("second.abx.journal");
+    assert_eq!(first.lines().count(), 1);
+    assert!(
+        first.contains(r#""path":"//@x","old":"x","new":"x2""#),
+        "{}",
+        first
+    );
+    assert_eq!(second.lines().count(), 1);
+    assert!(
+        second.contains(r#""path":"//@y","old":"y","new":"y2""#),
+        "{}",
+        second
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}